@@ -0,0 +1,37 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// How often a busy tenant's `last_activity_at` is rewritten.
+pub const ACTIVITY_WRITE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Remembers when each tenant's activity was last written, so a stream of
+/// pixel hits costs one `tenants` update a minute rather than one each.
+/// Starts empty, so the first activity after a restart is always written.
+pub struct ActivityTracker {
+    interval: Duration,
+    last_written: Mutex<HashMap<String, Instant>>,
+}
+
+impl ActivityTracker {
+    pub fn new(interval: Duration) -> Self {
+        ActivityTracker {
+            interval,
+            last_written: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Whether activity for `tenant_id` should be written now. Claims the
+    /// slot when it is, so concurrent callers don't both write.
+    pub fn due(&self, tenant_id: &str) -> bool {
+        let now = Instant::now();
+        let mut last_written = self.last_written.lock().unwrap();
+        match last_written.get(tenant_id) {
+            Some(last) if now.duration_since(*last) < self.interval => false,
+            _ => {
+                last_written.insert(tenant_id.to_string(), now);
+                true
+            }
+        }
+    }
+}
@@ -0,0 +1,281 @@
+use axum::{
+    extract::{rejection::QueryRejection, ConnectInfo, Path as UrlPath, Query, Request, State},
+    http::{header, HeaderMap},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::{get, patch, post},
+    Json, Router,
+};
+
+use serde::{Deserialize, Serialize};
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use crate::database::{Database, SortDirection, TenantSettingsPatch, TenantSort};
+use crate::error::{AppError, AppJson};
+use crate::AppState;
+
+const DEFAULT_TENANT_PAGE: i64 = 50;
+const MAX_TENANT_PAGE: i64 = 500;
+const DEFAULT_AUDIT_PAGE: i64 = 100;
+const MAX_AUDIT_PAGE: i64 = 1000;
+
+/// Operator-only routes, all behind `Config.admin_key` and
+/// `Config.admin_ip_allowlist`.
+pub fn router(state: AppState) -> Router<AppState> {
+    Router::new()
+        .route("/tenants", get(list_tenants))
+        .route("/tenants/:tenant_id/settings", patch(patch_tenant_settings))
+        .route("/diagnostics", get(diagnostics))
+        .route("/config", get(config))
+        .route("/backup", post(backup))
+        .route("/audit", get(list_audit))
+        .route_layer(middleware::from_fn_with_state(state.clone(), require_admin))
+        .route_layer(middleware::from_fn_with_state(state, require_allowed_ip))
+}
+
+/// The admin key from `Authorization: Bearer <key>` or `X-Admin-Key`.
+fn provided_key(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .or_else(|| headers.get("x-admin-key").and_then(|v| v.to_str().ok()))
+        .map(|key| key.trim())
+}
+
+/// Compares without bailing out at the first differing byte.
+fn keys_match(expected: &str, provided: &str) -> bool {
+    expected.len() == provided.len()
+        && expected
+            .bytes()
+            .zip(provided.bytes())
+            .fold(0u8, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+pub(crate) fn is_admin(state: &AppState, headers: &HeaderMap) -> bool {
+    match (state.config.admin_key.as_deref(), provided_key(headers)) {
+        (Some(expected), Some(provided)) => !expected.is_empty() && keys_match(expected, provided),
+        _ => false,
+    }
+}
+
+/// Who to record in the audit log: `admin` for requests carrying the admin
+/// key, otherwise the client address as [`trusted_client_ip`] sees it.
+pub fn actor(state: &AppState, headers: &HeaderMap, peer: Option<ConnectInfo<SocketAddr>>) -> String {
+    if is_admin(state, headers) {
+        return "admin".to_string();
+    }
+    match trusted_client_ip(state, headers, peer.as_ref()) {
+        Some(ip) => format!("ip:{}", ip),
+        None => "anonymous".to_string(),
+    }
+}
+
+pub(crate) async fn require_admin(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    request: Request,
+    next: Next,
+) -> Response {
+    // With no admin key configured the admin routes stay locked
+    if !is_admin(&state, &headers) {
+        return AppError::Unauthorized("missing or invalid admin key".to_string()).into_response();
+    }
+    next.run(request).await
+}
+
+/// The client address for access checks and auditing. Behind trusted
+/// proxies that's the forwarded client address; otherwise it's the peer
+/// itself, since a directly connected client can write any
+/// `X-Forwarded-For` it likes.
+pub fn trusted_client_ip(
+    state: &AppState,
+    headers: &HeaderMap,
+    peer: Option<&ConnectInfo<SocketAddr>>,
+) -> Option<IpAddr> {
+    if state.config.trusted_proxy_hops > 0 {
+        return state.config.client_ip(headers)?.parse().ok();
+    }
+    peer.map(|ConnectInfo(addr)| addr.ip().to_canonical())
+}
+
+/// Rejects clients outside `Config.admin_ip_allowlist` with 403. Clients
+/// whose address can't be determined are rejected too.
+pub(crate) async fn require_allowed_ip(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    if let Some(allowlist) = state.config.admin_ip_allowlist() {
+        let peer = request.extensions().get::<ConnectInfo<SocketAddr>>();
+        let allowed = trusted_client_ip(&state, request.headers(), peer)
+            .is_some_and(|ip| allowlist.iter().any(|network| network.contains(&ip)));
+        if !allowed {
+            return AppError::Forbidden("client address is not allowed".to_string()).into_response();
+        }
+    }
+    next.run(request).await
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TenantListQuery {
+    limit: Option<i64>,
+    offset: Option<i64>,
+    sort_by: Option<TenantSort>,
+    #[serde(default)]
+    direction: SortDirection,
+}
+
+pub async fn list_tenants(
+    State(state): State<AppState>,
+    query: Result<Query<TenantListQuery>, QueryRejection>,
+) -> impl IntoResponse {
+    let Query(query) = match query {
+        Ok(query) => query,
+        Err(rejection) => return AppError::BadRequest(rejection.body_text()).into_response(),
+    };
+    let limit = query.limit.unwrap_or(DEFAULT_TENANT_PAGE).clamp(1, MAX_TENANT_PAGE);
+    let offset = query.offset.unwrap_or(0).max(0);
+
+    match state
+        .db
+        .list_tenants_with_stats(query.sort_by, query.direction, limit, offset)
+        .await
+    {
+        Ok((tenants, total)) => Json(serde_json::json!({
+            "tenants": tenants,
+            "total": total,
+            "limit": limit,
+            "offset": offset,
+        }))
+        .into_response(),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            AppError::internal().into_response()
+        }
+    }
+}
+
+/// Changes any of a tenant's settings in one go, for operators. Unlike the
+/// tenant's own settings route this doesn't create missing tenants.
+pub async fn patch_tenant_settings(
+    UrlPath(tenant_id): UrlPath<String>,
+    State(state): State<AppState>,
+    AppJson(patch): AppJson<TenantSettingsPatch>,
+) -> impl IntoResponse {
+    if let Err(message) = patch.validate() {
+        return AppError::BadRequest(message).into_response();
+    }
+    match state.db.patch_tenant_settings(&tenant_id, &patch).await {
+        Ok(Some(settings)) => {
+            state.pixel_cache_modes.invalidate(&tenant_id);
+            let details = serde_json::to_value(&settings).unwrap_or_default();
+            state.record_audit("admin", "tenant_settings.update", &tenant_id, details).await;
+            Json(settings).into_response()
+        }
+        Ok(None) => AppError::NotFound(format!("tenant '{}' not found", tenant_id)).into_response(),
+        Err(e) => {
+            eprintln!("Failed to update tenant settings: {}", e);
+            AppError::internal().into_response()
+        }
+    }
+}
+
+/// The running configuration, with secrets redacted.
+pub async fn config(State(state): State<AppState>) -> impl IntoResponse {
+    Json(state.config.clone())
+}
+
+pub async fn diagnostics(State(state): State<AppState>) -> impl IntoResponse {
+    match state.db.diagnostics().await {
+        Ok(diagnostics) => Json(diagnostics).into_response(),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            AppError::internal().into_response()
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct BackupInfo {
+    pub path: PathBuf,
+    pub bytes: u64,
+    pub duration_ms: u64,
+}
+
+/// Writes a timestamped copy of the live database into `backup_dir`.
+pub async fn run_backup(db: &Database, backup_dir: &str) -> Result<BackupInfo, String> {
+    let dir = Path::new(backup_dir);
+    std::fs::create_dir_all(dir).map_err(|e| format!("failed to create {}: {}", dir.display(), e))?;
+
+    let started = Instant::now();
+    let path = dir.join(format!(
+        "tracking-{}.db",
+        chrono::Utc::now().format("%Y%m%dT%H%M%S%.3fZ")
+    ));
+    let result = db
+        .backup_to(&path)
+        .await
+        .map_err(|e| format!("backup failed: {}", e))
+        .and_then(|()| {
+            std::fs::metadata(&path)
+                .map(|metadata| metadata.len())
+                .map_err(|e| format!("failed to read {}: {}", path.display(), e))
+        });
+    let duration_ms = started.elapsed().as_millis() as u64;
+
+    match result {
+        Ok(bytes) => {
+            println!("Backed up database to {} ({} bytes) in {}ms", path.display(), bytes, duration_ms);
+            Ok(BackupInfo { path, bytes, duration_ms })
+        }
+        Err(e) => {
+            eprintln!("Database backup to {} failed after {}ms: {}", path.display(), duration_ms, e);
+            Err(e)
+        }
+    }
+}
+
+pub async fn backup(State(state): State<AppState>) -> impl IntoResponse {
+    match run_backup(&state.db, &state.config.backup_dir).await {
+        Ok(info) => {
+            let details = serde_json::json!({ "bytes": info.bytes, "duration_ms": info.duration_ms });
+            state
+                .record_audit("admin", "backup.create", &info.path.display().to_string(), details)
+                .await;
+            Json(info).into_response()
+        }
+        Err(e) => AppError::Internal(e).into_response(),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AuditQuery {
+    limit: Option<i64>,
+    offset: Option<i64>,
+}
+
+pub async fn list_audit(
+    State(state): State<AppState>,
+    query: Result<Query<AuditQuery>, QueryRejection>,
+) -> impl IntoResponse {
+    let Query(query) = match query {
+        Ok(query) => query,
+        Err(rejection) => return AppError::BadRequest(rejection.body_text()).into_response(),
+    };
+    let limit = query.limit.unwrap_or(DEFAULT_AUDIT_PAGE).clamp(1, MAX_AUDIT_PAGE);
+    let offset = query.offset.unwrap_or(0).max(0);
+
+    match state.db.list_audit(limit, offset).await {
+        Ok((entries, total)) => Json(serde_json::json!({
+            "entries": entries,
+            "total": total,
+            "limit": limit,
+            "offset": offset,
+        }))
+        .into_response(),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            AppError::internal().into_response()
+        }
+    }
+}
@@ -0,0 +1,80 @@
+use axum::{
+    extract::Path,
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+};
+use sha1::{Digest, Sha1};
+use std::sync::OnceLock;
+
+use crate::error::AppError;
+
+/// How long browsers may use an asset before revalidating it.
+pub(crate) const ASSET_MAX_AGE_SECS: u32 = 3600;
+
+/// A file compiled into the binary and served under `/assets/`.
+struct Asset {
+    name: &'static str,
+    content_type: &'static str,
+    bytes: &'static [u8],
+}
+
+const ASSETS: &[Asset] = &[
+    Asset {
+        name: "dashboard.css",
+        content_type: "text/css; charset=utf-8",
+        bytes: include_bytes!("../static/dashboard.css"),
+    },
+    Asset {
+        name: "dashboard.js",
+        content_type: "text/javascript; charset=utf-8",
+        bytes: include_bytes!("../static/dashboard.js"),
+    },
+];
+
+/// Quoted content hash of each asset, in `ASSETS` order, computed once.
+fn etags() -> &'static [String] {
+    static ETAGS: OnceLock<Vec<String>> = OnceLock::new();
+    ETAGS.get_or_init(|| {
+        ASSETS
+            .iter()
+            .map(|asset| {
+                let digest = Sha1::digest(asset.bytes);
+                let hex: String = digest.iter().take(8).map(|b| format!("{:02x}", b)).collect();
+                format!("\"{}\"", hex)
+            })
+            .collect()
+    })
+}
+
+/// Serves the dashboard's stylesheet and script. They're cached for an hour
+/// and revalidated by ETag, which changes whenever a build changes them.
+pub async fn serve_asset(Path(name): Path<String>, headers: HeaderMap) -> Response {
+    let Some(index) = ASSETS.iter().position(|asset| asset.name == name) else {
+        return AppError::NotFound(format!("no asset named '{}'", name)).into_response();
+    };
+    let asset = &ASSETS[index];
+    let etag = etags()[index].as_str();
+    let cache_control = format!("public, max-age={}", ASSET_MAX_AGE_SECS);
+
+    let unchanged = headers
+        .get(header::IF_NONE_MATCH)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.split(',').any(|tag| tag.trim() == etag));
+    if unchanged {
+        return (
+            StatusCode::NOT_MODIFIED,
+            [(header::ETAG, etag.to_string()), (header::CACHE_CONTROL, cache_control)],
+        )
+            .into_response();
+    }
+
+    (
+        [
+            (header::CONTENT_TYPE, asset.content_type.to_string()),
+            (header::ETAG, etag.to_string()),
+            (header::CACHE_CONTROL, cache_control),
+        ],
+        asset.bytes,
+    )
+        .into_response()
+}
@@ -0,0 +1,171 @@
+use serde::Serialize;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// Upper bounds (in microseconds) of the event write latency buckets. Writes
+/// slower than the last bound land in an overflow bucket.
+const WRITE_BUCKETS_US: [u64; 11] = [
+    100, 250, 500, 1_000, 2_500, 5_000, 10_000, 25_000, 50_000, 100_000, 250_000,
+];
+
+/// Latencies counted into fixed buckets, along with their count, total and
+/// max. Everything is atomic so recording never takes a lock of its own.
+#[derive(Debug)]
+pub struct LatencyHistogram {
+    /// Upper bounds in microseconds; slower samples go in an extra overflow
+    /// bucket.
+    bounds_us: &'static [u64],
+    buckets: Vec<AtomicU64>,
+    total_us: AtomicU64,
+    max_us: AtomicU64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct HistogramBucket {
+    /// Upper bound of the bucket in milliseconds, `None` for the overflow bucket.
+    pub le_ms: Option<f64>,
+    pub count: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LatencyMetrics {
+    pub count: u64,
+    pub avg_ms: f64,
+    /// Estimated from the histogram, so it's the upper bound of the bucket
+    /// the 95th percentile falls into (or the max for the overflow bucket).
+    pub p95_ms: Option<f64>,
+    pub max_ms: f64,
+    pub histogram: Vec<HistogramBucket>,
+}
+
+impl LatencyHistogram {
+    pub fn new(bounds_us: &'static [u64]) -> Self {
+        LatencyHistogram {
+            bounds_us,
+            buckets: (0..=bounds_us.len()).map(|_| AtomicU64::new(0)).collect(),
+            total_us: AtomicU64::new(0),
+            max_us: AtomicU64::new(0),
+        }
+    }
+
+    pub fn record(&self, elapsed: Duration) {
+        let micros = elapsed.as_micros() as u64;
+        let bucket = self
+            .bounds_us
+            .iter()
+            .position(|bound| micros <= *bound)
+            .unwrap_or(self.bounds_us.len());
+
+        self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        self.total_us.fetch_add(micros, Ordering::Relaxed);
+        self.max_us.fetch_max(micros, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> LatencyMetrics {
+        let counts: Vec<u64> = self
+            .buckets
+            .iter()
+            .map(|bucket| bucket.load(Ordering::Relaxed))
+            .collect();
+        let count: u64 = counts.iter().sum();
+        let total_us = self.total_us.load(Ordering::Relaxed);
+        let max_ms = self.max_us.load(Ordering::Relaxed) as f64 / 1000.0;
+
+        let bound_ms = |index: usize| self.bounds_us.get(index).map(|us| *us as f64 / 1000.0);
+
+        let p95_ms = if count == 0 {
+            None
+        } else {
+            let target = (count as f64 * 0.95).ceil() as u64;
+            let mut seen = 0;
+            counts
+                .iter()
+                .position(|bucket| {
+                    seen += bucket;
+                    seen >= target
+                })
+                .map(|index| bound_ms(index).unwrap_or(max_ms))
+        };
+
+        LatencyMetrics {
+            count,
+            avg_ms: if count == 0 {
+                0.0
+            } else {
+                total_us as f64 / count as f64 / 1000.0
+            },
+            p95_ms,
+            max_ms,
+            histogram: counts
+                .iter()
+                .enumerate()
+                .map(|(index, count)| HistogramBucket {
+                    le_ms: bound_ms(index),
+                    count: *count,
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Counters describing how hard the database is being worked.
+#[derive(Debug)]
+pub struct DbMetrics {
+    event_writes: LatencyHistogram,
+    lock_acquisitions: AtomicU64,
+    lock_contended: AtomicU64,
+    busy_retries: AtomicU64,
+}
+
+impl Default for DbMetrics {
+    fn default() -> Self {
+        DbMetrics {
+            event_writes: LatencyHistogram::new(&WRITE_BUCKETS_US),
+            lock_acquisitions: AtomicU64::new(0),
+            lock_contended: AtomicU64::new(0),
+            busy_retries: AtomicU64::new(0),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LockMetrics {
+    pub acquisitions: u64,
+    pub contended: u64,
+    /// Writes retried because SQLite reported the database busy or locked.
+    pub busy_retries: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DbMetricsSnapshot {
+    pub event_writes: LatencyMetrics,
+    pub lock: LockMetrics,
+}
+
+impl DbMetrics {
+    pub fn record_event_write(&self, elapsed: Duration) {
+        self.event_writes.record(elapsed);
+    }
+
+    pub fn record_lock(&self, contended: bool) {
+        self.lock_acquisitions.fetch_add(1, Ordering::Relaxed);
+        if contended {
+            self.lock_contended.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn record_busy_retry(&self) {
+        self.busy_retries.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> DbMetricsSnapshot {
+        DbMetricsSnapshot {
+            event_writes: self.event_writes.snapshot(),
+            lock: LockMetrics {
+                acquisitions: self.lock_acquisitions.load(Ordering::Relaxed),
+                contended: self.lock_contended.load(Ordering::Relaxed),
+                busy_retries: self.busy_retries.load(Ordering::Relaxed),
+            },
+        }
+    }
+}
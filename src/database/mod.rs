@@ -1,14 +1,197 @@
 use chrono::{DateTime, Utc};
-use rusqlite::{params, Connection, Result as SqliteResult};
+use rusqlite::{backup::{Backup, StepResult}, params, Connection, OpenFlags, OptionalExtension, Result as SqliteResult, Row};
 use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
 use std::sync::Arc;
-use tokio::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::sync::{Mutex, MutexGuard};
+
+mod metrics;
+pub use metrics::{DbMetrics, DbMetricsSnapshot, LatencyHistogram, LatencyMetrics};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Tenant {
     pub id: String,
     pub name: String,
+    #[serde(with = "crate::time_format")]
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TenantSummary {
+    pub id: String,
+    pub name: String,
+    #[serde(with = "crate::time_format")]
     pub created_at: DateTime<Utc>,
+    pub email_count: i64,
+    pub total_opens: i64,
+    pub total_clicks: i64,
+    /// When the tenant last created an email or logged an event, to within
+    /// a minute. None for tenants with no activity since this was tracked.
+    #[serde(default, with = "crate::time_format::option")]
+    pub last_activity_at: Option<DateTime<Utc>>,
+}
+
+/// Columns the admin tenant listing can be ordered by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TenantSort {
+    Name,
+    CreatedAt,
+    EmailCount,
+    OpenCount,
+    LastActivityAt,
+}
+
+impl TenantSort {
+    fn column(self) -> &'static str {
+        match self {
+            TenantSort::Name => "t.name",
+            TenantSort::CreatedAt => "t.created_at",
+            TenantSort::EmailCount => "email_count",
+            TenantSort::OpenCount => "total_opens",
+            TenantSort::LastActivityAt => "t.last_activity_at",
+        }
+    }
+}
+
+/// Columns the recipient listing can be ordered by.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RecipientSort {
+    Recipient,
+    EmailsCreated,
+    EmailsSent,
+    TotalOpens,
+    TotalClicks,
+    /// Opens plus clicks.
+    #[default]
+    Engagement,
+    LastEngagedAt,
+}
+
+impl RecipientSort {
+    fn column(self) -> &'static str {
+        match self {
+            RecipientSort::Recipient => "em.recipient",
+            RecipientSort::EmailsCreated => "emails_created",
+            RecipientSort::EmailsSent => "emails_sent",
+            RecipientSort::TotalOpens => "total_opens",
+            RecipientSort::TotalClicks => "total_clicks",
+            RecipientSort::Engagement => "total_opens + total_clicks",
+            RecipientSort::LastEngagedAt => "last_engaged_at",
+        }
+    }
+
+    /// Alphabetical for addresses, biggest or latest first for the rest.
+    pub fn default_direction(self) -> SortDirection {
+        match self {
+            RecipientSort::Recipient => SortDirection::Asc,
+            _ => SortDirection::Desc,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SortDirection {
+    #[default]
+    Asc,
+    Desc,
+}
+
+impl SortDirection {
+    fn keyword(self) -> &'static str {
+        match self {
+            SortDirection::Asc => "ASC",
+            SortDirection::Desc => "DESC",
+        }
+    }
+}
+
+/// Which caching headers the tracking pixel is served with.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PixelCacheMode {
+    /// Ask clients and proxies never to cache, so every open is seen.
+    #[default]
+    NoCache,
+    /// Allow a short private cache, which dedups client refetches and
+    /// blunts prefetch-driven inflation.
+    CacheShort,
+}
+
+impl PixelCacheMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PixelCacheMode::NoCache => "no_cache",
+            PixelCacheMode::CacheShort => "cache_short",
+        }
+    }
+
+    fn from_db(value: &str) -> Self {
+        match value {
+            "cache_short" => PixelCacheMode::CacheShort,
+            _ => PixelCacheMode::NoCache,
+        }
+    }
+}
+
+/// Per-tenant knobs, stored alongside the tenant row.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TenantSettings {
+    #[serde(default)]
+    pub pixel_cache_mode: PixelCacheMode,
+    /// Emails the tenant may create per UTC day. `None` falls back to the
+    /// server-wide default.
+    #[serde(default)]
+    pub daily_email_limit: Option<i64>,
+}
+
+/// A partial update to `TenantSettings`; absent fields are left alone.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TenantSettingsPatch {
+    pub pixel_cache_mode: Option<PixelCacheMode>,
+    /// `null` clears the limit, so the server-wide default applies again.
+    #[serde(default, deserialize_with = "present", skip_serializing_if = "Option::is_none")]
+    pub daily_email_limit: Option<Option<i64>>,
+}
+
+/// Tells a field sent as `null` (`Some(None)`) from one left out (`None`,
+/// through `#[serde(default)]`).
+fn present<'de, D: serde::Deserializer<'de>, T: Deserialize<'de>>(deserializer: D) -> Result<Option<T>, D::Error> {
+    T::deserialize(deserializer).map(Some)
+}
+
+fn check_daily_email_limit(limit: Option<i64>) -> Result<(), String> {
+    match limit {
+        Some(limit) if limit < 0 => Err(format!("daily_email_limit must not be negative, got {}", limit)),
+        _ => Ok(()),
+    }
+}
+
+impl TenantSettings {
+    pub fn apply(&mut self, patch: TenantSettingsPatch) {
+        if let Some(pixel_cache_mode) = patch.pixel_cache_mode {
+            self.pixel_cache_mode = pixel_cache_mode;
+        }
+        if let Some(daily_email_limit) = patch.daily_email_limit {
+            self.daily_email_limit = daily_email_limit;
+        }
+    }
+
+    /// Why these settings can't be stored, if they can't.
+    pub fn validate(&self) -> Result<(), String> {
+        check_daily_email_limit(self.daily_email_limit)
+    }
+}
+
+impl TenantSettingsPatch {
+    /// Why this patch can't be applied, if it can't.
+    pub fn validate(&self) -> Result<(), String> {
+        check_daily_email_limit(self.daily_email_limit.flatten())
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -17,242 +200,3021 @@ pub struct Email {
     pub tenant_id: String,
     pub subject: Option<String>,
     pub recipient: Option<String>,
+    #[serde(with = "crate::time_format")]
     pub created_at: DateTime<Utc>,
+    /// Opaque identifier used in tracking URLs. Emails created before tokens
+    /// were introduced don't have one.
+    pub public_token: Option<String>,
+    pub campaign_id: Option<String>,
+    /// Arbitrary JSON object attached by the sender.
+    pub metadata: Option<serde_json::Value>,
+    /// Whether the recipient agreed to open tracking. Opens of emails
+    /// without consent are not recorded.
+    pub tracking_consent: bool,
+    /// After this, opens and clicks still get their pixel or redirect but
+    /// aren't recorded.
+    #[serde(default, with = "crate::time_format::option")]
+    pub expires_at: Option<DateTime<Utc>>,
+    /// When the email was handed to the mail server, as reported through
+    /// `POST /:tenant_id/emails/:email_id/sent`. Only sent emails count
+    /// towards open and click rates.
+    #[serde(default, with = "crate::time_format::option")]
+    pub sent_at: Option<DateTime<Utc>>,
+    /// The email this one resends, as created by
+    /// `POST /:tenant_id/emails/:email_id/resend`.
+    #[serde(default)]
+    pub resend_of: Option<i64>,
+    /// The sender's own id for the email, unique within the tenant.
+    #[serde(default)]
+    pub external_id: Option<String>,
 }
 
+/// An email with its engagement totals, for listings.
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct Event {
-    pub id: i64,
-    pub email_id: i64,
-    pub event_type: String,
-    pub timestamp: DateTime<Utc>,
-    pub user_agent: Option<String>,
-    pub ip_address: Option<String>,
+pub struct EmailWithStats {
+    #[serde(flatten)]
+    pub email: Email,
+    pub open_count: i64,
+    pub click_count: i64,
+    #[serde(default, with = "crate::time_format::option")]
+    pub last_event_at: Option<DateTime<Utc>>,
+    /// Past its `expires_at`, so no longer recording events.
+    pub expired: bool,
 }
 
+/// One recipient's engagement across every email addressed to them.
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct EventStats {
+pub struct RecipientStats {
+    pub recipient: String,
+    pub emails_created: i64,
+    /// Emails marked sent.
+    pub emails_sent: i64,
     pub total_opens: i64,
     pub total_clicks: i64,
-    pub unique_opens: i64,
-    pub unique_clicks: i64,
-    pub recent_events: Vec<Event>,
+    /// The latest counted open or click.
+    #[serde(default, with = "crate::time_format::option")]
+    pub last_engaged_at: Option<DateTime<Utc>>,
 }
 
-pub struct Database {
-    conn: Arc<Mutex<Connection>>,
+/// How much one email has been engaged with so far.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct EmailEngagement {
+    pub open_count: i64,
+    pub click_count: i64,
+    #[serde(default, with = "crate::time_format::option")]
+    pub last_event_at: Option<DateTime<Utc>>,
 }
 
-impl Database {
-    pub async fn new(db_path: &str) -> SqliteResult<Self> {
-        let conn = Connection::open(db_path)?;
-        let database = Database {
-            conn: Arc::new(Mutex::new(conn)),
-        };
-        database.initialize().await?;
-        Ok(database)
+/// Fields supplied when creating an email record.
+#[derive(Debug, Clone, Default)]
+pub struct NewEmail {
+    pub subject: Option<String>,
+    pub recipient: Option<String>,
+    pub campaign_id: Option<String>,
+    pub metadata: Option<serde_json::Value>,
+    /// Client-supplied key making creation safe to retry, scoped per tenant.
+    pub idempotency_key: Option<String>,
+    /// Defaults to consented when not given.
+    pub tracking_consent: Option<bool>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub resend_of: Option<i64>,
+    /// The sender's own id; creating an email with one the tenant already
+    /// has returns that email instead.
+    pub external_id: Option<String>,
+}
+
+impl Email {
+    /// The identifier to put in tracking URLs: the public token when there
+    /// is one, otherwise the sequential id.
+    pub fn url_key(&self) -> String {
+        match &self.public_token {
+            Some(token) => token.clone(),
+            None => self.id.to_string(),
+        }
     }
 
-    async fn initialize(&self) -> SqliteResult<()> {
-        let conn = self.conn.lock().await;
-        
-        // Create tenants table
-        conn.execute(
-            "CREATE TABLE IF NOT EXISTS tenants (
-                id TEXT PRIMARY KEY,
-                name TEXT NOT NULL,
-                created_at TEXT NOT NULL
-            )",
-            params![],
-        )?;
+    /// Whether the email stopped recording events before `now`.
+    pub fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at <= now)
+    }
+}
 
-        // Create emails table
-        conn.execute(
-            "CREATE TABLE IF NOT EXISTS emails (
-                id INTEGER PRIMARY KEY AUTOINCREMENT,
-                tenant_id TEXT NOT NULL,
-                subject TEXT,
-                recipient TEXT,
-                created_at TEXT NOT NULL,
-                FOREIGN KEY (tenant_id) REFERENCES tenants (id)
-            )",
-            params![],
-        )?;
+/// An email from `create_email_within_quota`, with the tenant's quota
+/// after it.
+#[derive(Debug, Clone)]
+pub struct CreatedEmail {
+    pub email: Email,
+    /// False when an idempotency key or external id matched an existing
+    /// email, which is returned instead.
+    pub created: bool,
+    /// Emails the tenant may still create today, or None without a daily
+    /// limit.
+    pub quota_remaining: Option<i64>,
+}
 
-        // Create events table
-        conn.execute(
-            "CREATE TABLE IF NOT EXISTS events (
-                id INTEGER PRIMARY KEY AUTOINCREMENT,
-                email_id INTEGER NOT NULL,
-                event_type TEXT NOT NULL,
-                timestamp TEXT NOT NULL,
-                user_agent TEXT,
-                ip_address TEXT,
-                FOREIGN KEY (email_id) REFERENCES emails (id)
-            )",
-            params![],
-        )?;
+/// Creating the emails would have taken the tenant past its daily limit,
+/// so nothing was written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QuotaExceeded {
+    /// Emails the tenant may still create today.
+    pub remaining: i64,
+}
 
-        // Create indexes for better performance
-        conn.execute(
-            "CREATE INDEX IF NOT EXISTS idx_events_email_id ON events(email_id)",
-            params![],
-        )?;
+/// How a tracking URL refers to an email: by its sequential id or by its
+/// opaque public token.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum EmailRef {
+    Id(i64),
+    Token(String),
+}
 
-        conn.execute(
-            "CREATE INDEX IF NOT EXISTS idx_events_type ON events(event_type)",
-            params![],
-        )?;
+impl EmailRef {
+    pub fn parse(value: &str) -> Self {
+        match value.parse::<i64>() {
+            Ok(id) => EmailRef::Id(id),
+            Err(_) => EmailRef::Token(value.to_string()),
+        }
+    }
+}
 
-        conn.execute(
-            "CREATE INDEX IF NOT EXISTS idx_emails_tenant ON emails(tenant_id)",
-            params![],
-        )?;
+/// The id or token as it appears in a URL.
+impl std::fmt::Display for EmailRef {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EmailRef::Id(id) => write!(f, "{}", id),
+            EmailRef::Token(token) => f.write_str(token),
+        }
+    }
+}
 
-        Ok(())
+const EMAIL_COLUMNS: &str =
+    "id, tenant_id, subject, recipient, created_at, public_token, campaign_id, metadata, tracking_consent, expires_at, sent_at, resend_of, external_id";
+
+/// Number of columns in `EMAIL_COLUMNS`; anything selected after them
+/// starts at this index.
+const EMAIL_COLUMN_COUNT: usize = 13;
+
+fn email_from_row(row: &Row) -> SqliteResult<Email> {
+    Ok(Email {
+        id: row.get(0)?,
+        tenant_id: row.get(1)?,
+        subject: row.get(2)?,
+        recipient: row.get(3)?,
+        created_at: DateTime::parse_from_rfc3339(&row.get::<_, String>(4)?)
+            .unwrap()
+            .with_timezone(&Utc),
+        public_token: row.get(5)?,
+        campaign_id: row.get(6)?,
+        metadata: row
+            .get::<_, Option<String>>(7)?
+            .and_then(|metadata| serde_json::from_str(&metadata).ok()),
+        tracking_consent: row.get(8)?,
+        expires_at: row
+            .get::<_, Option<String>>(9)?
+            .map(|t| DateTime::parse_from_rfc3339(&t).unwrap().with_timezone(&Utc)),
+        sent_at: row
+            .get::<_, Option<String>>(10)?
+            .map(|t| DateTime::parse_from_rfc3339(&t).unwrap().with_timezone(&Utc)),
+        resend_of: row.get(11)?,
+        external_id: row.get(12)?,
+    })
+}
+
+const BASE62_ALPHABET: &[u8] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+
+/// Base62 digits needed for any 128-bit value.
+const PUBLIC_TOKEN_LEN: usize = 22;
+
+/// 16 random bytes rendered as base62, zero-padded to `PUBLIC_TOKEN_LEN`
+/// characters, e.g. `4fTq0bXkP2mZ9yWcR1sLhA`.
+fn generate_public_token() -> String {
+    let mut value = rand::random::<u128>();
+    let mut token = vec![BASE62_ALPHABET[0]; PUBLIC_TOKEN_LEN];
+    for digit in token.iter_mut().rev() {
+        *digit = BASE62_ALPHABET[(value % 62) as usize];
+        value /= 62;
     }
+    String::from_utf8(token).unwrap()
+}
 
-    pub async fn create_tenant(&self, tenant_id: &str, name: &str) -> SqliteResult<()> {
-        let conn = self.conn.lock().await;
-        let now = Utc::now();
-        
-        conn.execute(
-            "INSERT OR IGNORE INTO tenants (id, name, created_at) VALUES (?1, ?2, ?3)",
-            params![tenant_id, name, now.to_rfc3339()],
-        )?;
-        Ok(())
+/// Length of generated short codes; 62^8 leaves collisions vanishingly rare.
+const SHORT_CODE_LEN: usize = 8;
+const SHORT_CODE_ATTEMPTS: usize = 5;
+
+fn generate_short_code() -> String {
+    let mut value = uuid::Uuid::new_v4().as_u128();
+    let mut code = Vec::with_capacity(SHORT_CODE_LEN);
+    for _ in 0..SHORT_CODE_LEN {
+        code.push(BASE62_ALPHABET[(value % 62) as usize]);
+        value /= 62;
     }
+    String::from_utf8(code).unwrap()
+}
 
-    pub async fn get_tenant(&self, tenant_id: &str) -> SqliteResult<Option<Tenant>> {
-        let conn = self.conn.lock().await;
-        
-        let mut stmt = conn.prepare("SELECT id, name, created_at FROM tenants WHERE id = ?1")?;
-        let tenant_iter = stmt.query_map(params![tenant_id], |row| {
-            Ok(Tenant {
-                id: row.get(0)?,
-                name: row.get(1)?,
-                created_at: DateTime::parse_from_rfc3339(&row.get::<_, String>(2)?)
-                    .unwrap()
-                    .with_timezone(&Utc),
-            })
-        })?;
+/// WAL with `synchronous=NORMAL` gives far better write throughput than the
+/// defaults, and foreign keys are only enforced when switched on.
+fn configure_connection(conn: &Connection, options: &DatabaseOptions) -> SqliteResult<()> {
+    let synchronous = options.synchronous.to_ascii_uppercase();
+    if !SYNCHRONOUS_LEVELS.contains(&synchronous.as_str()) {
+        return Err(rusqlite::Error::InvalidParameterName(format!(
+            "unsupported synchronous level '{}'",
+            options.synchronous
+        )));
+    }
 
-        for tenant in tenant_iter {
-            return Ok(Some(tenant?));
-        }
-        Ok(None)
+    conn.pragma_update_and_check(None, "journal_mode", "WAL", |row| row.get::<_, String>(0))?;
+    conn.pragma_update(None, "synchronous", &synchronous)?;
+    conn.pragma_update(None, "foreign_keys", "ON")?;
+    conn.busy_timeout(options.busy_timeout)?;
+    Ok(())
+}
+
+/// One page of a tenant's emails, newest first, matching every metadata
+/// filter. Returns the SQL and its bound values.
+fn email_page_query(
+    tenant_id: &str,
+    metadata_filters: &[(String, String)],
+    expired: Option<bool>,
+    limit: i64,
+    offset: i64,
+) -> (String, Vec<String>) {
+    let mut sql = format!("SELECT {} FROM emails WHERE tenant_id = ?", EMAIL_COLUMNS);
+    let mut values: Vec<String> = vec![tenant_id.to_string()];
+    for (key, value) in metadata_filters {
+        sql.push_str(" AND CAST(json_extract(metadata, ?) AS TEXT) = ?");
+        values.push(metadata_path(key));
+        values.push(value.clone());
     }
+    match expired {
+        Some(true) => sql.push_str(" AND expires_at IS NOT NULL AND expires_at <= ?"),
+        Some(false) => sql.push_str(" AND (expires_at IS NULL OR expires_at > ?)"),
+        None => {}
+    }
+    if expired.is_some() {
+        values.push(Utc::now().to_rfc3339());
+    }
+    sql.push_str(&format!(" ORDER BY id DESC LIMIT {} OFFSET {}", limit, offset));
+    (sql, values)
+}
 
-    pub async fn create_email(&self, tenant_id: &str, subject: Option<&str>, recipient: Option<&str>) -> SqliteResult<i64> {
-        let conn = self.conn.lock().await;
-        let now = Utc::now();
-        
-        conn.execute(
-            "INSERT INTO emails (tenant_id, subject, recipient, created_at) VALUES (?1, ?2, ?3, ?4)",
-            params![tenant_id, subject, recipient, now.to_rfc3339()],
-        )?;
-        Ok(conn.last_insert_rowid())
+/// Midnight UTC at the start of the current day.
+fn start_of_today() -> DateTime<Utc> {
+    Utc::now().date_naive().and_hms_opt(0, 0, 0).unwrap().and_utc()
+}
+
+/// How many more emails the tenant may create today under `daily_limit`.
+/// Read inside the transaction that inserts them, so concurrent requests
+/// can't both take the last one.
+fn quota_remaining(conn: &Connection, tenant_id: &str, daily_limit: Option<i64>) -> SqliteResult<Option<i64>> {
+    let Some(limit) = daily_limit else {
+        return Ok(None);
+    };
+    let created: i64 = conn.query_row(
+        "SELECT COUNT(*) FROM emails WHERE tenant_id = ?1 AND created_at >= ?2",
+        params![tenant_id, start_of_today().to_rfc3339()],
+        |row| row.get(0),
+    )?;
+    Ok(Some((limit - created).max(0)))
+}
+
+/// Runs a backup as a single step, waiting out any lock held by a writer.
+fn copy_all_pages(backup: &Backup) -> SqliteResult<()> {
+    loop {
+        match backup.step(-1)? {
+            StepResult::Done => return Ok(()),
+            StepResult::More => {}
+            _ => std::thread::sleep(Duration::from_millis(50)),
+        }
     }
+}
 
-    pub async fn get_email(&self, email_id: i64, tenant_id: &str) -> SqliteResult<Option<Email>> {
-        let conn = self.conn.lock().await;
-        
-        let mut stmt = conn.prepare(
-            "SELECT id, tenant_id, subject, recipient, created_at FROM emails WHERE id = ?1 AND tenant_id = ?2"
-        )?;
-        let email_iter = stmt.query_map(params![email_id, tenant_id], |row| {
-            Ok(Email {
-                id: row.get(0)?,
-                tenant_id: row.get(1)?,
-                subject: row.get(2)?,
-                recipient: row.get(3)?,
-                created_at: DateTime::parse_from_rfc3339(&row.get::<_, String>(4)?)
-                    .unwrap()
-                    .with_timezone(&Utc),
+fn is_busy(error: &rusqlite::Error) -> bool {
+    matches!(
+        error.sqlite_error_code(),
+        Some(rusqlite::ErrorCode::DatabaseBusy | rusqlite::ErrorCode::DatabaseLocked)
+    )
+}
+
+/// The tenant's latest `RECENT_EVENT_COUNT` events matching `filter`, newest
+/// first.
+fn recent_events(conn: &Connection, tenant_id: &str, filter: &EventFilter) -> SqliteResult<Vec<Event>> {
+    let (conditions, filter_values) = filter.conditions();
+    let mut values = vec![tenant_id.to_string()];
+    values.extend(filter_values);
+
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {}
+         FROM events e
+         JOIN emails em ON e.email_id = em.id
+         WHERE em.tenant_id = ?{}
+         ORDER BY e.timestamp DESC, e.id DESC
+         LIMIT {}",
+        EVENT_COLUMNS, conditions, RECENT_EVENT_COUNT
+    ))?;
+    let events = stmt.query_map(rusqlite::params_from_iter(&values), event_from_row)?;
+    events.collect()
+}
+
+/// A tenant's settings, or `None` if the tenant doesn't exist.
+fn read_tenant_settings(conn: &Connection, tenant_id: &str) -> SqliteResult<Option<TenantSettings>> {
+    conn.query_row(
+        "SELECT t.pixel_cache_mode, q.daily_email_limit
+         FROM tenants t
+         LEFT JOIN tenant_quotas q ON q.tenant_id = t.id
+         WHERE t.id = ?1",
+        params![tenant_id],
+        |row| {
+            Ok(TenantSettings {
+                pixel_cache_mode: PixelCacheMode::from_db(&row.get::<_, String>(0)?),
+                daily_email_limit: row.get(1)?,
             })
-        })?;
+        },
+    )
+    .optional()
+}
 
-        for email in email_iter {
-            return Ok(Some(email?));
-        }
-        Ok(None)
+/// Stores settings across the tenant row and its quota row.
+fn write_tenant_settings(conn: &Connection, tenant_id: &str, settings: &TenantSettings) -> SqliteResult<()> {
+    conn.execute(
+        "UPDATE tenants SET pixel_cache_mode = ?1 WHERE id = ?2",
+        params![settings.pixel_cache_mode.as_str(), tenant_id],
+    )?;
+
+    match settings.daily_email_limit {
+        Some(limit) => conn.execute(
+            "INSERT INTO tenant_quotas (tenant_id, daily_email_limit) VALUES (?1, ?2)
+             ON CONFLICT(tenant_id) DO UPDATE SET daily_email_limit = excluded.daily_email_limit",
+            params![tenant_id, limit],
+        )?,
+        None => conn.execute("DELETE FROM tenant_quotas WHERE tenant_id = ?1", params![tenant_id])?,
+    };
+    Ok(())
+}
+
+fn schema_version(conn: &Connection) -> SqliteResult<i64> {
+    conn.query_row("PRAGMA user_version", params![], |row| row.get(0))
+}
+
+/// Inserts one email with a fresh public token.
+fn insert_email(conn: &Connection, tenant_id: &str, email: &NewEmail, now: DateTime<Utc>) -> SqliteResult<Email> {
+    let public_token = generate_public_token();
+    let metadata = email.metadata.as_ref().map(|metadata| metadata.to_string());
+    let tracking_consent = email.tracking_consent.unwrap_or(true);
+
+    conn.execute(
+        "INSERT INTO emails (tenant_id, subject, recipient, created_at, public_token, campaign_id, idempotency_key, metadata, tracking_consent, expires_at, resend_of, external_id) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)",
+        params![tenant_id, email.subject, email.recipient, now.to_rfc3339(), public_token, email.campaign_id, email.idempotency_key, metadata, tracking_consent, email.expires_at.map(|t| t.to_rfc3339()), email.resend_of, email.external_id],
+    )?;
+
+    Ok(Email {
+        id: conn.last_insert_rowid(),
+        tenant_id: tenant_id.to_string(),
+        subject: email.subject.clone(),
+        recipient: email.recipient.clone(),
+        created_at: now,
+        public_token: Some(public_token),
+        campaign_id: email.campaign_id.clone(),
+        metadata: email.metadata.clone(),
+        tracking_consent,
+        expires_at: email.expires_at,
+        sent_at: None,
+        resend_of: email.resend_of,
+        external_id: email.external_id.clone(),
+    })
+}
+
+/// The id of `user_agent` in the `user_agents` lookup table, adding it the
+/// first time it's seen.
+fn user_agent_id(conn: &Connection, user_agent: Option<&str>) -> SqliteResult<Option<i64>> {
+    let Some(user_agent) = user_agent else {
+        return Ok(None);
+    };
+    let existing = conn
+        .prepare_cached("SELECT id FROM user_agents WHERE user_agent = ?1")?
+        .query_row(params![user_agent], |row| row.get(0))
+        .optional()?;
+    if existing.is_some() {
+        return Ok(existing);
     }
+    conn.prepare_cached("INSERT INTO user_agents (user_agent) VALUES (?1)")?
+        .execute(params![user_agent])?;
+    Ok(Some(conn.last_insert_rowid()))
+}
 
-    pub async fn log_event(
-        &self,
-        email_id: i64,
-        event_type: &str,
-        user_agent: Option<&str>,
-        ip_address: Option<&str>,
-    ) -> SqliteResult<()> {
-        let conn = self.conn.lock().await;
-        let now = Utc::now();
-        
+/// Adds a column to an existing table if an older schema doesn't have it yet.
+/// Returns whether it was added.
+fn add_column_if_missing(conn: &Connection, table: &str, column: &str, definition: &str) -> SqliteResult<bool> {
+    let mut stmt = conn.prepare(&format!("PRAGMA table_info({})", table))?;
+    let exists = stmt
+        .query_map(params![], |row| row.get::<_, String>(1))?
+        .collect::<SqliteResult<Vec<_>>>()?
+        .iter()
+        .any(|name| name == column);
+
+    if !exists {
         conn.execute(
-            "INSERT INTO events (email_id, event_type, timestamp, user_agent, ip_address) VALUES (?1, ?2, ?3, ?4, ?5)",
-            params![email_id, event_type, now.to_rfc3339(), user_agent, ip_address],
+            &format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, definition),
+            params![],
         )?;
-        Ok(())
     }
+    Ok(!exists)
+}
 
-    pub async fn get_tenant_stats(&self, tenant_id: &str) -> SqliteResult<EventStats> {
-        let conn = self.conn.lock().await;
-        
-        // Get total opens and clicks
-        let mut stmt = conn.prepare(
-            "SELECT 
-                COUNT(CASE WHEN e.event_type = 'open' THEN 1 END) as total_opens,
-                COUNT(CASE WHEN e.event_type = 'click' THEN 1 END) as total_clicks,
-                COUNT(DISTINCT CASE WHEN e.event_type = 'open' THEN e.email_id END) as unique_opens,
-                COUNT(DISTINCT CASE WHEN e.event_type = 'click' THEN e.email_id END) as unique_clicks
-             FROM events e 
-             JOIN emails em ON e.email_id = em.id 
-             WHERE em.tenant_id = ?1"
-        )?;
-        
-        let stats = stmt.query_row(params![tenant_id], |row| {
-            Ok((
-                row.get::<_, i64>(0)?,
-                row.get::<_, i64>(1)?,
-                row.get::<_, i64>(2)?,
-                row.get::<_, i64>(3)?,
-            ))
-        })?;
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Event {
+    pub id: i64,
+    pub email_id: i64,
+    pub event_type: String,
+    #[serde(with = "crate::time_format")]
+    pub timestamp: DateTime<Utc>,
+    pub user_agent: Option<String>,
+    pub ip_address: Option<String>,
+    /// Destination of a click; unset for other event types.
+    pub target_url: Option<String>,
+    /// The `lb_uid` cookie the client sent or was given with an open, when
+    /// `Config.set_client_cookie` is on.
+    #[serde(default)]
+    pub client_id: Option<String>,
+    /// An open past `Config.max_opens_per_email`, left out of open counts.
+    #[serde(default)]
+    pub suspicious: bool,
+    /// An open within `Config.open_grace_secs` of the email's creation,
+    /// e.g. a preflight check or spam filter, left out of open counts.
+    #[serde(default)]
+    pub premature: bool,
+}
 
-        // Get recent events
-        let mut stmt = conn.prepare(
-            "SELECT e.id, e.email_id, e.event_type, e.timestamp, e.user_agent, e.ip_address
-             FROM events e 
-             JOIN emails em ON e.email_id = em.id 
-             WHERE em.tenant_id = ?1 
-             ORDER BY e.timestamp DESC 
-             LIMIT 50"
-        )?;
-        
-        let event_iter = stmt.query_map(params![tenant_id], |row| {
-            Ok(Event {
-                id: row.get(0)?,
-                email_id: row.get(1)?,
-                event_type: row.get(2)?,
-                timestamp: DateTime::parse_from_rfc3339(&row.get::<_, String>(3)?)
-                    .unwrap()
-                    .with_timezone(&Utc),
-                user_agent: row.get(4)?,
-                ip_address: row.get(5)?,
-            })
-        })?;
+/// Prefixed with `e.` so it can be used in queries that join on emails. The
+/// user agent is looked up from `user_agents`.
+const EVENT_COLUMNS: &str = "e.id, e.email_id, e.event_type, e.timestamp,
+    (SELECT ua.user_agent FROM user_agents ua WHERE ua.id = e.user_agent_id),
+    e.ip_address, e.target_url, e.client_id, e.suspicious, e.premature";
 
-        let mut recent_events = Vec::new();
-        for event in event_iter {
-            recent_events.push(event?);
-        }
+fn event_from_row(row: &Row) -> SqliteResult<Event> {
+    Ok(Event {
+        id: row.get(0)?,
+        email_id: row.get(1)?,
+        event_type: row.get(2)?,
+        timestamp: DateTime::parse_from_rfc3339(&row.get::<_, String>(3)?)
+            .unwrap()
+            .with_timezone(&Utc),
+        user_agent: row.get(4)?,
+        ip_address: row.get(5)?,
+        target_url: row.get(6)?,
+        client_id: row.get(7)?,
+        suspicious: row.get(8)?,
+        premature: row.get(9)?,
+    })
+}
+
+/// An event reported to us directly rather than observed by a tracking route.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NewEvent {
+    pub email_id: i64,
+    pub event_type: String,
+    #[serde(default, with = "crate::time_format::option")]
+    pub timestamp: Option<DateTime<Utc>>,
+    pub user_agent: Option<String>,
+    pub ip_address: Option<String>,
+    pub target_url: Option<String>,
+    #[serde(default)]
+    pub client_id: Option<String>,
+    /// Set by the open pixel, never by callers of the ingestion API.
+    #[serde(skip)]
+    pub suspicious: bool,
+    /// Set by the open pixel, never by callers of the ingestion API.
+    #[serde(skip)]
+    pub premature: bool,
+}
+
+/// Counts of rows written by a tenant import.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ImportSummary {
+    pub emails: usize,
+    pub events: usize,
+}
+
+/// Which of a tenant's emails `delete_emails_where` removes: those matching
+/// every filter given.
+#[derive(Debug, Clone, Default)]
+pub struct EmailDeleteFilter {
+    pub campaign_id: Option<String>,
+    /// Exclusive.
+    pub created_before: Option<DateTime<Utc>>,
+}
+
+impl EmailDeleteFilter {
+    pub fn is_empty(&self) -> bool {
+        self.campaign_id.is_none() && self.created_before.is_none()
+    }
+}
+
+/// Counts of rows removed by `delete_emails_where`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DeleteSummary {
+    pub emails: usize,
+    pub events: usize,
+    pub links: usize,
+    pub webhook_deliveries: usize,
+}
+
+/// A short code that redirects to `target_url`, counting as a click on the
+/// email.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ShortLink {
+    pub short_code: String,
+    pub email_id: i64,
+    pub target_url: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LinkClicks {
+    pub url: String,
+    pub clicks: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EventStats {
+    pub total_opens: i64,
+    pub total_clicks: i64,
+    /// Distinct emails opened.
+    pub unique_opens: i64,
+    pub unique_clicks: i64,
+    /// Distinct recipients who opened any email; emails without a recipient
+    /// aren't counted.
+    pub unique_open_recipients: i64,
+    /// Distinct client addresses that opened any email; opens without an
+    /// address aren't counted.
+    pub unique_open_ips: i64,
+    /// Distinct `lb_uid` cookies among opens; only opens recorded with
+    /// `Config.set_client_cookie` on, from clients that keep cookies, count.
+    pub unique_open_clients: i64,
+    /// Opens past `Config.max_opens_per_email`, which none of the other open
+    /// counts include unless `StatsFilter.include_suspicious` is set.
+    pub suspicious_opens: i64,
+    /// Opens within `Config.open_grace_secs` of their email's creation,
+    /// which none of the other open counts include.
+    pub premature_opens: i64,
+    pub emails_created: i64,
+    /// Emails marked sent; the denominator of `open_rate` and `click_rate`,
+    /// unless there are none, when every email created is counted instead.
+    pub emails_sent: i64,
+    pub open_rate: f64,
+    pub click_rate: f64,
+    /// Total events per type, including custom types from the ingestion API.
+    pub events_by_type: BTreeMap<String, i64>,
+    pub recent_events: Vec<Event>,
+    /// Set when `unique_opens` is a HyperLogLog estimate rather than an
+    /// exact count (see `Config.unique_open_sketch`).
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub approximate: bool,
+}
+
+/// Narrows the events counted by `get_tenant_stats_filtered`. `from` is
+/// inclusive and `until` exclusive.
+#[derive(Debug, Clone, Default)]
+pub struct EventFilter {
+    pub event_type: Option<String>,
+    pub from: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+}
+
+impl EventFilter {
+    pub fn is_empty(&self) -> bool {
+        self.event_type.is_none() && self.from.is_none() && self.until.is_none()
+    }
+
+    /// SQL conditions on `e` to append to a tenant-scoped query, and their
+    /// values.
+    fn conditions(&self) -> (String, Vec<String>) {
+        let mut sql = String::new();
+        let mut values = Vec::new();
+        if let Some(event_type) = &self.event_type {
+            sql.push_str(" AND e.event_type = ?");
+            values.push(event_type.clone());
+        }
+        if let Some(from) = self.from {
+            sql.push_str(" AND e.timestamp >= ?");
+            values.push(from.to_rfc3339());
+        }
+        if let Some(until) = self.until {
+            sql.push_str(" AND e.timestamp < ?");
+            values.push(until.to_rfc3339());
+        }
+        (sql, values)
+    }
+}
+
+/// `LIKE` patterns for the user agents of crawlers, link scanners and
+/// scripted clients, whose events `StatsFilter` can leave out. Matched case
+/// insensitively; events without a user agent are never bots.
+pub const BOT_USER_AGENT_PATTERNS: &[&str] = &[
+    "%bot%",
+    "%crawl%",
+    "%spider%",
+    "%slurp%",
+    "%headless%",
+    "%scanner%",
+    "curl/%",
+    "wget/%",
+    "python-requests/%",
+    "go-http-client/%",
+];
+
+/// Which tagged events the stats count. The default matches the stats from
+/// before bots were told apart: bot events are counted, suspicious opens
+/// (past `Config.max_opens_per_email`) are left out of the open counts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StatsFilter {
+    /// Count events whose user agent matches `BOT_USER_AGENT_PATTERNS`.
+    pub include_bots: bool,
+    /// Count suspicious opens in the open counts, not just
+    /// `EventStats.suspicious_opens`.
+    pub include_suspicious: bool,
+}
+
+impl Default for StatsFilter {
+    fn default() -> Self {
+        StatsFilter {
+            include_bots: true,
+            include_suspicious: false,
+        }
+    }
+}
+
+impl StatsFilter {
+    /// Opens and clicks by people only, as far as can be told.
+    pub fn human_only() -> Self {
+        StatsFilter {
+            include_bots: false,
+            include_suspicious: false,
+        }
+    }
+
+    /// SQL condition on `e` for the opens counted as opens. Premature opens
+    /// never are.
+    fn counted_opens(&self) -> &'static str {
+        if self.include_suspicious {
+            " AND NOT e.premature"
+        } else {
+            " AND NOT e.suspicious AND NOT e.premature"
+        }
+    }
+
+    /// SQL conditions on `e` to append to a tenant-scoped query. The
+    /// patterns are constants, so they're inlined rather than bound. They're
+    /// matched against the `user_agents` table once, not per event.
+    fn conditions(&self) -> String {
+        if self.include_bots {
+            return String::new();
+        }
+        let matches: Vec<String> = BOT_USER_AGENT_PATTERNS
+            .iter()
+            .map(|pattern| format!("user_agent LIKE '{}'", pattern))
+            .collect();
+        format!(
+            " AND (e.user_agent_id IS NULL OR e.user_agent_id NOT IN (SELECT id FROM user_agents WHERE {}))",
+            matches.join(" OR ")
+        )
+    }
+}
+
+/// Events listed in `EventStats.recent_events`.
+pub const RECENT_EVENT_COUNT: usize = 50;
+
+/// Just the headline counters, cheap enough to recompute on every event.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EventCounts {
+    pub total_opens: i64,
+    pub total_clicks: i64,
+    pub unique_opens: i64,
+    pub unique_clicks: i64,
+}
+
+/// The headline counters for a tenant, counting opens that match
+/// `counted_opens` (a condition on `e` starting with `AND`, or empty).
+/// Without `unique_opens` that column is left 0 rather than counted.
+fn event_counts_query(counted_opens: &str, unique_opens: bool) -> String {
+    let unique_opens = if unique_opens {
+        format!("COUNT(DISTINCT CASE WHEN e.event_type = 'open'{} THEN e.email_id END)", counted_opens)
+    } else {
+        "0".to_string()
+    };
+    format!(
+        "SELECT 
+        COUNT(CASE WHEN e.event_type = 'open'{} THEN 1 END) as total_opens,
+        COUNT(CASE WHEN e.event_type = 'click' THEN 1 END) as total_clicks,
+        {} as unique_opens,
+        COUNT(DISTINCT CASE WHEN e.event_type = 'click' THEN e.email_id END) as unique_clicks
+     FROM events e 
+     JOIN emails em ON e.email_id = em.id 
+     WHERE em.tenant_id = ?",
+        counted_opens, unique_opens
+    )
+}
+
+fn event_counts_from_row(row: &Row) -> SqliteResult<EventCounts> {
+    Ok(EventCounts {
+        total_opens: row.get(0)?,
+        total_clicks: row.get(1)?,
+        unique_opens: row.get(2)?,
+        unique_clicks: row.get(3)?,
+    })
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CampaignStats {
+    pub campaign_id: String,
+    pub emails_created: i64,
+    /// Emails marked sent; the denominator of `open_rate` and `click_rate`,
+    /// unless there are none, when every email created is counted instead.
+    pub emails_sent: i64,
+    pub total_opens: i64,
+    pub total_clicks: i64,
+    pub unique_opens: i64,
+    pub unique_clicks: i64,
+    pub open_rate: f64,
+    pub click_rate: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LatencyStats {
+    pub emails_opened: usize,
+    pub min_seconds: Option<f64>,
+    pub median_seconds: Option<f64>,
+    pub p90_seconds: Option<f64>,
+    pub max_seconds: Option<f64>,
+}
+
+/// Opens and clicks within one window of a `WindowedSummary`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct WindowCounts {
+    pub opens: i64,
+    pub clicks: i64,
+}
+
+/// Event counts over trailing windows, all in UTC. `today` starts at
+/// midnight; the others are rolling.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct WindowedSummary {
+    pub today: WindowCounts,
+    pub last_7_days: WindowCounts,
+    pub last_30_days: WindowCounts,
+}
+
+/// Opens per UTC hour of day, `hours[0]` covering 00:00-00:59. With
+/// `weekdays`, the same split again per day of the week, Monday first.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct HourlyHeatmap {
+    pub hours: [i64; 24],
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub weekdays: Option<[[i64; 24]; 7]>,
+}
+
+/// Schema version this binary migrates to, stored in `PRAGMA user_version`.
+/// Bump it whenever `initialize` gains a table, column or index.
+pub const SCHEMA_VERSION: i64 = 13;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SchemaStatus {
+    pub schema_version: i64,
+    pub expected_version: i64,
+    pub pending_migrations: i64,
+}
+
+/// Connection-level tuning applied when the database is opened.
+#[derive(Debug, Clone)]
+pub struct DatabaseOptions {
+    /// Value for `PRAGMA synchronous`: OFF, NORMAL, FULL or EXTRA.
+    pub synchronous: String,
+    /// How long SQLite itself waits on another connection's lock.
+    pub busy_timeout: Duration,
+    /// How many more times a write is tried after still finding the
+    /// database busy or locked.
+    pub busy_retries: u32,
+}
+
+impl Default for DatabaseOptions {
+    fn default() -> Self {
+        DatabaseOptions {
+            synchronous: "NORMAL".to_string(),
+            busy_timeout: Duration::from_millis(5000),
+            busy_retries: 3,
+        }
+    }
+}
+
+const SYNCHRONOUS_LEVELS: &[&str] = &["OFF", "NORMAL", "FULL", "EXTRA"];
+
+/// Wait before the first busy retry; it doubles with each one after.
+const BUSY_RETRY_BACKOFF: Duration = Duration::from_millis(25);
+
+/// An endpoint that receives a tenant's events.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Webhook {
+    pub id: i64,
+    pub tenant_id: String,
+    pub url: String,
+    /// Key for the `X-LittleBell-Signature` HMAC. Only shown when created.
+    #[serde(skip_serializing)]
+    pub secret: String,
+    #[serde(with = "crate::time_format")]
+    pub created_at: DateTime<Utc>,
+}
+
+/// Where delivery of one event to one webhook stands.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DeliveryStatus {
+    Pending,
+    Delivered,
+    Failed,
+}
+
+impl DeliveryStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DeliveryStatus::Pending => "pending",
+            DeliveryStatus::Delivered => "delivered",
+            DeliveryStatus::Failed => "failed",
+        }
+    }
+
+    fn from_db(value: &str) -> Self {
+        match value {
+            "delivered" => DeliveryStatus::Delivered,
+            "failed" => DeliveryStatus::Failed,
+            _ => DeliveryStatus::Pending,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WebhookDelivery {
+    pub id: i64,
+    pub webhook_id: i64,
+    pub event_id: i64,
+    pub status: DeliveryStatus,
+    pub attempts: i64,
+    /// HTTP status of the last attempt, if the receiver answered at all.
+    pub status_code: Option<i64>,
+    pub error: Option<String>,
+    #[serde(with = "crate::time_format")]
+    pub created_at: DateTime<Utc>,
+    #[serde(with = "crate::time_format")]
+    pub updated_at: DateTime<Utc>,
+}
+
+/// A delivery still marked pending, e.g. because the server stopped between
+/// retries, with the webhook and event it's for.
+#[derive(Debug, Clone)]
+pub struct PendingDelivery {
+    pub delivery_id: i64,
+    /// Attempts already made.
+    pub attempts: i64,
+    pub webhook: Webhook,
+    pub event: Event,
+}
+
+/// One administrative action, as recorded in the audit log.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditEntry {
+    pub id: i64,
+    #[serde(with = "crate::time_format")]
+    pub created_at: DateTime<Utc>,
+    pub actor: String,
+    pub action: String,
+    pub target: String,
+    pub details: Option<serde_json::Value>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Diagnostics {
+    pub tenants: i64,
+    pub emails: i64,
+    pub events: i64,
+    pub suppressions: i64,
+    /// Timestamps of the first and last events inserted.
+    #[serde(default, with = "crate::time_format::option")]
+    pub oldest_event_at: Option<DateTime<Utc>>,
+    #[serde(default, with = "crate::time_format::option")]
+    pub newest_event_at: Option<DateTime<Utc>>,
+    pub database_bytes: i64,
+    /// Size of the write-ahead log, when the database lives in a file.
+    pub wal_bytes: Option<u64>,
+}
+
+pub struct Database {
+    conn: Arc<Mutex<Connection>>,
+    metrics: Arc<DbMetrics>,
+    path: String,
+    busy_timeout: Duration,
+    busy_retries: u32,
+}
+
+impl Database {
+    pub async fn new(db_path: &str) -> SqliteResult<Self> {
+        Self::with_options(db_path, &DatabaseOptions::default()).await
+    }
+
+    pub async fn with_options(db_path: &str, options: &DatabaseOptions) -> SqliteResult<Self> {
+        let conn = Connection::open(db_path)?;
+        configure_connection(&conn, options)?;
+        let database = Database {
+            conn: Arc::new(Mutex::new(conn)),
+            metrics: Arc::new(DbMetrics::default()),
+            path: db_path.to_string(),
+            busy_timeout: options.busy_timeout,
+            busy_retries: options.busy_retries,
+        };
+        database.initialize().await?;
+        Ok(database)
+    }
+
+    pub fn metrics(&self) -> Arc<DbMetrics> {
+        self.metrics.clone()
+    }
+
+    /// Locks the connection, counting the times we had to wait for it.
+    async fn lock(&self) -> MutexGuard<'_, Connection> {
+        match self.conn.try_lock() {
+            Ok(guard) => {
+                self.metrics.record_lock(false);
+                guard
+            }
+            Err(_) => {
+                self.metrics.record_lock(true);
+                self.conn.lock().await
+            }
+        }
+    }
+
+    /// Runs a write, retrying with backoff while SQLite reports the database
+    /// busy or locked (e.g. another process holding it past the busy
+    /// timeout). The connection is released between attempts.
+    async fn write<T>(&self, mut op: impl FnMut(&mut Connection) -> SqliteResult<T>) -> SqliteResult<T> {
+        let mut retries = 0;
+        loop {
+            let result = op(&mut *self.lock().await);
+            match result {
+                Err(e) if is_busy(&e) && retries < self.busy_retries => {
+                    self.metrics.record_busy_retry();
+                    tokio::time::sleep(BUSY_RETRY_BACKOFF * 2u32.pow(retries)).await;
+                    retries += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    async fn initialize(&self) -> SqliteResult<()> {
+        let conn = self.lock().await;
+        
+        // Create tenants table
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS tenants (
+                id TEXT PRIMARY KEY,
+                name TEXT NOT NULL,
+                created_at TEXT NOT NULL,
+                pixel_cache_mode TEXT NOT NULL DEFAULT 'no_cache',
+                last_activity_at TEXT
+            )",
+            params![],
+        )?;
+        add_column_if_missing(&conn, "tenants", "pixel_cache_mode", "TEXT NOT NULL DEFAULT 'no_cache'")?;
+        add_column_if_missing(&conn, "tenants", "last_activity_at", "TEXT")?;
+
+        // Create emails table
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS emails (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                tenant_id TEXT NOT NULL,
+                subject TEXT,
+                recipient TEXT,
+                created_at TEXT NOT NULL,
+                public_token TEXT,
+                campaign_id TEXT,
+                idempotency_key TEXT,
+                metadata TEXT,
+                tracking_consent INTEGER NOT NULL DEFAULT 1,
+                expires_at TEXT,
+                sent_at TEXT,
+                resend_of INTEGER,
+                external_id TEXT,
+                FOREIGN KEY (tenant_id) REFERENCES tenants (id)
+            )",
+            params![],
+        )?;
+        add_column_if_missing(&conn, "emails", "public_token", "TEXT")?;
+        add_column_if_missing(&conn, "emails", "campaign_id", "TEXT")?;
+        add_column_if_missing(&conn, "emails", "idempotency_key", "TEXT")?;
+        add_column_if_missing(&conn, "emails", "metadata", "TEXT")?;
+        add_column_if_missing(&conn, "emails", "tracking_consent", "INTEGER NOT NULL DEFAULT 1")?;
+        add_column_if_missing(&conn, "emails", "expires_at", "TEXT")?;
+        if add_column_if_missing(&conn, "emails", "sent_at", "TEXT")? {
+            // Emails from before sends were recorded went out when created, as
+            // far as anyone knows; leaving them unsent would zero their rates
+            conn.execute("UPDATE emails SET sent_at = created_at", params![])?;
+        }
+        add_column_if_missing(&conn, "emails", "resend_of", "INTEGER")?;
+        add_column_if_missing(&conn, "emails", "external_id", "TEXT")?;
+
+        // Create user_agents table (each distinct user agent stored once)
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS user_agents (
+                id INTEGER PRIMARY KEY,
+                user_agent TEXT NOT NULL UNIQUE
+            )",
+            params![],
+        )?;
+
+        // Create events table. `user_agent` is no longer written; events
+        // point into user_agents instead. The column stays so older binaries
+        // can still open the database, though they'll see no user agent on
+        // events moved below and so won't flag them as bots.
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS events (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                email_id INTEGER NOT NULL,
+                event_type TEXT NOT NULL,
+                timestamp TEXT NOT NULL,
+                user_agent TEXT,
+                ip_address TEXT,
+                target_url TEXT,
+                client_id TEXT,
+                suspicious INTEGER NOT NULL DEFAULT 0,
+                premature INTEGER NOT NULL DEFAULT 0,
+                user_agent_id INTEGER REFERENCES user_agents (id),
+                FOREIGN KEY (email_id) REFERENCES emails (id)
+            )",
+            params![],
+        )?;
+        add_column_if_missing(&conn, "events", "target_url", "TEXT")?;
+        add_column_if_missing(&conn, "events", "client_id", "TEXT")?;
+        add_column_if_missing(&conn, "events", "suspicious", "INTEGER NOT NULL DEFAULT 0")?;
+        add_column_if_missing(&conn, "events", "premature", "INTEGER NOT NULL DEFAULT 0")?;
+        add_column_if_missing(&conn, "events", "user_agent_id", "INTEGER REFERENCES user_agents (id)")?;
+
+        // Move user agents stored on the events themselves into the lookup
+        // table: every one the first time, and after that whatever an older
+        // binary wrote since. Both steps commit together, so an interrupted
+        // start leaves no event pointing at a missing row and is simply
+        // redone next time. Once nothing is left to move, the partial index
+        // on legacy user agents below makes this a quick no-op. The space
+        // freed is reused for new rows; only `VACUUM` returns it to the file
+        // system.
+        let tx = conn.unchecked_transaction()?;
+        tx.execute(
+            "INSERT OR IGNORE INTO user_agents (user_agent)
+             SELECT DISTINCT user_agent FROM events WHERE user_agent IS NOT NULL",
+            params![],
+        )?;
+        tx.execute(
+            "UPDATE events
+             SET user_agent_id = (SELECT id FROM user_agents WHERE user_agent = events.user_agent),
+                 user_agent = NULL
+             WHERE user_agent IS NOT NULL",
+            params![],
+        )?;
+        tx.commit()?;
+
+        // Create tenant_quotas table
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS tenant_quotas (
+                tenant_id TEXT PRIMARY KEY,
+                daily_email_limit INTEGER NOT NULL,
+                FOREIGN KEY (tenant_id) REFERENCES tenants (id)
+            )",
+            params![],
+        )?;
+
+        // Create unique_open_sketches table (HyperLogLog registers per tenant)
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS unique_open_sketches (
+                tenant_id TEXT PRIMARY KEY,
+                registers BLOB NOT NULL,
+                through_event_id INTEGER NOT NULL,
+                updated_at TEXT NOT NULL,
+                FOREIGN KEY (tenant_id) REFERENCES tenants (id)
+            )",
+            params![],
+        )?;
+
+        // Create suppressions table (recipients who unsubscribed)
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS suppressions (
+                tenant_id TEXT NOT NULL,
+                recipient TEXT NOT NULL,
+                email_id INTEGER,
+                created_at TEXT NOT NULL,
+                PRIMARY KEY (tenant_id, recipient),
+                FOREIGN KEY (tenant_id) REFERENCES tenants (id)
+            )",
+            params![],
+        )?;
+
+        // Create links table (short codes standing in for click URLs)
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS links (
+                short_code TEXT PRIMARY KEY,
+                email_id INTEGER NOT NULL,
+                target_url TEXT NOT NULL,
+                created_at TEXT NOT NULL,
+                FOREIGN KEY (email_id) REFERENCES emails (id)
+            )",
+            params![],
+        )?;
+
+        // Create webhooks table
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS webhooks (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                tenant_id TEXT NOT NULL,
+                url TEXT NOT NULL,
+                secret TEXT NOT NULL,
+                created_at TEXT NOT NULL,
+                FOREIGN KEY (tenant_id) REFERENCES tenants (id)
+            )",
+            params![],
+        )?;
+
+        // Create webhook_deliveries table (one row per event per webhook)
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS webhook_deliveries (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                webhook_id INTEGER NOT NULL,
+                event_id INTEGER NOT NULL,
+                status TEXT NOT NULL DEFAULT 'pending',
+                attempts INTEGER NOT NULL DEFAULT 0,
+                status_code INTEGER,
+                error TEXT,
+                created_at TEXT NOT NULL,
+                updated_at TEXT NOT NULL,
+                FOREIGN KEY (webhook_id) REFERENCES webhooks (id) ON DELETE CASCADE
+            )",
+            params![],
+        )?;
+
+        // Create audit_log table (administrative actions, kept for compliance)
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS audit_log (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                created_at TEXT NOT NULL,
+                actor TEXT NOT NULL,
+                action TEXT NOT NULL,
+                target TEXT NOT NULL,
+                details TEXT
+            )",
+            params![],
+        )?;
+
+        // Create indexes for better performance
+        // Stats join events to a tenant's emails and count by type; with both
+        // columns in the index those counts never touch the events table.
+        // It also serves lookups by email_id alone, replacing the old index.
+        conn.execute(
+            "CREATE INDEX IF NOT EXISTS idx_events_email_type ON events(email_id, event_type)",
+            params![],
+        )?;
+        conn.execute("DROP INDEX IF EXISTS idx_events_email_id", params![])?;
+
+        conn.execute(
+            "CREATE INDEX IF NOT EXISTS idx_events_type ON events(event_type)",
+            params![],
+        )?;
+
+        // Only holds events an older binary stored a user agent on, so the
+        // backfill above finds them without scanning every event
+        conn.execute(
+            "CREATE INDEX IF NOT EXISTS idx_events_legacy_user_agent ON events(user_agent) WHERE user_agent IS NOT NULL",
+            params![],
+        )?;
+
+        // Secondary indexes carry the rowid, so this is effectively
+        // (tenant_id, id) and covers the tenant side of those joins.
+        conn.execute(
+            "CREATE INDEX IF NOT EXISTS idx_emails_tenant ON emails(tenant_id)",
+            params![],
+        )?;
+
+        conn.execute(
+            "CREATE UNIQUE INDEX IF NOT EXISTS idx_emails_public_token ON emails(public_token)",
+            params![],
+        )?;
+
+        conn.execute(
+            "CREATE INDEX IF NOT EXISTS idx_emails_campaign ON emails(tenant_id, campaign_id)",
+            params![],
+        )?;
+
+        conn.execute(
+            "CREATE UNIQUE INDEX IF NOT EXISTS idx_emails_idempotency_key ON emails(tenant_id, idempotency_key)",
+            params![],
+        )?;
+
+        conn.execute(
+            "CREATE UNIQUE INDEX IF NOT EXISTS idx_emails_external_id ON emails(tenant_id, external_id)",
+            params![],
+        )?;
+
+        conn.execute(
+            "CREATE UNIQUE INDEX IF NOT EXISTS idx_links_email_target ON links(email_id, target_url)",
+            params![],
+        )?;
+
+        conn.execute(
+            "CREATE INDEX IF NOT EXISTS idx_webhooks_tenant ON webhooks(tenant_id)",
+            params![],
+        )?;
+
+        conn.execute(
+            "CREATE INDEX IF NOT EXISTS idx_webhook_deliveries_webhook ON webhook_deliveries(webhook_id, id)",
+            params![],
+        )?;
+
+        // Never lower the version: a newer binary may have migrated further
+        if schema_version(&conn)? < SCHEMA_VERSION {
+            conn.pragma_update(None, "user_version", SCHEMA_VERSION)?;
+        }
+
+        Ok(())
+    }
+
+    /// How the database's schema compares with what this binary expects.
+    pub async fn schema_status(&self) -> SqliteResult<SchemaStatus> {
+        let conn = self.lock().await;
+        let version = schema_version(&conn)?;
+
+        Ok(SchemaStatus {
+            schema_version: version,
+            expected_version: SCHEMA_VERSION,
+            pending_migrations: (SCHEMA_VERSION - version).max(0),
+        })
+    }
+
+    pub async fn create_tenant(&self, tenant_id: &str, name: &str) -> SqliteResult<()> {
+        let now = Utc::now();
+
+        self.write(|conn| {
+            conn.execute(
+                "INSERT OR IGNORE INTO tenants (id, name, created_at) VALUES (?1, ?2, ?3)",
+                params![tenant_id, name, now.to_rfc3339()],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    pub async fn get_tenant(&self, tenant_id: &str) -> SqliteResult<Option<Tenant>> {
+        let conn = self.lock().await;
+        
+        let mut stmt = conn.prepare("SELECT id, name, created_at FROM tenants WHERE id = ?1")?;
+        let mut tenant_iter = stmt.query_map(params![tenant_id], |row| {
+            Ok(Tenant {
+                id: row.get(0)?,
+                name: row.get(1)?,
+                created_at: DateTime::parse_from_rfc3339(&row.get::<_, String>(2)?)
+                    .unwrap()
+                    .with_timezone(&Utc),
+            })
+        })?;
+
+        tenant_iter.next().transpose()
+    }
+
+    pub async fn create_webhook(&self, tenant_id: &str, url: &str, secret: &str) -> SqliteResult<Webhook> {
+        let now = Utc::now();
+
+        let id = self
+            .write(|conn| {
+                conn.execute(
+                    "INSERT INTO webhooks (tenant_id, url, secret, created_at) VALUES (?1, ?2, ?3, ?4)",
+                    params![tenant_id, url, secret, now.to_rfc3339()],
+                )?;
+                Ok(conn.last_insert_rowid())
+            })
+            .await?;
+        Ok(Webhook {
+            id,
+            tenant_id: tenant_id.to_string(),
+            url: url.to_string(),
+            secret: secret.to_string(),
+            created_at: now,
+        })
+    }
+
+    pub async fn list_webhooks(&self, tenant_id: &str) -> SqliteResult<Vec<Webhook>> {
+        let conn = self.lock().await;
+
+        let mut stmt = conn.prepare(
+            "SELECT id, tenant_id, url, secret, created_at FROM webhooks WHERE tenant_id = ?1 ORDER BY id",
+        )?;
+        let webhooks = stmt.query_map(params![tenant_id], |row| {
+            Ok(Webhook {
+                id: row.get(0)?,
+                tenant_id: row.get(1)?,
+                url: row.get(2)?,
+                secret: row.get(3)?,
+                created_at: DateTime::parse_from_rfc3339(&row.get::<_, String>(4)?)
+                    .unwrap()
+                    .with_timezone(&Utc),
+            })
+        })?;
+        webhooks.collect()
+    }
+
+    /// Removes a webhook and its delivery log. Returns whether it existed.
+    pub async fn delete_webhook(&self, tenant_id: &str, webhook_id: i64) -> SqliteResult<bool> {
+        let deleted = self.write(|conn| {
+            conn.execute(
+                "DELETE FROM webhooks WHERE id = ?1 AND tenant_id = ?2",
+                params![webhook_id, tenant_id],
+            )
+        })
+        .await?;
+        Ok(deleted > 0)
+    }
+
+    pub async fn create_webhook_delivery(&self, webhook_id: i64, event_id: i64) -> SqliteResult<i64> {
+        let now = Utc::now().to_rfc3339();
+
+        self.write(|conn| {
+            conn.execute(
+                "INSERT INTO webhook_deliveries (webhook_id, event_id, created_at, updated_at) VALUES (?1, ?2, ?3, ?3)",
+                params![webhook_id, event_id, now],
+            )?;
+            Ok(conn.last_insert_rowid())
+        })
+        .await
+    }
+
+    /// Records the outcome of the latest attempt at a delivery.
+    pub async fn record_webhook_attempt(
+        &self,
+        delivery_id: i64,
+        status: DeliveryStatus,
+        status_code: Option<u16>,
+        error: Option<&str>,
+    ) -> SqliteResult<()> {
+        let now = Utc::now().to_rfc3339();
+
+        self.write(|conn| {
+            conn.execute(
+                "UPDATE webhook_deliveries
+                 SET status = ?1, attempts = attempts + 1, status_code = ?2, error = ?3, updated_at = ?4
+                 WHERE id = ?5",
+                params![status.as_str(), status_code, error, now, delivery_id],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    /// Fails a delivery without an attempt, e.g. when it can't be queued.
+    pub async fn abandon_webhook_delivery(&self, delivery_id: i64, error: &str) -> SqliteResult<()> {
+        let now = Utc::now().to_rfc3339();
+
+        self.write(|conn| {
+            conn.execute(
+                "UPDATE webhook_deliveries SET status = ?1, error = ?2, updated_at = ?3 WHERE id = ?4",
+                params![DeliveryStatus::Failed.as_str(), error, now, delivery_id],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    /// Every delivery still pending, oldest first.
+    pub async fn pending_webhook_deliveries(&self) -> SqliteResult<Vec<PendingDelivery>> {
+        let conn = self.lock().await;
+
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {}, d.id, d.attempts, w.id, w.tenant_id, w.url, w.secret, w.created_at
+             FROM webhook_deliveries d
+             JOIN webhooks w ON d.webhook_id = w.id
+             JOIN events e ON d.event_id = e.id
+             WHERE d.status = ?1
+             ORDER BY d.id",
+            EVENT_COLUMNS
+        ))?;
+        let deliveries = stmt.query_map(params![DeliveryStatus::Pending.as_str()], |row| {
+            Ok(PendingDelivery {
+                event: event_from_row(row)?,
+                delivery_id: row.get(10)?,
+                attempts: row.get(11)?,
+                webhook: Webhook {
+                    id: row.get(12)?,
+                    tenant_id: row.get(13)?,
+                    url: row.get(14)?,
+                    secret: row.get(15)?,
+                    created_at: DateTime::parse_from_rfc3339(&row.get::<_, String>(16)?)
+                        .unwrap()
+                        .with_timezone(&Utc),
+                },
+            })
+        })?;
+        deliveries.collect()
+    }
+
+    /// The tenant's most recent deliveries across all its webhooks,
+    /// optionally only those with a given status.
+    pub async fn list_webhook_deliveries(
+        &self,
+        tenant_id: &str,
+        status: Option<DeliveryStatus>,
+        limit: i64,
+    ) -> SqliteResult<Vec<WebhookDelivery>> {
+        let conn = self.lock().await;
+
+        let mut stmt = conn.prepare(
+            "SELECT d.id, d.webhook_id, d.event_id, d.status, d.attempts, d.status_code, d.error,
+                d.created_at, d.updated_at
+             FROM webhook_deliveries d
+             JOIN webhooks w ON d.webhook_id = w.id
+             WHERE w.tenant_id = ?1 AND (?2 IS NULL OR d.status = ?2)
+             ORDER BY d.id DESC
+             LIMIT ?3",
+        )?;
+        let parse_time = |value: String| DateTime::parse_from_rfc3339(&value).unwrap().with_timezone(&Utc);
+        let deliveries = stmt.query_map(
+            params![tenant_id, status.map(|status| status.as_str()), limit],
+            |row| {
+                Ok(WebhookDelivery {
+                    id: row.get(0)?,
+                    webhook_id: row.get(1)?,
+                    event_id: row.get(2)?,
+                    status: DeliveryStatus::from_db(&row.get::<_, String>(3)?),
+                    attempts: row.get(4)?,
+                    status_code: row.get(5)?,
+                    error: row.get(6)?,
+                    created_at: parse_time(row.get(7)?),
+                    updated_at: parse_time(row.get(8)?),
+                })
+            },
+        )?;
+        deliveries.collect()
+    }
+
+    /// Appends an entry to the audit log. `actor` is who made the change and
+    /// `target` what it was made to, usually a tenant id.
+    pub async fn record_audit(
+        &self,
+        actor: &str,
+        action: &str,
+        target: &str,
+        details: &serde_json::Value,
+    ) -> SqliteResult<()> {
+        let now = Utc::now().to_rfc3339();
+        let details = details.to_string();
+
+        self.write(|conn| {
+            conn.execute(
+                "INSERT INTO audit_log (created_at, actor, action, target, details) VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![now, actor, action, target, details],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    /// A page of the audit log, newest first, plus the total entry count.
+    pub async fn list_audit(&self, limit: i64, offset: i64) -> SqliteResult<(Vec<AuditEntry>, i64)> {
+        let conn = self.lock().await;
+
+        let total = conn.query_row("SELECT COUNT(*) FROM audit_log", params![], |row| row.get(0))?;
+        let mut stmt = conn.prepare(
+            "SELECT id, created_at, actor, action, target, details
+             FROM audit_log
+             ORDER BY id DESC
+             LIMIT ?1 OFFSET ?2",
+        )?;
+        let entries = stmt.query_map(params![limit, offset], |row| {
+            Ok(AuditEntry {
+                id: row.get(0)?,
+                created_at: DateTime::parse_from_rfc3339(&row.get::<_, String>(1)?)
+                    .unwrap()
+                    .with_timezone(&Utc),
+                actor: row.get(2)?,
+                action: row.get(3)?,
+                target: row.get(4)?,
+                details: row
+                    .get::<_, Option<String>>(5)?
+                    .and_then(|details| serde_json::from_str(&details).ok()),
+            })
+        })?;
+        Ok((entries.collect::<SqliteResult<_>>()?, total))
+    }
+
+    /// Copies the database to `dest` with SQLite's online backup API.
+    ///
+    /// On-disk databases are read through a separate connection, so under WAL
+    /// writers carry on while the copy is taken; copying every page in one
+    /// step keeps the snapshot consistent. The copy runs on a blocking
+    /// thread, since it may wait out writers holding the database.
+    pub async fn backup_to(&self, dest: &Path) -> SqliteResult<()> {
+        let dest = dest.to_path_buf();
+        // In-memory databases can only be read through our own connection
+        let shared = if self.path.is_empty() || self.path == ":memory:" {
+            Some(self.conn.clone().lock_owned().await)
+        } else {
+            None
+        };
+        let path = self.path.clone();
+        let busy_timeout = self.busy_timeout;
+
+        tokio::task::spawn_blocking(move || {
+            let mut target = Connection::open(dest)?;
+            if let Some(conn) = shared {
+                let backup = Backup::new(&conn, &mut target)?;
+                return copy_all_pages(&backup);
+            }
+            let source = Connection::open_with_flags(&path, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+            source.busy_timeout(busy_timeout)?;
+            let backup = Backup::new(&source, &mut target)?;
+            copy_all_pages(&backup)
+        })
+        .await
+        .unwrap_or_else(|e| std::panic::resume_unwind(e.into_panic()))
+    }
+
+    /// Row counts and storage sizes. Event age is read by primary key, so
+    /// it reflects insertion order rather than scanning every timestamp.
+    pub async fn diagnostics(&self) -> SqliteResult<Diagnostics> {
+        let conn = self.lock().await;
+
+        let count = |table: &str| -> SqliteResult<i64> {
+            conn.query_row(&format!("SELECT COUNT(*) FROM {}", table), params![], |row| row.get(0))
+        };
+        let event_timestamp = |order: &str| -> SqliteResult<Option<DateTime<Utc>>> {
+            conn.query_row(
+                &format!("SELECT timestamp FROM events ORDER BY id {} LIMIT 1", order),
+                params![],
+                |row| row.get::<_, String>(0),
+            )
+            .optional()
+            .map(|timestamp| {
+                timestamp.map(|t| DateTime::parse_from_rfc3339(&t).unwrap().with_timezone(&Utc))
+            })
+        };
+
+        let page_count: i64 = conn.query_row("PRAGMA page_count", params![], |row| row.get(0))?;
+        let page_size: i64 = conn.query_row("PRAGMA page_size", params![], |row| row.get(0))?;
+        let wal_bytes = std::fs::metadata(format!("{}-wal", self.path))
+            .ok()
+            .map(|metadata| metadata.len());
+
+        Ok(Diagnostics {
+            tenants: count("tenants")?,
+            emails: count("emails")?,
+            events: count("events")?,
+            suppressions: count("suppressions")?,
+            oldest_event_at: event_timestamp("ASC")?,
+            newest_event_at: event_timestamp("DESC")?,
+            database_bytes: page_count * page_size,
+            wal_bytes,
+        })
+    }
+
+    /// A page of tenants with their totals, plus how many tenants exist in
+    /// all. Without a sort column tenants come back in id order.
+    pub async fn list_tenants_with_stats(
+        &self,
+        sort: Option<TenantSort>,
+        direction: SortDirection,
+        limit: i64,
+        offset: i64,
+    ) -> SqliteResult<(Vec<TenantSummary>, i64)> {
+        let conn = self.lock().await;
+
+        let total = conn.query_row("SELECT COUNT(*) FROM tenants", params![], |row| row.get(0))?;
+
+        // Only the fixed strings from TenantSort/SortDirection reach the SQL
+        let order_by = match sort {
+            Some(sort) => format!("{} {}, t.id", sort.column(), direction.keyword()),
+            None => format!("t.id {}", direction.keyword()),
+        };
+        let mut stmt = conn.prepare(&format!(
+            "SELECT t.id, t.name, t.created_at,
+                COUNT(DISTINCT em.id) as email_count,
+                COUNT(CASE WHEN e.event_type = 'open' AND NOT e.suspicious AND NOT e.premature THEN 1 END) as total_opens,
+                COUNT(CASE WHEN e.event_type = 'click' THEN 1 END) as total_clicks,
+                t.last_activity_at
+             FROM tenants t
+             LEFT JOIN emails em ON em.tenant_id = t.id
+             LEFT JOIN events e ON e.email_id = em.id
+             GROUP BY t.id
+             ORDER BY {}
+             LIMIT ?1 OFFSET ?2",
+            order_by
+        ))?;
+
+        let tenants = stmt.query_map(params![limit, offset], |row| {
+            Ok(TenantSummary {
+                id: row.get(0)?,
+                name: row.get(1)?,
+                created_at: DateTime::parse_from_rfc3339(&row.get::<_, String>(2)?)
+                    .unwrap()
+                    .with_timezone(&Utc),
+                email_count: row.get(3)?,
+                total_opens: row.get(4)?,
+                total_clicks: row.get(5)?,
+                last_activity_at: row.get::<_, Option<String>>(6)?.map(|t| {
+                    DateTime::parse_from_rfc3339(&t).unwrap().with_timezone(&Utc)
+                }),
+            })
+        })?;
+        Ok((tenants.collect::<SqliteResult<_>>()?, total))
+    }
+
+    /// Stamps the tenant as active at `at`.
+    pub async fn touch_tenant(&self, tenant_id: &str, at: DateTime<Utc>) -> SqliteResult<()> {
+        self.write(|conn| {
+            conn.execute(
+                "UPDATE tenants SET last_activity_at = ?2 WHERE id = ?1",
+                params![tenant_id, at.to_rfc3339()],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    /// Settings for a tenant, or the defaults if the tenant doesn't exist yet.
+    pub async fn get_tenant_settings(&self, tenant_id: &str) -> SqliteResult<TenantSettings> {
+        let conn = self.lock().await;
+        Ok(read_tenant_settings(&conn, tenant_id)?.unwrap_or_default())
+    }
+
+    /// Applies `patch` to an existing tenant's settings in one transaction
+    /// and returns the result, or `None` if there's no such tenant.
+    pub async fn patch_tenant_settings(
+        &self,
+        tenant_id: &str,
+        patch: &TenantSettingsPatch,
+    ) -> SqliteResult<Option<TenantSettings>> {
+        self.write(|conn| {
+            let tx = conn.transaction()?;
+            let Some(mut settings) = read_tenant_settings(&tx, tenant_id)? else {
+                return Ok(None);
+            };
+            settings.apply(patch.clone());
+            write_tenant_settings(&tx, tenant_id, &settings)?;
+            tx.commit()?;
+            Ok(Some(settings))
+        })
+        .await
+    }
+
+    pub async fn update_tenant_settings(&self, tenant_id: &str, settings: &TenantSettings) -> SqliteResult<()> {
+        self.write(|conn| {
+            let tx = conn.transaction()?;
+            write_tenant_settings(&tx, tenant_id, settings)?;
+            tx.commit()
+        })
+        .await
+    }
+
+    /// Creates the email unless the tenant already has one with the same
+    /// idempotency key or external id, in which case that one is returned.
+    /// The flag says whether it was created.
+    pub async fn create_email(&self, tenant_id: &str, email: &NewEmail) -> SqliteResult<(Email, bool)> {
+        let created = self
+            .create_email_within_quota(tenant_id, email, None)
+            .await?
+            .expect("no limit to exceed");
+        Ok((created.email, created.created))
+    }
+
+    /// Like `create_email`, but refuses to create the email once the tenant
+    /// has created `daily_limit` today. Replays of an existing email are
+    /// returned whatever the quota.
+    pub async fn create_email_within_quota(
+        &self,
+        tenant_id: &str,
+        email: &NewEmail,
+        daily_limit: Option<i64>,
+    ) -> SqliteResult<Result<CreatedEmail, QuotaExceeded>> {
+        let now = Utc::now();
+
+        self.write(|conn| {
+            let tx = conn.transaction()?;
+            let remaining = quota_remaining(&tx, tenant_id, daily_limit)?;
+            let replay = |email: Email| -> SqliteResult<Result<CreatedEmail, QuotaExceeded>> {
+                Ok(Ok(CreatedEmail { email, created: false, quota_remaining: remaining }))
+            };
+
+            if let Some(key) = &email.idempotency_key {
+                let existing = tx
+                    .query_row(
+                        &format!("SELECT {} FROM emails WHERE tenant_id = ?1 AND idempotency_key = ?2", EMAIL_COLUMNS),
+                        params![tenant_id, key],
+                        email_from_row,
+                    )
+                    .optional()?;
+                if let Some(email) = existing {
+                    return replay(email);
+                }
+            }
+
+            if let Some(external_id) = &email.external_id {
+                let existing = tx
+                    .query_row(
+                        &format!("SELECT {} FROM emails WHERE tenant_id = ?1 AND external_id = ?2", EMAIL_COLUMNS),
+                        params![tenant_id, external_id],
+                        email_from_row,
+                    )
+                    .optional()?;
+                if let Some(email) = existing {
+                    return replay(email);
+                }
+            }
+
+            if remaining == Some(0) {
+                return Ok(Err(QuotaExceeded { remaining: 0 }));
+            }
+            let created = insert_email(&tx, tenant_id, email, now)?;
+            tx.commit()?;
+            Ok(Ok(CreatedEmail {
+                email: created,
+                created: true,
+                quota_remaining: remaining.map(|remaining| remaining - 1),
+            }))
+        })
+        .await
+    }
+
+    /// Creates a copy of one of the tenant's emails with a new tracking
+    /// identity, keeping its subject, recipient, campaign, metadata and
+    /// consent and linking back to it through `resend_of`. None if the
+    /// tenant has no such email. The copy counts against `daily_limit`.
+    pub async fn resend_email(
+        &self,
+        tenant_id: &str,
+        email_id: i64,
+        daily_limit: Option<i64>,
+    ) -> SqliteResult<Option<Result<CreatedEmail, QuotaExceeded>>> {
+        let now = Utc::now();
+
+        self.write(|conn| {
+            let tx = conn.transaction()?;
+            let original = tx
+                .query_row(
+                    &format!("SELECT {} FROM emails WHERE id = ?1 AND tenant_id = ?2", EMAIL_COLUMNS),
+                    params![email_id, tenant_id],
+                    email_from_row,
+                )
+                .optional()?;
+            let Some(original) = original else {
+                return Ok(None);
+            };
+            let remaining = quota_remaining(&tx, tenant_id, daily_limit)?;
+            if remaining == Some(0) {
+                return Ok(Some(Err(QuotaExceeded { remaining: 0 })));
+            }
+
+            let resend = NewEmail {
+                subject: original.subject,
+                recipient: original.recipient,
+                campaign_id: original.campaign_id,
+                metadata: original.metadata,
+                tracking_consent: Some(original.tracking_consent),
+                resend_of: Some(original.id),
+                ..NewEmail::default()
+            };
+            let created = insert_email(&tx, tenant_id, &resend, now)?;
+            tx.commit()?;
+            Ok(Some(Ok(CreatedEmail {
+                email: created,
+                created: true,
+                quota_remaining: remaining.map(|remaining| remaining - 1),
+            })))
+        })
+        .await
+    }
+
+    /// Creates all of `emails` in one transaction, in order, or none of them
+    /// if they don't all fit in the tenant's `daily_limit`. Idempotency keys
+    /// and external ids aren't checked for replays here.
+    pub async fn create_emails(
+        &self,
+        tenant_id: &str,
+        emails: &[NewEmail],
+        daily_limit: Option<i64>,
+    ) -> SqliteResult<Result<Vec<Email>, QuotaExceeded>> {
+        let now = Utc::now();
+
+        self.write(|conn| {
+            let tx = conn.transaction()?;
+            if let Some(remaining) = quota_remaining(&tx, tenant_id, daily_limit)? {
+                if (remaining as usize) < emails.len() {
+                    return Ok(Err(QuotaExceeded { remaining }));
+                }
+            }
+            let created = emails
+                .iter()
+                .map(|email| insert_email(&tx, tenant_id, email, now))
+                .collect::<SqliteResult<Vec<_>>>()?;
+            tx.commit()?;
+            Ok(Ok(created))
+        })
+        .await
+    }
+
+    pub async fn get_email(&self, email_id: i64, tenant_id: &str) -> SqliteResult<Option<Email>> {
+        let conn = self.lock().await;
+        
+        conn.query_row(
+            &format!("SELECT {} FROM emails WHERE id = ?1 AND tenant_id = ?2", EMAIL_COLUMNS),
+            params![email_id, tenant_id],
+            email_from_row,
+        )
+        .optional()
+    }
+
+    /// A page of the tenant's emails, newest first. Each filter is a
+    /// top-level metadata key and the value it must have; `expired` keeps
+    /// only emails past (or not past) their `expires_at`.
+    pub async fn list_emails(
+        &self,
+        tenant_id: &str,
+        metadata_filters: &[(String, String)],
+        expired: Option<bool>,
+        limit: i64,
+        offset: i64,
+    ) -> SqliteResult<Vec<Email>> {
+        let conn = self.lock().await;
+
+        let (sql, values) = email_page_query(tenant_id, metadata_filters, expired, limit, offset);
+        let mut stmt = conn.prepare(&sql)?;
+        let emails = stmt.query_map(rusqlite::params_from_iter(values), email_from_row)?;
+        emails.collect()
+    }
+
+    /// Like `list_emails`, with each email's event totals. Emails without
+    /// events are included with zero counts.
+    pub async fn list_emails_with_stats(
+        &self,
+        tenant_id: &str,
+        metadata_filters: &[(String, String)],
+        expired: Option<bool>,
+        limit: i64,
+        offset: i64,
+    ) -> SqliteResult<Vec<EmailWithStats>> {
+        let conn = self.lock().await;
+        let now = Utc::now();
+
+        // Page the emails first so only that page is joined against events
+        let (page, values) = email_page_query(tenant_id, metadata_filters, expired, limit, offset);
+        let sql = format!(
+            "SELECT em.*,
+                COUNT(CASE WHEN e.event_type = 'open' AND NOT e.suspicious AND NOT e.premature THEN 1 END),
+                COUNT(CASE WHEN e.event_type = 'click' THEN 1 END),
+                MAX(e.timestamp)
+             FROM ({}) em
+             LEFT JOIN events e ON e.email_id = em.id
+             GROUP BY em.id
+             ORDER BY em.id DESC",
+            page
+        );
+
+        let mut stmt = conn.prepare(&sql)?;
+        let emails = stmt.query_map(rusqlite::params_from_iter(values), |row| {
+            let email = email_from_row(row)?;
+            Ok(EmailWithStats {
+                expired: email.is_expired(now),
+                email,
+                open_count: row.get(EMAIL_COLUMN_COUNT)?,
+                click_count: row.get(EMAIL_COLUMN_COUNT + 1)?,
+                last_event_at: row
+                    .get::<_, Option<String>>(EMAIL_COLUMN_COUNT + 2)?
+                    .map(|t| DateTime::parse_from_rfc3339(&t).unwrap().with_timezone(&Utc)),
+            })
+        })?;
+        emails.collect()
+    }
+
+    pub async fn get_email_engagement(&self, email_id: i64) -> SqliteResult<EmailEngagement> {
+        let conn = self.lock().await;
+
+        conn.query_row(
+            "SELECT
+                COUNT(CASE WHEN event_type = 'open' AND NOT suspicious AND NOT premature THEN 1 END),
+                COUNT(CASE WHEN event_type = 'click' THEN 1 END),
+                MAX(timestamp)
+             FROM events
+             WHERE email_id = ?1",
+            params![email_id],
+            |row| {
+                Ok(EmailEngagement {
+                    open_count: row.get(0)?,
+                    click_count: row.get(1)?,
+                    last_event_at: row
+                        .get::<_, Option<String>>(2)?
+                        .map(|t| DateTime::parse_from_rfc3339(&t).unwrap().with_timezone(&Utc)),
+                })
+            },
+        )
+    }
+
+    pub async fn get_email_by_token(&self, token: &str, tenant_id: &str) -> SqliteResult<Option<Email>> {
+        let conn = self.lock().await;
+
+        conn.query_row(
+            &format!("SELECT {} FROM emails WHERE public_token = ?1 AND tenant_id = ?2", EMAIL_COLUMNS),
+            params![token, tenant_id],
+            email_from_row,
+        )
+        .optional()
+    }
+
+    /// An email by id whichever tenant owns it. Only for telling a
+    /// cross-tenant request apart from one for an email that doesn't exist;
+    /// lookups on behalf of a tenant go through `get_email`.
+    pub async fn get_email_any_tenant(&self, email_id: i64) -> SqliteResult<Option<Email>> {
+        let conn = self.lock().await;
+
+        conn.query_row(
+            &format!("SELECT {} FROM emails WHERE id = ?1", EMAIL_COLUMNS),
+            params![email_id],
+            email_from_row,
+        )
+        .optional()
+    }
+
+    /// Like `get_email_any_tenant`, by public token.
+    pub async fn get_email_by_token_any_tenant(&self, token: &str) -> SqliteResult<Option<Email>> {
+        let conn = self.lock().await;
+
+        conn.query_row(
+            &format!("SELECT {} FROM emails WHERE public_token = ?1", EMAIL_COLUMNS),
+            params![token],
+            email_from_row,
+        )
+        .optional()
+    }
+
+    /// Looks an email up by whichever identifier a tracking URL carried.
+    pub async fn find_email(&self, email_ref: &EmailRef, tenant_id: &str) -> SqliteResult<Option<Email>> {
+        match email_ref {
+            EmailRef::Id(id) => self.get_email(*id, tenant_id).await,
+            EmailRef::Token(token) => self.get_email_by_token(token, tenant_id).await,
+        }
+    }
+
+    pub async fn log_event(
+        &self,
+        email_id: i64,
+        event_type: &str,
+        user_agent: Option<&str>,
+        ip_address: Option<&str>,
+        target_url: Option<&str>,
+        client_id: Option<&str>,
+    ) -> SqliteResult<Event> {
+        self.log_new_event(&NewEvent {
+            email_id,
+            event_type: event_type.to_string(),
+            timestamp: None,
+            user_agent: user_agent.map(|s| s.to_string()),
+            ip_address: ip_address.map(|s| s.to_string()),
+            target_url: target_url.map(|s| s.to_string()),
+            client_id: client_id.map(|s| s.to_string()),
+            suspicious: false,
+            premature: false,
+        })
+        .await
+    }
+
+    /// Opens of an email that count towards its stats, i.e. flagged neither
+    /// suspicious nor premature.
+    pub async fn count_counted_opens(&self, email_id: i64) -> SqliteResult<i64> {
+        let conn = self.lock().await;
+        conn.query_row(
+            "SELECT COUNT(*) FROM events WHERE email_id = ?1 AND event_type = 'open' AND NOT suspicious AND NOT premature",
+            params![email_id],
+            |row| row.get(0),
+        )
+    }
+
+    /// Inserts one event, at its own timestamp when it has one and now
+    /// otherwise.
+    pub async fn log_new_event(&self, event: &NewEvent) -> SqliteResult<Event> {
+        let started = Instant::now();
+        let timestamp = event.timestamp.unwrap_or_else(Utc::now);
+
+        let id = self
+            .write(|conn| {
+                let user_agent_id = user_agent_id(conn, event.user_agent.as_deref())?;
+                conn.execute(
+                    "INSERT INTO events (email_id, event_type, timestamp, user_agent_id, ip_address, target_url, client_id, suspicious, premature) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+                    params![
+                        event.email_id,
+                        event.event_type,
+                        timestamp.to_rfc3339(),
+                        user_agent_id,
+                        event.ip_address,
+                        event.target_url,
+                        event.client_id,
+                        event.suspicious,
+                        event.premature,
+                    ],
+                )?;
+                Ok(conn.last_insert_rowid())
+            })
+            .await?;
+        self.metrics.record_event_write(started.elapsed());
+        Ok(Event {
+            id,
+            email_id: event.email_id,
+            event_type: event.event_type.clone(),
+            timestamp,
+            user_agent: event.user_agent.clone(),
+            ip_address: event.ip_address.clone(),
+            target_url: event.target_url.clone(),
+            client_id: event.client_id.clone(),
+            suspicious: event.suspicious,
+            premature: event.premature,
+        })
+    }
+
+    /// Inserts all events in a single transaction, so either every event is
+    /// recorded or none are.
+    pub async fn log_events_batch(&self, events: &[NewEvent]) -> SqliteResult<Vec<Event>> {
+        let now = Utc::now();
+
+        self.write(|conn| {
+            let tx = conn.transaction()?;
+
+            let mut logged = Vec::with_capacity(events.len());
+            {
+                let mut stmt = tx.prepare(
+                    "INSERT INTO events (email_id, event_type, timestamp, user_agent_id, ip_address, target_url, client_id, suspicious, premature) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)"
+                )?;
+                for event in events {
+                    let timestamp = event.timestamp.unwrap_or(now);
+                    stmt.execute(params![
+                        event.email_id,
+                        event.event_type,
+                        timestamp.to_rfc3339(),
+                        user_agent_id(&tx, event.user_agent.as_deref())?,
+                        event.ip_address,
+                        event.target_url,
+                        event.client_id,
+                        event.suspicious,
+                        event.premature,
+                    ])?;
+                    logged.push(Event {
+                        id: tx.last_insert_rowid(),
+                        email_id: event.email_id,
+                        event_type: event.event_type.clone(),
+                        timestamp,
+                        user_agent: event.user_agent.clone(),
+                        ip_address: event.ip_address.clone(),
+                        target_url: event.target_url.clone(),
+                        client_id: event.client_id.clone(),
+                        suspicious: event.suspicious,
+                        premature: event.premature,
+                    });
+                }
+            }
+
+            tx.commit()?;
+            Ok(logged)
+        })
+        .await
+    }
+
+    /// Emails created since midnight UTC.
+    pub async fn count_emails_today(&self, tenant_id: &str) -> SqliteResult<i64> {
+        let conn = self.lock().await;
+
+        conn.query_row(
+            "SELECT COUNT(*) FROM emails WHERE tenant_id = ?1 AND created_at >= ?2",
+            params![tenant_id, start_of_today().to_rfc3339()],
+            |row| row.get(0),
+        )
+    }
+
+    /// Loads exported emails and events into `tenant_id` in one transaction.
+    ///
+    /// Emails get fresh ids, and events are attached to the new id of the
+    /// email they referenced; events for emails not in `emails` are skipped.
+    /// Public tokens are kept so existing tracking URLs keep working, unless
+    /// another email already uses them. `settings` replace the tenant's when
+    /// given. Returns `None`, without writing anything, if the tenant already
+    /// has emails and `merge` is false.
+    pub async fn import_tenant(
+        &self,
+        tenant_id: &str,
+        tenant_name: &str,
+        settings: Option<&TenantSettings>,
+        emails: &[Email],
+        events: &[Event],
+        merge: bool,
+    ) -> SqliteResult<Option<ImportSummary>> {
+        self.write(|conn| {
+            let tx = conn.transaction()?;
+
+            let has_data: bool = tx.query_row(
+                "SELECT EXISTS(SELECT 1 FROM emails WHERE tenant_id = ?1)",
+                params![tenant_id],
+                |row| row.get(0),
+            )?;
+            if has_data && !merge {
+                return Ok(None);
+            }
+
+            tx.execute(
+                "INSERT OR IGNORE INTO tenants (id, name, created_at) VALUES (?1, ?2, ?3)",
+                params![tenant_id, tenant_name, Utc::now().to_rfc3339()],
+            )?;
+            if let Some(settings) = settings {
+                write_tenant_settings(&tx, tenant_id, settings)?;
+            }
+
+            let mut summary = ImportSummary::default();
+            let mut new_ids = HashMap::new();
+            for email in emails {
+                let token_taken = match &email.public_token {
+                    Some(token) => tx.query_row(
+                        "SELECT EXISTS(SELECT 1 FROM emails WHERE public_token = ?1)",
+                        params![token],
+                        |row| row.get(0),
+                    )?,
+                    None => false,
+                };
+                let public_token = match &email.public_token {
+                    Some(token) if !token_taken => token.clone(),
+                    _ => generate_public_token(),
+                };
+                // External ids only need to be unique within the tenant
+                let external_id = match &email.external_id {
+                    Some(external_id) => {
+                        let taken: bool = tx.query_row(
+                            "SELECT EXISTS(SELECT 1 FROM emails WHERE tenant_id = ?1 AND external_id = ?2)",
+                            params![tenant_id, external_id],
+                            |row| row.get(0),
+                        )?;
+                        (!taken).then(|| external_id.clone())
+                    }
+                    None => None,
+                };
+
+                tx.execute(
+                    "INSERT INTO emails (tenant_id, subject, recipient, created_at, public_token, campaign_id, metadata, tracking_consent, expires_at, sent_at, resend_of, external_id) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)",
+                    params![
+                        tenant_id,
+                        email.subject,
+                        email.recipient,
+                        email.created_at.to_rfc3339(),
+                        public_token,
+                        email.campaign_id,
+                        email.metadata.as_ref().map(|metadata| metadata.to_string()),
+                        email.tracking_consent,
+                        email.expires_at.map(|t| t.to_rfc3339()),
+                        email.sent_at.map(|t| t.to_rfc3339()),
+                        // Originals come first in an export, so are already in
+                        email.resend_of.and_then(|id| new_ids.get(&id).copied()),
+                        external_id,
+                    ],
+                )?;
+                new_ids.insert(email.id, tx.last_insert_rowid());
+                summary.emails += 1;
+            }
+
+            for event in events {
+                let Some(email_id) = new_ids.get(&event.email_id) else {
+                    continue;
+                };
+                tx.execute(
+                    "INSERT INTO events (email_id, event_type, timestamp, user_agent_id, ip_address, target_url, client_id, suspicious, premature) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+                    params![
+                        email_id,
+                        event.event_type,
+                        event.timestamp.to_rfc3339(),
+                        user_agent_id(&tx, event.user_agent.as_deref())?,
+                        event.ip_address,
+                        event.target_url,
+                        event.client_id,
+                        event.suspicious,
+                        event.premature,
+                    ],
+                )?;
+                summary.events += 1;
+            }
+
+            tx.commit()?;
+            Ok(Some(summary))
+        })
+        .await
+    }
+
+    /// Up to `limit` of the tenant's emails with ids above `after_id`, in id
+    /// order, for walking every email a page at a time.
+    pub async fn emails_after(&self, tenant_id: &str, after_id: i64, limit: i64) -> SqliteResult<Vec<Email>> {
+        let conn = self.lock().await;
+
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM emails WHERE tenant_id = ?1 AND id > ?2 ORDER BY id LIMIT ?3",
+            EMAIL_COLUMNS
+        ))?;
+        let emails = stmt.query_map(params![tenant_id, after_id, limit], email_from_row)?;
+        emails.collect()
+    }
+
+    /// Like `emails_after`, for the tenant's events.
+    pub async fn events_after(&self, tenant_id: &str, after_id: i64, limit: i64) -> SqliteResult<Vec<Event>> {
+        let conn = self.lock().await;
+
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {}
+             FROM events e
+             JOIN emails em ON e.email_id = em.id
+             WHERE em.tenant_id = ?1 AND e.id > ?2
+             ORDER BY e.id
+             LIMIT ?3",
+            EVENT_COLUMNS
+        ))?;
+        let events = stmt.query_map(params![tenant_id, after_id, limit], event_from_row)?;
+        events.collect()
+    }
+
+    /// A page of one email's events, oldest first, or None when the tenant
+    /// has no such email.
+    pub async fn list_email_events(
+        &self,
+        email_id: i64,
+        tenant_id: &str,
+        limit: i64,
+        offset: i64,
+    ) -> SqliteResult<Option<Vec<Event>>> {
+        let conn = self.lock().await;
+
+        let owned = conn
+            .query_row(
+                "SELECT 1 FROM emails WHERE id = ?1 AND tenant_id = ?2",
+                params![email_id, tenant_id],
+                |_| Ok(()),
+            )
+            .optional()?;
+        if owned.is_none() {
+            return Ok(None);
+        }
+
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {}
+             FROM events e
+             WHERE e.email_id = ?1
+             ORDER BY e.timestamp, e.id
+             LIMIT ?2 OFFSET ?3",
+            EVENT_COLUMNS
+        ))?;
+        let events = stmt.query_map(params![email_id, limit, offset], event_from_row)?;
+        events.collect::<SqliteResult<_>>().map(Some)
+    }
+
+    pub async fn get_email_by_external_id(&self, tenant_id: &str, external_id: &str) -> SqliteResult<Option<Email>> {
+        let conn = self.lock().await;
+
+        conn.query_row(
+            &format!("SELECT {} FROM emails WHERE tenant_id = ?1 AND external_id = ?2", EMAIL_COLUMNS),
+            params![tenant_id, external_id],
+            email_from_row,
+        )
+        .optional()
+    }
+
+    pub async fn get_email_by_idempotency_key(&self, tenant_id: &str, key: &str) -> SqliteResult<Option<Email>> {
+        let conn = self.lock().await;
+
+        conn.query_row(
+            &format!("SELECT {} FROM emails WHERE tenant_id = ?1 AND idempotency_key = ?2", EMAIL_COLUMNS),
+            params![tenant_id, key],
+            email_from_row,
+        )
+        .optional()
+    }
+
+    /// Records that the email was handed to the mail server at `at`, unless
+    /// it already was. Returns the sent time on record and whether this call
+    /// set it.
+    pub async fn mark_email_sent(&self, email_id: i64, at: DateTime<Utc>) -> SqliteResult<(DateTime<Utc>, bool)> {
+        self.write(|conn| {
+            let marked = conn.execute(
+                "UPDATE emails SET sent_at = ?1 WHERE id = ?2 AND sent_at IS NULL",
+                params![at.to_rfc3339(), email_id],
+            )?;
+            let sent_at: String =
+                conn.query_row("SELECT sent_at FROM emails WHERE id = ?1", params![email_id], |row| row.get(0))?;
+            let sent_at = DateTime::parse_from_rfc3339(&sent_at).unwrap().with_timezone(&Utc);
+            Ok((sent_at, marked > 0))
+        })
+        .await
+    }
+
+    pub async fn count_emails(&self, tenant_id: &str) -> SqliteResult<i64> {
+        let conn = self.lock().await;
+
+        conn.query_row(
+            "SELECT COUNT(*) FROM emails WHERE tenant_id = ?1",
+            params![tenant_id],
+            |row| row.get(0),
+        )
+    }
+
+    pub async fn get_tenant_counts(&self, tenant_id: &str) -> SqliteResult<EventCounts> {
+        let conn = self.lock().await;
+
+        conn.query_row(
+            &event_counts_query(StatsFilter::default().counted_opens(), true),
+            params![tenant_id],
+            event_counts_from_row,
+        )
+    }
+
+    pub async fn get_tenant_stats(&self, tenant_id: &str) -> SqliteResult<EventStats> {
+        self.get_tenant_stats_filtered(tenant_id, &EventFilter::default(), &StatsFilter::default())
+            .await
+    }
+
+    /// Tenant statistics counting only the events that match `filter`, and
+    /// bot or suspicious ones as `counted` says. The number of emails sent,
+    /// and so the rates' denominator, is unaffected. `recent_events` lists
+    /// the latest events matching `filter` whatever `counted` says.
+    pub async fn get_tenant_stats_filtered(
+        &self,
+        tenant_id: &str,
+        filter: &EventFilter,
+        counted: &StatsFilter,
+    ) -> SqliteResult<EventStats> {
+        self.tenant_stats(tenant_id, filter, counted, None, true).await
+    }
+
+    /// Like `get_tenant_stats_filtered` but with `recent_events` left empty,
+    /// for callers that have them from elsewhere.
+    pub async fn get_tenant_stats_without_recent(
+        &self,
+        tenant_id: &str,
+        filter: &EventFilter,
+        counted: &StatsFilter,
+    ) -> SqliteResult<EventStats> {
+        self.tenant_stats(tenant_id, filter, counted, None, false).await
+    }
+
+    /// Unfiltered tenant statistics with `unique_opens` (and so the open
+    /// rate) taken as given, e.g. estimated from a sketch, rather than
+    /// counted. `recent_events` is left empty. Once any email is marked
+    /// sent the estimate is ignored and opens counted, since the sketch
+    /// can't tell sent emails' opens from the rest.
+    pub async fn get_tenant_stats_estimated(&self, tenant_id: &str, unique_opens: i64) -> SqliteResult<EventStats> {
+        self.tenant_stats(
+            tenant_id,
+            &EventFilter::default(),
+            &StatsFilter::default(),
+            Some(unique_opens),
+            false,
+        )
+        .await
+    }
+
+    /// The tenant's `RECENT_EVENT_COUNT` latest events, newest first.
+    pub async fn recent_events(&self, tenant_id: &str) -> SqliteResult<Vec<Event>> {
+        let conn = self.lock().await;
+        recent_events(&conn, tenant_id, &EventFilter::default())
+    }
+
+    async fn tenant_stats(
+        &self,
+        tenant_id: &str,
+        filter: &EventFilter,
+        counted: &StatsFilter,
+        unique_opens: Option<i64>,
+        with_recent: bool,
+    ) -> SqliteResult<EventStats> {
+        let emails_created = self.count_emails(tenant_id).await?;
+        let conn = self.lock().await;
+        let emails_sent = conn.query_row(
+            "SELECT COUNT(*) FROM emails WHERE tenant_id = ?1 AND sent_at IS NOT NULL",
+            params![tenant_id],
+            |row| row.get(0),
+        )?;
+        let unique_opens = unique_opens.filter(|_| emails_sent == 0);
+        let (mut conditions, filter_values) = filter.conditions();
+        conditions.push_str(&counted.conditions());
+        let mut values = vec![tenant_id.to_string()];
+        values.extend(filter_values);
+        
+        // Get total opens and clicks
+        let counts = conn.query_row(
+            &format!("{}{}", event_counts_query(counted.counted_opens(), unique_opens.is_none()), conditions),
+            rusqlite::params_from_iter(&values),
+            event_counts_from_row,
+        )?;
+        let stats = (
+            counts.total_opens,
+            counts.total_clicks,
+            unique_opens.unwrap_or(counts.unique_opens),
+            counts.unique_clicks,
+        );
+        let (unique_open_recipients, unique_open_ips, unique_open_clients) = conn.query_row(
+            &format!(
+                "SELECT COUNT(DISTINCT em.recipient), COUNT(DISTINCT e.ip_address), COUNT(DISTINCT e.client_id)
+                 FROM events e
+                 JOIN emails em ON e.email_id = em.id
+                 WHERE em.tenant_id = ? AND e.event_type = 'open'{}{}",
+                counted.counted_opens(),
+                conditions
+            ),
+            rusqlite::params_from_iter(&values),
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+        )?;
+        let (suspicious_opens, premature_opens) = conn.query_row(
+            &format!(
+                "SELECT COUNT(CASE WHEN e.suspicious THEN 1 END), COUNT(CASE WHEN e.premature THEN 1 END)
+                 FROM events e
+                 JOIN emails em ON e.email_id = em.id
+                 WHERE em.tenant_id = ? AND e.event_type = 'open'{}",
+                conditions
+            ),
+            rusqlite::params_from_iter(&values),
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?;
+        // Rates over sent emails only count those emails' opens and clicks
+        let (rated_opens, rated_clicks) = if emails_sent > 0 {
+            conn.query_row(
+                &format!(
+                    "SELECT
+                        COUNT(DISTINCT CASE WHEN e.event_type = 'open'{} THEN e.email_id END),
+                        COUNT(DISTINCT CASE WHEN e.event_type = 'click' THEN e.email_id END)
+                     FROM events e
+                     JOIN emails em ON e.email_id = em.id
+                     WHERE em.tenant_id = ? AND em.sent_at IS NOT NULL{}",
+                    counted.counted_opens(),
+                    conditions
+                ),
+                rusqlite::params_from_iter(&values),
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )?
+        } else {
+            (stats.2, stats.3)
+        };
+
+        let mut stmt = conn.prepare(&format!(
+            "SELECT e.event_type, COUNT(*)
+             FROM events e
+             JOIN emails em ON e.email_id = em.id
+             WHERE em.tenant_id = ?{}
+             GROUP BY e.event_type",
+            conditions
+        ))?;
+        let events_by_type = stmt
+            .query_map(rusqlite::params_from_iter(&values), |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<SqliteResult<BTreeMap<String, i64>>>()?;
+
+        let recent_events = if with_recent {
+            recent_events(&conn, tenant_id, filter)?
+        } else {
+            Vec::new()
+        };
 
         Ok(EventStats {
             total_opens: stats.0,
             total_clicks: stats.1,
             unique_opens: stats.2,
             unique_clicks: stats.3,
+            unique_open_recipients,
+            unique_open_ips,
+            unique_open_clients,
+            suspicious_opens,
+            premature_opens,
+            emails_created,
+            emails_sent,
+            open_rate: rate(rated_opens, rate_base(emails_created, emails_sent)),
+            click_rate: rate(rated_clicks, rate_base(emails_created, emails_sent)),
+            events_by_type,
             recent_events,
+            approximate: unique_opens.is_some(),
+        })
+    }
+
+    pub async fn get_campaign_stats(&self, tenant_id: &str, campaign_id: &str) -> SqliteResult<CampaignStats> {
+        let conn = self.lock().await;
+
+        let (emails_created, emails_sent) = conn.query_row(
+            "SELECT COUNT(*), COUNT(sent_at) FROM emails WHERE tenant_id = ?1 AND campaign_id = ?2",
+            params![tenant_id, campaign_id],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?;
+
+        let stats = conn.query_row(
+            "SELECT 
+                COUNT(CASE WHEN e.event_type = 'open' AND NOT e.suspicious AND NOT e.premature THEN 1 END) as total_opens,
+                COUNT(CASE WHEN e.event_type = 'click' THEN 1 END) as total_clicks,
+                COUNT(DISTINCT CASE WHEN e.event_type = 'open' AND NOT e.suspicious AND NOT e.premature THEN e.email_id END) as unique_opens,
+                COUNT(DISTINCT CASE WHEN e.event_type = 'click' THEN e.email_id END) as unique_clicks,
+                COUNT(DISTINCT CASE WHEN e.event_type = 'open' AND NOT e.suspicious AND NOT e.premature AND em.sent_at IS NOT NULL THEN e.email_id END) as sent_unique_opens,
+                COUNT(DISTINCT CASE WHEN e.event_type = 'click' AND em.sent_at IS NOT NULL THEN e.email_id END) as sent_unique_clicks
+             FROM events e 
+             JOIN emails em ON e.email_id = em.id 
+             WHERE em.tenant_id = ?1 AND em.campaign_id = ?2",
+            params![tenant_id, campaign_id],
+            |row| {
+                Ok((
+                    row.get::<_, i64>(0)?,
+                    row.get::<_, i64>(1)?,
+                    row.get::<_, i64>(2)?,
+                    row.get::<_, i64>(3)?,
+                    row.get::<_, i64>(4)?,
+                    row.get::<_, i64>(5)?,
+                ))
+            },
+        )?;
+        // Rates over sent emails only count those emails' opens and clicks
+        let (rated_opens, rated_clicks) = if emails_sent > 0 {
+            (stats.4, stats.5)
+        } else {
+            (stats.2, stats.3)
+        };
+
+        Ok(CampaignStats {
+            campaign_id: campaign_id.to_string(),
+            emails_created,
+            emails_sent,
+            total_opens: stats.0,
+            total_clicks: stats.1,
+            unique_opens: stats.2,
+            unique_clicks: stats.3,
+            open_rate: rate(rated_opens, rate_base(emails_created, emails_sent)),
+            click_rate: rate(rated_clicks, rate_base(emails_created, emails_sent)),
+        })
+    }
+
+    /// The short code for this email and target, creating one the first time.
+    pub async fn get_or_create_short_link(&self, email_id: i64, target_url: &str) -> SqliteResult<ShortLink> {
+        self.write(|conn| {
+            let existing = conn
+                .query_row(
+                    "SELECT short_code FROM links WHERE email_id = ?1 AND target_url = ?2",
+                    params![email_id, target_url],
+                    |row| row.get(0),
+                )
+                .optional()?;
+            if let Some(short_code) = existing {
+                return Ok(ShortLink { short_code, email_id, target_url: target_url.to_string() });
+            }
+
+            // Codes are random, so on the rare collision just draw another
+            let mut attempts = 0;
+            loop {
+                let short_code = generate_short_code();
+                let inserted = conn.execute(
+                    "INSERT OR IGNORE INTO links (short_code, email_id, target_url, created_at) VALUES (?1, ?2, ?3, ?4)",
+                    params![short_code, email_id, target_url, Utc::now().to_rfc3339()],
+                )?;
+                if inserted == 1 {
+                    return Ok(ShortLink { short_code, email_id, target_url: target_url.to_string() });
+                }
+                attempts += 1;
+                if attempts == SHORT_CODE_ATTEMPTS {
+                    return Err(rusqlite::Error::SqliteFailure(
+                        rusqlite::ffi::Error::new(rusqlite::ffi::SQLITE_CONSTRAINT),
+                        Some("could not find a free short code".to_string()),
+                    ));
+                }
+            }
+        })
+        .await
+    }
+
+    pub async fn get_short_link(&self, tenant_id: &str, short_code: &str) -> SqliteResult<Option<ShortLink>> {
+        let conn = self.lock().await;
+
+        conn.query_row(
+            "SELECT l.short_code, l.email_id, l.target_url
+             FROM links l
+             JOIN emails em ON l.email_id = em.id
+             WHERE l.short_code = ?1 AND em.tenant_id = ?2",
+            params![short_code, tenant_id],
+            |row| {
+                Ok(ShortLink {
+                    short_code: row.get(0)?,
+                    email_id: row.get(1)?,
+                    target_url: row.get(2)?,
+                })
+            },
+        )
+        .optional()
+    }
+
+    /// Permanently removes the tenant's emails matching `filter`, along with
+    /// their events, short links and webhook deliveries, in one transaction.
+    /// Suppressions stay, so unsubscribed recipients remain unsubscribed.
+    /// Returns what was removed and the deleted emails themselves. An empty
+    /// filter deletes nothing; callers must ask for something.
+    pub async fn delete_emails_where(
+        &self,
+        tenant_id: &str,
+        filter: &EmailDeleteFilter,
+    ) -> SqliteResult<(DeleteSummary, Vec<Email>)> {
+        if filter.is_empty() {
+            return Ok((DeleteSummary::default(), Vec::new()));
+        }
+        let mut conditions = String::from("tenant_id = ?1");
+        let mut values = vec![tenant_id.to_string()];
+        if let Some(campaign_id) = &filter.campaign_id {
+            values.push(campaign_id.clone());
+            conditions.push_str(&format!(" AND campaign_id = ?{}", values.len()));
+        }
+        if let Some(created_before) = filter.created_before {
+            values.push(created_before.to_rfc3339());
+            conditions.push_str(&format!(" AND created_at < ?{}", values.len()));
+        }
+        let matching = format!("SELECT id FROM emails WHERE {}", conditions);
+
+        self.write(|conn| {
+            let tx = conn.transaction()?;
+            let emails = {
+                let mut stmt = tx.prepare(&format!("SELECT {} FROM emails WHERE {}", EMAIL_COLUMNS, conditions))?;
+                let emails = stmt.query_map(rusqlite::params_from_iter(&values), email_from_row)?;
+                emails.collect::<SqliteResult<Vec<Email>>>()?
+            };
+            let webhook_deliveries = tx.execute(
+                &format!(
+                    "DELETE FROM webhook_deliveries WHERE event_id IN (SELECT id FROM events WHERE email_id IN ({}))",
+                    matching
+                ),
+                rusqlite::params_from_iter(&values),
+            )?;
+            let events = tx.execute(
+                &format!("DELETE FROM events WHERE email_id IN ({})", matching),
+                rusqlite::params_from_iter(&values),
+            )?;
+            let links = tx.execute(
+                &format!("DELETE FROM links WHERE email_id IN ({})", matching),
+                rusqlite::params_from_iter(&values),
+            )?;
+            let deleted = tx.execute(
+                &format!("DELETE FROM emails WHERE {}", conditions),
+                rusqlite::params_from_iter(&values),
+            )?;
+            // Deleted opens can't be taken out of a sketch; it's rebuilt
+            if events > 0 {
+                tx.execute("DELETE FROM unique_open_sketches WHERE tenant_id = ?1", params![tenant_id])?;
+            }
+            tx.commit()?;
+            let summary = DeleteSummary {
+                emails: deleted,
+                events,
+                links,
+                webhook_deliveries,
+            };
+            Ok((summary, emails))
+        })
+        .await
+    }
+
+    /// Deletes every event logged for the tenant's emails, leaving the
+    /// emails and tenant in place. Returns how many events were removed.
+    pub async fn delete_events_for_tenant(&self, tenant_id: &str) -> SqliteResult<usize> {
+        self.write(|conn| {
+            let tx = conn.transaction()?;
+            let deleted = tx.execute(
+                "DELETE FROM events WHERE email_id IN (SELECT id FROM emails WHERE tenant_id = ?1)",
+                params![tenant_id],
+            )?;
+            tx.execute("DELETE FROM unique_open_sketches WHERE tenant_id = ?1", params![tenant_id])?;
+            tx.commit()?;
+            Ok(deleted)
+        })
+        .await
+    }
+
+    /// The tenant's stored unique open sketch and the newest event it
+    /// covers, if one has been saved.
+    pub async fn load_unique_open_sketch(&self, tenant_id: &str) -> SqliteResult<Option<(Vec<u8>, i64)>> {
+        let conn = self.lock().await;
+        conn.query_row(
+            "SELECT registers, through_event_id FROM unique_open_sketches WHERE tenant_id = ?1",
+            params![tenant_id],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .optional()
+    }
+
+    pub async fn save_unique_open_sketch(
+        &self,
+        tenant_id: &str,
+        registers: &[u8],
+        through_event_id: i64,
+    ) -> SqliteResult<()> {
+        self.write(|conn| {
+            conn.execute(
+                "INSERT INTO unique_open_sketches (tenant_id, registers, through_event_id, updated_at) VALUES (?1, ?2, ?3, ?4)
+                 ON CONFLICT(tenant_id) DO UPDATE SET registers = excluded.registers,
+                     through_event_id = excluded.through_event_id, updated_at = excluded.updated_at",
+                params![tenant_id, registers, through_event_id, Utc::now().to_rfc3339()],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    /// The emails with counted opens stored after event `after_event_id`,
+    /// and the newest such open's id.
+    pub async fn opened_email_ids_since(
+        &self,
+        tenant_id: &str,
+        after_event_id: i64,
+    ) -> SqliteResult<(Vec<i64>, Option<i64>)> {
+        let conn = self.lock().await;
+        let mut stmt = conn.prepare(
+            "SELECT e.email_id, MAX(e.id)
+             FROM events e
+             JOIN emails em ON e.email_id = em.id
+             WHERE em.tenant_id = ?1 AND e.event_type = 'open' AND NOT e.suspicious AND NOT e.premature AND e.id > ?2
+             GROUP BY e.email_id",
+        )?;
+        let rows = stmt
+            .query_map(params![tenant_id, after_event_id], |row| Ok((row.get::<_, i64>(0)?, row.get::<_, i64>(1)?)))?
+            .collect::<SqliteResult<Vec<_>>>()?;
+        let newest = rows.iter().map(|(_, id)| *id).max();
+        Ok((rows.into_iter().map(|(email_id, _)| email_id).collect(), newest))
+    }
+
+    /// Adds a recipient to the tenant's suppression list. Already suppressed
+    /// recipients keep their original entry.
+    pub async fn add_suppression(&self, tenant_id: &str, recipient: &str, email_id: Option<i64>) -> SqliteResult<()> {
+        let now = Utc::now();
+        let recipient = normalize_recipient(recipient);
+
+        self.write(|conn| {
+            conn.execute(
+                "INSERT OR IGNORE INTO suppressions (tenant_id, recipient, email_id, created_at) VALUES (?1, ?2, ?3, ?4)",
+                params![tenant_id, recipient, email_id, now.to_rfc3339()],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    pub async fn is_suppressed(&self, tenant_id: &str, recipient: &str) -> SqliteResult<bool> {
+        let conn = self.lock().await;
+
+        conn.query_row(
+            "SELECT EXISTS(SELECT 1 FROM suppressions WHERE tenant_id = ?1 AND recipient = ?2)",
+            params![tenant_id, normalize_recipient(recipient)],
+            |row| row.get(0),
+        )
+    }
+
+    /// Click targets ranked by how often they were clicked.
+    pub async fn top_links(&self, tenant_id: &str, limit: i64) -> SqliteResult<Vec<LinkClicks>> {
+        let conn = self.lock().await;
+
+        let mut stmt = conn.prepare(
+            "SELECT e.target_url, COUNT(*) as clicks
+             FROM events e
+             JOIN emails em ON e.email_id = em.id
+             WHERE em.tenant_id = ?1 AND e.event_type = 'click' AND e.target_url IS NOT NULL
+             GROUP BY e.target_url
+             ORDER BY clicks DESC, e.target_url
+             LIMIT ?2"
+        )?;
+
+        let links = stmt.query_map(params![tenant_id, limit], |row| {
+            Ok(LinkClicks {
+                url: row.get(0)?,
+                clicks: row.get(1)?,
+            })
+        })?;
+        links.collect()
+    }
+
+    /// The tenant's recipients with their engagement totals, grouped by the
+    /// `recipient` column exactly as stored, plus how many there are in all
+    /// for paging. Emails without a recipient are left out.
+    pub async fn list_recipients_with_stats(
+        &self,
+        tenant_id: &str,
+        sort: RecipientSort,
+        direction: SortDirection,
+        limit: i64,
+        offset: i64,
+    ) -> SqliteResult<(Vec<RecipientStats>, i64)> {
+        let conn = self.lock().await;
+
+        let total = conn.query_row(
+            "SELECT COUNT(DISTINCT recipient) FROM emails WHERE tenant_id = ?1 AND recipient IS NOT NULL",
+            params![tenant_id],
+            |row| row.get(0),
+        )?;
+
+        // Only the fixed strings from RecipientSort/SortDirection reach the SQL
+        let mut stmt = conn.prepare(&format!(
+            "SELECT em.recipient,
+                COUNT(DISTINCT em.id) as emails_created,
+                COUNT(DISTINCT CASE WHEN em.sent_at IS NOT NULL THEN em.id END) as emails_sent,
+                COUNT(CASE WHEN e.event_type = 'open' AND NOT e.suspicious AND NOT e.premature THEN 1 END) as total_opens,
+                COUNT(CASE WHEN e.event_type = 'click' THEN 1 END) as total_clicks,
+                MAX(CASE WHEN (e.event_type = 'open' AND NOT e.suspicious AND NOT e.premature) OR e.event_type = 'click'
+                    THEN e.timestamp END) as last_engaged_at
+             FROM emails em
+             LEFT JOIN events e ON e.email_id = em.id
+             WHERE em.tenant_id = ?1 AND em.recipient IS NOT NULL
+             GROUP BY em.recipient
+             ORDER BY {} {}, em.recipient
+             LIMIT ?2 OFFSET ?3",
+            sort.column(),
+            direction.keyword()
+        ))?;
+
+        let recipients = stmt.query_map(params![tenant_id, limit, offset], |row| {
+            Ok(RecipientStats {
+                recipient: row.get(0)?,
+                emails_created: row.get(1)?,
+                emails_sent: row.get(2)?,
+                total_opens: row.get(3)?,
+                total_clicks: row.get(4)?,
+                last_engaged_at: row
+                    .get::<_, Option<String>>(5)?
+                    .map(|t| DateTime::parse_from_rfc3339(&t).unwrap().with_timezone(&Utc)),
+            })
+        })?;
+        Ok((recipients.collect::<SqliteResult<_>>()?, total))
+    }
+
+    /// The tenant's opens and clicks over each trailing window. Opens
+    /// leave out suspicious and premature ones, like the other totals.
+    pub async fn get_windowed_summary(&self, tenant_id: &str) -> SqliteResult<WindowedSummary> {
+        let conn = self.lock().await;
+        let now = Utc::now();
+
+        conn.query_row(
+            "SELECT
+                COUNT(CASE WHEN e.event_type = 'open' AND NOT e.suspicious AND NOT e.premature AND e.timestamp >= ?2 THEN 1 END),
+                COUNT(CASE WHEN e.event_type = 'click' AND e.timestamp >= ?2 THEN 1 END),
+                COUNT(CASE WHEN e.event_type = 'open' AND NOT e.suspicious AND NOT e.premature AND e.timestamp >= ?3 THEN 1 END),
+                COUNT(CASE WHEN e.event_type = 'click' AND e.timestamp >= ?3 THEN 1 END),
+                COUNT(CASE WHEN e.event_type = 'open' AND NOT e.suspicious AND NOT e.premature THEN 1 END),
+                COUNT(CASE WHEN e.event_type = 'click' THEN 1 END)
+             FROM events e
+             JOIN emails em ON e.email_id = em.id
+             WHERE em.tenant_id = ?1 AND e.timestamp >= ?4",
+            params![
+                tenant_id,
+                start_of_today().to_rfc3339(),
+                (now - chrono::Duration::days(7)).to_rfc3339(),
+                (now - chrono::Duration::days(30)).to_rfc3339(),
+            ],
+            |row| {
+                Ok(WindowedSummary {
+                    today: WindowCounts { opens: row.get(0)?, clicks: row.get(1)? },
+                    last_7_days: WindowCounts { opens: row.get(2)?, clicks: row.get(3)? },
+                    last_30_days: WindowCounts { opens: row.get(4)?, clicks: row.get(5)? },
+                })
+            },
+        )
+    }
+
+    /// The tenant's opens counted per UTC hour of day, ignoring suspicious
+    /// and premature ones.
+    pub async fn opens_by_hour_of_day(&self, tenant_id: &str) -> SqliteResult<[i64; 24]> {
+        Ok(hour_totals(&self.opens_by_weekday_and_hour(tenant_id).await?))
+    }
+
+    /// The tenant's opens counted per UTC weekday (Monday first) and hour,
+    /// ignoring suspicious and premature ones.
+    pub async fn opens_by_weekday_and_hour(&self, tenant_id: &str) -> SqliteResult<[[i64; 24]; 7]> {
+        let conn = self.lock().await;
+
+        // Timestamps are stored in UTC, and %w counts from Sunday
+        let mut stmt = conn.prepare(
+            "SELECT (CAST(strftime('%w', e.timestamp) AS INTEGER) + 6) % 7,
+                CAST(strftime('%H', e.timestamp) AS INTEGER), COUNT(*)
+             FROM events e
+             JOIN emails em ON e.email_id = em.id
+             WHERE em.tenant_id = ?1 AND e.event_type = 'open' AND NOT e.suspicious AND NOT e.premature
+             GROUP BY 1, 2"
+        )?;
+
+        let mut grid = [[0; 24]; 7];
+        let cells = stmt.query_map(params![tenant_id], |row| {
+            Ok((row.get::<_, usize>(0)?, row.get::<_, usize>(1)?, row.get::<_, i64>(2)?))
+        })?;
+        for cell in cells {
+            let (weekday, hour, opens) = cell?;
+            grid[weekday][hour] = opens;
+        }
+        Ok(grid)
+    }
+
+    /// Seconds between each email's creation and its first counted open,
+    /// summarised across the tenant. Emails that were never opened are left
+    /// out.
+    pub async fn get_first_open_latency(&self, tenant_id: &str) -> SqliteResult<LatencyStats> {
+        let conn = self.lock().await;
+
+        let mut stmt = conn.prepare(
+            "SELECT em.created_at, MIN(e.timestamp)
+             FROM emails em
+             JOIN events e ON e.email_id = em.id
+             WHERE em.tenant_id = ?1 AND e.event_type = 'open'
+               AND NOT e.suspicious AND NOT e.premature
+             GROUP BY em.id"
+        )?;
+
+        let latency_iter = stmt.query_map(params![tenant_id], |row| {
+            let created_at = DateTime::parse_from_rfc3339(&row.get::<_, String>(0)?)
+                .unwrap()
+                .with_timezone(&Utc);
+            let first_open = DateTime::parse_from_rfc3339(&row.get::<_, String>(1)?)
+                .unwrap()
+                .with_timezone(&Utc);
+            Ok((first_open - created_at).num_milliseconds() as f64 / 1000.0)
+        })?;
+
+        let mut latencies = Vec::new();
+        for latency in latency_iter {
+            latencies.push(latency?);
+        }
+        latencies.sort_by(|a, b| a.total_cmp(b));
+
+        Ok(LatencyStats {
+            emails_opened: latencies.len(),
+            min_seconds: latencies.first().copied(),
+            median_seconds: percentile(&latencies, 50.0),
+            p90_seconds: percentile(&latencies, 90.0),
+            max_seconds: latencies.last().copied(),
+        })
+    }
+}
+
+/// Whether `db_path` names a database that only lives as long as the
+/// process: `:memory:`, an in-memory `file:` URI, or the empty path (a
+/// temporary file SQLite deletes on close).
+pub fn is_in_memory(db_path: &str) -> bool {
+    let path = db_path.trim();
+    path.is_empty()
+        || path == ":memory:"
+        || path.starts_with("file::memory:")
+        || path.strip_prefix("file:").and_then(|uri| uri.split_once('?')).is_some_and(|(_, query)| {
+            query.split('&').any(|param| param == "mode=memory")
         })
+}
+
+/// JSON path selecting a top-level key. Callers only pass plain keys
+/// (see `is_valid_metadata_key`), quoted so dashes and dots stay literal.
+fn metadata_path(key: &str) -> String {
+    format!("$.\"{}\"", key)
+}
+
+/// Keys usable in metadata filters: letters, digits, `_`, `-` and `.`.
+pub fn is_valid_metadata_key(key: &str) -> bool {
+    !key.is_empty()
+        && key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'))
+}
+
+/// Suppressions match recipients case-insensitively.
+fn normalize_recipient(recipient: &str) -> String {
+    recipient.trim().to_lowercase()
+}
+
+/// Sums a weekday-by-hour grid into per-hour totals.
+pub fn hour_totals(grid: &[[i64; 24]; 7]) -> [i64; 24] {
+    let mut hours = [0; 24];
+    for day in grid {
+        for (total, opens) in hours.iter_mut().zip(day) {
+            *total += opens;
+        }
+    }
+    hours
+}
+
+/// Nearest-rank percentile over an already sorted slice.
+fn percentile(sorted: &[f64], pct: f64) -> Option<f64> {
+    if sorted.is_empty() {
+        return None;
+    }
+    let rank = ((pct / 100.0) * sorted.len() as f64).ceil() as usize;
+    Some(sorted[rank.clamp(1, sorted.len()) - 1])
+}
+
+/// What rates are computed over: the emails marked sent, or every email
+/// created when none are, for tenants that don't report sends. Over sent
+/// emails, only their own opens and clicks count towards the rate.
+fn rate_base(emails_created: i64, emails_sent: i64) -> i64 {
+    if emails_sent > 0 {
+        emails_sent
+    } else {
+        emails_created
     }
-}
\ No newline at end of file
+}
+
+/// Ratio of `count` to `total`, reported as 0.0 when nothing has been sent.
+fn rate(count: i64, total: i64) -> f64 {
+    if total == 0 {
+        0.0
+    } else {
+        count as f64 / total as f64
+    }
+}
@@ -0,0 +1,90 @@
+use lru::LruCache;
+use serde::Serialize;
+use std::num::NonZeroUsize;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+use crate::database::{Email, EmailRef};
+
+type Key = (String, EmailRef);
+
+/// Recently looked-up emails, so repeat pixel and click hits on the same
+/// email skip the database. Entries only leave when evicted for space or
+/// explicitly invalidated, so anything that changes or deletes an email
+/// (marking it sent, bulk deletes) must call `invalidate`. Only emails that
+/// were found are cached, which keeps new emails visible immediately.
+pub struct EmailCache {
+    /// None when caching is disabled.
+    entries: Option<Mutex<LruCache<Key, Email>>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct EmailCacheStats {
+    pub capacity: usize,
+    pub entries: usize,
+    pub hits: u64,
+    pub misses: u64,
+}
+
+impl EmailCache {
+    /// A cache holding up to `capacity` emails; 0 disables it.
+    pub fn new(capacity: usize) -> Self {
+        EmailCache {
+            entries: NonZeroUsize::new(capacity).map(|capacity| Mutex::new(LruCache::new(capacity))),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    pub fn get(&self, tenant_id: &str, email_ref: &EmailRef) -> Option<Email> {
+        let entries = self.entries.as_ref()?;
+        let found = entries
+            .lock()
+            .unwrap()
+            .get(&(tenant_id.to_string(), email_ref.clone()))
+            .cloned();
+        let counter = if found.is_some() { &self.hits } else { &self.misses };
+        counter.fetch_add(1, Ordering::Relaxed);
+        found
+    }
+
+    pub fn insert(&self, tenant_id: &str, email_ref: &EmailRef, email: &Email) {
+        if let Some(entries) = &self.entries {
+            entries
+                .lock()
+                .unwrap()
+                .put((tenant_id.to_string(), email_ref.clone()), email.clone());
+        }
+    }
+
+    /// Drops an email under every form of reference it may be cached by.
+    /// Call this whenever an email is changed or deleted.
+    pub fn invalidate(&self, email: &Email) {
+        let Some(entries) = &self.entries else {
+            return;
+        };
+        let mut entries = entries.lock().unwrap();
+        entries.pop(&(email.tenant_id.clone(), EmailRef::Id(email.id)));
+        if let Some(token) = &email.public_token {
+            entries.pop(&(email.tenant_id.clone(), EmailRef::Token(token.clone())));
+        }
+    }
+
+    pub fn stats(&self) -> EmailCacheStats {
+        let (capacity, entries) = match &self.entries {
+            Some(entries) => {
+                let entries = entries.lock().unwrap();
+                (entries.cap().get(), entries.len())
+            }
+            None => (0, 0),
+        };
+        EmailCacheStats {
+            capacity,
+            entries,
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+        }
+    }
+}
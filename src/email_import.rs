@@ -0,0 +1,228 @@
+use axum::{
+    extract::{rejection::QueryRejection, FromRequest, Multipart, Path, Query, Request, State},
+    http::{header, StatusCode},
+    response::IntoResponse,
+    Json,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::database::{NewEmail, QuotaExceeded};
+use crate::error::{AppError, AppJson};
+use crate::{daily_email_limit, AppState, CreateEmailResponse};
+
+/// Name of the multipart field holding the CSV.
+const CSV_FIELD: &str = "file";
+
+/// One recipient to create an email for, as a CSV row or a JSON array item.
+#[derive(Debug, Deserialize)]
+pub struct ImportRow {
+    pub recipient: Option<String>,
+    pub subject: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ImportQuery {
+    /// Reject the whole import if any row is bad.
+    #[serde(default)]
+    strict: bool,
+}
+
+/// A row that couldn't be imported; `row` counts data rows from 1, not
+/// including a CSV header.
+#[derive(Debug, Serialize)]
+struct RowError {
+    row: usize,
+    error: String,
+}
+
+#[derive(Serialize)]
+struct ImportedEmail {
+    row: usize,
+    #[serde(flatten)]
+    email: CreateEmailResponse,
+}
+
+/// Creates one email per recipient from a `multipart/form-data` upload with
+/// a `file` CSV field (columns `recipient,subject`) or a JSON array of
+/// `{"recipient", "subject"}` objects. All emails are created in one
+/// transaction. Bad rows are reported alongside the created emails, or with
+/// `?strict=true` reject the import.
+pub async fn import_emails(
+    Path(tenant_id): Path<String>,
+    query: Result<Query<ImportQuery>, QueryRejection>,
+    State(state): State<AppState>,
+    request: Request,
+) -> impl IntoResponse {
+    let Query(query) = match query {
+        Ok(query) => query,
+        Err(rejection) => return AppError::BadRequest(rejection.body_text()).into_response(),
+    };
+    let is_multipart = request
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("multipart/form-data"));
+
+    let rows = if is_multipart {
+        let multipart = match Multipart::from_request(request, &state).await {
+            Ok(multipart) => multipart,
+            Err(rejection) => return AppError::BadRequest(rejection.body_text()).into_response(),
+        };
+        match read_csv_field(multipart).await.and_then(|csv| parse_csv(&csv)) {
+            Ok(rows) => rows,
+            Err(e) => return e.into_response(),
+        }
+    } else {
+        match AppJson::<Vec<ImportRow>>::from_request(request, &state).await {
+            Ok(AppJson(rows)) => rows.into_iter().map(Ok).collect(),
+            Err(e) => return e.into_response(),
+        }
+    };
+    if rows.is_empty() {
+        return AppError::BadRequest("no rows to import".to_string()).into_response();
+    }
+
+    if let Err(e) = state.db.create_tenant(&tenant_id, &tenant_id).await {
+        eprintln!("Failed to create/ensure tenant: {}", e);
+        return AppError::internal().into_response();
+    }
+
+    let mut errors = Vec::new();
+    let mut accepted = Vec::new();
+    for (index, row) in rows.into_iter().enumerate() {
+        let row_number = index + 1;
+        let row = match row {
+            Ok(row) => row,
+            Err(error) => {
+                errors.push(RowError { row: row_number, error });
+                continue;
+            }
+        };
+        let Some(recipient) = non_empty(row.recipient) else {
+            errors.push(RowError {
+                row: row_number,
+                error: "recipient is required".to_string(),
+            });
+            continue;
+        };
+        if state.config.reject_suppressed_recipients {
+            match state.db.is_suppressed(&tenant_id, &recipient).await {
+                Ok(true) => {
+                    errors.push(RowError {
+                        row: row_number,
+                        error: "recipient has unsubscribed".to_string(),
+                    });
+                    continue;
+                }
+                Ok(false) => {}
+                Err(e) => {
+                    eprintln!("Database error: {}", e);
+                    return AppError::internal().into_response();
+                }
+            }
+        }
+        accepted.push((
+            row_number,
+            NewEmail {
+                subject: non_empty(row.subject),
+                recipient: Some(recipient),
+                ..NewEmail::default()
+            },
+        ));
+    }
+
+    if query.strict && !errors.is_empty() {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(serde_json::json!({
+                "error": "import rejected",
+                "code": "IMPORT_REJECTED",
+                "details": errors,
+            })),
+        )
+            .into_response();
+    }
+
+    let daily_limit = match daily_email_limit(&state, &tenant_id).await {
+        Ok(limit) => limit,
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return AppError::internal().into_response();
+        }
+    };
+
+    let (row_numbers, new_emails): (Vec<_>, Vec<_>) = accepted.into_iter().unzip();
+    let emails = match state.db.create_emails(&tenant_id, &new_emails, daily_limit).await {
+        Ok(Ok(emails)) => emails,
+        Ok(Err(QuotaExceeded { remaining })) => {
+            return AppError::QuotaExceeded(format!(
+                "import needs {} emails but only {} remain in today's quota",
+                new_emails.len(),
+                remaining
+            ))
+            .into_response();
+        }
+        Err(e) => {
+            eprintln!("Failed to import emails: {}", e);
+            return AppError::internal().into_response();
+        }
+    };
+    if !emails.is_empty() {
+        state.record_activity(&tenant_id).await;
+    }
+
+    let base_url = state.config.public_url();
+    let created: Vec<ImportedEmail> = row_numbers
+        .into_iter()
+        .zip(&emails)
+        .map(|(row, email)| ImportedEmail {
+            row,
+            email: CreateEmailResponse::for_email(&base_url, &tenant_id, email),
+        })
+        .collect();
+    (
+        StatusCode::CREATED,
+        Json(serde_json::json!({
+            "created": created,
+            "errors": errors,
+        })),
+    )
+        .into_response()
+}
+
+/// The contents of the `file` field.
+async fn read_csv_field(mut multipart: Multipart) -> Result<String, AppError> {
+    loop {
+        let field = multipart
+            .next_field()
+            .await
+            .map_err(|e| AppError::BadRequest(e.body_text()))?
+            .ok_or_else(|| AppError::BadRequest(format!("expected a '{}' field with the CSV", CSV_FIELD)))?;
+        if field.name() == Some(CSV_FIELD) {
+            return field.text().await.map_err(|e| AppError::BadRequest(e.body_text()));
+        }
+    }
+}
+
+/// Each data row of a CSV with a header line, or why it couldn't be read.
+/// Columns other than `recipient` and `subject` are ignored.
+fn parse_csv(csv: &str) -> Result<Vec<Result<ImportRow, String>>, AppError> {
+    let mut reader = csv::ReaderBuilder::new().trim(csv::Trim::All).from_reader(csv.as_bytes());
+    let has_recipient = reader
+        .headers()
+        .map_err(|e| AppError::BadRequest(format!("unreadable CSV header: {}", e)))?
+        .iter()
+        .any(|column| column == "recipient");
+    if !has_recipient {
+        return Err(AppError::BadRequest("CSV header needs a 'recipient' column".to_string()));
+    }
+
+    Ok(reader
+        .deserialize::<ImportRow>()
+        .map(|row| row.map_err(|e| e.to_string()))
+        .collect())
+}
+
+fn non_empty(value: Option<String>) -> Option<String> {
+    value.filter(|value| !value.is_empty())
+}
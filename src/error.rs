@@ -0,0 +1,117 @@
+use axum::{
+    extract::rejection::JsonRejection,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+
+/// Errors returned to API clients as `{"error": "...", "code": "..."}`, plus
+/// the `request_id` when raised while handling a request.
+#[derive(Debug)]
+pub enum AppError {
+    BadRequest(String),
+    InvalidUrl(String),
+    Unauthorized(String),
+    Forbidden(String),
+    NotFound(String),
+    /// The email doesn't exist, or belongs to another tenant.
+    EmailNotFound(String),
+    /// No route matches the request path.
+    RouteNotFound,
+    Conflict(String),
+    PayloadTooLarge(String),
+    QuotaExceeded(String),
+    /// The client is sending requests faster than it's allowed to.
+    RateLimited(String),
+    Internal(String),
+    /// The server can't take the request right now, e.g. it timed out or
+    /// a dependency is down; retrying later may succeed.
+    ServiceUnavailable(String),
+}
+
+impl AppError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            AppError::BadRequest(_) | AppError::InvalidUrl(_) => StatusCode::BAD_REQUEST,
+            AppError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            AppError::Forbidden(_) => StatusCode::FORBIDDEN,
+            AppError::NotFound(_) | AppError::EmailNotFound(_) | AppError::RouteNotFound => {
+                StatusCode::NOT_FOUND
+            }
+            AppError::Conflict(_) => StatusCode::CONFLICT,
+            AppError::PayloadTooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            AppError::QuotaExceeded(_) | AppError::RateLimited(_) => StatusCode::TOO_MANY_REQUESTS,
+            AppError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            AppError::ServiceUnavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+        }
+    }
+
+    /// Stable machine-readable identifier; clients branch on this rather
+    /// than on the message text, so existing codes must never change.
+    pub fn code(&self) -> &'static str {
+        match self {
+            AppError::BadRequest(_) => "BAD_REQUEST",
+            AppError::InvalidUrl(_) => "INVALID_URL",
+            AppError::Unauthorized(_) => "UNAUTHORIZED",
+            AppError::Forbidden(_) => "FORBIDDEN",
+            AppError::NotFound(_) => "NOT_FOUND",
+            AppError::EmailNotFound(_) => "EMAIL_NOT_FOUND",
+            AppError::RouteNotFound => "ROUTE_NOT_FOUND",
+            AppError::Conflict(_) => "CONFLICT",
+            AppError::PayloadTooLarge(_) => "PAYLOAD_TOO_LARGE",
+            AppError::QuotaExceeded(_) => "QUOTA_EXCEEDED",
+            AppError::RateLimited(_) => "RATE_LIMITED",
+            AppError::Internal(_) => "INTERNAL_ERROR",
+            AppError::ServiceUnavailable(_) => "SERVICE_UNAVAILABLE",
+        }
+    }
+
+    /// Server errors whose cause is only logged; clients get no detail.
+    pub fn internal() -> Self {
+        AppError::Internal("internal server error".to_string())
+    }
+
+    pub fn message(&self) -> String {
+        match self {
+            AppError::BadRequest(message)
+            | AppError::InvalidUrl(message)
+            | AppError::Unauthorized(message)
+            | AppError::Forbidden(message)
+            | AppError::NotFound(message)
+            | AppError::Conflict(message)
+            | AppError::PayloadTooLarge(message)
+            | AppError::QuotaExceeded(message)
+            | AppError::RateLimited(message)
+            | AppError::Internal(message)
+            | AppError::ServiceUnavailable(message) => message.clone(),
+            AppError::EmailNotFound(email_id) => format!("email {} not found", email_id),
+            AppError::RouteNotFound => "not found".to_string(),
+        }
+    }
+}
+
+impl IntoResponse for AppError {
+    fn into_response(self) -> Response {
+        let mut body = serde_json::json!({ "error": self.message(), "code": self.code() });
+        if let Some(request_id) = crate::request_id::current() {
+            body["request_id"] = request_id.into();
+        }
+        (self.status(), Json(body)).into_response()
+    }
+}
+
+impl From<JsonRejection> for AppError {
+    fn from(rejection: JsonRejection) -> Self {
+        // Bodies cut off by the size limit surface as a failure to buffer.
+        if rejection.status() == StatusCode::PAYLOAD_TOO_LARGE {
+            return AppError::PayloadTooLarge(rejection.body_text());
+        }
+        AppError::BadRequest(rejection.body_text())
+    }
+}
+
+/// `Json` extractor whose rejections use our error envelope instead of
+/// axum's plain-text responses.
+#[derive(Debug, axum::extract::FromRequest)]
+#[from_request(via(Json), rejection(AppError))]
+pub struct AppJson<T>(pub T);
@@ -0,0 +1,121 @@
+use chrono::{NaiveDate, Utc};
+use serde::Serialize;
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, Sender};
+
+use crate::database::Event;
+use crate::time_format::{self, TimeFormat};
+
+/// Mirrors every stored event to `events-YYYY-MM-DD.ndjson` files in a
+/// directory, one JSON object per line, for cheap archival. The database
+/// stays the source of truth: a failed append is logged and otherwise
+/// ignored. Files are named for the UTC day the event was stored, not its
+/// own timestamp.
+///
+/// Lines are written by a dedicated thread so requests never wait on the
+/// disk. It writes whatever has queued up and then flushes once, so a
+/// crash can lose at most the lines still waiting their turn.
+pub struct EventLog {
+    dir: PathBuf,
+    lines: Sender<QueuedLine>,
+}
+
+struct QueuedLine {
+    date: NaiveDate,
+    event_id: i64,
+    json: Vec<u8>,
+}
+
+#[derive(Serialize)]
+struct Line<'a> {
+    tenant_id: &'a str,
+    #[serde(flatten)]
+    event: &'a Event,
+}
+
+impl EventLog {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        let dir = dir.into();
+        let (sender, receiver) = mpsc::channel();
+        let writer_dir = dir.clone();
+        std::thread::Builder::new()
+            .name("event-log".to_string())
+            .spawn(move || write_lines(&writer_dir, receiver))
+            .expect("failed to start the event log writer");
+        EventLog {
+            dir,
+            lines: sender,
+        }
+    }
+
+    /// The file events stored on `date` go to.
+    pub fn path_for(&self, date: NaiveDate) -> PathBuf {
+        file_for(&self.dir, date)
+    }
+
+    /// Queues the event to be written; the write itself happens later.
+    pub fn append(&self, tenant_id: &str, event: &Event) {
+        // RFC 3339 whatever the responses use, since this may run inside a
+        // request
+        let json = match time_format::with(TimeFormat::Rfc3339, || {
+            serde_json::to_vec(&Line { tenant_id, event })
+        }) {
+            Ok(json) => json,
+            Err(e) => {
+                eprintln!("Warning: failed to serialize event {} for the event log: {}", event.id, e);
+                return;
+            }
+        };
+        let line = QueuedLine {
+            date: Utc::now().date_naive(),
+            event_id: event.id,
+            json,
+        };
+        if self.lines.send(line).is_err() {
+            eprintln!("Warning: event log writer has stopped; event {} not appended", event.id);
+        }
+    }
+}
+
+fn file_for(dir: &Path, date: NaiveDate) -> PathBuf {
+    dir.join(format!("events-{}.ndjson", date.format("%Y-%m-%d")))
+}
+
+/// Runs until every `EventLog` sender is gone, writing lines as they come.
+fn write_lines(dir: &Path, receiver: Receiver<QueuedLine>) {
+    let mut current: Option<(NaiveDate, BufWriter<File>)> = None;
+    while let Ok(first) = receiver.recv() {
+        for line in std::iter::once(first).chain(receiver.try_iter()) {
+            if let Err(e) = write_line(dir, &mut current, &line) {
+                eprintln!("Warning: failed to append event {} to {}: {}", line.event_id, dir.display(), e);
+            }
+        }
+        if let Some((date, writer)) = current.as_mut() {
+            if let Err(e) = writer.flush() {
+                eprintln!("Warning: failed to flush {}: {}", file_for(dir, *date).display(), e);
+            }
+        }
+    }
+}
+
+fn write_line(
+    dir: &Path,
+    current: &mut Option<(NaiveDate, BufWriter<File>)>,
+    line: &QueuedLine,
+) -> std::io::Result<()> {
+    if current.as_ref().map(|(date, _)| *date) != Some(line.date) {
+        // Whatever the old file still buffers goes out before it's dropped
+        if let Some((_, mut old)) = current.take() {
+            old.flush()?;
+        }
+        std::fs::create_dir_all(dir)?;
+        let file = OpenOptions::new().create(true).append(true).open(file_for(dir, line.date))?;
+        *current = Some((line.date, BufWriter::new(file)));
+    }
+
+    let (_, writer) = current.as_mut().expect("opened above");
+    writer.write_all(&line.json)?;
+    writer.write_all(b"\n")
+}
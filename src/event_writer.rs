@@ -0,0 +1,190 @@
+use serde::Serialize;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot};
+use tokio::time::Instant;
+
+use crate::database::NewEvent;
+use crate::AppState;
+
+/// Most events written in one transaction.
+pub const BATCH_SIZE: usize = 100;
+/// Longest a queued event waits for its batch to fill.
+pub const BATCH_WINDOW: Duration = Duration::from_millis(100);
+
+enum Message {
+    Event(String, NewEvent),
+    /// Write everything queued so far, then stop.
+    Close(oneshot::Sender<()>),
+}
+
+/// Hands tracking events to a background task that writes them in batches,
+/// so pixel and click requests don't wait on the database. Disabled (and
+/// every event written inline) when `Config.event_buffer_size` is 0.
+#[derive(Clone)]
+pub struct EventWriter {
+    tx: Option<mpsc::Sender<Message>>,
+    /// Deepest the queue has been since startup.
+    high_water: Arc<AtomicUsize>,
+    /// Queue depth past which the writer reports itself degraded.
+    degraded_at: usize,
+    /// Events whose write failed even on their own.
+    dropped: Arc<AtomicU64>,
+}
+
+/// How far the writer has fallen behind, for `/ready` and `/metrics`.
+#[derive(Debug, Clone, Serialize)]
+pub struct Backlog {
+    pub queued: usize,
+    pub capacity: usize,
+    pub high_water: usize,
+    pub threshold: usize,
+    /// More than `threshold` events queued; hits will soon wait for room.
+    pub degraded: bool,
+    /// Events lost since startup because they couldn't be written.
+    pub dropped: u64,
+}
+
+/// The receiving end, for `spawn` once the `AppState` holding the writer
+/// exists.
+pub struct EventReceiver(mpsc::Receiver<Message>);
+
+impl EventWriter {
+    /// A writer queueing up to `capacity` events, or a disabled one for 0.
+    /// It reports itself degraded with more than `degraded_at` queued.
+    pub fn channel(capacity: usize, degraded_at: usize) -> (Self, Option<EventReceiver>) {
+        let high_water = Arc::new(AtomicUsize::new(0));
+        let dropped = Arc::new(AtomicU64::new(0));
+        if capacity == 0 {
+            let writer = EventWriter {
+                tx: None,
+                high_water,
+                degraded_at,
+                dropped,
+            };
+            return (writer, None);
+        }
+        let (tx, rx) = mpsc::channel(capacity);
+        let writer = EventWriter {
+            tx: Some(tx),
+            high_water,
+            degraded_at,
+            dropped,
+        };
+        (writer, Some(EventReceiver(rx)))
+    }
+
+    /// The current queue depth, or `None` when the writer is disabled.
+    pub fn backlog(&self) -> Option<Backlog> {
+        let tx = self.tx.as_ref()?;
+        let queued = tx.max_capacity() - tx.capacity();
+        Some(Backlog {
+            queued,
+            capacity: tx.max_capacity(),
+            high_water: self.high_water.load(Ordering::Relaxed).max(queued),
+            threshold: self.degraded_at,
+            degraded: queued > self.degraded_at,
+            dropped: self.dropped.load(Ordering::Relaxed),
+        })
+    }
+
+    /// Queues an event, waiting for room when the queue is full. Gives the
+    /// event back when the writer is disabled or closed, for the caller to
+    /// write itself.
+    pub async fn queue(&self, tenant_id: &str, event: NewEvent) -> Result<(), NewEvent> {
+        let Some(tx) = &self.tx else {
+            return Err(event);
+        };
+        tx.send(Message::Event(tenant_id.to_string(), event))
+            .await
+            .map_err(|mpsc::error::SendError(message)| match message {
+                Message::Event(_, event) => event,
+                Message::Close(_) => unreachable!("only events are sent here"),
+            })?;
+        let queued = tx.max_capacity() - tx.capacity();
+        self.high_water.fetch_max(queued, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Writes everything queued and stops the background task. Events
+    /// queued afterwards are written inline.
+    pub async fn close(&self) {
+        let Some(tx) = &self.tx else {
+            return;
+        };
+        let (done, closed) = oneshot::channel();
+        if tx.send(Message::Close(done)).await.is_ok() {
+            let _ = closed.await;
+        }
+    }
+}
+
+/// Starts the background task writing what `receiver` gets, publishing
+/// each event once it's stored.
+pub fn spawn(state: AppState, receiver: EventReceiver) {
+    tokio::spawn(run(state, receiver.0));
+}
+
+async fn run(state: AppState, mut rx: mpsc::Receiver<Message>) {
+    let mut batch = Vec::with_capacity(BATCH_SIZE);
+    while let Some(message) = rx.recv().await {
+        let mut closing = None;
+        match message {
+            Message::Event(tenant_id, event) => batch.push((tenant_id, event)),
+            Message::Close(done) => closing = Some(done),
+        }
+
+        let deadline = Instant::now() + BATCH_WINDOW;
+        while closing.is_none() && batch.len() < BATCH_SIZE {
+            match tokio::time::timeout_at(deadline, rx.recv()).await {
+                Ok(Some(Message::Event(tenant_id, event))) => batch.push((tenant_id, event)),
+                Ok(Some(Message::Close(done))) => closing = Some(done),
+                Ok(None) | Err(_) => break,
+            }
+        }
+        write_batch(&state, &mut batch).await;
+
+        if let Some(done) = closing {
+            // Anything that slipped in behind the close still gets written
+            rx.close();
+            while let Some(message) = rx.recv().await {
+                if let Message::Event(tenant_id, event) = message {
+                    batch.push((tenant_id, event));
+                }
+            }
+            write_batch(&state, &mut batch).await;
+            let _ = done.send(());
+            return;
+        }
+    }
+}
+
+/// Writes the batch in one transaction. If that fails, each event is
+/// retried on its own so one bad row doesn't lose the rest; those that
+/// still fail are dropped and counted.
+async fn write_batch(state: &AppState, batch: &mut Vec<(String, NewEvent)>) {
+    if batch.is_empty() {
+        return;
+    }
+    let (tenants, events): (Vec<_>, Vec<_>) = std::mem::take(batch).into_iter().unzip();
+    match state.db.log_events_batch(&events).await {
+        Ok(logged) => {
+            for (tenant_id, event) in tenants.iter().zip(logged) {
+                state.publish_event(tenant_id, event).await;
+            }
+        }
+        Err(e) => {
+            eprintln!("Failed to write {} queued events, retrying one at a time: {}", events.len(), e);
+            for (tenant_id, event) in tenants.iter().zip(&events) {
+                match state.db.log_new_event(event).await {
+                    Ok(logged) => state.publish_event(tenant_id, logged).await,
+                    Err(e) => {
+                        state.event_writer.dropped.fetch_add(1, Ordering::Relaxed);
+                        eprintln!("Dropping queued {} event for email {}: {}", event.event_type, event.email_id, e);
+                    }
+                }
+            }
+        }
+    }
+}
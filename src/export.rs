@@ -0,0 +1,252 @@
+use axum::{
+    body::Body,
+    extract::{rejection::QueryRejection, ConnectInfo, Path, Query, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::net::SocketAddr;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+
+use crate::database::{Email, Event, Tenant, TenantSettings};
+use crate::error::{AppError, AppJson};
+use crate::time_format::{self, TimeFormat};
+use crate::{admin, AppState};
+
+/// Identifies export documents so an import can refuse anything else.
+pub const EXPORT_FORMAT: &str = "little-bell-export";
+pub const EXPORT_VERSION: i64 = 1;
+
+/// Rows read per query while exporting.
+const EXPORT_PAGE: i64 = 500;
+
+type Chunk = Result<String, std::io::Error>;
+
+/// Streams everything stored for a tenant as one JSON document:
+/// `{"format", "version", "exported_at", "tenant", "settings", "emails": [...], "events": [...]}`.
+///
+/// Emails and events are read a page at a time and written as they arrive,
+/// so large tenants don't have to fit in memory.
+pub async fn export_tenant(
+    Path(tenant_id): Path<String>,
+    headers: HeaderMap,
+    peer: Option<ConnectInfo<SocketAddr>>,
+    State(state): State<AppState>,
+) -> impl IntoResponse {
+    let tenant = match state.db.get_tenant(&tenant_id).await {
+        Ok(Some(tenant)) => tenant,
+        Ok(None) => return AppError::NotFound(format!("tenant '{}' not found", tenant_id)).into_response(),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return AppError::internal().into_response();
+        }
+    };
+    let settings = match state.db.get_tenant_settings(&tenant_id).await {
+        Ok(settings) => settings,
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return AppError::internal().into_response();
+        }
+    };
+
+    let actor = admin::actor(&state, &headers, peer);
+    state
+        .record_audit(&actor, "tenant.export", &tenant_id, serde_json::Value::Null)
+        .await;
+
+    // Exports are always RFC 3339, like the rows streamed below, whatever
+    // the configured response format
+    let header = time_format::with(TimeFormat::Rfc3339, || {
+        serde_json::json!({
+            "format": EXPORT_FORMAT,
+            "version": EXPORT_VERSION,
+            "exported_at": chrono::Utc::now(),
+            "tenant": tenant,
+            "settings": settings,
+        })
+        .to_string()
+    });
+
+    let (tx, rx) = mpsc::channel::<Chunk>(4);
+    tokio::spawn(async move {
+        // The object is written by hand: everything up to the closing brace
+        // of the header, then the two arrays.
+        let opening = format!("{},\"emails\":[", header.strip_suffix('}').unwrap_or(&header));
+        if tx.send(Ok(opening)).await.is_err() {
+            return;
+        }
+
+        let db = state.db.clone();
+        let emails = stream_pages(&tx, |after| {
+            let db = db.clone();
+            let tenant_id = tenant_id.clone();
+            async move {
+                let emails = db.emails_after(&tenant_id, after, EXPORT_PAGE).await?;
+                Ok(emails.into_iter().map(|email| (email.id, email)).collect())
+            }
+        })
+        .await;
+        if emails.is_err() || tx.send(Ok("],\"events\":[".to_string())).await.is_err() {
+            return;
+        }
+
+        let events = stream_pages(&tx, |after| {
+            let db = db.clone();
+            let tenant_id = tenant_id.clone();
+            async move {
+                let events = db.events_after(&tenant_id, after, EXPORT_PAGE).await?;
+                Ok(events.into_iter().map(|event| (event.id, event)).collect())
+            }
+        })
+        .await;
+        if events.is_ok() {
+            let _ = tx.send(Ok("]}".to_string())).await;
+        }
+    });
+
+    Response::builder()
+        .header(header::CONTENT_TYPE, "application/json")
+        .header(
+            header::CONTENT_DISPOSITION,
+            format!("attachment; filename=\"{}-export.json\"", tenant.id),
+        )
+        .body(Body::from_stream(ReceiverStream::new(rx)))
+        .unwrap()
+        .into_response()
+}
+
+/// Sends comma-separated JSON rows from `fetch` until it returns an empty
+/// page. `fetch` gets the last id seen and returns `(id, row)` pairs.
+///
+/// Errors (a failed query, or the client going away) end the stream early; a
+/// failed query is passed on so the response is cut off rather than
+/// silently incomplete.
+async fn stream_pages<T, F, Fut>(tx: &mpsc::Sender<Chunk>, mut fetch: F) -> Result<(), ()>
+where
+    T: Serialize,
+    F: FnMut(i64) -> Fut,
+    Fut: std::future::Future<Output = rusqlite::Result<Vec<(i64, T)>>>,
+{
+    let mut after = 0;
+    let mut first = true;
+    loop {
+        let page = match fetch(after).await {
+            Ok(page) => page,
+            Err(e) => {
+                eprintln!("Export failed: {}", e);
+                let _ = tx.send(Err(std::io::Error::other(e))).await;
+                return Err(());
+            }
+        };
+        let Some((last_id, _)) = page.last() else {
+            return Ok(());
+        };
+        after = *last_id;
+
+        let mut chunk = String::new();
+        for (_, row) in &page {
+            if !first {
+                chunk.push(',');
+            }
+            first = false;
+            chunk.push_str(&serde_json::to_string(row).unwrap_or_default());
+        }
+        if tx.send(Ok(chunk)).await.is_err() {
+            return Err(());
+        }
+    }
+}
+
+/// The document written by `export_tenant`.
+#[derive(Debug, Deserialize)]
+pub struct ExportDocument {
+    pub format: String,
+    pub version: i64,
+    pub tenant: Option<Tenant>,
+    pub settings: Option<TenantSettings>,
+    #[serde(default)]
+    pub emails: Vec<Email>,
+    #[serde(default)]
+    pub events: Vec<Event>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ImportQuery {
+    #[serde(default)]
+    merge: bool,
+}
+
+/// Loads an export document into the tenant named in the path, which need
+/// not match the exported one. Refuses tenants that already have emails
+/// unless `?merge=true`.
+pub async fn import_tenant(
+    Path(tenant_id): Path<String>,
+    query: Result<Query<ImportQuery>, QueryRejection>,
+    headers: HeaderMap,
+    peer: Option<ConnectInfo<SocketAddr>>,
+    State(state): State<AppState>,
+    AppJson(document): AppJson<ExportDocument>,
+) -> impl IntoResponse {
+    let Query(query) = match query {
+        Ok(query) => query,
+        Err(rejection) => return AppError::BadRequest(rejection.body_text()).into_response(),
+    };
+    if document.format != EXPORT_FORMAT || document.version != EXPORT_VERSION {
+        return AppError::BadRequest(format!(
+            "expected a {} document, version {}",
+            EXPORT_FORMAT, EXPORT_VERSION
+        ))
+        .into_response();
+    }
+
+    let email_ids: HashSet<i64> = document.emails.iter().map(|email| email.id).collect();
+    if let Some(orphan) = document.events.iter().find(|event| !email_ids.contains(&event.email_id)) {
+        return AppError::BadRequest(format!(
+            "event {} references email {}, which isn't in the document",
+            orphan.id, orphan.email_id
+        ))
+        .into_response();
+    }
+
+    let tenant_name = document
+        .tenant
+        .as_ref()
+        .map(|tenant| tenant.name.as_str())
+        .unwrap_or(&tenant_id);
+    let result = state
+        .db
+        .import_tenant(
+            &tenant_id,
+            tenant_name,
+            document.settings.as_ref(),
+            &document.emails,
+            &document.events,
+            query.merge,
+        )
+        .await;
+
+    match result {
+        Ok(Some(summary)) => {
+            // Imported events skip `publish_event`, so the buffer and sketch
+            // would miss them
+            state.recent_events.clear(&tenant_id);
+            if let Some(unique_opens) = &state.unique_opens {
+                unique_opens.forget(&tenant_id);
+            }
+            state.pixel_cache_modes.invalidate(&tenant_id);
+            let actor = admin::actor(&state, &headers, peer);
+            let details = serde_json::to_value(&summary).unwrap_or_default();
+            state.record_audit(&actor, "tenant.import", &tenant_id, details).await;
+            (StatusCode::CREATED, Json(summary)).into_response()
+        }
+        Ok(None) => AppError::Conflict("tenant already has data; pass merge=true to add to it".to_string())
+            .into_response(),
+        Err(e) => {
+            eprintln!("Failed to import tenant: {}", e);
+            AppError::internal().into_response()
+        }
+    }
+}
@@ -0,0 +1,45 @@
+use axum::{
+    extract::{Request, State},
+    http::{header, HeaderMap, HeaderValue},
+    middleware::Next,
+    response::Response,
+};
+
+use crate::AppState;
+
+/// Whether the request reached our outermost proxy over HTTPS, going by the
+/// `X-Forwarded-Proto` our own proxies add. Without trusted proxies the
+/// header could come from anyone, and the server itself only speaks plain
+/// HTTP, so it's never taken as HTTPS.
+fn forwarded_https(headers: &HeaderMap, trusted_proxy_hops: usize) -> bool {
+    if trusted_proxy_hops == 0 {
+        return false;
+    }
+    let Some(proto) = headers
+        .get("x-forwarded-proto")
+        .and_then(|value| value.to_str().ok())
+    else {
+        return false;
+    };
+    // Proxies that append rather than overwrite leave the client's own value
+    // on the left, so take the one our outermost proxy wrote
+    let protos: Vec<&str> = proto.split(',').map(str::trim).collect();
+    let outermost = protos.len().saturating_sub(trusted_proxy_hops);
+    protos[outermost].eq_ignore_ascii_case("https")
+}
+
+/// Adds `Strict-Transport-Security` to responses for requests that came in
+/// over HTTPS, when `Config.hsts_max_age_secs` is set. Browsers ignore the
+/// header on plain HTTP, so it's never sent there.
+pub async fn add_header(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    let max_age = state
+        .config
+        .hsts_max_age_secs
+        .filter(|_| forwarded_https(request.headers(), state.config.trusted_proxy_hops));
+    let mut response = next.run(request).await;
+    if let Some(max_age) = max_age {
+        let value = HeaderValue::from_str(&format!("max-age={}", max_age)).expect("digits are a valid header value");
+        response.headers_mut().insert(header::STRICT_TRANSPORT_SECURITY, value);
+    }
+    response
+}
@@ -1,284 +1,2772 @@
 use askama::Template;
 use axum::{
-    extract::{Path, Query, State},
-    http::{HeaderMap, StatusCode},
-    response::{Html, IntoResponse, Redirect, Response},
-    routing::{get, post},
+    extract::{
+        ws::{close_code, CloseFrame, Message, WebSocket, WebSocketUpgrade},
+        rejection::{PathRejection, QueryRejection},
+        ConnectInfo, DefaultBodyLimit, Path, Query, State,
+    },
+    http::{header, HeaderMap, HeaderName, HeaderValue, Method, StatusCode},
+    response::{
+        sse::{self, KeepAlive, Sse},
+        Html, IntoResponse, Redirect, Response,
+    },
+    routing::{delete, get, patch, post, put},
     Json, Router,
 };
 use serde::{Deserialize, Serialize};
+use chrono::DateTime;
+use ipnet::IpNet;
 use std::collections::HashMap;
+use std::convert::Infallible;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
-use tower_http::compression::CompressionLayer;
+use std::time::Duration;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio_stream::{wrappers::BroadcastStream, Stream, StreamExt};
+use tower::ServiceExt;
+use tower_http::compression::{
+    predicate::{DefaultPredicate, NotForContentType, Predicate},
+    CompressionLayer,
+};
+use tower_http::cors::{AllowOrigin, CorsLayer};
+use tower_http::limit::RequestBodyLimitLayer;
 
+pub mod activity;
+pub mod admin;
+pub mod assets;
 pub mod database;
-use database::{Database, EventStats};
+pub mod email_cache;
+pub mod email_import;
+pub mod error;
+pub mod event_log;
+pub mod event_writer;
+pub mod export;
+pub mod hsts;
+pub mod open_limiter;
+pub mod pixel_cache_modes;
+pub mod privacy;
+pub mod recent_events;
+pub mod request_id;
+pub mod request_timeout;
+pub mod route_metrics;
+pub mod stats_format;
+pub mod time_format;
+pub mod unique_opens;
+pub mod webhooks;
+use database::{
+    CreatedEmail, Database, DatabaseOptions, DbMetrics, Email, EmailEngagement, EmailRef, Event, EventFilter, EventStats, NewEmail,
+    HourlyHeatmap, NewEvent, PixelCacheMode, RecipientSort, SortDirection, StatsFilter, TenantSettings,
+    TenantSettingsPatch,
+};
+use error::{AppError, AppJson};
+
+/// How many live events can queue up for a slow subscriber before it starts
+/// missing some.
+const LIVE_EVENT_CAPACITY: usize = 256;
+
+/// Minimum gap between live stats pushes over the WebSocket.
+const LIVE_STATS_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Max-age used for the pixel when a tenant opts into `cache_short`.
+const PIXEL_SHORT_CACHE_SECONDS: u32 = 300;
+
+/// IMF-fixdate, the format of `Last-Modified` and `If-Modified-Since`.
+const HTTP_DATE_FORMAT: &str = "%a, %d %b %Y %H:%M:%S GMT";
+
+/// Cookie identifying a client across opens when `set_client_cookie` is on.
+pub const CLIENT_COOKIE: &str = "lb_uid";
+/// Two years.
+const CLIENT_COOKIE_MAX_AGE: u64 = 2 * 365 * 24 * 60 * 60;
+
+const DEFAULT_EMAIL_PAGE: i64 = 100;
+const MAX_EMAIL_PAGE: i64 = 1000;
+
+const DEFAULT_EMAIL_EVENT_PAGE: i64 = 100;
+const MAX_EMAIL_EVENT_PAGE: i64 = 1000;
+
+const DEFAULT_RECIPIENT_PAGE: i64 = 100;
+const MAX_RECIPIENT_PAGE: i64 = 1000;
+
+const DEFAULT_TOP_LINKS: i64 = 10;
+const MAX_TOP_LINKS: i64 = 100;
+
+/// Shows whether a secret is set without revealing it.
+fn redact_secret<S: serde::Serializer>(secret: &Option<String>, serializer: S) -> Result<S::Ok, S::Error> {
+    match secret {
+        Some(_) => serializer.serialize_str("[redacted]"),
+        None => serializer.serialize_none(),
+    }
+}
+
+/// Serialized (by `GET /admin/config`) field for field, except that secrets
+/// are marked `#[serde(serialize_with = "redact_secret")]`.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct Config {
+    #[serde(default = "default_port")]
+    pub port: u16,
+    #[serde(default = "default_database_url")]
+    pub database_url: String,
+    /// Start even though `database_url` is in memory, losing everything on
+    /// restart. Off by default, since that's almost always a mistake.
+    #[serde(default)]
+    pub allow_in_memory: bool,
+    #[serde(default = "default_base_url")]
+    pub base_url: String,
+    /// Path all routes are mounted under, e.g. `/bell` when served from a
+    /// subdirectory behind a reverse proxy. Empty mounts at the root.
+    #[serde(default)]
+    pub path_prefix: String,
+    /// Tenant that tenant-less `/pixel/:email_id`, `/click/:email_id`,
+    /// `/emails` and `/dashboard` routes stand for, for single-tenant
+    /// deployments. While set, no tenant may be named `pixel`, `click`,
+    /// `emails` or `dashboard`. Only the tenant-scoped routes exist when
+    /// unset.
+    pub default_tenant: Option<String>,
+    /// Comma-separated list of origins allowed to call the API, or `*` for any.
+    /// Startup fails if none of the listed origins is valid.
+    #[serde(default = "default_cors_allowed_origins")]
+    pub cors_allowed_origins: String,
+    /// Number of our own proxies in front of the server, each appending the
+    /// address it was connected from to `X-Forwarded-For`.
+    #[serde(default)]
+    pub trusted_proxy_hops: usize,
+    /// `max-age` of the `Strict-Transport-Security` header sent on requests
+    /// our proxies received over HTTPS (per `X-Forwarded-Proto`). Unset (the
+    /// default) sends none.
+    pub hsts_max_age_secs: Option<u64>,
+    /// How tenant, email and event timestamps appear in JSON responses:
+    /// `rfc3339` (the default) or `epoch_millis`.
+    #[serde(default)]
+    pub time_format: time_format::TimeFormat,
+    /// Comma-separated headers carrying the client address, in the order
+    /// they're tried, e.g. `cf-connecting-ip` behind Cloudflare. Each is read
+    /// like `X-Forwarded-For`, counting `trusted_proxy_hops` from the right.
+    #[serde(default = "default_client_ip_header")]
+    pub client_ip_header: String,
+    /// Largest request body accepted by the write endpoints.
+    #[serde(default = "default_max_body_bytes")]
+    pub max_body_bytes: usize,
+    /// Largest request body accepted by the batch event endpoint.
+    #[serde(default = "default_max_batch_body_bytes")]
+    pub max_batch_body_bytes: usize,
+    /// Largest export document accepted by the tenant import endpoint.
+    #[serde(default = "default_max_import_body_bytes")]
+    pub max_import_body_bytes: usize,
+    /// SQLite `synchronous` level; use FULL where durability matters more
+    /// than write throughput.
+    #[serde(default = "default_sqlite_synchronous")]
+    pub sqlite_synchronous: String,
+    /// How long SQLite waits on a lock held by another connection, in ms.
+    #[serde(default = "default_sqlite_busy_timeout_ms")]
+    pub sqlite_busy_timeout_ms: u64,
+    /// Further attempts at a write that still finds the database busy.
+    #[serde(default = "default_sqlite_busy_retries")]
+    pub sqlite_busy_retries: u32,
+    /// Tokio worker threads; 0 uses one per CPU core.
+    #[serde(default)]
+    pub worker_threads: usize,
+    /// Cap on tokio's blocking thread pool; 0 keeps tokio's default (512).
+    #[serde(default)]
+    pub max_blocking_threads: usize,
+    /// Key required by the `/admin` routes; they're disabled when unset.
+    #[serde(serialize_with = "redact_secret")]
+    pub admin_key: Option<String>,
+    /// Comma-separated CIDRs (or single addresses) allowed to reach
+    /// `/metrics` and `/admin`. Everyone is allowed when empty.
+    #[serde(default)]
+    pub admin_ip_allowlist: String,
+    /// Directory `POST /admin/backup` and scheduled backups write to.
+    #[serde(default = "default_backup_dir")]
+    pub backup_dir: String,
+    /// Take a backup this often, in seconds. No scheduled backups when unset.
+    pub backup_interval_secs: Option<u64>,
+    /// Record settings changes, deletions and backups in the audit log.
+    #[serde(default = "default_audit_log_enabled")]
+    pub audit_log_enabled: bool,
+    /// Emails kept in the lookup cache used by tracking hits; 0 disables it.
+    #[serde(default = "default_email_cache_size")]
+    pub email_cache_size: usize,
+    /// Opens and clicks queued for the batched background writer. 0 (the
+    /// default) writes each one inline before the response.
+    #[serde(default)]
+    pub event_buffer_size: usize,
+    /// Queued events past which the batched writer counts as degraded and
+    /// `/ready` answers 503. Defaults to 90% of `event_buffer_size`.
+    pub event_backlog_threshold: Option<usize>,
+    /// Longest shutdown waits for the batched writer to store what it still
+    /// holds, in seconds. Events not written by then are lost.
+    #[serde(default = "default_shutdown_timeout_secs")]
+    pub shutdown_timeout_secs: u64,
+    /// Longest a request may take to produce its response, in seconds. Past
+    /// it the client gets a 503, or for the tracking pixels the image with
+    /// the open left unrecorded. Unset (the default) never times out.
+    pub request_timeout_secs: Option<u64>,
+    /// Directory to also append every stored event to, as daily
+    /// `events-YYYY-MM-DD.ndjson` files. Unset (the default) disables it.
+    pub event_log_path: Option<String>,
+    /// Tenants whose latest events are kept in memory for the dashboard, so
+    /// it can list them without a query. 0 (the default) disables it.
+    #[serde(default)]
+    pub recent_events_cache_size: usize,
+    /// Estimate big tenants' `unique_opens` from a HyperLogLog sketch kept
+    /// in memory (and saved every minute) instead of counting distinct
+    /// opens on every `GET /:tenant_id/stats`. Off by default.
+    #[serde(default)]
+    pub unique_open_sketch: bool,
+    /// Tenants with fewer emails than this are still counted exactly.
+    #[serde(default = "default_unique_open_sketch_min_emails")]
+    pub unique_open_sketch_min_emails: i64,
+    /// Longest target URL `get_click_url` will wrap; longer ones get
+    /// truncated by some mail clients.
+    #[serde(default = "default_max_click_url_len")]
+    pub max_click_url_len: usize,
+    /// How long a webhook receiver gets to answer each attempt, in seconds.
+    #[serde(default = "default_webhook_timeout_secs")]
+    pub webhook_timeout_secs: u64,
+    /// Attempts per webhook delivery, including the first.
+    #[serde(default = "default_webhook_max_attempts")]
+    pub webhook_max_attempts: u32,
+    /// Delay before the first webhook retry; it doubles with each attempt,
+    /// less up to half at random.
+    #[serde(default = "default_webhook_retry_base_ms")]
+    pub webhook_retry_base_ms: u64,
+    /// Failed webhook attempts in a row after which a tenant's deliveries
+    /// are paused.
+    #[serde(default = "default_webhook_circuit_threshold")]
+    pub webhook_circuit_threshold: u32,
+    /// How long a tenant's webhook deliveries stay paused, in seconds.
+    #[serde(default = "default_webhook_circuit_cooldown_secs")]
+    pub webhook_circuit_cooldown_secs: u64,
+    /// Deliveries held per tenant while paused; more are dropped.
+    #[serde(default = "default_webhook_queue_size")]
+    pub webhook_queue_size: usize,
+    /// Lets webhooks reach loopback, link-local and private addresses, for
+    /// receivers on the same network. Off by default so webhooks can't be
+    /// used to probe internal services.
+    #[serde(default)]
+    pub webhook_allow_private_networks: bool,
+    /// Where to send people after they unsubscribe. A plain confirmation
+    /// page is shown when unset.
+    pub unsubscribe_redirect_url: Option<String>,
+    /// Mask recipients and truncate subjects wherever they're logged or
+    /// rendered. Stored data is unaffected.
+    #[serde(default)]
+    pub mask_pii: bool,
+    /// Refuse to create emails for recipients on the suppression list.
+    #[serde(default)]
+    pub reject_suppressed_recipients: bool,
+    /// Where clicks go when their `url` is missing or invalid. Without it
+    /// those clicks get a 400.
+    pub click_fallback_url: Option<String>,
+    /// Comma-separated URL schemes clicks may redirect to and click URLs
+    /// may wrap. Anything else (`javascript:`, `data:`) is refused.
+    #[serde(default = "default_allowed_redirect_schemes")]
+    pub allowed_redirect_schemes: String,
+    /// Fraction of pixel hits (0.0-1.0) that get an access log line. Every
+    /// open is still recorded; this only thins out the log.
+    #[serde(default = "default_open_log_sample_rate")]
+    pub open_log_sample_rate: f64,
+    /// Send `Last-Modified` with the pixel and answer `If-Modified-Since`
+    /// with 304 (the open is still recorded). Off by default since it lets
+    /// clients and proxies cache the pixel.
+    #[serde(default)]
+    pub pixel_conditional_requests: bool,
+    /// Give pixel clients a long-lived `lb_uid` cookie and record it with
+    /// each open, so uniques can be counted per client. Off by default for
+    /// privacy; many mail clients drop cookies anyway.
+    #[serde(default)]
+    pub set_client_cookie: bool,
+    /// Opens counted per email. Pixel hits past it are still recorded but
+    /// flagged suspicious (e.g. a scanner or proxy refetching the image) and
+    /// left out of open stats. Unlimited when unset.
+    pub max_opens_per_email: Option<i64>,
+    /// Seconds after an email is created during which its opens are taken
+    /// for preflight checks or spam filters rather than people: recorded,
+    /// but flagged premature and left out of open stats. 0 (the default)
+    /// turns this off.
+    #[serde(default)]
+    pub open_grace_secs: i64,
+    /// Opens an email's pixel may record per `open_rate_window_secs` before
+    /// it cools down for `open_rate_cooldown_secs`: the pixel is still
+    /// served, but its opens aren't recorded. Unlimited when unset.
+    pub open_rate_limit: Option<u32>,
+    #[serde(default = "default_open_rate_window_secs")]
+    pub open_rate_window_secs: u64,
+    #[serde(default = "default_open_rate_cooldown_secs")]
+    pub open_rate_cooldown_secs: u64,
+    /// Apply `open_rate_limit` per client IP and email rather than per
+    /// email, so one scanner can't stop a real recipient's open counting.
+    #[serde(default)]
+    pub open_rate_limit_per_ip: bool,
+    /// Status used for click redirects.
+    #[serde(default)]
+    pub redirect_status: RedirectStatus,
+    /// Comma-separated event types accepted by the ingestion API, e.g.
+    /// `open,click,forward,print`. The built-in tracking types are always
+    /// accepted.
+    #[serde(default = "default_allowed_event_types")]
+    pub allowed_event_types: String,
+    /// How far past the server clock an ingested event's `timestamp` may be.
+    #[serde(default = "default_event_clock_skew_secs")]
+    pub event_clock_skew_secs: i64,
+    /// Oldest ingested `timestamp` accepted, in days before now.
+    #[serde(default = "default_max_event_age_days")]
+    pub max_event_age_days: i64,
+    /// Emails a tenant may create per UTC day, unless the tenant's settings
+    /// say otherwise. Unlimited when unset.
+    pub default_daily_email_limit: Option<i64>,
+}
+
+/// How clicks are redirected. Browsers cache permanent redirects and skip
+/// us on repeat clicks, so `Permanent` records fewer clicks.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RedirectStatus {
+    /// 307 Temporary Redirect
+    #[default]
+    Temporary,
+    /// 302 Found
+    Found,
+    /// 301 Moved Permanently
+    Permanent,
+}
+
+impl RedirectStatus {
+    pub fn redirect(self, url: &str) -> Response {
+        let status = match self {
+            RedirectStatus::Temporary => return Redirect::temporary(url).into_response(),
+            // axum has no constructors for 302 or 301
+            RedirectStatus::Found => StatusCode::FOUND,
+            RedirectStatus::Permanent => StatusCode::MOVED_PERMANENTLY,
+        };
+        match HeaderValue::from_str(url) {
+            Ok(location) => (status, [(header::LOCATION, location)]).into_response(),
+            Err(_) => AppError::BadRequest("redirect target is not a valid header value".to_string()).into_response(),
+        }
+    }
+}
+
+fn default_port() -> u16 {
+    3000
+}
+
+fn default_database_url() -> String {
+    "sqlite:data/tracking.db".to_string()
+}
+
+fn default_base_url() -> String {
+    "http://localhost:3000".to_string()
+}
+
+fn default_cors_allowed_origins() -> String {
+    "*".to_string()
+}
+
+fn default_open_log_sample_rate() -> f64 {
+    1.0
+}
+
+fn default_unique_open_sketch_min_emails() -> i64 {
+    100_000
+}
+
+fn default_open_rate_window_secs() -> u64 {
+    10
+}
+
+fn default_open_rate_cooldown_secs() -> u64 {
+    60
+}
+
+fn default_audit_log_enabled() -> bool {
+    true
+}
+
+fn default_client_ip_header() -> String {
+    DEFAULT_CLIENT_IP_HEADERS.join(",")
+}
+
+fn default_shutdown_timeout_secs() -> u64 {
+    30
+}
+
+fn default_email_cache_size() -> usize {
+    10_000
+}
+
+fn default_max_click_url_len() -> usize {
+    2048
+}
+
+fn default_allowed_redirect_schemes() -> String {
+    "http,https".to_string()
+}
+
+fn default_webhook_timeout_secs() -> u64 {
+    10
+}
+
+fn default_webhook_max_attempts() -> u32 {
+    5
+}
+
+fn default_webhook_retry_base_ms() -> u64 {
+    1000
+}
+
+fn default_webhook_circuit_threshold() -> u32 {
+    5
+}
+
+fn default_webhook_circuit_cooldown_secs() -> u64 {
+    60
+}
+
+fn default_webhook_queue_size() -> usize {
+    1000
+}
+
+fn default_backup_dir() -> String {
+    "data/backups".to_string()
+}
+
+fn default_allowed_event_types() -> String {
+    "open,click".to_string()
+}
+
+fn default_event_clock_skew_secs() -> i64 {
+    300
+}
+
+fn default_max_event_age_days() -> i64 {
+    3650
+}
+
+fn default_max_body_bytes() -> usize {
+    256 * 1024
+}
+
+fn default_max_batch_body_bytes() -> usize {
+    4 * 1024 * 1024
+}
+
+fn default_max_import_body_bytes() -> usize {
+    64 * 1024 * 1024
+}
+
+fn default_sqlite_synchronous() -> String {
+    "NORMAL".to_string()
+}
+
+fn default_sqlite_busy_timeout_ms() -> u64 {
+    5000
+}
+
+fn default_sqlite_busy_retries() -> u32 {
+    3
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            port: 3000,
+            database_url: "sqlite:data/tracking.db".to_string(),
+            allow_in_memory: false,
+            base_url: "http://localhost:3000".to_string(),
+            path_prefix: String::new(),
+            default_tenant: None,
+            cors_allowed_origins: "*".to_string(),
+            trusted_proxy_hops: 0,
+            hsts_max_age_secs: None,
+            time_format: time_format::TimeFormat::default(),
+            client_ip_header: default_client_ip_header(),
+            max_body_bytes: default_max_body_bytes(),
+            max_batch_body_bytes: default_max_batch_body_bytes(),
+            max_import_body_bytes: default_max_import_body_bytes(),
+            sqlite_synchronous: default_sqlite_synchronous(),
+            sqlite_busy_timeout_ms: default_sqlite_busy_timeout_ms(),
+            sqlite_busy_retries: default_sqlite_busy_retries(),
+            worker_threads: 0,
+            max_blocking_threads: 0,
+            admin_key: None,
+            admin_ip_allowlist: String::new(),
+            backup_dir: default_backup_dir(),
+            backup_interval_secs: None,
+            audit_log_enabled: default_audit_log_enabled(),
+            email_cache_size: default_email_cache_size(),
+            event_buffer_size: 0,
+            shutdown_timeout_secs: default_shutdown_timeout_secs(),
+            request_timeout_secs: None,
+            event_backlog_threshold: None,
+            event_log_path: None,
+            recent_events_cache_size: 0,
+            unique_open_sketch: false,
+            unique_open_sketch_min_emails: default_unique_open_sketch_min_emails(),
+            max_click_url_len: default_max_click_url_len(),
+            webhook_timeout_secs: default_webhook_timeout_secs(),
+            webhook_max_attempts: default_webhook_max_attempts(),
+            webhook_retry_base_ms: default_webhook_retry_base_ms(),
+            webhook_circuit_threshold: default_webhook_circuit_threshold(),
+            webhook_circuit_cooldown_secs: default_webhook_circuit_cooldown_secs(),
+            webhook_queue_size: default_webhook_queue_size(),
+            webhook_allow_private_networks: false,
+            unsubscribe_redirect_url: None,
+            mask_pii: false,
+            reject_suppressed_recipients: false,
+            click_fallback_url: None,
+            allowed_redirect_schemes: default_allowed_redirect_schemes(),
+            open_log_sample_rate: default_open_log_sample_rate(),
+            pixel_conditional_requests: false,
+            set_client_cookie: false,
+            max_opens_per_email: None,
+            open_grace_secs: 0,
+            open_rate_limit: None,
+            open_rate_window_secs: default_open_rate_window_secs(),
+            open_rate_cooldown_secs: default_open_rate_cooldown_secs(),
+            open_rate_limit_per_ip: false,
+            redirect_status: RedirectStatus::default(),
+            allowed_event_types: default_allowed_event_types(),
+            event_clock_skew_secs: default_event_clock_skew_secs(),
+            max_event_age_days: default_max_event_age_days(),
+            default_daily_email_limit: None,
+        }
+    }
+}
+
+impl Config {
+    pub fn from_env() -> Result<Self, envy::Error> {
+        envy::from_env()
+    }
+
+    /// `path_prefix` with a leading slash and no trailing one, or empty.
+    pub fn normalized_path_prefix(&self) -> String {
+        let prefix = self.path_prefix.trim().trim_matches('/');
+        if prefix.is_empty() {
+            String::new()
+        } else {
+            format!("/{}", prefix)
+        }
+    }
+
+    /// Base for every URL we hand out: `base_url` plus the path prefix.
+    pub fn public_url(&self) -> String {
+        format!(
+            "{}{}",
+            self.base_url.trim_end_matches('/'),
+            self.normalized_path_prefix()
+        )
+    }
+
+    /// `client_ip_header` as lowercase header names.
+    pub fn client_ip_headers(&self) -> Vec<String> {
+        self.client_ip_header
+            .split(',')
+            .map(|name| name.trim().to_ascii_lowercase())
+            .filter(|name| !name.is_empty())
+            .collect()
+    }
+
+    /// The client address from the configured headers, which are all read
+    /// with the same `trusted_proxy_hops`; see [`extract_client_ip_from`].
+    pub fn client_ip(&self, headers: &HeaderMap) -> Option<String> {
+        extract_client_ip_from(headers, &self.client_ip_headers(), self.trusted_proxy_hops)
+    }
+
+    /// Whether events of this type may be recorded.
+    pub fn accepts_event_type(&self, event_type: &str) -> bool {
+        EVENT_TYPES.contains(&event_type)
+            || self
+                .allowed_event_types
+                .split(',')
+                .any(|allowed| allowed.trim() == event_type)
+    }
+
+    /// Checks a client-supplied event time against the skew and age limits.
+    pub fn check_event_timestamp(&self, timestamp: DateTime<chrono::Utc>) -> Result<(), String> {
+        let now = chrono::Utc::now();
+        if timestamp > now + chrono::Duration::seconds(self.event_clock_skew_secs) {
+            return Err(format!("timestamp {} is in the future", timestamp.to_rfc3339()));
+        }
+        if timestamp < now - chrono::Duration::days(self.max_event_age_days) {
+            return Err(format!(
+                "timestamp {} is more than {} days old",
+                timestamp.to_rfc3339(),
+                self.max_event_age_days
+            ));
+        }
+        Ok(())
+    }
+
+    /// The networks in `admin_ip_allowlist`, or None when it's unset.
+    /// Invalid entries are logged and skipped, so a list of only invalid
+    /// entries locks everyone out rather than letting everyone in.
+    pub fn admin_ip_allowlist(&self) -> Option<Vec<IpNet>> {
+        let entries: Vec<&str> = self
+            .admin_ip_allowlist
+            .split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+            .collect();
+        if entries.is_empty() {
+            return None;
+        }
+        let networks = entries
+            .into_iter()
+            .filter_map(|entry| {
+                let network = entry
+                    .parse::<IpNet>()
+                    .ok()
+                    .or_else(|| entry.parse::<IpAddr>().ok().map(IpNet::from));
+                if network.is_none() {
+                    eprintln!("Ignoring invalid admin allowlist entry: {}", entry);
+                }
+                network
+            })
+            .collect();
+        Some(networks)
+    }
+
+    /// The origins in `cors_allowed_origins`, or None when any origin is
+    /// allowed (unset or `*`). Invalid entries are logged and skipped; a
+    /// list of only invalid entries is an error, since allowing every
+    /// origin is surely not what was meant.
+    pub fn cors_origins(&self) -> Result<Option<Vec<HeaderValue>>, String> {
+        let entries: Vec<&str> = self
+            .cors_allowed_origins
+            .split(',')
+            .map(str::trim)
+            .filter(|origin| !origin.is_empty())
+            .collect();
+        if entries.is_empty() || entries.contains(&"*") {
+            return Ok(None);
+        }
+        let origins: Vec<HeaderValue> = entries
+            .iter()
+            .filter_map(|origin| match HeaderValue::from_str(origin) {
+                Ok(value) => Some(value),
+                Err(_) => {
+                    eprintln!("Ignoring invalid CORS origin: {}", origin);
+                    None
+                }
+            })
+            .collect();
+        if origins.is_empty() {
+            return Err(format!(
+                "none of CORS_ALLOWED_ORIGINS '{}' is a valid origin",
+                self.cors_allowed_origins
+            ));
+        }
+        Ok(Some(origins))
+    }
+
+    /// Whether `scheme` is in `allowed_redirect_schemes`, ignoring case.
+    pub fn allows_redirect_scheme(&self, scheme: &str) -> bool {
+        self.allowed_redirect_schemes
+            .split(',')
+            .map(str::trim)
+            .any(|allowed| allowed.eq_ignore_ascii_case(scheme))
+    }
+
+    /// Whether this pixel hit should be logged, per `open_log_sample_rate`.
+    pub fn sample_open_log(&self) -> bool {
+        let rate = self.open_log_sample_rate;
+        rate >= 1.0 || (rate > 0.0 && rand::random::<f64>() < rate)
+    }
+
+    pub fn database_options(&self) -> DatabaseOptions {
+        DatabaseOptions {
+            synchronous: self.sqlite_synchronous.clone(),
+            busy_timeout: Duration::from_millis(self.sqlite_busy_timeout_ms),
+            busy_retries: self.sqlite_busy_retries,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct AppState {
+    pub db: Arc<Database>,
+    pub config: Config,
+    pub live_events: broadcast::Sender<LiveEvent>,
+    pub db_metrics: Arc<DbMetrics>,
+    pub webhooks: webhooks::WebhookDispatcher,
+    pub email_cache: Arc<email_cache::EmailCache>,
+    pub pixel_cache_modes: Arc<pixel_cache_modes::PixelCacheModes>,
+    pub activity: Arc<activity::ActivityTracker>,
+    pub event_writer: event_writer::EventWriter,
+    /// Tracking requests naming an email owned by a different tenant.
+    pub cross_tenant_lookups: Arc<AtomicU64>,
+    pub route_metrics: Arc<route_metrics::RouteMetrics>,
+    pub event_log: Option<Arc<event_log::EventLog>>,
+    pub recent_events: Arc<recent_events::RecentEvents>,
+    /// None unless `Config.open_rate_limit` is set.
+    pub open_limiter: Option<Arc<open_limiter::OpenLimiter>>,
+    /// None unless `Config.unique_open_sketch` is on.
+    pub unique_opens: Option<Arc<unique_opens::UniqueOpenSketches>>,
+}
+
+impl AppState {
+    /// State for `router`, starting the background event writer when
+    /// `Config.event_buffer_size` enables it and resuming webhook deliveries
+    /// left pending by the last run.
+    pub fn new(db: Arc<Database>, config: Config) -> Self {
+        let (live_events, _) = broadcast::channel(LIVE_EVENT_CAPACITY);
+        let backlog_threshold = config
+            .event_backlog_threshold
+            .unwrap_or(config.event_buffer_size / 10 * 9);
+        let (event_writer, event_receiver) =
+            event_writer::EventWriter::channel(config.event_buffer_size, backlog_threshold);
+        let state = AppState {
+            db_metrics: db.metrics(),
+            webhooks: webhooks::WebhookDispatcher::new(db.clone(), &config),
+            email_cache: Arc::new(email_cache::EmailCache::new(config.email_cache_size)),
+            pixel_cache_modes: Arc::new(pixel_cache_modes::PixelCacheModes::default()),
+            activity: Arc::new(activity::ActivityTracker::new(activity::ACTIVITY_WRITE_INTERVAL)),
+            event_writer,
+            cross_tenant_lookups: Arc::new(AtomicU64::new(0)),
+            route_metrics: Arc::new(route_metrics::RouteMetrics::default()),
+            event_log: config
+                .event_log_path
+                .as_ref()
+                .map(|path| Arc::new(event_log::EventLog::new(path))),
+            recent_events: Arc::new(recent_events::RecentEvents::new(config.recent_events_cache_size)),
+            open_limiter: config.open_rate_limit.map(|limit| {
+                Arc::new(open_limiter::OpenLimiter::new(
+                    limit,
+                    Duration::from_secs(config.open_rate_window_secs),
+                    Duration::from_secs(config.open_rate_cooldown_secs),
+                    config.open_rate_limit_per_ip,
+                ))
+            }),
+            unique_opens: config.unique_open_sketch.then(|| {
+                Arc::new(unique_opens::UniqueOpenSketches::new(config.unique_open_sketch_min_emails))
+            }),
+            db,
+            config,
+            live_events,
+        };
+        if let Some(receiver) = event_receiver {
+            event_writer::spawn(state.clone(), receiver);
+        }
+        if let Some(unique_opens) = &state.unique_opens {
+            unique_opens.spawn_flusher(state.db.clone());
+        }
+        state.webhooks.spawn_resume();
+        state
+    }
+
+    /// Writes out what's still buffered once requests have stopped: waits
+    /// up to `shutdown_timeout_secs` for the event writer to store its
+    /// queue, then saves the unique open sketches. Returns false when the
+    /// writer didn't finish in time.
+    pub async fn shutdown(&self) -> bool {
+        let timeout = Duration::from_secs(self.config.shutdown_timeout_secs);
+        let closed = tokio::time::timeout(timeout, self.event_writer.close()).await.is_ok();
+        if let Some(unique_opens) = &self.unique_opens {
+            unique_opens.flush(&self.db).await;
+        }
+        if closed {
+            return true;
+        }
+        let queued = self.event_writer.backlog().map_or(0, |backlog| backlog.queued);
+        eprintln!(
+            "Warning: gave up flushing the event writer after {:?}; about {} queued events were not stored",
+            timeout, queued
+        );
+        false
+    }
+
+    /// Looks up an email the tenant owns, through the email cache.
+    pub async fn find_email(&self, tenant_id: &str, email_ref: &EmailRef) -> rusqlite::Result<Option<Email>> {
+        if let Some(email) = self.email_cache.get(tenant_id, email_ref) {
+            return Ok(Some(email));
+        }
+        let email = self.db.find_email(email_ref, tenant_id).await?;
+        if let Some(email) = &email {
+            self.email_cache.insert(tenant_id, email_ref, email);
+        }
+        Ok(email)
+    }
+
+    /// How the tenant's pixels may be cached, through the pixel cache mode
+    /// cache.
+    pub async fn pixel_cache_mode(&self, tenant_id: &str) -> rusqlite::Result<PixelCacheMode> {
+        if let Some(mode) = self.pixel_cache_modes.get(tenant_id) {
+            return Ok(mode);
+        }
+        let mode = self.db.get_tenant_settings(tenant_id).await?.pixel_cache_mode;
+        self.pixel_cache_modes.insert(tenant_id, mode);
+        Ok(mode)
+    }
+
+    /// Hands a freshly logged event to any dashboards streaming this tenant
+    /// and to its webhooks.
+    pub async fn publish_event(&self, tenant_id: &str, event: Event) {
+        self.record_activity(tenant_id).await;
+        if let Some(event_log) = &self.event_log {
+            event_log.append(tenant_id, &event);
+        }
+        self.recent_events.push(tenant_id, &event);
+        if let Some(unique_opens) = &self.unique_opens {
+            unique_opens.record(tenant_id, &event);
+        }
+        self.webhooks.dispatch(tenant_id, &event);
+        // Sending only fails when nobody is listening, which is fine.
+        let _ = self.live_events.send(LiveEvent {
+            tenant_id: tenant_id.to_string(),
+            event,
+        });
+    }
+
+    /// Records an event seen by a tracking route: queued for the batched
+    /// writer when it's running, otherwise written and published right away.
+    pub async fn log_tracking_event(&self, tenant_id: &str, event: NewEvent) -> rusqlite::Result<()> {
+        let event = match self.event_writer.queue(tenant_id, event).await {
+            Ok(()) => return Ok(()),
+            Err(event) => event,
+        };
+        let logged = self.db.log_new_event(&event).await?;
+        self.publish_event(tenant_id, logged).await;
+        Ok(())
+    }
+
+    /// Updates the tenant's `last_activity_at`, at most once a minute. A
+    /// failed write is only logged.
+    pub async fn record_activity(&self, tenant_id: &str) {
+        if !self.activity.due(tenant_id) {
+            return;
+        }
+        if let Err(e) = self.db.touch_tenant(tenant_id, chrono::Utc::now()).await {
+            eprintln!("Failed to record activity for {}: {}", tenant_id, e);
+        }
+    }
+
+    /// Adds an entry to the audit log. A failure to record is logged rather
+    /// than undoing the action it describes.
+    pub async fn record_audit(&self, actor: &str, action: &str, target: &str, details: serde_json::Value) {
+        if !self.config.audit_log_enabled {
+            return;
+        }
+        if let Err(e) = self.db.record_audit(actor, action, target, &details).await {
+            eprintln!("Failed to record audit entry '{}' on {}: {}", action, target, e);
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct LiveEvent {
+    pub tenant_id: String,
+    pub event: Event,
+}
+
+#[derive(Template)]
+#[template(path = "dashboard.html")]
+struct DashboardTemplate {
+    tenant_id: String,
+    stats: EventStats,
+    base_url: String,
+    path_prefix: String,
+    filter: DashboardQuery,
+}
+
+/// Optional dashboard filters, echoed back into the filter form. `from` and
+/// `to` take a date (whole UTC days, both inclusive) or an RFC 3339
+/// timestamp (`to` exclusive).
+#[derive(Debug, Default, Deserialize)]
+pub struct DashboardQuery {
+    #[serde(default)]
+    pub event_type: String,
+    #[serde(default)]
+    pub from: String,
+    #[serde(default)]
+    pub to: String,
+    /// The dashboard counts people only unless these toggles are on.
+    #[serde(default)]
+    pub include_bots: bool,
+    #[serde(default)]
+    pub include_suspicious: bool,
+}
+
+impl DashboardQuery {
+    pub fn is_active(&self) -> bool {
+        !(self.event_type.is_empty() && self.from.is_empty() && self.to.is_empty())
+    }
+
+    fn counted(&self) -> StatsFilter {
+        StatsFilter {
+            include_bots: self.include_bots,
+            include_suspicious: self.include_suspicious,
+        }
+    }
+
+    fn to_filter(&self) -> Result<EventFilter, AppError> {
+        let from = match self.from.trim() {
+            "" => None,
+            value => Some(parse_filter_bound(value, "from", false)?),
+        };
+        let until = match self.to.trim() {
+            "" => None,
+            value => Some(parse_filter_bound(value, "to", true)?),
+        };
+        Ok(EventFilter {
+            event_type: Some(self.event_type.trim())
+                .filter(|t| !t.is_empty())
+                .map(str::to_string),
+            from,
+            until,
+        })
+    }
+}
+
+/// Parses a `from`/`to` bound. A bare `to` date means the end of that day.
+fn parse_filter_bound(value: &str, name: &str, end_of_day: bool) -> Result<chrono::DateTime<chrono::Utc>, AppError> {
+    if let Ok(date) = chrono::NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+        let date = if end_of_day { date.succ_opt().unwrap_or(date) } else { date };
+        return Ok(date.and_hms_opt(0, 0, 0).unwrap().and_utc());
+    }
+    DateTime::parse_from_rfc3339(value)
+        .map(|t| t.with_timezone(&chrono::Utc))
+        .map_err(|_| AppError::BadRequest(format!("'{}' must be a YYYY-MM-DD date or an RFC 3339 timestamp", name)))
+}
+
+#[derive(Deserialize)]
+pub struct ClickQuery {
+    pub url: Option<String>,
+}
+
+/// Returns the URL if it's an absolute URL we can safely put in a
+/// `Location` header.
+pub fn valid_redirect_target(url: &str) -> Option<&str> {
+    HeaderValue::from_str(url).ok()?;
+    let uri = url.parse::<axum::http::Uri>().ok()?;
+    (uri.scheme().is_some() && uri.host().is_some()).then_some(url)
+}
+
+/// Refuses redirects to a scheme outside `allowed_redirect_schemes`, such
+/// as `javascript:` or `data:`, which run in the clicker's browser. URLs
+/// that don't parse are left to the other checks.
+fn check_redirect_scheme(url: &str, config: &Config) -> Result<(), AppError> {
+    match url::Url::parse(url) {
+        Ok(url) if !config.allows_redirect_scheme(url.scheme()) => Err(AppError::InvalidUrl(format!(
+            "'url' scheme '{}' is not allowed; use one of: {}",
+            url.scheme(),
+            config.allowed_redirect_schemes
+        ))),
+        _ => Ok(()),
+    }
+}
+
+/// Checks a URL handed to `get_click_url` before it's wrapped: it must be an
+/// absolute URL with an allowed scheme, no longer than `max_click_url_len`
+/// bytes.
+fn validate_click_target(target_url: &str, config: &Config) -> Result<(), AppError> {
+    if target_url.len() > config.max_click_url_len {
+        return Err(AppError::InvalidUrl(format!(
+            "'url' is {} bytes long; the limit is {}",
+            target_url.len(),
+            config.max_click_url_len
+        )));
+    }
+    check_redirect_scheme(target_url, config)?;
+    match url::Url::parse(target_url) {
+        Ok(url) if url.has_host() => Ok(()),
+        _ => Err(AppError::InvalidUrl("'url' must be an absolute URL".to_string())),
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct CreateEmailRequest {
+    pub subject: Option<String>,
+    pub recipient: Option<String>,
+    pub campaign_id: Option<String>,
+    /// Must be a JSON object when present.
+    pub metadata: Option<serde_json::Value>,
+    /// Set to false when the recipient hasn't agreed to open tracking.
+    pub tracking_consent: Option<bool>,
+    /// Stop recording opens and clicks after this time.
+    #[serde(default, with = "crate::time_format::option")]
+    pub expires_at: Option<DateTime<chrono::Utc>>,
+    /// The sender's own id for the email. Creating an email with an id the
+    /// tenant already has returns the existing email instead.
+    pub external_id: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct CreateEmailResponse {
+    pub email_id: i64,
+    pub public_token: Option<String>,
+    pub tracking_pixel_url: String,
+    pub unsubscribe_url: String,
+    /// Set when an `Idempotency-Key` or `external_id` replay returned an
+    /// email created earlier.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub existing: bool,
+    /// The existing email's engagement so far; only present with `existing`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stats: Option<EmailEngagement>,
+    /// The email this one resends, if it's a resend.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub resend_of: Option<i64>,
+}
+
+impl CreateEmailResponse {
+    pub fn for_email(base_url: &str, tenant_id: &str, email: &Email) -> Self {
+        CreateEmailResponse {
+            email_id: email.id,
+            public_token: email.public_token.clone(),
+            tracking_pixel_url: tracking_pixel_url(base_url, tenant_id, email),
+            unsubscribe_url: unsubscribe_url(base_url, tenant_id, email),
+            existing: false,
+            stats: None,
+            resend_of: email.resend_of,
+        }
+    }
+}
+
+pub fn unsubscribe_url(base_url: &str, tenant_id: &str, email: &Email) -> String {
+    format!("{}/{}/unsubscribe/{}", base_url, tenant_id, email.url_key())
+}
+
+/// The pixel URL to embed for an email, keyed by its public token.
+pub fn tracking_pixel_url(base_url: &str, tenant_id: &str, email: &Email) -> String {
+    format!("{}/{}/pixel/{}.gif", base_url, tenant_id, email.url_key())
+}
+
+/// Event types the tracking routes record themselves.
+pub const EVENT_TYPES: &[&str] = &["open", "click", "unsubscribe", "sent"];
+
+/// Why an event couldn't be recorded against an email.
+#[derive(Debug)]
+pub enum EventRejection {
+    UnknownEmail,
+    /// The email exists but belongs to another tenant. Reported to clients
+    /// exactly like `UnknownEmail` so it doesn't reveal that it exists.
+    WrongTenant,
+    UnsupportedEventType(String),
+    Database(rusqlite::Error),
+}
+
+impl std::fmt::Display for EventRejection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EventRejection::UnknownEmail | EventRejection::WrongTenant => {
+                write!(f, "email not found for this tenant")
+            }
+            EventRejection::UnsupportedEventType(event_type) => {
+                write!(f, "unsupported event type '{}'", event_type)
+            }
+            EventRejection::Database(e) => write!(f, "database error: {}", e),
+        }
+    }
+}
+
+const MAX_TENANT_ID_LEN: usize = 64;
+
+/// Tenant ids come from the URL and tenants are created on first use, so
+/// only short lowercase `[a-z0-9_-]` ids are accepted. That keeps typos from
+/// minting odd tenants and the ids safe to put in logs and file names.
+pub fn validate_tenant_id(tenant_id: &str) -> Result<(), AppError> {
+    let valid = !tenant_id.is_empty()
+        && tenant_id.len() <= MAX_TENANT_ID_LEN
+        && tenant_id
+            .bytes()
+            .all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'_' || b == b'-');
+    if valid {
+        Ok(())
+    } else {
+        Err(AppError::BadRequest(format!(
+            "invalid tenant id '{}': use 1-{} characters from a-z, 0-9, _ and -",
+            tenant_id.escape_default(),
+            MAX_TENANT_ID_LEN
+        )))
+    }
+}
+
+/// First path segments of the tenant-less routes `Config.default_tenant`
+/// adds. Tenants can't be named for them while it's set, since their own
+/// routes would be taken for the default tenant's.
+const DEFAULT_TENANT_ALIASES: &[&str] = &["pixel", "click", "emails", "dashboard"];
+
+/// Rejects requests for malformed tenant ids, and for tenant ids shadowed
+/// by the default tenant's routes, before any handler runs.
+async fn require_valid_tenant(
+    State(state): State<AppState>,
+    params: Result<Path<HashMap<String, String>>, PathRejection>,
+    request: axum::extract::Request,
+    next: axum::middleware::Next,
+) -> Response {
+    let tenant_id = match &params {
+        Ok(Path(params)) => params.get("tenant_id").map(String::as_str).unwrap_or_default(),
+        Err(rejection) => return AppError::BadRequest(rejection.body_text()).into_response(),
+    };
+    if let Err(e) = validate_tenant_id(tenant_id) {
+        return e.into_response();
+    }
+    if state.config.default_tenant.is_some() && DEFAULT_TENANT_ALIASES.contains(&tenant_id) {
+        return AppError::BadRequest(format!(
+            "tenant id '{}' is reserved while a default tenant is configured",
+            tenant_id
+        ))
+        .into_response();
+    }
+    next.run(request).await
+}
+
+/// Checks that an event of `event_type` may be recorded for the referenced
+/// email, returning that email. Shared by the tracking routes and the event
+/// ingestion API.
+pub async fn validate_event(
+    state: &AppState,
+    tenant_id: &str,
+    email_ref: &EmailRef,
+    event_type: &str,
+) -> Result<Email, EventRejection> {
+    if !state.config.accepts_event_type(event_type) {
+        return Err(EventRejection::UnsupportedEventType(event_type.to_string()));
+    }
+
+    match state.find_email(tenant_id, email_ref).await {
+        Ok(Some(email)) => Ok(email),
+        Ok(None) => Err(unknown_email_rejection(state, tenant_id, email_ref).await),
+        Err(e) => Err(EventRejection::Database(e)),
+    }
+}
+
+/// Why a tenant's lookup missed: a request naming another tenant's email
+/// may be someone probing for ids, so it's logged and counted.
+async fn unknown_email_rejection(state: &AppState, tenant_id: &str, email_ref: &EmailRef) -> EventRejection {
+    let owner = match email_ref {
+        EmailRef::Id(id) => state.db.get_email_any_tenant(*id).await,
+        EmailRef::Token(token) => state.db.get_email_by_token_any_tenant(token).await,
+    };
+    match owner {
+        Ok(Some(email)) => {
+            state.cross_tenant_lookups.fetch_add(1, Ordering::Relaxed);
+            eprintln!(
+                "Warning: tenant {} asked for email {} belonging to tenant {}",
+                tenant_id, email.id, email.tenant_id
+            );
+            EventRejection::WrongTenant
+        }
+        Ok(None) => EventRejection::UnknownEmail,
+        Err(e) => EventRejection::Database(e),
+    }
+}
+
+/// Headers tried for the client address when `client_ip_header` isn't set.
+pub const DEFAULT_CLIENT_IP_HEADERS: &[&str] = &["x-forwarded-for", "x-real-ip"];
+
+/// Works out the client IP from `X-Forwarded-For`, falling back to
+/// `X-Real-IP`; see [`extract_client_ip_from`].
+pub fn extract_client_ip(headers: &HeaderMap, trusted_proxy_hops: usize) -> Option<String> {
+    extract_client_ip_from(headers, DEFAULT_CLIENT_IP_HEADERS, trusted_proxy_hops)
+}
+
+/// Works out the client IP from the first of `header_names` the request
+/// carries; later names are only tried when earlier ones are absent.
+///
+/// Each header is read as a comma-separated list like `X-Forwarded-For`.
+/// With `trusted_proxy_hops` of 0 the leftmost entry is used. Otherwise the
+/// rightmost `trusted_proxy_hops` entries were appended by our own proxies,
+/// each recording the address it was connected from, so the leftmost of
+/// those is the client as our outermost proxy saw it. Anything further left
+/// could have been supplied by the client, so a header with fewer entries
+/// than that gives `None` rather than falling back to one of them. The
+/// address comes back in canonical form (see [`normalize_ip`]), or `None` if
+/// it's malformed.
+pub fn extract_client_ip_from<S: AsRef<str>>(
+    headers: &HeaderMap,
+    header_names: &[S],
+    trusted_proxy_hops: usize,
+) -> Option<String> {
+    let forwarded = header_names
+        .iter()
+        .find_map(|name| headers.get(name.as_ref()))?
+        .to_str()
+        .ok()?;
+    let hops: Vec<&str> = forwarded
+        .split(',')
+        .map(|hop| hop.trim())
+        .filter(|hop| !hop.is_empty())
+        .collect();
+
+    let client = if trusted_proxy_hops == 0 {
+        hops.first()
+    } else {
+        hops.len()
+            .checked_sub(trusted_proxy_hops)
+            .and_then(|index| hops.get(index))
+    };
+    client.and_then(|ip| normalize_ip(ip))
+}
+
+/// Parses an IPv4 or IPv6 address as proxies write it, with or without a
+/// port (`203.0.113.7:443`, `[2001:db8::1]:443`, `[2001:db8::1]`), and
+/// returns its canonical text. IPv4-mapped IPv6 addresses come back as IPv4.
+pub fn normalize_ip(raw: &str) -> Option<String> {
+    let raw = raw.trim();
+    let ip = raw
+        .parse::<IpAddr>()
+        .or_else(|_| raw.parse::<SocketAddr>().map(|addr| addr.ip()))
+        .ok()
+        .or_else(|| {
+            raw.strip_prefix('[')
+                .and_then(|rest| rest.strip_suffix(']'))
+                .and_then(|inner| inner.parse::<IpAddr>().ok())
+        })?;
+    Some(ip.to_canonical().to_string())
+}
+
+pub async fn health_check(State(state): State<AppState>) -> impl IntoResponse {
+    let mut body = serde_json::json!({
+        "status": "healthy",
+        "service": "little-bell",
+        "version": "0.1.0"
+    });
+    match state.db.schema_status().await {
+        Ok(schema) => {
+            body["schema_version"] = schema.schema_version.into();
+            body["pending_migrations"] = schema.pending_migrations.into();
+        }
+        Err(e) => eprintln!("Failed to read schema version: {}", e),
+    }
+    Json(body)
+}
+
+/// Readiness probe: a node whose database isn't at the schema version it
+/// was built for (missing migrations, or already migrated by a newer
+/// binary), or whose batched event writer is falling behind, shouldn't be
+/// sent traffic.
+pub async fn readiness_check(State(state): State<AppState>) -> impl IntoResponse {
+    match state.db.schema_status().await {
+        Ok(schema) => {
+            let backlog = state.event_writer.backlog();
+            let degraded = backlog.as_ref().is_some_and(|backlog| backlog.degraded);
+            let ready = schema.schema_version == schema.expected_version && !degraded;
+            let status = if ready { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE };
+            let mut body = serde_json::json!({
+                "ready": ready,
+                "schema_version": schema.schema_version,
+                "expected_schema_version": schema.expected_version,
+                "pending_migrations": schema.pending_migrations,
+            });
+            if let Some(backlog) = backlog {
+                body["event_writer"] = serde_json::json!(backlog);
+            }
+            (status, Json(body)).into_response()
+        }
+        Err(e) => {
+            eprintln!("Failed to read schema version: {}", e);
+            AppError::ServiceUnavailable("schema version unavailable".to_string()).into_response()
+        }
+    }
+}
+
+pub async fn metrics(State(state): State<AppState>) -> impl IntoResponse {
+    Json(serde_json::json!({
+        "database": state.db_metrics.snapshot(),
+        "email_cache": state.email_cache.stats(),
+        "event_writer": state.event_writer.backlog(),
+        "routes": state.route_metrics.snapshot(),
+        "tracking": {
+            "cross_tenant_lookups": state.cross_tenant_lookups.load(Ordering::Relaxed),
+        },
+    }))
+}
+
+/// The 1x1 transparent image served for an open, in one of its formats.
+struct PixelImage {
+    extension: &'static str,
+    content_type: &'static str,
+    bytes: &'static [u8],
+}
+
+const PIXEL_GIF: PixelImage = PixelImage {
+    extension: ".gif",
+    content_type: "image/gif",
+    bytes: include_bytes!("pixel.gif"),
+};
+
+/// For senders embedding the pixel as a CSS `background-image`, which some
+/// clients load when they block `<img>`.
+const PIXEL_PNG: PixelImage = PixelImage {
+    extension: ".png",
+    content_type: "image/png",
+    bytes: include_bytes!("pixel.png"),
+};
+
+const PIXEL_WEBP: PixelImage = PixelImage {
+    extension: ".webp",
+    content_type: "image/webp",
+    bytes: include_bytes!("pixel.webp"),
+};
+
+/// The formats `GET /:tenant_id/pixel/:email_id` can serve.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PixelFormat {
+    Gif,
+    Png,
+    WebP,
+}
+
+impl PixelFormat {
+    fn from_media_type(media_type: &str) -> Option<Self> {
+        let essence = media_type.split(';').next()?.trim().to_ascii_lowercase();
+        match essence.as_str() {
+            "image/gif" => Some(PixelFormat::Gif),
+            "image/png" => Some(PixelFormat::Png),
+            "image/webp" => Some(PixelFormat::WebP),
+            _ => None,
+        }
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        self.image().content_type
+    }
+
+    fn image(&self) -> &'static PixelImage {
+        match self {
+            PixelFormat::Gif => &PIXEL_GIF,
+            PixelFormat::Png => &PIXEL_PNG,
+            PixelFormat::WebP => &PIXEL_WEBP,
+        }
+    }
+}
+
+/// The pixel format named in `Accept` with the highest quality, ties going
+/// to the one listed first. Only types named outright count: `image/*` and
+/// `*/*` accept anything, so they, like no header at all, get GIF.
+pub fn select_pixel_format(headers: &HeaderMap) -> PixelFormat {
+    let accept = headers
+        .get(header::ACCEPT)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+    let mut best: Option<(f32, PixelFormat)> = None;
+    for media_type in accept.split(',') {
+        let Some(format) = PixelFormat::from_media_type(media_type) else {
+            continue;
+        };
+        let quality = media_type
+            .split(';')
+            .filter_map(|param| param.trim().strip_prefix("q="))
+            .find_map(|q| q.parse::<f32>().ok())
+            .unwrap_or(1.0);
+        if quality > 0.0 && best.is_none_or(|(best_quality, _)| quality > best_quality) {
+            best = Some((quality, format));
+        }
+    }
+    best.map_or(PixelFormat::Gif, |(_, format)| format)
+}
+
+/// Serves the pixel, logging an open for `GET`. Mail clients probing the
+/// image with `HEAD` get the same headers and no body, and aren't counted.
+/// Whatever the URL's extension, the image is a GIF unless the client's
+/// `Accept` header asks for PNG or WebP; the open is logged either way.
+pub async fn track_open(
+    Path((tenant_id, email_id_str)): Path<(String, String)>,
+    method: Method,
+    headers: HeaderMap,
+    State(state): State<AppState>,
+) -> impl IntoResponse {
+    let probe = method == Method::HEAD;
+    let image = select_pixel_format(&headers).image();
+    record_open(&state, &tenant_id, &email_id_str, &headers, image, probe).await
+}
+
+/// The pixel image on its own, for composer previews and for checking the
+/// asset. Belongs to no tenant or email and records nothing.
+pub async fn preview_pixel() -> impl IntoResponse {
+    (
+        [
+            (header::CONTENT_TYPE, PIXEL_GIF.content_type.to_string()),
+            (header::CACHE_CONTROL, format!("public, max-age={}", assets::ASSET_MAX_AGE_SECS)),
+        ],
+        PIXEL_GIF.bytes,
+    )
+}
+
+/// Whether a tracking URL points at one of the tenant's emails, without
+/// recording anything, for link checkers. Takes the same id or token as the
+/// pixel, with or without its image extension. Unknown emails still get a
+/// 200 so checkers don't treat them as broken links.
+pub async fn verify_email(
+    Path((tenant_id, email_id_str)): Path<(String, String)>,
+    State(state): State<AppState>,
+) -> impl IntoResponse {
+    let email_id_str = strip_pixel_extension(&email_id_str);
+    if email_id_str.is_empty() {
+        return Json(serde_json::json!({ "valid": false })).into_response();
+    }
+
+    match state.find_email(&tenant_id, &EmailRef::parse(email_id_str)).await {
+        Ok(email) => Json(serde_json::json!({ "valid": email.is_some() })).into_response(),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            AppError::internal().into_response()
+        }
+    }
+}
+
+pub async fn track_background_open(
+    Path((tenant_id, email_id_str)): Path<(String, String)>,
+    method: Method,
+    headers: HeaderMap,
+    State(state): State<AppState>,
+) -> impl IntoResponse {
+    let probe = method == Method::HEAD;
+    record_open(&state, &tenant_id, &email_id_str, &headers, &PIXEL_PNG, probe).await
+}
+
+/// The id or token in a pixel path, without any image extension.
+fn strip_pixel_extension(email_id_str: &str) -> &str {
+    [PIXEL_GIF.extension, PIXEL_PNG.extension, PIXEL_WEBP.extension]
+        .iter()
+        .find_map(|extension| email_id_str.strip_suffix(extension))
+        .unwrap_or(email_id_str)
+}
+
+/// Logs an open of the email named by `email_id_str` (with or without an
+/// image extension) and serves `image`. A `probe` is answered the same way
+/// but not logged.
+async fn record_open(
+    state: &AppState,
+    tenant_id: &str,
+    email_id_str: &str,
+    headers: &HeaderMap,
+    image: &PixelImage,
+    probe: bool,
+) -> Response {
+    // Extract email ID or token from the path (remove the extension)
+    let email_id_str = strip_pixel_extension(email_id_str);
+    if email_id_str.is_empty() {
+        return AppError::BadRequest("missing email id".to_string()).into_response();
+    }
+    let email_ref = EmailRef::parse(email_id_str);
+
+    // Extract user agent and IP address
+    let user_agent = headers
+        .get("user-agent")
+        .and_then(|v| v.to_str().ok())
+        .map(|s| s.to_string());
+    
+    let ip_address = state.config.client_ip(headers);
+
+    // Verify email exists and belongs to tenant
+    match validate_event(state, tenant_id, &email_ref, "open").await {
+        Ok(email) => {
+            if state.config.sample_open_log() {
+                println!(
+                    "Open: tenant={} email={} consent={}",
+                    tenant_id, email.id, email.tracking_consent
+                );
+            }
+
+            // Without consent, once the email has expired, for a HEAD probe
+            // or while the pixel is rate limited, the pixel is still served,
+            // just not recorded (and no cookie is handed out)
+            let record = !probe
+                && email.tracking_consent
+                && !email.is_expired(chrono::Utc::now())
+                && state
+                    .open_limiter
+                    .as_ref()
+                    .is_none_or(|limiter| limiter.allow(tenant_id, email.id, ip_address.as_deref()));
+            let sent_client_id = client_id_cookie(headers);
+            let client_id = (state.config.set_client_cookie && record)
+                .then(|| sent_client_id.clone().unwrap_or_else(|| uuid::Uuid::new_v4().to_string()));
+            if record {
+                let suspicious = match state.config.max_opens_per_email {
+                    Some(cap) => match state.db.count_counted_opens(email.id).await {
+                        Ok(opens) => opens >= cap,
+                        Err(e) => {
+                            eprintln!("Failed to count opens: {}", e);
+                            false
+                        }
+                    },
+                    None => false,
+                };
+                let now = chrono::Utc::now();
+                let premature = now < email.created_at + chrono::Duration::seconds(state.config.open_grace_secs);
+                let event = NewEvent {
+                    email_id: email.id,
+                    event_type: "open".to_string(),
+                    timestamp: Some(now),
+                    user_agent: user_agent.clone(),
+                    ip_address: ip_address.clone(),
+                    target_url: None,
+                    client_id: client_id.clone(),
+                    suspicious,
+                    premature,
+                };
+                match state.log_tracking_event(tenant_id, event).await {
+                    Ok(()) => {}
+                    Err(e) => {
+                        eprintln!("Failed to log open event: {}", e);
+                        return AppError::internal().into_response();
+                    }
+                }
+            }
+
+            let cache_mode = match state.pixel_cache_mode(tenant_id).await {
+                Ok(mode) => mode,
+                Err(e) => {
+                    eprintln!("Failed to load tenant settings: {}", e);
+                    PixelCacheMode::default()
+                }
+            };
+
+            // The pixel never changes, so it was last modified when the email
+            // was created. Revalidating clients get a 304, but the open above
+            // has already been recorded.
+            let last_modified = email.created_at.format(HTTP_DATE_FORMAT).to_string();
+            let not_modified = state.config.pixel_conditional_requests
+                && headers
+                    .get(header::IF_MODIFIED_SINCE)
+                    .and_then(|v| v.to_str().ok())
+                    .and_then(|since| DateTime::parse_from_rfc2822(since).ok())
+                    .is_some_and(|since| since.timestamp() >= email.created_at.timestamp());
+
+            // Return the 1x1 transparent image
+            let response = if not_modified {
+                Response::builder().status(StatusCode::NOT_MODIFIED)
+            } else {
+                Response::builder().header("Content-Type", image.content_type)
+            };
+            // The format depends on `Accept`, so caches must key on it
+            let response = response.header(header::VARY, "Accept");
+            let response = if state.config.pixel_conditional_requests {
+                response.header(header::LAST_MODIFIED, last_modified)
+            } else {
+                response
+            };
+            let response = match client_id {
+                Some(client_id) if sent_client_id.is_none() => {
+                    response.header(header::SET_COOKIE, client_set_cookie(&state.config, &client_id))
+                }
+                _ => response,
+            };
+            let response = match cache_mode {
+                PixelCacheMode::NoCache => response
+                    .header("Cache-Control", "no-store, no-cache, must-revalidate")
+                    .header("Pragma", "no-cache")
+                    .header("Expires", "0"),
+                PixelCacheMode::CacheShort => response.header(
+                    "Cache-Control",
+                    format!("private, max-age={}", PIXEL_SHORT_CACHE_SECONDS),
+                ),
+            };
+            let body = if not_modified {
+                axum::body::Body::empty()
+            } else {
+                axum::body::Body::from(image.bytes)
+            };
+            response.body(body).unwrap().into_response()
+        }
+        Err(EventRejection::Database(e)) => {
+            eprintln!("Database error: {}", e);
+            AppError::internal().into_response()
+        }
+        Err(_) => AppError::EmailNotFound(email_ref.to_string()).into_response(),
+    }
+}
+
+/// The `lb_uid` cookie sent with a request, if it looks like one we issued.
+fn client_id_cookie(headers: &HeaderMap) -> Option<String> {
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .filter_map(|pair| pair.trim().strip_prefix(CLIENT_COOKIE)?.strip_prefix('='))
+        .find(|id| uuid::Uuid::parse_str(id).is_ok())
+        .map(|id| id.to_string())
+}
+
+/// Pixels load inside mail clients and webmail on other sites, so over
+/// https the cookie is `SameSite=None` to be sent back there at all.
+fn client_set_cookie(config: &Config, client_id: &str) -> String {
+    let path = config.normalized_path_prefix();
+    let path = if path.is_empty() { "/" } else { path.as_str() };
+    let same_site = if config.base_url.starts_with("https://") {
+        "SameSite=None; Secure"
+    } else {
+        "SameSite=Lax"
+    };
+    format!(
+        "{}={}; Max-Age={}; Path={}; HttpOnly; {}",
+        CLIENT_COOKIE, client_id, CLIENT_COOKIE_MAX_AGE, path, same_site
+    )
+}
+
+pub async fn track_click(
+    Path((tenant_id, email_id)): Path<(String, String)>,
+    Query(params): Query<ClickQuery>,
+    headers: HeaderMap,
+    State(state): State<AppState>,
+) -> impl IntoResponse {
+    let email_ref = EmailRef::parse(&email_id);
+
+    // Never bounce anyone to a script, fallback page or not
+    if let Some(url) = params.url.as_deref() {
+        if let Err(e) = check_redirect_scheme(url, &state.config) {
+            return e.into_response();
+        }
+    }
+
+    // A missing or broken target goes to the fallback page when one is
+    // configured, so the person clicking never sees an error.
+    let redirect_url = match params.url.as_deref().and_then(valid_redirect_target) {
+        Some(url) => url.to_string(),
+        None => match &state.config.click_fallback_url {
+            Some(fallback) => fallback.clone(),
+            None => return AppError::BadRequest("missing or invalid 'url' parameter".to_string()).into_response(),
+        },
+    };
+
+    record_click(&state, &tenant_id, &email_ref, &headers, params.url.as_deref(), &redirect_url).await
+}
+
+/// Logs a click on the referenced email and redirects to `redirect_url`.
+async fn record_click(
+    state: &AppState,
+    tenant_id: &str,
+    email_ref: &EmailRef,
+    headers: &HeaderMap,
+    target_url: Option<&str>,
+    redirect_url: &str,
+) -> Response {
+    // Extract user agent and IP address
+    let user_agent = headers
+        .get("user-agent")
+        .and_then(|v| v.to_str().ok())
+        .map(|s| s.to_string());
+    
+    let ip_address = state.config.client_ip(headers);
+
+    // Verify email exists and belongs to tenant
+    match validate_event(state, tenant_id, email_ref, "click").await {
+        Ok(email) => {
+            // Clicks on an expired email still redirect, they just aren't
+            // logged
+            let now = chrono::Utc::now();
+            if !email.is_expired(now) {
+                let event = NewEvent {
+                    email_id: email.id,
+                    event_type: "click".to_string(),
+                    timestamp: Some(now),
+                    user_agent,
+                    ip_address,
+                    target_url: target_url.map(|url| url.to_string()),
+                    client_id: None,
+                    suspicious: false,
+                    premature: false,
+                };
+                match state.log_tracking_event(tenant_id, event).await {
+                    Ok(()) => {}
+                    Err(e) => {
+                        eprintln!("Failed to log click event: {}", e);
+                        return AppError::internal().into_response();
+                    }
+                }
+            }
+
+            // Redirect to the original URL
+            state.config.redirect_status.redirect(redirect_url)
+        }
+        Err(EventRejection::Database(e)) => {
+            eprintln!("Database error: {}", e);
+            AppError::internal().into_response()
+        }
+        Err(_) => AppError::EmailNotFound(email_ref.to_string()).into_response(),
+    }
+}
+
+pub async fn track_short_click(
+    Path((tenant_id, short_code)): Path<(String, String)>,
+    headers: HeaderMap,
+    State(state): State<AppState>,
+) -> impl IntoResponse {
+    let link = match state.db.get_short_link(&tenant_id, &short_code).await {
+        Ok(Some(link)) => link,
+        Ok(None) => return AppError::NotFound(format!("short link '{}' not found", short_code)).into_response(),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return AppError::internal().into_response();
+        }
+    };
+
+    let email_ref = EmailRef::Id(link.email_id);
+    record_click(&state, &tenant_id, &email_ref, &headers, Some(&link.target_url), &link.target_url).await
+}
+
+pub async fn track_unsubscribe(
+    Path((tenant_id, email_id)): Path<(String, String)>,
+    headers: HeaderMap,
+    State(state): State<AppState>,
+) -> impl IntoResponse {
+    let email_ref = EmailRef::parse(&email_id);
+    let user_agent = headers
+        .get("user-agent")
+        .and_then(|v| v.to_str().ok())
+        .map(|s| s.to_string());
+    let ip_address = state.config.client_ip(&headers);
+
+    let email = match validate_event(&state, &tenant_id, &email_ref, "unsubscribe").await {
+        Ok(email) => email,
+        Err(EventRejection::Database(e)) => {
+            eprintln!("Database error: {}", e);
+            return AppError::internal().into_response();
+        }
+        Err(_) => return AppError::EmailNotFound(email_id).into_response(),
+    };
+
+    match state.db.log_event(
+        email.id,
+        "unsubscribe",
+        user_agent.as_deref(),
+        ip_address.as_deref(),
+        None,
+        None,
+    ).await {
+        Ok(event) => state.publish_event(&tenant_id, event).await,
+        Err(e) => {
+            eprintln!("Failed to log unsubscribe event: {}", e);
+            return AppError::internal().into_response();
+        }
+    }
+
+    if let Some(recipient) = email.recipient.as_deref() {
+        if let Err(e) = state.db.add_suppression(&tenant_id, recipient, Some(email.id)).await {
+            eprintln!(
+                "Failed to record suppression of {}: {}",
+                privacy::display_recipient(recipient, state.config.mask_pii),
+                e
+            );
+            return AppError::internal().into_response();
+        }
+    }
+
+    match &state.config.unsubscribe_redirect_url {
+        Some(url) => Redirect::to(url).into_response(),
+        None => Html("<p>You have been unsubscribed.</p>").into_response(),
+    }
+}
+
+/// Clears the tenant's events so its statistics start again from zero.
+/// Emails, links and settings are kept.
+pub async fn reset_stats(
+    Path(tenant_id): Path<String>,
+    headers: HeaderMap,
+    peer: Option<ConnectInfo<SocketAddr>>,
+    State(state): State<AppState>,
+) -> impl IntoResponse {
+    match state.db.delete_events_for_tenant(&tenant_id).await {
+        Ok(deleted) => {
+            state.recent_events.clear(&tenant_id);
+            if let Some(unique_opens) = &state.unique_opens {
+                unique_opens.forget(&tenant_id);
+            }
+            let actor = admin::actor(&state, &headers, peer);
+            state
+                .record_audit(&actor, "tenant.reset_stats", &tenant_id, serde_json::json!({ "deleted": deleted }))
+                .await;
+            Json(serde_json::json!({ "deleted": deleted })).into_response()
+        }
+        Err(e) => {
+            eprintln!("Failed to reset stats: {}", e);
+            AppError::internal().into_response()
+        }
+    }
+}
+
+pub async fn show_dashboard(
+    Path(tenant_id): Path<String>,
+    Query(query): Query<DashboardQuery>,
+    State(state): State<AppState>,
+) -> impl IntoResponse {
+    let filter = match query.to_filter() {
+        Ok(filter) => filter,
+        Err(e) => return e.into_response(),
+    };
+    let counted = query.counted();
+
+    // Ensure tenant exists (create if not)
+    if let Err(e) = state.db.create_tenant(&tenant_id, &tenant_id).await {
+        eprintln!("Failed to create/ensure tenant: {}", e);
+        return AppError::internal().into_response();
+    }
+
+    // The unfiltered list can come from the in-memory buffer; a cold one is
+    // seeded from the query's own recent events
+    let buffered = if filter.is_empty() { state.recent_events.get(&tenant_id) } else { None };
+    let stats = match buffered {
+        Some(recent_events) => state
+            .db
+            .get_tenant_stats_without_recent(&tenant_id, &filter, &counted)
+            .await
+            .map(|stats| EventStats { recent_events, ..stats }),
+        None => state.db.get_tenant_stats_filtered(&tenant_id, &filter, &counted).await.inspect(|stats| {
+            if filter.is_empty() {
+                state.recent_events.warm(&tenant_id, stats.recent_events.clone());
+            }
+        }),
+    };
+
+    match stats {
+        Ok(stats) => {
+            let template = DashboardTemplate {
+                tenant_id,
+                stats,
+                base_url: state.config.public_url(),
+                path_prefix: state.config.normalized_path_prefix(),
+                filter: query,
+            };
+            match template.render() {
+                Ok(html) => Html(html).into_response(),
+                Err(e) => {
+                    eprintln!("Template render error: {}", e);
+                    AppError::internal().into_response()
+                }
+            }
+        }
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            AppError::internal().into_response()
+        }
+    }
+}
+
+pub async fn ingest_events(
+    Path(tenant_id): Path<String>,
+    State(state): State<AppState>,
+    AppJson(mut events): AppJson<Vec<NewEvent>>,
+) -> impl IntoResponse {
+    // Store reported addresses the same way as observed ones
+    for event in &mut events {
+        event.ip_address = event.ip_address.as_deref().and_then(normalize_ip);
+    }
+
+    // Validate everything up front so a bad item rejects the whole batch
+    let mut errors = Vec::new();
+    for (index, event) in events.iter().enumerate() {
+        if let Some(Err(error)) = event.timestamp.map(|timestamp| state.config.check_event_timestamp(timestamp)) {
+            errors.push(serde_json::json!({
+                "index": index,
+                "email_id": event.email_id,
+                "error": error,
+            }));
+            continue;
+        }
+        // Sends are recorded on the email too, which a bare event wouldn't do
+        if event.event_type == "sent" {
+            errors.push(serde_json::json!({
+                "index": index,
+                "email_id": event.email_id,
+                "error": "record sends with POST /:tenant_id/emails/:email_id/sent",
+            }));
+            continue;
+        }
+        let email_ref = EmailRef::Id(event.email_id);
+        match validate_event(&state, &tenant_id, &email_ref, &event.event_type).await {
+            Ok(_) => {}
+            Err(EventRejection::Database(e)) => {
+                eprintln!("Database error: {}", e);
+                return AppError::internal().into_response();
+            }
+            Err(rejection) => errors.push(serde_json::json!({
+                "index": index,
+                "email_id": event.email_id,
+                "error": rejection.to_string(),
+            })),
+        }
+    }
+
+    if !errors.is_empty() {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(serde_json::json!({
+                "error": "batch rejected",
+                "code": "BATCH_REJECTED",
+                "details": errors,
+            })),
+        )
+            .into_response();
+    }
+
+    match state.db.log_events_batch(&events).await {
+        Ok(logged) => {
+            let inserted = logged.len();
+            for event in logged {
+                state.publish_event(&tenant_id, event).await;
+            }
+            (
+                StatusCode::CREATED,
+                Json(serde_json::json!({ "inserted": inserted })),
+            )
+                .into_response()
+        }
+        Err(e) => {
+            eprintln!("Failed to log event batch: {}", e);
+            AppError::internal().into_response()
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct StatsQuery {
+    pub format: Option<String>,
+    /// Count bot events; true unless turned off.
+    pub include_bots: Option<bool>,
+    /// Count suspicious opens as opens; false unless turned on.
+    pub include_suspicious: Option<bool>,
+}
+
+/// The tenant's statistics as JSON, CSV, a text table or Prometheus
+/// metrics, chosen by `?format=` or the `Accept` header.
+pub async fn get_stats(
+    Path(tenant_id): Path<String>,
+    Query(query): Query<StatsQuery>,
+    headers: HeaderMap,
+    State(state): State<AppState>,
+) -> impl IntoResponse {
+    let format = match stats_format::StatsFormat::negotiate(query.format.as_deref(), &headers) {
+        Ok(format) => format,
+        Err(e) => return e.into_response(),
+    };
+    let defaults = StatsFilter::default();
+    let counted = StatsFilter {
+        include_bots: query.include_bots.unwrap_or(defaults.include_bots),
+        include_suspicious: query.include_suspicious.unwrap_or(defaults.include_suspicious),
+    };
+    // Big tenants' unique opens can come from the sketch, but only for the
+    // default counting it tracks
+    let estimate = match &state.unique_opens {
+        Some(unique_opens) if counted == defaults => match unique_opens.estimate(&state.db, &tenant_id).await {
+            Ok(estimate) => estimate,
+            Err(e) => {
+                eprintln!("Database error: {}", e);
+                return AppError::internal().into_response();
+            }
+        },
+        _ => None,
+    };
+    let stats = if let Some(unique_opens) = estimate {
+        let stats = state.db.get_tenant_stats_estimated(&tenant_id, unique_opens).await;
+        match stats {
+            Ok(stats) if format.includes_recent_events() => state
+                .db
+                .recent_events(&tenant_id)
+                .await
+                .map(|recent_events| EventStats { recent_events, ..stats }),
+            stats => stats,
+        }
+    } else if format.includes_recent_events() {
+        state
+            .db
+            .get_tenant_stats_filtered(&tenant_id, &EventFilter::default(), &counted)
+            .await
+    } else {
+        state
+            .db
+            .get_tenant_stats_without_recent(&tenant_id, &EventFilter::default(), &counted)
+            .await
+    };
+    match stats {
+        Ok(stats) => format.respond(&tenant_id, &stats),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            AppError::internal().into_response()
+        }
+    }
+}
+
+pub async fn stream_events(
+    Path(tenant_id): Path<String>,
+    State(state): State<AppState>,
+) -> Sse<impl Stream<Item = Result<sse::Event, Infallible>>> {
+    // Lagged receivers just skip what they missed; the stream ends (and the
+    // receiver is dropped) as soon as the client disconnects.
+    let stream = BroadcastStream::new(state.live_events.subscribe()).filter_map(move |message| {
+        let live = message.ok().filter(|live| live.tenant_id == tenant_id)?;
+        time_format::with(state.config.time_format, || sse::Event::default().json_data(&live.event))
+            .ok()
+            .map(Ok)
+    });
+
+    Sse::new(stream).keep_alive(KeepAlive::default())
+}
+
+#[derive(Deserialize)]
+pub struct TopLinksQuery {
+    pub limit: Option<i64>,
+}
+
+pub async fn get_top_links(
+    Path(tenant_id): Path<String>,
+    Query(query): Query<TopLinksQuery>,
+    State(state): State<AppState>,
+) -> impl IntoResponse {
+    let limit = query.limit.unwrap_or(DEFAULT_TOP_LINKS).clamp(1, MAX_TOP_LINKS);
+
+    match state.db.top_links(&tenant_id, limit).await {
+        Ok(links) => Json(links).into_response(),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            AppError::internal().into_response()
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct HeatmapQuery {
+    #[serde(default)]
+    pub by_weekday: bool,
+}
+
+/// Opens per UTC hour of day, for finding the best send time. Clients shift
+/// the buckets to their own time zone.
+pub async fn get_hourly_heatmap(
+    Path(tenant_id): Path<String>,
+    Query(query): Query<HeatmapQuery>,
+    State(state): State<AppState>,
+) -> impl IntoResponse {
+    let heatmap = if query.by_weekday {
+        state
+            .db
+            .opens_by_weekday_and_hour(&tenant_id)
+            .await
+            .map(|grid| HourlyHeatmap {
+                hours: database::hour_totals(&grid),
+                weekdays: Some(grid),
+            })
+    } else {
+        state
+            .db
+            .opens_by_hour_of_day(&tenant_id)
+            .await
+            .map(|hours| HourlyHeatmap { hours, weekdays: None })
+    };
+
+    match heatmap {
+        Ok(heatmap) => Json(heatmap).into_response(),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            AppError::internal().into_response()
+        }
+    }
+}
+
+pub async fn get_campaign_stats(
+    Path((tenant_id, campaign_id)): Path<(String, String)>,
+    State(state): State<AppState>,
+) -> impl IntoResponse {
+    match state.db.get_campaign_stats(&tenant_id, &campaign_id).await {
+        Ok(stats) => Json(stats).into_response(),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            AppError::internal().into_response()
+        }
+    }
+}
+
+pub async fn stats_socket(
+    Path(tenant_id): Path<String>,
+    ws: WebSocketUpgrade,
+    State(state): State<AppState>,
+) -> impl IntoResponse {
+    ws.on_upgrade(move |socket| push_live_stats(socket, tenant_id, state))
+}
+
+/// Sends the tenant's counters whenever they change, at most once per
+/// `LIVE_STATS_INTERVAL`. Events arriving in between just mark the counters
+/// stale, so a slow client gets the latest numbers rather than a backlog.
+/// If the counters can't be read the socket is closed with code 1011 and a
+/// reason.
+async fn push_live_stats(mut socket: WebSocket, tenant_id: String, state: AppState) {
+    let mut events = state.live_events.subscribe();
+    let mut ticker = tokio::time::interval(LIVE_STATS_INTERVAL);
+    // Send the current numbers straight away
+    let mut stale = true;
+
+    loop {
+        tokio::select! {
+            message = events.recv() => match message {
+                Ok(live) => stale |= live.tenant_id == tenant_id,
+                Err(RecvError::Lagged(_)) => stale = true,
+                Err(RecvError::Closed) => break,
+            },
+            _ = ticker.tick() => {
+                if !stale {
+                    continue;
+                }
+                stale = false;
+
+                let counts = match state.db.get_tenant_counts(&tenant_id).await {
+                    Ok(counts) => counts,
+                    Err(e) => {
+                        eprintln!("Database error: {}", e);
+                        // Tell the client why, so it can reconnect later
+                        // rather than treat the drop as a network blip
+                        let frame = CloseFrame {
+                            code: close_code::ERROR,
+                            reason: "internal server error".into(),
+                        };
+                        let _ = socket.send(Message::Close(Some(frame))).await;
+                        break;
+                    }
+                };
+                let payload = serde_json::to_string(&counts).unwrap();
+                if socket.send(Message::Text(payload)).await.is_err() {
+                    break;
+                }
+            }
+            incoming = socket.recv() => match incoming {
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => {}
+            },
+        }
+    }
+}
+
+pub async fn get_summary(
+    Path(tenant_id): Path<String>,
+    State(state): State<AppState>,
+) -> impl IntoResponse {
+    match state.db.get_windowed_summary(&tenant_id).await {
+        Ok(summary) => Json(summary).into_response(),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            AppError::internal().into_response()
+        }
+    }
+}
+
+pub async fn get_latency(
+    Path(tenant_id): Path<String>,
+    State(state): State<AppState>,
+) -> impl IntoResponse {
+    match state.db.get_first_open_latency(&tenant_id).await {
+        Ok(latency) => Json(latency).into_response(),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            AppError::internal().into_response()
+        }
+    }
+}
+
+pub async fn get_tenant_settings(
+    Path(tenant_id): Path<String>,
+    State(state): State<AppState>,
+) -> impl IntoResponse {
+    match state.db.get_tenant_settings(&tenant_id).await {
+        Ok(settings) => Json(settings).into_response(),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            AppError::internal().into_response()
+        }
+    }
+}
+
+pub async fn update_tenant_settings(
+    Path(tenant_id): Path<String>,
+    headers: HeaderMap,
+    peer: Option<ConnectInfo<SocketAddr>>,
+    State(state): State<AppState>,
+    AppJson(settings): AppJson<TenantSettings>,
+) -> impl IntoResponse {
+    if let Err(message) = settings.validate() {
+        return AppError::BadRequest(message).into_response();
+    }
+    // Ensure tenant exists (create if not)
+    if let Err(e) = state.db.create_tenant(&tenant_id, &tenant_id).await {
+        eprintln!("Failed to create/ensure tenant: {}", e);
+        return AppError::internal().into_response();
+    }
+
+    let result = if admin::is_admin(&state, &headers) {
+        state
+            .db
+            .update_tenant_settings(&tenant_id, &settings)
+            .await
+            .map(|()| Some(settings))
+    } else {
+        // A PUT without the limit leaves it alone rather than clearing it
+        if let Some(limit) = settings.daily_email_limit {
+            if let Err(response) = require_unchanged_daily_limit(&state, &tenant_id, Some(limit)).await {
+                return response;
+            }
+        }
+        // Written as a patch so the limit stays whatever it is by now
+        let patch = TenantSettingsPatch {
+            pixel_cache_mode: Some(settings.pixel_cache_mode),
+            daily_email_limit: None,
+        };
+        state.db.patch_tenant_settings(&tenant_id, &patch).await
+    };
+
+    match result {
+        Ok(Some(settings)) => {
+            state.pixel_cache_modes.invalidate(&tenant_id);
+            let actor = admin::actor(&state, &headers, peer);
+            let details = serde_json::to_value(&settings).unwrap_or_default();
+            state.record_audit(&actor, "tenant_settings.update", &tenant_id, details).await;
+            Json(settings).into_response()
+        }
+        Ok(None) => AppError::internal().into_response(),
+        Err(e) => {
+            eprintln!("Failed to update tenant settings: {}", e);
+            AppError::internal().into_response()
+        }
+    }
+}
+
+pub async fn patch_tenant_settings(
+    Path(tenant_id): Path<String>,
+    headers: HeaderMap,
+    peer: Option<ConnectInfo<SocketAddr>>,
+    State(state): State<AppState>,
+    AppJson(mut patch): AppJson<TenantSettingsPatch>,
+) -> impl IntoResponse {
+    if let Err(message) = patch.validate() {
+        return AppError::BadRequest(message).into_response();
+    }
+    // Ensure tenant exists (create if not)
+    if let Err(e) = state.db.create_tenant(&tenant_id, &tenant_id).await {
+        eprintln!("Failed to create/ensure tenant: {}", e);
+        return AppError::internal().into_response();
+    }
+    if !admin::is_admin(&state, &headers) {
+        if let Some(limit) = patch.daily_email_limit.take() {
+            if let Err(response) = require_unchanged_daily_limit(&state, &tenant_id, limit).await {
+                return response;
+            }
+        }
+    }
 
-#[derive(Debug, Deserialize, Clone)]
-pub struct Config {
-    #[serde(default = "default_port")]
-    pub port: u16,
-    #[serde(default = "default_database_url")]
-    pub database_url: String,
-    #[serde(default = "default_base_url")]
-    pub base_url: String,
+    match state.db.patch_tenant_settings(&tenant_id, &patch).await {
+        Ok(Some(settings)) => {
+            state.pixel_cache_modes.invalidate(&tenant_id);
+            let actor = admin::actor(&state, &headers, peer);
+            let details = serde_json::to_value(&settings).unwrap_or_default();
+            state.record_audit(&actor, "tenant_settings.update", &tenant_id, details).await;
+            Json(settings).into_response()
+        }
+        Ok(None) => AppError::internal().into_response(),
+        Err(e) => {
+            eprintln!("Failed to update tenant settings: {}", e);
+            AppError::internal().into_response()
+        }
+    }
 }
 
-fn default_port() -> u16 {
-    3000
+/// The tenant settings routes are open to anyone, but the daily email limit
+/// is the operator's quota, so changing it there takes the admin key.
+/// Sending the current limit back, as a PUT of fetched settings does, is
+/// not a change.
+async fn require_unchanged_daily_limit(
+    state: &AppState,
+    tenant_id: &str,
+    requested: Option<i64>,
+) -> Result<(), Response> {
+    let current = match state.db.get_tenant_settings(tenant_id).await {
+        Ok(settings) => settings.daily_email_limit,
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return Err(AppError::internal().into_response());
+        }
+    };
+    if requested != current {
+        return Err(AppError::Unauthorized("changing daily_email_limit requires the admin key".to_string()).into_response());
+    }
+    Ok(())
 }
 
-fn default_database_url() -> String {
-    "sqlite:data/tracking.db".to_string()
+/// How many emails the tenant may create per day, or `None` when it has no
+/// daily limit. It's enforced where the emails are inserted.
+pub(crate) async fn daily_email_limit(state: &AppState, tenant_id: &str) -> rusqlite::Result<Option<i64>> {
+    let settings = state.db.get_tenant_settings(tenant_id).await?;
+    Ok(settings.daily_email_limit.or(state.config.default_daily_email_limit))
 }
 
-fn default_base_url() -> String {
-    "http://localhost:3000".to_string()
-}
+pub async fn create_email(
+    Path(tenant_id): Path<String>,
+    headers: HeaderMap,
+    State(state): State<AppState>,
+    AppJson(payload): AppJson<CreateEmailRequest>,
+) -> impl IntoResponse {
+    let idempotency_key = headers
+        .get("idempotency-key")
+        .and_then(|v| v.to_str().ok())
+        .map(|key| key.trim().to_string())
+        .filter(|key| !key.is_empty());
 
-impl Default for Config {
-    fn default() -> Self {
-        Config {
-            port: 3000,
-            database_url: "sqlite:data/tracking.db".to_string(),
-            base_url: "http://localhost:3000".to_string(),
+    // Ensure tenant exists (create if not)
+    if let Err(e) = state.db.create_tenant(&tenant_id, &tenant_id).await {
+        eprintln!("Failed to create/ensure tenant: {}", e);
+        return AppError::internal().into_response();
+    }
+
+    if matches!(&payload.metadata, Some(metadata) if !metadata.is_object()) {
+        return AppError::BadRequest("metadata must be a JSON object".to_string()).into_response();
+    }
+    let external_id = payload
+        .external_id
+        .map(|id| id.trim().to_string())
+        .filter(|id| !id.is_empty());
+
+    let daily_limit = match daily_email_limit(&state, &tenant_id).await {
+        Ok(limit) => limit,
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return AppError::internal().into_response();
+        }
+    };
+
+    if state.config.reject_suppressed_recipients {
+        if let Some(recipient) = payload.recipient.as_deref() {
+            match state.db.is_suppressed(&tenant_id, recipient).await {
+                Ok(true) => {
+                    return AppError::Conflict("recipient has unsubscribed".to_string())
+                        .into_response()
+                }
+                Ok(false) => {}
+                Err(e) => {
+                    eprintln!("Database error: {}", e);
+                    return AppError::internal().into_response();
+                }
+            }
         }
     }
-}
 
-impl Config {
-    pub fn from_env() -> Result<Self, envy::Error> {
-        envy::from_env()
+    // Create email record
+    let new_email = NewEmail {
+        subject: payload.subject,
+        recipient: payload.recipient,
+        campaign_id: payload.campaign_id,
+        metadata: payload.metadata,
+        idempotency_key,
+        tracking_consent: payload.tracking_consent,
+        expires_at: payload.expires_at,
+        resend_of: None,
+        external_id,
+    };
+    // Replaying an earlier request doesn't create anything, so it goes
+    // through even once the quota is used up
+    match state.db.create_email_within_quota(&tenant_id, &new_email, daily_limit).await {
+        Ok(Ok(CreatedEmail { email, created, quota_remaining })) => {
+            if created {
+                state.record_activity(&tenant_id).await;
+            }
+            let mut response = CreateEmailResponse::for_email(&state.config.public_url(), &tenant_id, &email);
+            // A replayed idempotency key or external id gets the original
+            // email back, with how far it's been engaged with so the caller
+            // can skip resending
+            if !created {
+                response.existing = true;
+                response.stats = match state.db.get_email_engagement(email.id).await {
+                    Ok(stats) => Some(stats),
+                    Err(e) => {
+                        eprintln!("Database error: {}", e);
+                        return AppError::internal().into_response();
+                    }
+                };
+            }
+            let status = if created { StatusCode::CREATED } else { StatusCode::OK };
+            let mut response = (status, Json(response)).into_response();
+            if let Some(remaining) = quota_remaining {
+                response.headers_mut().insert("x-quota-remaining", HeaderValue::from(remaining));
+            }
+            response
+        }
+        Ok(Err(_)) => AppError::QuotaExceeded("daily email quota exceeded".to_string()).into_response(),
+        Err(e) => {
+            eprintln!("Failed to create email: {}", e);
+            AppError::internal().into_response()
+        }
     }
 }
 
-#[derive(Clone)]
-pub struct AppState {
-    pub db: Arc<Database>,
-    pub config: Config,
-}
+/// Lists emails. Besides `limit` and `offset`, any `meta.<key>=<value>`
+/// parameter filters on that metadata key.
+pub async fn list_emails(
+    Path(tenant_id): Path<String>,
+    Query(params): Query<HashMap<String, String>>,
+    State(state): State<AppState>,
+) -> impl IntoResponse {
+    let parse_number = |name: &str| -> Result<Option<i64>, AppError> {
+        params
+            .get(name)
+            .map(|value| {
+                value
+                    .parse::<i64>()
+                    .map_err(|_| AppError::BadRequest(format!("'{}' must be an integer", name)))
+            })
+            .transpose()
+    };
+    let (limit, offset) = match (parse_number("limit"), parse_number("offset")) {
+        (Ok(limit), Ok(offset)) => (
+            limit.unwrap_or(DEFAULT_EMAIL_PAGE).clamp(1, MAX_EMAIL_PAGE),
+            offset.unwrap_or(0).max(0),
+        ),
+        (Err(e), _) | (_, Err(e)) => return e.into_response(),
+    };
 
-#[derive(Template)]
-#[template(path = "dashboard.html")]
-struct DashboardTemplate {
-    tenant_id: String,
-    stats: EventStats,
-    base_url: String,
-}
+    let mut metadata_filters = Vec::new();
+    for (name, value) in &params {
+        if let Some(key) = name.strip_prefix("meta.") {
+            if !database::is_valid_metadata_key(key) {
+                return AppError::BadRequest(format!("invalid metadata key '{}'", key)).into_response();
+            }
+            metadata_filters.push((key.to_string(), value.clone()));
+        }
+    }
+    let expired = match params.get("expired").map(|value| value.parse::<bool>()) {
+        None => None,
+        Some(Ok(expired)) => Some(expired),
+        Some(Err(_)) => {
+            return AppError::BadRequest("'expired' must be true or false".to_string()).into_response()
+        }
+    };
 
-#[derive(Deserialize)]
-struct ClickQuery {
-    url: String,
+    match state
+        .db
+        .list_emails_with_stats(&tenant_id, &metadata_filters, expired, limit, offset)
+        .await
+    {
+        Ok(emails) => Json(emails).into_response(),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            AppError::internal().into_response()
+        }
+    }
 }
 
-#[derive(Deserialize, Serialize)]
-pub struct CreateEmailRequest {
-    pub subject: Option<String>,
-    pub recipient: Option<String>,
-}
+/// Permanently deletes the tenant's emails matching `campaign_id` and/or
+/// `created_before` (a date means its midnight), with their events. At
+/// least one filter is required so a bare request can't wipe the tenant.
+pub async fn delete_emails(
+    Path(tenant_id): Path<String>,
+    Query(params): Query<HashMap<String, String>>,
+    headers: HeaderMap,
+    peer: Option<ConnectInfo<SocketAddr>>,
+    State(state): State<AppState>,
+) -> impl IntoResponse {
+    let created_before = match params
+        .get("created_before")
+        .map(|value| parse_filter_bound(value, "created_before", false))
+        .transpose()
+    {
+        Ok(created_before) => created_before,
+        Err(e) => return e.into_response(),
+    };
+    let filter = database::EmailDeleteFilter {
+        campaign_id: params.get("campaign_id").cloned(),
+        created_before,
+    };
+    if filter.is_empty() {
+        return AppError::BadRequest("give 'campaign_id' or 'created_before' to choose which emails to delete".to_string())
+            .into_response();
+    }
 
-#[derive(Serialize)]
-pub struct CreateEmailResponse {
-    pub email_id: i64,
-    pub tracking_pixel_url: String,
+    match state.db.delete_emails_where(&tenant_id, &filter).await {
+        Ok((summary, emails)) => {
+            for email in &emails {
+                state.email_cache.invalidate(email);
+            }
+            state.recent_events.clear(&tenant_id);
+            if let Some(unique_opens) = &state.unique_opens {
+                unique_opens.forget(&tenant_id);
+            }
+            let actor = admin::actor(&state, &headers, peer);
+            state
+                .record_audit(
+                    &actor,
+                    "emails.delete",
+                    &tenant_id,
+                    serde_json::json!({
+                        "campaign_id": filter.campaign_id,
+                        "created_before": filter.created_before,
+                        "deleted": summary,
+                    }),
+                )
+                .await;
+            Json(summary).into_response()
+        }
+        Err(e) => {
+            eprintln!("Failed to delete emails: {}", e);
+            AppError::internal().into_response()
+        }
+    }
 }
 
-pub async fn health_check() -> impl IntoResponse {
-    Json(serde_json::json!({
-        "status": "healthy",
-        "service": "little-bell",
-        "version": "0.1.0"
-    }))
+/// Records that an email was handed to the mail server, so it counts
+/// towards open and click rates, and logs a `sent` event. Marking an email
+/// sent again keeps the first time and logs nothing.
+pub async fn mark_email_sent(
+    Path((tenant_id, email_id)): Path<(String, i64)>,
+    State(state): State<AppState>,
+) -> impl IntoResponse {
+    let email = match validate_event(&state, &tenant_id, &EmailRef::Id(email_id), "sent").await {
+        Ok(email) => email,
+        Err(EventRejection::Database(e)) => {
+            eprintln!("Database error: {}", e);
+            return AppError::internal().into_response();
+        }
+        Err(_) => return AppError::EmailNotFound(email_id.to_string()).into_response(),
+    };
+
+    let (sent_at, marked) = match state.db.mark_email_sent(email.id, chrono::Utc::now()).await {
+        Ok(sent) => sent,
+        Err(e) => {
+            eprintln!("Failed to mark email {} sent: {}", email.id, e);
+            return AppError::internal().into_response();
+        }
+    };
+    if marked {
+        state.email_cache.invalidate(&email);
+        let event = NewEvent {
+            email_id: email.id,
+            event_type: "sent".to_string(),
+            timestamp: Some(sent_at),
+            user_agent: None,
+            ip_address: None,
+            target_url: None,
+            client_id: None,
+            suspicious: false,
+            premature: false,
+        };
+        if let Err(e) = state.log_tracking_event(&tenant_id, event).await {
+            eprintln!("Failed to log sent event: {}", e);
+            return AppError::internal().into_response();
+        }
+    }
+
+    Json(serde_json::json!({ "email_id": email.id, "sent_at": time_format::to_json(&sent_at) })).into_response()
 }
 
-pub async fn track_open(
-    Path((tenant_id, email_id_str)): Path<(String, String)>,
-    headers: HeaderMap,
+/// Creates a new email copying an existing one's subject, recipient,
+/// campaign and metadata, with its own id and token so the resend's
+/// engagement is tracked apart from the original's. Counts against the
+/// daily quota like any other email.
+pub async fn resend_email(
+    Path((tenant_id, email_id)): Path<(String, i64)>,
     State(state): State<AppState>,
 ) -> impl IntoResponse {
-    // Extract email ID from the path (remove .gif extension)
-    let email_id_str = email_id_str.strip_suffix(".gif").unwrap_or(&email_id_str);
-    let email_id = match email_id_str.parse::<i64>() {
-        Ok(id) => id,
-        Err(_) => return StatusCode::BAD_REQUEST.into_response(),
+    let original = match state.db.get_email(email_id, &tenant_id).await {
+        Ok(Some(email)) => email,
+        Ok(None) => return AppError::EmailNotFound(email_id.to_string()).into_response(),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return AppError::internal().into_response();
+        }
     };
 
-    // Extract user agent and IP address
-    let user_agent = headers
-        .get("user-agent")
-        .and_then(|v| v.to_str().ok())
-        .map(|s| s.to_string());
-    
-    let ip_address = headers
-        .get("x-forwarded-for")
-        .or_else(|| headers.get("x-real-ip"))
-        .and_then(|v| v.to_str().ok())
-        .map(|s| s.split(',').next().unwrap_or(s).trim().to_string());
+    let daily_limit = match daily_email_limit(&state, &tenant_id).await {
+        Ok(limit) => limit,
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return AppError::internal().into_response();
+        }
+    };
 
-    // Verify email exists and belongs to tenant
-    match state.db.get_email(email_id, &tenant_id).await {
-        Ok(Some(_)) => {
-            // Log the open event
-            if let Err(e) = state.db.log_event(
-                email_id,
-                "open",
-                user_agent.as_deref(),
-                ip_address.as_deref(),
-            ).await {
-                eprintln!("Failed to log open event: {}", e);
-                return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    if state.config.reject_suppressed_recipients {
+        if let Some(recipient) = original.recipient.as_deref() {
+            match state.db.is_suppressed(&tenant_id, recipient).await {
+                Ok(true) => {
+                    return AppError::Conflict("recipient has unsubscribed".to_string()).into_response()
+                }
+                Ok(false) => {}
+                Err(e) => {
+                    eprintln!("Database error: {}", e);
+                    return AppError::internal().into_response();
+                }
             }
+        }
+    }
 
-            // Return 1x1 transparent GIF
-            let gif_bytes = include_bytes!("pixel.gif");
-            Response::builder()
-                .header("Content-Type", "image/gif")
-                .header("Cache-Control", "no-store, no-cache, must-revalidate")
-                .header("Pragma", "no-cache")
-                .header("Expires", "0")
-                .body(axum::body::Body::from(&gif_bytes[..]))
-                .unwrap()
-                .into_response()
+    match state.db.resend_email(&tenant_id, original.id, daily_limit).await {
+        Ok(Some(Ok(CreatedEmail { email, quota_remaining, .. }))) => {
+            state.record_activity(&tenant_id).await;
+            let response = CreateEmailResponse::for_email(&state.config.public_url(), &tenant_id, &email);
+            let mut response = (StatusCode::CREATED, Json(response)).into_response();
+            if let Some(remaining) = quota_remaining {
+                response.headers_mut().insert("x-quota-remaining", HeaderValue::from(remaining));
+            }
+            response
         }
-        Ok(None) => StatusCode::NOT_FOUND.into_response(),
+        Ok(Some(Err(_))) => AppError::QuotaExceeded("daily email quota exceeded".to_string()).into_response(),
+        // Deleted in the meantime
+        Ok(None) => AppError::EmailNotFound(email_id.to_string()).into_response(),
         Err(e) => {
-            eprintln!("Database error: {}", e);
-            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+            eprintln!("Failed to resend email {}: {}", email_id, e);
+            AppError::internal().into_response()
         }
     }
 }
 
-pub async fn track_click(
+pub async fn get_pixel_url(
     Path((tenant_id, email_id)): Path<(String, i64)>,
-    Query(params): Query<ClickQuery>,
-    headers: HeaderMap,
     State(state): State<AppState>,
 ) -> impl IntoResponse {
-    // Extract user agent and IP address
-    let user_agent = headers
-        .get("user-agent")
-        .and_then(|v| v.to_str().ok())
-        .map(|s| s.to_string());
-    
-    let ip_address = headers
-        .get("x-forwarded-for")
-        .or_else(|| headers.get("x-real-ip"))
-        .and_then(|v| v.to_str().ok())
-        .map(|s| s.split(',').next().unwrap_or(s).trim().to_string());
-
-    // Verify email exists and belongs to tenant
     match state.db.get_email(email_id, &tenant_id).await {
-        Ok(Some(_)) => {
-            // Log the click event
-            if let Err(e) = state.db.log_event(
-                email_id,
-                "click",
-                user_agent.as_deref(),
-                ip_address.as_deref(),
-            ).await {
-                eprintln!("Failed to log click event: {}", e);
-                return StatusCode::INTERNAL_SERVER_ERROR.into_response();
-            }
-
-            // Redirect to the original URL
-            Redirect::temporary(&params.url).into_response()
+        Ok(Some(email)) => {
+            Json(CreateEmailResponse::for_email(&state.config.public_url(), &tenant_id, &email)).into_response()
         }
-        Ok(None) => StatusCode::NOT_FOUND.into_response(),
+        Ok(None) => AppError::EmailNotFound(email_id.to_string()).into_response(),
         Err(e) => {
             eprintln!("Database error: {}", e);
-            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+            AppError::internal().into_response()
         }
     }
 }
 
-pub async fn show_dashboard(
-    Path(tenant_id): Path<String>,
+#[derive(Debug, Deserialize)]
+pub struct EmailEventsQuery {
+    pub limit: Option<i64>,
+    pub offset: Option<i64>,
+}
+
+/// Every event recorded for one email, oldest first, for looking into what
+/// happened with a particular recipient.
+pub async fn list_email_events(
+    Path((tenant_id, email_id)): Path<(String, i64)>,
+    Query(query): Query<EmailEventsQuery>,
     State(state): State<AppState>,
 ) -> impl IntoResponse {
-    // Ensure tenant exists (create if not)
-    if let Err(e) = state.db.create_tenant(&tenant_id, &tenant_id).await {
-        eprintln!("Failed to create/ensure tenant: {}", e);
-        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
-    }
+    let limit = query.limit.unwrap_or(DEFAULT_EMAIL_EVENT_PAGE).clamp(1, MAX_EMAIL_EVENT_PAGE);
+    let offset = query.offset.unwrap_or(0).max(0);
 
-    // Get statistics for the tenant
-    match state.db.get_tenant_stats(&tenant_id).await {
-        Ok(stats) => {
-            let template = DashboardTemplate {
-                tenant_id,
-                stats,
-                base_url: state.config.base_url.clone(),
-            };
-            match template.render() {
-                Ok(html) => Html(html).into_response(),
-                Err(e) => {
-                    eprintln!("Template render error: {}", e);
-                    StatusCode::INTERNAL_SERVER_ERROR.into_response()
-                }
-            }
-        }
+    match state.db.list_email_events(email_id, &tenant_id, limit, offset).await {
+        Ok(Some(events)) => Json(events).into_response(),
+        Ok(None) => AppError::EmailNotFound(email_id.to_string()).into_response(),
         Err(e) => {
             eprintln!("Database error: {}", e);
-            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+            AppError::internal().into_response()
         }
     }
 }
 
-pub async fn create_email(
+#[derive(Debug, Deserialize)]
+pub struct RecipientListQuery {
+    pub limit: Option<i64>,
+    pub offset: Option<i64>,
+    #[serde(default)]
+    pub sort_by: RecipientSort,
+    /// Defaults per column, see `RecipientSort::default_direction`.
+    pub direction: Option<SortDirection>,
+}
+
+/// Each distinct recipient with their engagement across all their emails,
+/// for account management.
+pub async fn list_recipients(
     Path(tenant_id): Path<String>,
+    query: Result<Query<RecipientListQuery>, QueryRejection>,
     State(state): State<AppState>,
-    Json(payload): Json<CreateEmailRequest>,
 ) -> impl IntoResponse {
-    // Ensure tenant exists (create if not)
-    if let Err(e) = state.db.create_tenant(&tenant_id, &tenant_id).await {
-        eprintln!("Failed to create/ensure tenant: {}", e);
-        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
-    }
+    let Query(query) = match query {
+        Ok(query) => query,
+        Err(rejection) => return AppError::BadRequest(rejection.body_text()).into_response(),
+    };
+    let limit = query.limit.unwrap_or(DEFAULT_RECIPIENT_PAGE).clamp(1, MAX_RECIPIENT_PAGE);
+    let offset = query.offset.unwrap_or(0).max(0);
+    let direction = query.direction.unwrap_or(query.sort_by.default_direction());
 
-    // Create email record
-    match state.db.create_email(
-        &tenant_id,
-        payload.subject.as_deref(),
-        payload.recipient.as_deref(),
-    ).await {
-        Ok(email_id) => {
-            let tracking_pixel_url = format!(
-                "{}/{}/pixel/{}.gif",
-                state.config.base_url, tenant_id, email_id
-            );
-            
-            let response = CreateEmailResponse {
-                email_id,
-                tracking_pixel_url,
-            };
-            
-            (StatusCode::CREATED, Json(response)).into_response()
+    match state
+        .db
+        .list_recipients_with_stats(&tenant_id, query.sort_by, direction, limit, offset)
+        .await
+    {
+        Ok((recipients, total)) => Json(serde_json::json!({
+            "recipients": recipients,
+            "total": total,
+            "limit": limit,
+            "offset": offset,
+        }))
+        .into_response(),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            AppError::internal().into_response()
         }
+    }
+}
+
+/// Ready-to-paste markup for an email's tracking pixel.
+pub fn pixel_snippet(pixel_url: &str) -> String {
+    let src = pixel_url
+        .replace('&', "&amp;")
+        .replace('"', "&quot;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;");
+    format!(
+        "<img src=\"{}\" width=\"1\" height=\"1\" alt=\"\" style=\"display:none\">",
+        src
+    )
+}
+
+/// The pixel `<img>` tag for an email, as JSON (`html` and `url`) or, when
+/// the client asks for text, just the tag.
+pub async fn get_pixel_snippet(
+    Path((tenant_id, email_id)): Path<(String, String)>,
+    headers: HeaderMap,
+    State(state): State<AppState>,
+) -> impl IntoResponse {
+    let email = match state.find_email(&tenant_id, &EmailRef::parse(&email_id)).await {
+        Ok(Some(email)) => email,
+        Ok(None) => return AppError::EmailNotFound(email_id).into_response(),
         Err(e) => {
-            eprintln!("Failed to create email: {}", e);
-            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+            eprintln!("Database error: {}", e);
+            return AppError::internal().into_response();
         }
+    };
+
+    let url = tracking_pixel_url(&state.config.public_url(), &tenant_id, &email);
+    let html = pixel_snippet(&url);
+
+    let accept = headers
+        .get(header::ACCEPT)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default();
+    let wants_text = (accept.contains("text/plain") || accept.contains("text/html"))
+        && !accept.contains("application/json");
+    if wants_text {
+        return ([(header::CONTENT_TYPE, "text/plain; charset=utf-8")], html).into_response();
     }
+    Json(serde_json::json!({ "html": html, "url": url })).into_response()
 }
 
 pub async fn get_click_url(
-    Path((tenant_id, email_id)): Path<(String, i64)>,
+    Path((tenant_id, email_id)): Path<(String, String)>,
     Query(mut params): Query<HashMap<String, String>>,
     State(state): State<AppState>,
 ) -> impl IntoResponse {
     let target_url = match params.remove("url") {
         Some(url) => url,
-        None => return (StatusCode::BAD_REQUEST, "Missing 'url' parameter").into_response(),
+        None => return AppError::BadRequest("missing 'url' parameter".to_string()).into_response(),
     };
 
+    if let Err(e) = validate_click_target(&target_url, &state.config) {
+        return e.into_response();
+    }
+    let short = matches!(params.get("short").map(String::as_str), Some("true" | "1"));
+
     // Verify email exists and belongs to tenant
-    match state.db.get_email(email_id, &tenant_id).await {
-        Ok(Some(_)) => {
+    match state.find_email(&tenant_id, &EmailRef::parse(&email_id)).await {
+        Ok(Some(email)) if short => match state.db.get_or_create_short_link(email.id, &target_url).await {
+            Ok(link) => Json(serde_json::json!({
+                "click_url": format!("{}/{}/l/{}", state.config.public_url(), tenant_id, link.short_code),
+                "original_url": target_url
+            })).into_response(),
+            Err(e) => {
+                eprintln!("Failed to create short link: {}", e);
+                AppError::internal().into_response()
+            }
+        },
+        Ok(Some(email)) => {
             let click_url = format!(
                 "{}/{}/click/{}?url={}",
-                state.config.base_url,
+                state.config.public_url(),
                 tenant_id,
-                email_id,
+                email.url_key(),
                 urlencoding::encode(&target_url)
             );
             Json(serde_json::json!({
@@ -286,24 +2774,231 @@ pub async fn get_click_url(
                 "original_url": target_url
             })).into_response()
         }
-        Ok(None) => StatusCode::NOT_FOUND.into_response(),
+        Ok(None) => AppError::EmailNotFound(email_id).into_response(),
         Err(e) => {
             eprintln!("Database error: {}", e);
-            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+            AppError::internal().into_response()
         }
     }
 }
 
+fn cors_layer(config: &Config) -> CorsLayer {
+    let origins = match config.cors_origins() {
+        Ok(Some(origins)) => origins,
+        Ok(None) => return CorsLayer::permissive(),
+        // Startup refuses this config; anyone building the app regardless
+        // gets no cross-origin access rather than all of it
+        Err(message) => {
+            eprintln!("Invalid CORS configuration: {}", message);
+            Vec::new()
+        }
+    };
+
+    // A concrete origin list lets browsers send cookies along, which the
+    // wildcard form can't do.
+    CorsLayer::new()
+        .allow_origin(AllowOrigin::list(origins))
+        .allow_methods([Method::GET, Method::POST, Method::PUT, Method::PATCH, Method::DELETE])
+        .allow_headers([
+            header::CONTENT_TYPE,
+            header::AUTHORIZATION,
+            HeaderName::from_static("idempotency-key"),
+            HeaderName::from_static("x-admin-key"),
+        ])
+        .expose_headers([request_id::REQUEST_ID_HEADER, HeaderName::from_static("x-quota-remaining")])
+        .allow_credentials(true)
+}
+
+/// Brotli or gzip for JSON and HTML, negotiated from `Accept-Encoding`. The
+/// tracking pixels are tiny and already compressed, so they're sent as-is
+/// (the default predicate also skips small bodies, images and SSE).
+fn compression_layer() -> CompressionLayer<impl Predicate> {
+    let predicate = DefaultPredicate::new()
+        .and(NotForContentType::const_new("image/gif"))
+        .and(NotForContentType::const_new("image/png"))
+        .and(NotForContentType::const_new("image/webp"));
+
+    CompressionLayer::new()
+        .br(true)
+        .gzip(true)
+        .compress_when(predicate)
+}
+
+/// The default tenant's path for a tenant-less `/pixel/:email_id`,
+/// `/click/:email_id`, `/emails` or `/dashboard` path under `path_prefix`,
+/// or None if `path` isn't one of those.
+fn default_tenant_path(path: &str, path_prefix: &str, tenant_id: &str) -> Option<String> {
+    let rest = path.strip_prefix(path_prefix)?;
+    let segments: Vec<&str> = rest.strip_prefix('/')?.split('/').collect();
+    let aliased = match segments.as_slice() {
+        ["pixel" | "click", email_id] => !email_id.is_empty(),
+        ["emails" | "dashboard"] => true,
+        _ => false,
+    };
+    aliased.then(|| format!("{}/{}{}", path_prefix, tenant_id, rest))
+}
+
+/// Caps request bodies at `max_bytes` in place of axum's default limit.
+/// Oversized requests are rejected with 413.
+fn body_limit(max_bytes: usize) -> (DefaultBodyLimit, RequestBodyLimitLayer) {
+    (DefaultBodyLimit::disable(), RequestBodyLimitLayer::new(max_bytes))
+}
+
 pub async fn create_app(db: Arc<Database>, config: Config) -> Router {
-    let state = AppState { db, config };
+    router(AppState::new(db, config))
+}
 
-    Router::new()
-        .route("/health", get(health_check))
-        .route("/:tenant_id/pixel/:email_id", get(track_open))
+/// The application's routes over an existing `AppState`, for callers that
+/// need the state back, e.g. to call `AppState::shutdown`.
+pub fn router(state: AppState) -> Router {
+    let cors = cors_layer(&state.config);
+    let max_body_bytes = state.config.max_body_bytes;
+    let max_batch_body_bytes = state.config.max_batch_body_bytes;
+    let max_import_body_bytes = state.config.max_import_body_bytes;
+    let path_prefix = state.config.normalized_path_prefix();
+
+    let tenant_routes = Router::new()
+        .route("/:tenant_id/pixel/:email_id", get(track_open).head(track_open))
+        .route(
+            "/:tenant_id/bg/:email_id",
+            get(track_background_open).head(track_background_open),
+        )
+        .route("/:tenant_id/verify/:email_id", get(verify_email))
         .route("/:tenant_id/click/:email_id", get(track_click))
+        .route("/:tenant_id/l/:short_code", get(track_short_click))
+        .route("/:tenant_id/unsubscribe/:email_id", get(track_unsubscribe))
         .route("/:tenant_id/dashboard", get(show_dashboard))
-        .route("/:tenant_id/emails", post(create_email))
+        .route("/:tenant_id/stats", get(get_stats))
+        .route("/:tenant_id/campaigns/:campaign_id/stats", get(get_campaign_stats))
+        .route("/:tenant_id/summary", get(get_summary))
+        .route("/:tenant_id/latency", get(get_latency))
+        .route("/:tenant_id/top-links", get(get_top_links))
+        .route("/:tenant_id/hourly-heatmap", get(get_hourly_heatmap))
+        .route(
+            "/:tenant_id/events",
+            post(ingest_events).layer(body_limit(max_batch_body_bytes)),
+        )
+        .route("/:tenant_id/events/stream", get(stream_events))
+        .route("/:tenant_id/ws/stats", get(stats_socket))
+        .route(
+            "/:tenant_id/settings",
+            get(get_tenant_settings)
+                .merge(put(update_tenant_settings).layer(body_limit(max_body_bytes)))
+                .merge(patch(patch_tenant_settings).layer(body_limit(max_body_bytes))),
+        )
+        .route(
+            "/:tenant_id/emails",
+            get(list_emails)
+                .merge(post(create_email).layer(body_limit(max_body_bytes)))
+                .merge(delete(delete_emails).route_layer(axum::middleware::from_fn_with_state(
+                    state.clone(),
+                    admin::require_admin,
+                ))),
+        )
+        .route(
+            "/:tenant_id/emails/import",
+            post(email_import::import_emails).layer(body_limit(max_import_body_bytes)),
+        )
+        .route("/:tenant_id/emails/:email_id/events", get(list_email_events))
+        .route("/:tenant_id/recipients", get(list_recipients))
+        .route("/:tenant_id/emails/:email_id/sent", post(mark_email_sent))
+        .route("/:tenant_id/emails/:email_id/resend", post(resend_email))
+        .route("/:tenant_id/emails/:email_id/pixel-url", get(get_pixel_url))
+        .route("/:tenant_id/emails/:email_id/snippet", get(get_pixel_snippet))
         .route("/:tenant_id/click-url/:email_id", get(get_click_url))
-        .layer(CompressionLayer::new())
-        .with_state(state)
+        .route(
+            "/:tenant_id/webhooks",
+            get(webhooks::list_webhooks)
+                .merge(post(webhooks::create_webhook).layer(body_limit(max_body_bytes)))
+                .route_layer(axum::middleware::from_fn_with_state(
+                    state.clone(),
+                    admin::require_admin,
+                )),
+        )
+        .route(
+            "/:tenant_id/webhooks/deliveries",
+            get(webhooks::list_deliveries).route_layer(axum::middleware::from_fn_with_state(
+                state.clone(),
+                admin::require_admin,
+            )),
+        )
+        .route(
+            "/:tenant_id/webhooks/:webhook_id",
+            axum::routing::delete(webhooks::delete_webhook).route_layer(axum::middleware::from_fn_with_state(
+                state.clone(),
+                admin::require_admin,
+            )),
+        )
+        .route(
+            "/:tenant_id/export.json",
+            get(export::export_tenant).route_layer(axum::middleware::from_fn_with_state(
+                state.clone(),
+                admin::require_admin,
+            )),
+        )
+        .route(
+            "/:tenant_id/reset-stats",
+            post(reset_stats).route_layer(axum::middleware::from_fn_with_state(
+                state.clone(),
+                admin::require_admin,
+            )),
+        )
+        .route(
+            "/:tenant_id/import.json",
+            post(export::import_tenant)
+                .layer(body_limit(max_import_body_bytes))
+                .route_layer(axum::middleware::from_fn_with_state(
+                    state.clone(),
+                    admin::require_admin,
+                )),
+        )
+        .route_layer(axum::middleware::from_fn_with_state(state.clone(), require_valid_tenant));
+
+    let routes = Router::new()
+        .route("/health", get(health_check))
+        .route("/ready", get(readiness_check))
+        .route("/assets/:name", get(assets::serve_asset))
+        .route("/pixel.gif", get(preview_pixel))
+        .route(
+            "/metrics",
+            get(metrics).route_layer(axum::middleware::from_fn_with_state(
+                state.clone(),
+                admin::require_allowed_ip,
+            )),
+        )
+        .nest("/admin", admin::router(state.clone()))
+        .merge(tenant_routes);
+
+    let app = if path_prefix.is_empty() {
+        routes
+    } else {
+        Router::new().nest(&path_prefix, routes)
+    };
+    let app = app.fallback(|| async { AppError::RouteNotFound });
+
+    let default_tenant = state.config.default_tenant.clone();
+    let app = app
+        .layer(axum::middleware::from_fn_with_state(state.clone(), request_timeout::enforce))
+        .layer(axum::middleware::from_fn_with_state(state.clone(), route_metrics::track))
+        .layer(axum::middleware::from_fn_with_state(state.clone(), time_format::apply))
+        .layer(axum::middleware::from_fn_with_state(state.clone(), hsts::add_header))
+        .layer(compression_layer())
+        .layer(cors)
+        .layer(axum::middleware::from_fn(request_id::propagate))
+        .with_state(state);
+
+    let Some(tenant_id) = default_tenant else {
+        return app;
+    };
+    // Tenant-less paths are rewritten before routing, so they're served
+    // (and measured) exactly like the default tenant's own
+    Router::new().fallback_service(app.map_request(move |mut request: axum::extract::Request| {
+        if let Some(path) = default_tenant_path(request.uri().path(), &path_prefix, &tenant_id) {
+            let query = request.uri().query().map(|query| format!("?{}", query)).unwrap_or_default();
+            if let Ok(uri) = format!("{}{}", path, query).parse() {
+                *request.uri_mut() = uri;
+            }
+        }
+        request
+    }))
 }
\ No newline at end of file
@@ -1,20 +1,71 @@
-use envy;
-use little_bell::{create_app, database::Database, Config};
+use little_bell::{admin, create_app, database::{self, Database}, router, validate_tenant_id, AppState, Config};
 use std::sync::Arc;
+use std::time::Duration;
+
+fn main() {
+    // `--check` validates config and the database, then exits without serving
+    let check_only = std::env::args()
+        .skip(1)
+        .any(|arg| arg == "--check" || arg == "--check-config");
 
-#[tokio::main]
-async fn main() {
     // Load configuration from environment
     let config = match envy::from_env::<Config>() {
         Ok(config) => config,
+        Err(e) if check_only => {
+            eprintln!("Invalid configuration: {}", e);
+            std::process::exit(1);
+        }
         Err(e) => {
             eprintln!("Failed to load configuration: {}", e);
             Config::default()
         }
     };
 
+    // Configuration decides how the runtime is sized, so it's built by hand
+    let mut runtime = tokio::runtime::Builder::new_multi_thread();
+    runtime.enable_all();
+    if config.worker_threads > 0 {
+        runtime.worker_threads(config.worker_threads);
+    }
+    if config.max_blocking_threads > 0 {
+        runtime.max_blocking_threads(config.max_blocking_threads);
+    }
+    let runtime = match runtime.build() {
+        Ok(runtime) => runtime,
+        Err(e) => {
+            eprintln!("Failed to start runtime: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    runtime.block_on(run(config, check_only));
+}
+
+async fn run(config: Config, check_only: bool) {
+    if let Some(tenant_id) = &config.default_tenant {
+        if validate_tenant_id(tenant_id).is_err() {
+            eprintln!("Invalid DEFAULT_TENANT '{}': not a valid tenant id", tenant_id);
+            std::process::exit(1);
+        }
+    }
+
+    if let Err(message) = config.cors_origins() {
+        eprintln!("Invalid CORS_ALLOWED_ORIGINS: {}", message);
+        std::process::exit(1);
+    }
+
     // Ensure data directory exists
     let db_path = config.database_url.strip_prefix("sqlite:").unwrap_or(&config.database_url);
+    if database::is_in_memory(db_path) {
+        if !config.allow_in_memory {
+            eprintln!(
+                "Refusing to start: DATABASE_URL '{}' is in memory, so all data would be lost on restart. Set ALLOW_IN_MEMORY=true if that's intended.",
+                config.database_url
+            );
+            std::process::exit(1);
+        }
+        eprintln!("Warning: DATABASE_URL is in memory; all data will be lost when the server stops");
+    }
     if let Some(parent) = std::path::Path::new(db_path).parent() {
         if let Err(e) = std::fs::create_dir_all(parent) {
             eprintln!("Failed to create data directory: {}", e);
@@ -23,7 +74,7 @@ async fn main() {
     }
 
     // Initialize database
-    let db = match Database::new(db_path).await {
+    let db = match Database::with_options(db_path, &config.database_options()).await {
         Ok(db) => Arc::new(db),
         Err(e) => {
             eprintln!("Failed to initialize database: {}", e);
@@ -31,8 +82,60 @@ async fn main() {
         }
     };
 
-    // Create the application
-    let app = create_app(db, config.clone()).await;
+    if check_only {
+        let diagnostics = match db.diagnostics().await {
+            Ok(diagnostics) => diagnostics,
+            Err(e) => {
+                eprintln!("Failed to query database: {}", e);
+                std::process::exit(1);
+            }
+        };
+
+        // Building the router catches bad route configuration such as the path prefix
+        let _ = create_app(db, config.clone()).await;
+
+        println!("Configuration OK");
+        println!("Port: {}", config.port);
+        println!("Base URL: {}", config.public_url());
+        println!("Database: {}", config.database_url);
+        println!(
+            "Rows: {} tenants, {} emails, {} events",
+            diagnostics.tenants, diagnostics.emails, diagnostics.events
+        );
+        println!(
+            "Admin endpoints: {}",
+            if config.admin_key.is_some() { "enabled" } else { "disabled" }
+        );
+        return;
+    }
+
+    if let Some(interval_secs) = config.backup_interval_secs.filter(|secs| *secs > 0) {
+        let db = db.clone();
+        let backup_dir = config.backup_dir.clone();
+        let audit_log_enabled = config.audit_log_enabled;
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(Duration::from_secs(interval_secs));
+            // The first tick fires immediately; skip it so startup isn't a backup
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                // Failures are logged by run_backup; try again next interval
+                if let Ok(info) = admin::run_backup(&db, &backup_dir).await {
+                    if audit_log_enabled {
+                        let details = serde_json::json!({ "bytes": info.bytes, "duration_ms": info.duration_ms });
+                        let target = info.path.display().to_string();
+                        if let Err(e) = db.record_audit("scheduler", "backup.create", &target, &details).await {
+                            eprintln!("Failed to record audit entry for backup: {}", e);
+                        }
+                    }
+                }
+            }
+        });
+    }
+
+    // Create the application, keeping the state to close it down afterwards
+    let state = AppState::new(db, config.clone());
+    let app = router(state.clone());
 
     // Start the server
     let bind_addr = format!("0.0.0.0:{}", config.port);
@@ -48,8 +151,53 @@ async fn main() {
         }
     };
 
-    if let Err(e) = axum::serve(listener, app).await {
+    if config.admin_ip_allowlist().is_none() {
+        eprintln!("Warning: ADMIN_IP_ALLOWLIST is empty, so /metrics and /admin accept any client address");
+    }
+
+    let app = app.into_make_service_with_connect_info::<std::net::SocketAddr>();
+    if let Err(e) = axum::serve(listener, app)
+        .with_graceful_shutdown(shutdown_signal())
+        .await
+    {
         eprintln!("Server error: {}", e);
         std::process::exit(1);
     }
+
+    // Requests have drained; write whatever the event writer still holds
+    if !state.shutdown().await {
+        std::process::exit(1);
+    }
+    println!("Shut down");
+}
+
+/// Resolves on Ctrl-C or, on Unix, SIGTERM.
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            eprintln!("Failed to listen for Ctrl-C: {}", e);
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(e) => {
+                eprintln!("Failed to listen for SIGTERM: {}", e);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
+    println!("Shutting down, finishing in-flight requests");
 }
\ No newline at end of file
@@ -0,0 +1,103 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// A tenant's email, plus the client address when limits are per IP.
+type Key = (String, i64, Option<String>);
+
+struct Window {
+    started: Instant,
+    opens: u32,
+    /// Set once `opens` passes the limit; until then nothing is recorded.
+    cooling_until: Option<Instant>,
+}
+
+/// Caps how fast one email's pixel (or one client's, per email) may record
+/// opens, so a scanner refetching it thousands of times a second doesn't
+/// turn into as many database writes. Past `limit` opens within `window`
+/// the key cools down: the pixel is still served, the opens just aren't
+/// recorded until `cooldown` has passed. Counters live only in memory and
+/// are swept once they've expired.
+pub struct OpenLimiter {
+    limit: u32,
+    window: Duration,
+    cooldown: Duration,
+    per_ip: bool,
+    windows: Mutex<HashMap<Key, Window>>,
+    last_sweep: Mutex<Instant>,
+}
+
+impl OpenLimiter {
+    pub fn new(limit: u32, window: Duration, cooldown: Duration, per_ip: bool) -> Self {
+        OpenLimiter {
+            limit,
+            window,
+            cooldown,
+            per_ip,
+            windows: Mutex::new(HashMap::new()),
+            last_sweep: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Whether an open of `email_id` from `ip` should be recorded, counting
+    /// it if so.
+    pub fn allow(&self, tenant_id: &str, email_id: i64, ip: Option<&str>) -> bool {
+        let now = Instant::now();
+        self.sweep(now);
+
+        let key = (
+            tenant_id.to_string(),
+            email_id,
+            if self.per_ip { ip.map(str::to_string) } else { None },
+        );
+        let mut windows = self.windows.lock().unwrap();
+        let window = windows.entry(key).or_insert(Window {
+            started: now,
+            opens: 0,
+            cooling_until: None,
+        });
+        match window.cooling_until {
+            Some(until) if now < until => return false,
+            Some(_) => {
+                window.started = now;
+                window.opens = 0;
+                window.cooling_until = None;
+            }
+            None if now.duration_since(window.started) >= self.window => {
+                window.started = now;
+                window.opens = 0;
+            }
+            None => {}
+        }
+
+        window.opens += 1;
+        if window.opens > self.limit {
+            window.cooling_until = Some(now + self.cooldown);
+            eprintln!(
+                "Warning: more than {} opens of email {} in {}s; not recording its opens for {}s",
+                self.limit,
+                email_id,
+                self.window.as_secs(),
+                self.cooldown.as_secs()
+            );
+            return false;
+        }
+        true
+    }
+
+    /// Drops counters whose window and cooldown have both run out, at most
+    /// once per window.
+    fn sweep(&self, now: Instant) {
+        {
+            let mut last_sweep = self.last_sweep.lock().unwrap();
+            if now.duration_since(*last_sweep) < self.window {
+                return;
+            }
+            *last_sweep = now;
+        }
+        self.windows.lock().unwrap().retain(|_, window| match window.cooling_until {
+            Some(until) => now < until,
+            None => now.duration_since(window.started) < self.window,
+        });
+    }
+}
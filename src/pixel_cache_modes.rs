@@ -0,0 +1,39 @@
+use lru::LruCache;
+use std::num::NonZeroUsize;
+use std::sync::Mutex;
+
+use crate::database::PixelCacheMode;
+
+/// Tenants whose pixel cache mode is kept in memory.
+pub const PIXEL_CACHE_MODE_CAPACITY: usize = 10_000;
+
+/// Each recently seen tenant's pixel cache mode, so pixel hits don't read
+/// the tenant's settings. Anything that writes a tenant's settings must
+/// call `invalidate`.
+pub struct PixelCacheModes {
+    entries: Mutex<LruCache<String, PixelCacheMode>>,
+}
+
+impl Default for PixelCacheModes {
+    fn default() -> Self {
+        let capacity = NonZeroUsize::new(PIXEL_CACHE_MODE_CAPACITY).expect("capacity is not zero");
+        PixelCacheModes {
+            entries: Mutex::new(LruCache::new(capacity)),
+        }
+    }
+}
+
+impl PixelCacheModes {
+    pub fn get(&self, tenant_id: &str) -> Option<PixelCacheMode> {
+        self.entries.lock().unwrap().get(tenant_id).copied()
+    }
+
+    pub fn insert(&self, tenant_id: &str, mode: PixelCacheMode) {
+        self.entries.lock().unwrap().put(tenant_id.to_string(), mode);
+    }
+
+    /// Call this whenever the tenant's settings are written.
+    pub fn invalidate(&self, tenant_id: &str) {
+        self.entries.lock().unwrap().pop(tenant_id);
+    }
+}
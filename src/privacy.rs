@@ -0,0 +1,50 @@
+//! Masking for personal data on its way into logs and rendered pages.
+//! Storage always keeps the real values; only what's shown is masked, and
+//! only when `Config.mask_pii` is on.
+
+use std::borrow::Cow;
+
+/// Characters of a subject kept when masking.
+const SUBJECT_PREFIX_CHARS: usize = 12;
+
+/// `jane@example.com` -> `j***@example.com`. Anything without a usable
+/// local part and domain is masked entirely.
+pub fn mask_recipient(recipient: &str) -> String {
+    match recipient.trim().split_once('@') {
+        Some((local, domain)) if !local.is_empty() && !domain.is_empty() => {
+            let first = local.chars().next().unwrap();
+            format!("{}***@{}", first, domain)
+        }
+        _ => "***".to_string(),
+    }
+}
+
+/// Keeps the first few characters of a subject, enough to tell campaigns
+/// apart without carrying personalised text.
+pub fn truncate_subject(subject: &str) -> String {
+    let mut chars = subject.chars();
+    let prefix: String = chars.by_ref().take(SUBJECT_PREFIX_CHARS).collect();
+    if chars.next().is_some() {
+        format!("{}…", prefix)
+    } else {
+        prefix
+    }
+}
+
+/// The recipient as it may be shown: masked when `mask` is set.
+pub fn display_recipient(recipient: &str, mask: bool) -> Cow<'_, str> {
+    if mask {
+        Cow::Owned(mask_recipient(recipient))
+    } else {
+        Cow::Borrowed(recipient)
+    }
+}
+
+/// The subject as it may be shown: truncated when `mask` is set.
+pub fn display_subject(subject: &str, mask: bool) -> Cow<'_, str> {
+    if mask {
+        Cow::Owned(truncate_subject(subject))
+    } else {
+        Cow::Borrowed(subject)
+    }
+}
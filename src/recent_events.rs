@@ -0,0 +1,106 @@
+use lru::LruCache;
+use std::collections::VecDeque;
+use std::num::NonZeroUsize;
+use std::sync::Mutex;
+
+use crate::database::{Event, RECENT_EVENT_COUNT};
+
+struct Entry {
+    /// Newest first, at most `RECENT_EVENT_COUNT` long.
+    events: VecDeque<Event>,
+    /// Seeded from the database, so `events` is the tenant's true latest.
+    /// Until then events are collected but not served.
+    warm: bool,
+}
+
+/// The latest events of the busiest tenants, kept in memory so the
+/// dashboard can list them without a query. A tenant's buffer is seeded
+/// from the database the first time it's asked for and kept current from
+/// then on by every stored event; the oldest event drops out as a new one
+/// arrives. Tenants beyond `capacity` are evicted least recently used.
+pub struct RecentEvents {
+    /// None when disabled.
+    tenants: Option<Mutex<LruCache<String, Entry>>>,
+}
+
+impl RecentEvents {
+    /// Buffers for up to `capacity` tenants; 0 disables them.
+    pub fn new(capacity: usize) -> Self {
+        RecentEvents {
+            tenants: NonZeroUsize::new(capacity).map(|capacity| Mutex::new(LruCache::new(capacity))),
+        }
+    }
+
+    /// The tenant's latest events, newest first, if its buffer is warm.
+    /// Otherwise starts collecting them, for `warm` to complete once the
+    /// caller has read them from the database.
+    pub fn get(&self, tenant_id: &str) -> Option<Vec<Event>> {
+        let mut tenants = self.tenants.as_ref()?.lock().unwrap();
+        match tenants.get(tenant_id) {
+            Some(entry) if entry.warm => Some(entry.events.iter().cloned().collect()),
+            Some(_) => None,
+            None => {
+                tenants.put(
+                    tenant_id.to_string(),
+                    Entry {
+                        events: VecDeque::with_capacity(RECENT_EVENT_COUNT),
+                        warm: false,
+                    },
+                );
+                None
+            }
+        }
+    }
+
+    /// Merges the tenant's latest events as read from the database with
+    /// any stored since `get` started collecting, and starts serving them.
+    pub fn warm(&self, tenant_id: &str, events: Vec<Event>) {
+        let Some(tenants) = &self.tenants else {
+            return;
+        };
+        let mut tenants = tenants.lock().unwrap();
+        // Evicted (or cleared) in the meantime; the next `get` starts over
+        let Some(entry) = tenants.get_mut(tenant_id) else {
+            return;
+        };
+        for event in events {
+            insert(&mut entry.events, event);
+        }
+        entry.warm = true;
+    }
+
+    /// Adds a newly stored event to its tenant's buffer, if it has one.
+    pub fn push(&self, tenant_id: &str, event: &Event) {
+        let Some(tenants) = &self.tenants else {
+            return;
+        };
+        if let Some(entry) = tenants.lock().unwrap().peek_mut(tenant_id) {
+            insert(&mut entry.events, event.clone());
+        }
+    }
+
+    /// Forgets a tenant's buffer, e.g. after its events are deleted.
+    pub fn clear(&self, tenant_id: &str) {
+        if let Some(tenants) = &self.tenants {
+            tenants.lock().unwrap().pop(tenant_id);
+        }
+    }
+}
+
+/// Inserts `event` in newest-first order (backfilled events can be older
+/// than ones already held), skipping duplicates and dropping the oldest
+/// past `RECENT_EVENT_COUNT`.
+fn insert(events: &mut VecDeque<Event>, event: Event) {
+    if events.iter().any(|held| held.id == event.id) {
+        return;
+    }
+    let position = events
+        .iter()
+        .position(|held| (held.timestamp, held.id) < (event.timestamp, event.id))
+        .unwrap_or(events.len());
+    if position >= RECENT_EVENT_COUNT {
+        return;
+    }
+    events.insert(position, event);
+    events.truncate(RECENT_EVENT_COUNT);
+}
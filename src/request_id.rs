@@ -0,0 +1,50 @@
+use axum::{
+    extract::Request,
+    http::{HeaderName, HeaderValue},
+    middleware::Next,
+    response::Response,
+};
+
+pub const REQUEST_ID_HEADER: HeaderName = HeaderName::from_static("x-request-id");
+
+/// Longest caller-supplied id we pass through; anything else gets a fresh one.
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+tokio::task_local! {
+    static REQUEST_ID: String;
+}
+
+/// The id of the request being handled, if called from inside one.
+pub fn current() -> Option<String> {
+    REQUEST_ID.try_with(|id| id.clone()).ok()
+}
+
+fn incoming_id(request: &Request) -> Option<String> {
+    request
+        .headers()
+        .get(&REQUEST_ID_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .map(str::trim)
+        .filter(|id| !id.is_empty() && id.len() <= MAX_REQUEST_ID_LEN)
+        .map(str::to_string)
+}
+
+/// Reuses the caller's `X-Request-Id` (e.g. from a load balancer) or mints
+/// one, makes it available to handlers via [`current`], and echoes it on the
+/// response.
+pub async fn propagate(mut request: Request, next: Next) -> Response {
+    let id = incoming_id(&request).unwrap_or_else(|| uuid::Uuid::new_v4().simple().to_string());
+    // Both sources are valid header values: checked above, or hex
+    let value = HeaderValue::from_str(&id).expect("request id is a valid header value");
+    request.headers_mut().insert(REQUEST_ID_HEADER, value.clone());
+
+    let method = request.method().clone();
+    let path = request.uri().path().to_string();
+    let mut response = REQUEST_ID.scope(id.clone(), next.run(request)).await;
+
+    if response.status().is_server_error() {
+        eprintln!("[{}] {} {} -> {}", id, method, path, response.status());
+    }
+    response.headers_mut().insert(REQUEST_ID_HEADER, value);
+    response
+}
@@ -0,0 +1,56 @@
+use axum::{
+    extract::{MatchedPath, Request, State},
+    http::header,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use std::time::Duration;
+
+use crate::error::AppError;
+use crate::{select_pixel_format, AppState, PixelFormat};
+
+/// Gives up on requests that take longer than `Config.request_timeout_secs`,
+/// e.g. stuck waiting for the database. Most get a 503, but the tracking
+/// pixels are answered with the bare image so mail clients aren't left
+/// hanging; the open they were recording is lost. Only producing the
+/// response is timed, so streams (SSE, WebSocket, exports) run as long as
+/// they need once started.
+pub async fn enforce(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    let Some(secs) = state.config.request_timeout_secs else {
+        return next.run(request).await;
+    };
+    let route = request.extensions().get::<MatchedPath>().map(|path| path.as_str().to_string());
+    let pixel = match route.as_deref() {
+        Some(route) if route.ends_with("/:tenant_id/pixel/:email_id") => {
+            Some(select_pixel_format(request.headers()))
+        }
+        Some(route) if route.ends_with("/:tenant_id/bg/:email_id") => Some(PixelFormat::Png),
+        _ => None,
+    };
+
+    match tokio::time::timeout(Duration::from_secs(secs), next.run(request)).await {
+        Ok(response) => response,
+        Err(_) => {
+            eprintln!(
+                "Warning: {} timed out after {}s",
+                route.as_deref().unwrap_or("request"),
+                secs
+            );
+            match pixel {
+                Some(format) => {
+                    let image = format.image();
+                    (
+                        [
+                            (header::CONTENT_TYPE, image.content_type),
+                            (header::CACHE_CONTROL, "no-store, no-cache, must-revalidate"),
+                            (header::VARY, "Accept"),
+                        ],
+                        image.bytes,
+                    )
+                        .into_response()
+                }
+                None => AppError::ServiceUnavailable("request timed out".to_string()).into_response(),
+            }
+        }
+    }
+}
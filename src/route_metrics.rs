@@ -0,0 +1,128 @@
+use axum::{
+    extract::{MatchedPath, Request, State},
+    http::Method,
+    middleware::Next,
+    response::Response,
+};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::Instant;
+
+use crate::database::{LatencyHistogram, LatencyMetrics};
+use crate::AppState;
+
+/// Upper bounds (in microseconds) of the request latency buckets.
+const REQUEST_BUCKETS_US: [u64; 12] = [
+    500, 1_000, 2_500, 5_000, 10_000, 25_000, 50_000, 100_000, 250_000, 500_000, 1_000_000, 5_000_000,
+];
+
+/// Key for requests that matched no route, so probes for random paths share
+/// one entry.
+const UNMATCHED_ROUTE: &str = "<unmatched>";
+
+/// Key for methods outside the standard set, which clients can make up
+/// freely and would otherwise each get an entry.
+const OTHER_METHOD: &str = "OTHER";
+
+#[derive(Debug)]
+struct RouteStats {
+    /// Responses per status class, 1xx through 5xx.
+    statuses: [AtomicU64; 5],
+    latency: LatencyHistogram,
+}
+
+/// Request counts, status classes and latency per matched route pattern
+/// (e.g. `GET /:tenant_id/pixel/:email_id`), never per raw path.
+#[derive(Debug, Default)]
+pub struct RouteMetrics {
+    routes: RwLock<HashMap<String, Arc<RouteStats>>>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RouteMetricsSnapshot {
+    /// Responses per status class, e.g. `"2xx"`; classes with none are left
+    /// out.
+    pub statuses: BTreeMap<String, u64>,
+    pub latency: LatencyMetrics,
+}
+
+impl RouteMetrics {
+    pub fn record(&self, route: &str, status: u16, started: Instant) {
+        let stats = self.routes.read().unwrap().get(route).cloned();
+        let stats = match stats {
+            Some(stats) => stats,
+            None => self
+                .routes
+                .write()
+                .unwrap()
+                .entry(route.to_string())
+                .or_insert_with(|| {
+                    Arc::new(RouteStats {
+                        statuses: Default::default(),
+                        latency: LatencyHistogram::new(&REQUEST_BUCKETS_US),
+                    })
+                })
+                .clone(),
+        };
+
+        let class = (status / 100).clamp(1, 5) as usize - 1;
+        stats.statuses[class].fetch_add(1, Ordering::Relaxed);
+        stats.latency.record(started.elapsed());
+    }
+
+    pub fn snapshot(&self) -> BTreeMap<String, RouteMetricsSnapshot> {
+        self.routes
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(route, stats)| {
+                let statuses = stats
+                    .statuses
+                    .iter()
+                    .enumerate()
+                    .map(|(class, count)| (format!("{}xx", class + 1), count.load(Ordering::Relaxed)))
+                    .filter(|(_, count)| *count > 0)
+                    .collect();
+                let snapshot = RouteMetricsSnapshot {
+                    statuses,
+                    latency: stats.latency.snapshot(),
+                };
+                (route.clone(), snapshot)
+            })
+            .collect()
+    }
+}
+
+/// Times each request and records it against its method and route pattern.
+pub async fn track(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    let started = Instant::now();
+    let route = format!(
+        "{} {}",
+        method_label(request.method()),
+        request
+            .extensions()
+            .get::<MatchedPath>()
+            .map(MatchedPath::as_str)
+            .unwrap_or(UNMATCHED_ROUTE)
+    );
+    let response = next.run(request).await;
+    state.route_metrics.record(&route, response.status().as_u16(), started);
+    response
+}
+
+fn method_label(method: &Method) -> &str {
+    match *method {
+        Method::GET
+        | Method::HEAD
+        | Method::POST
+        | Method::PUT
+        | Method::DELETE
+        | Method::PATCH
+        | Method::OPTIONS
+        | Method::CONNECT
+        | Method::TRACE => method.as_str(),
+        _ => OTHER_METHOD,
+    }
+}
@@ -0,0 +1,161 @@
+use axum::{
+    http::{header, HeaderMap},
+    response::{IntoResponse, Response},
+    Json,
+};
+
+use crate::database::EventStats;
+use crate::error::AppError;
+
+/// The representations `GET /:tenant_id/stats` can answer in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StatsFormat {
+    Json,
+    /// `metric,value` rows.
+    Csv,
+    /// An aligned two-column table for terminals.
+    Text,
+    /// The Prometheus text exposition format, labelled with the tenant.
+    Prometheus,
+}
+
+impl StatsFormat {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "json" => Some(StatsFormat::Json),
+            "csv" => Some(StatsFormat::Csv),
+            "text" => Some(StatsFormat::Text),
+            "prometheus" => Some(StatsFormat::Prometheus),
+            _ => None,
+        }
+    }
+
+    fn from_media_type(media_type: &str) -> Option<Self> {
+        let mut parts = media_type.split(';').map(str::trim);
+        let essence = parts.next()?.to_ascii_lowercase();
+        match essence.as_str() {
+            "application/json" => Some(StatsFormat::Json),
+            "text/csv" => Some(StatsFormat::Csv),
+            "application/openmetrics-text" => Some(StatsFormat::Prometheus),
+            // Prometheus scrapes ask for text/plain with a version parameter
+            "text/plain" if parts.any(|param| param.starts_with("version=")) => Some(StatsFormat::Prometheus),
+            "text/plain" => Some(StatsFormat::Text),
+            _ => None,
+        }
+    }
+
+    /// `?format=` when given (`json`, `csv`, `text` or `prometheus`),
+    /// otherwise the `Accept` type with the highest quality we can produce.
+    /// Anything ambiguous, like `*/*` or no header at all, gets JSON.
+    pub fn negotiate(format: Option<&str>, headers: &HeaderMap) -> Result<Self, AppError> {
+        if let Some(name) = format {
+            return Self::from_name(name).ok_or_else(|| {
+                AppError::BadRequest(format!(
+                    "unknown format '{}'; use json, csv, text or prometheus",
+                    name
+                ))
+            });
+        }
+        let accept = headers
+            .get(header::ACCEPT)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default();
+        let mut best: Option<(f32, Self)> = None;
+        for media_type in accept.split(',') {
+            let Some(format) = Self::from_media_type(media_type) else {
+                continue;
+            };
+            let quality = media_type
+                .split(';')
+                .filter_map(|param| param.trim().strip_prefix("q="))
+                .find_map(|q| q.parse::<f32>().ok())
+                .unwrap_or(1.0);
+            // Ties go to the type listed first
+            if quality > 0.0 && best.is_none_or(|(best_quality, _)| quality > best_quality) {
+                best = Some((quality, format));
+            }
+        }
+        Ok(best.map_or(StatsFormat::Json, |(_, format)| format))
+    }
+
+    /// Whether the format includes `recent_events`; only JSON does.
+    pub fn includes_recent_events(&self) -> bool {
+        *self == StatsFormat::Json
+    }
+
+    pub fn respond(&self, tenant_id: &str, stats: &EventStats) -> Response {
+        match self {
+            StatsFormat::Json => Json(stats).into_response(),
+            StatsFormat::Csv => {
+                // Writing to memory can't fail
+                let mut writer = csv::Writer::from_writer(Vec::new());
+                writer.write_record(["metric", "value"]).expect("CSV goes to memory");
+                for (name, value) in metrics(stats) {
+                    writer.write_record([name, value]).expect("CSV goes to memory");
+                }
+                let body = writer.into_inner().expect("CSV goes to memory");
+                ([(header::CONTENT_TYPE, "text/csv; charset=utf-8")], body).into_response()
+            }
+            StatsFormat::Text => {
+                let rows = metrics(stats);
+                let width = rows.iter().map(|(name, _)| name.len()).max().unwrap_or(0);
+                let body: String = rows
+                    .iter()
+                    .map(|(name, value)| format!("{:<width$}  {}\n", name, value, width = width))
+                    .collect();
+                ([(header::CONTENT_TYPE, "text/plain; charset=utf-8")], body).into_response()
+            }
+            StatsFormat::Prometheus => {
+                let tenant = escape_label(tenant_id);
+                let mut body = String::new();
+                for (name, value) in metrics(stats) {
+                    let line = match name.strip_prefix("events.") {
+                        Some(event_type) => format!(
+                            "little_bell_events{{tenant=\"{}\",event_type=\"{}\"}} {}\n",
+                            tenant,
+                            escape_label(event_type),
+                            value
+                        ),
+                        None => format!("little_bell_{}{{tenant=\"{}\"}} {}\n", name, tenant, value),
+                    };
+                    body.push_str(&line);
+                }
+                ([(header::CONTENT_TYPE, "text/plain; version=0.0.4; charset=utf-8")], body).into_response()
+            }
+        }
+    }
+}
+
+/// The stats as flat `(name, value)` pairs, counts per event type named
+/// `events.<type>`. `recent_events` is left out.
+fn metrics(stats: &EventStats) -> Vec<(String, String)> {
+    let mut rows: Vec<(String, String)> = [
+        ("total_opens", stats.total_opens),
+        ("total_clicks", stats.total_clicks),
+        ("unique_opens", stats.unique_opens),
+        ("unique_clicks", stats.unique_clicks),
+        ("unique_open_recipients", stats.unique_open_recipients),
+        ("unique_open_ips", stats.unique_open_ips),
+        ("unique_open_clients", stats.unique_open_clients),
+        ("suspicious_opens", stats.suspicious_opens),
+        ("premature_opens", stats.premature_opens),
+        ("emails_created", stats.emails_created),
+        ("emails_sent", stats.emails_sent),
+    ]
+    .into_iter()
+    .map(|(name, value)| (name.to_string(), value.to_string()))
+    .collect();
+    rows.push(("open_rate".to_string(), stats.open_rate.to_string()));
+    rows.push(("click_rate".to_string(), stats.click_rate.to_string()));
+    rows.extend(
+        stats
+            .events_by_type
+            .iter()
+            .map(|(event_type, count)| (format!("events.{}", event_type), count.to_string())),
+    );
+    rows
+}
+
+fn escape_label(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
@@ -0,0 +1,92 @@
+use axum::{
+    extract::{Request, State},
+    middleware::Next,
+    response::Response,
+};
+use chrono::{DateTime, TimeZone, Utc};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::AppState;
+
+/// How timestamps on tenants, emails and events are written in JSON
+/// responses, per `Config.time_format`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TimeFormat {
+    /// `2026-03-01T10:00:00+00:00`
+    #[default]
+    Rfc3339,
+    /// Milliseconds since the Unix epoch, as a number.
+    EpochMillis,
+}
+
+tokio::task_local! {
+    static TIME_FORMAT: TimeFormat;
+}
+
+/// The format for whatever is being serialized now: the request's, or
+/// RFC 3339 outside of one (background tasks, files, webhook payloads).
+pub fn current() -> TimeFormat {
+    TIME_FORMAT.try_with(|format| *format).unwrap_or_default()
+}
+
+/// Runs `f` serializing timestamps as `format`, e.g. for a response body
+/// built outside the request's task.
+pub fn with<R>(format: TimeFormat, f: impl FnOnce() -> R) -> R {
+    TIME_FORMAT.sync_scope(format, f)
+}
+
+/// Serializes timestamps in the handler's response as configured.
+pub async fn apply(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    TIME_FORMAT.scope(state.config.time_format, next.run(request)).await
+}
+
+/// For `#[serde(with = "crate::time_format")]` on a `DateTime<Utc>`.
+pub fn serialize<S: Serializer>(time: &DateTime<Utc>, serializer: S) -> Result<S::Ok, S::Error> {
+    match current() {
+        TimeFormat::Rfc3339 => time.serialize(serializer),
+        TimeFormat::EpochMillis => serializer.serialize_i64(time.timestamp_millis()),
+    }
+}
+
+/// The timestamp as a JSON value in the current format, for bodies built
+/// with `json!`.
+pub fn to_json(time: &DateTime<Utc>) -> serde_json::Value {
+    serialize(time, serde_json::value::Serializer).expect("timestamps always serialize")
+}
+
+/// Accepts either format, so documents written in one can be read back
+/// whatever the current setting.
+pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<DateTime<Utc>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Raw {
+        Millis(i64),
+        Text(DateTime<Utc>),
+    }
+    match Raw::deserialize(deserializer)? {
+        Raw::Text(time) => Ok(time),
+        Raw::Millis(millis) => Utc
+            .timestamp_millis_opt(millis)
+            .single()
+            .ok_or_else(|| serde::de::Error::custom(format!("timestamp {} is out of range", millis))),
+    }
+}
+
+/// The same for `Option<DateTime<Utc>>`, with `#[serde(default)]`.
+pub mod option {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(time: &Option<DateTime<Utc>>, serializer: S) -> Result<S::Ok, S::Error> {
+        match time {
+            Some(time) => super::serialize(time, serializer),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<DateTime<Utc>>, D::Error> {
+        #[derive(Deserialize)]
+        struct Wrapped(#[serde(deserialize_with = "super::deserialize")] DateTime<Utc>);
+        Ok(Option::<Wrapped>::deserialize(deserializer)?.map(|Wrapped(time)| time))
+    }
+}
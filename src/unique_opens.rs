@@ -0,0 +1,234 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::database::{Database, Event};
+
+/// Bits of the hash that pick a register: 2^14 registers, 16 KiB per
+/// tenant, for a standard error of about 0.8%.
+const PRECISION: u32 = 14;
+const REGISTERS: usize = 1 << PRECISION;
+
+/// How often changed sketches are written to the database.
+pub const SKETCH_FLUSH_INTERVAL: Duration = Duration::from_secs(60);
+
+/// A HyperLogLog counter of distinct email ids.
+#[derive(Clone)]
+pub struct HyperLogLog {
+    registers: Vec<u8>,
+}
+
+impl Default for HyperLogLog {
+    fn default() -> Self {
+        HyperLogLog {
+            registers: vec![0; REGISTERS],
+        }
+    }
+}
+
+impl HyperLogLog {
+    /// A sketch as stored by `to_bytes`; None if it was made with another
+    /// precision.
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        (bytes.len() == REGISTERS).then(|| HyperLogLog {
+            registers: bytes.to_vec(),
+        })
+    }
+
+    pub fn to_bytes(&self) -> &[u8] {
+        &self.registers
+    }
+
+    /// Counts `email_id`. Returns whether the sketch changed.
+    pub fn insert(&mut self, email_id: i64) -> bool {
+        let hash = splitmix64(email_id as u64);
+        let index = (hash >> (64 - PRECISION)) as usize;
+        // The remaining bits, with a sentinel so the rank is at most
+        // 64 - PRECISION + 1
+        let rest = (hash << PRECISION) | (1 << (PRECISION - 1));
+        let rank = rest.leading_zeros() as u8 + 1;
+        if rank > self.registers[index] {
+            self.registers[index] = rank;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Folds `other` in, so the sketch counts the union of both.
+    pub fn merge(&mut self, other: &HyperLogLog) {
+        for (register, theirs) in self.registers.iter_mut().zip(&other.registers) {
+            *register = (*register).max(*theirs);
+        }
+    }
+
+    /// Estimated number of distinct ids counted, with linear counting for
+    /// small cardinalities where the raw estimate is biased.
+    pub fn estimate(&self) -> f64 {
+        let m = REGISTERS as f64;
+        let alpha = 0.7213 / (1.0 + 1.079 / m);
+        let sum: f64 = self.registers.iter().map(|r| 2f64.powi(-i32::from(*r))).sum();
+        let raw = alpha * m * m / sum;
+
+        let zeros = self.registers.iter().filter(|r| **r == 0).count();
+        if raw <= 2.5 * m && zeros > 0 {
+            m * (m / zeros as f64).ln()
+        } else {
+            raw
+        }
+    }
+}
+
+/// Spreads sequential ids over the whole 64-bit range. Fixed rather than
+/// `std`'s hasher, whose output may change between releases and would no
+/// longer match sketches already stored.
+fn splitmix64(value: u64) -> u64 {
+    let mut z = value.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+struct Sketch {
+    hll: HyperLogLog,
+    /// Newest event folded in, so a reload only has to add later ones.
+    through_event_id: i64,
+    /// Changed since it was last written out.
+    dirty: bool,
+    /// Caught up with the database. Until then opens are folded in but no
+    /// estimate is served.
+    loaded: bool,
+}
+
+/// Per-tenant HyperLogLog sketches of opened emails, so big tenants' unique
+/// opens are read from memory instead of counting distinct opens on every
+/// stats request. A tenant's sketch is loaded the first time its stats are
+/// asked for, catching up on opens stored since it was last written, and
+/// kept current from then on by every counted open. Once loaded it's read
+/// without touching the database; tenants without one have their emails
+/// counted on each request to see whether they've grown big enough.
+/// Changed sketches are written out every `SKETCH_FLUSH_INTERVAL` and on
+/// shutdown.
+pub struct UniqueOpenSketches {
+    /// Tenants with fewer emails are counted exactly.
+    min_emails: i64,
+    sketches: Mutex<HashMap<String, Sketch>>,
+}
+
+impl UniqueOpenSketches {
+    pub fn new(min_emails: i64) -> Self {
+        UniqueOpenSketches {
+            min_emails,
+            sketches: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Starts writing changed sketches out periodically.
+    pub fn spawn_flusher(self: &Arc<Self>, db: Arc<Database>) {
+        let sketches = Arc::downgrade(self);
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(SKETCH_FLUSH_INTERVAL);
+            ticker.tick().await;
+            loop {
+                ticker.tick().await;
+                let Some(sketches) = sketches.upgrade() else {
+                    break;
+                };
+                sketches.flush(&db).await;
+            }
+        });
+    }
+
+    /// Folds a newly stored event into its tenant's sketch, if it's a
+    /// counted open and the tenant's opens are being sketched.
+    pub fn record(&self, tenant_id: &str, event: &Event) {
+        if event.event_type != "open" || event.suspicious || event.premature {
+            return;
+        }
+        if let Some(sketch) = self.sketches.lock().unwrap().get_mut(tenant_id) {
+            sketch.dirty |= sketch.hll.insert(event.email_id);
+            sketch.through_event_id = sketch.through_event_id.max(event.id);
+        }
+    }
+
+    /// The tenant's estimated unique opens, or None if it's small enough to
+    /// count exactly.
+    pub async fn estimate(&self, db: &Database, tenant_id: &str) -> rusqlite::Result<Option<i64>> {
+        // Emails only go away through deletes, which forget the sketch, so
+        // a loaded one means the tenant is still past the threshold
+        if let Some(sketch) = self.sketches.lock().unwrap().get(tenant_id) {
+            if sketch.loaded {
+                return Ok(Some(sketch.hll.estimate().round() as i64));
+            }
+        }
+        if db.count_emails(tenant_id).await? < self.min_emails {
+            return Ok(None);
+        }
+        {
+            let mut sketches = self.sketches.lock().unwrap();
+            // Tracked from here on, so opens stored while it loads aren't lost
+            let sketch = sketches.entry(tenant_id.to_string()).or_insert_with(|| Sketch {
+                hll: HyperLogLog::default(),
+                through_event_id: 0,
+                dirty: false,
+                loaded: false,
+            });
+            if sketch.loaded {
+                return Ok(Some(sketch.hll.estimate().round() as i64));
+            }
+        }
+
+        let (mut hll, through_event_id) = match db.load_unique_open_sketch(tenant_id).await? {
+            Some((bytes, through_event_id)) => match HyperLogLog::from_bytes(&bytes) {
+                Some(hll) => (hll, through_event_id),
+                None => (HyperLogLog::default(), 0),
+            },
+            None => (HyperLogLog::default(), 0),
+        };
+        let (email_ids, newest) = db.opened_email_ids_since(tenant_id, through_event_id).await?;
+        for email_id in email_ids {
+            hll.insert(email_id);
+        }
+
+        let mut sketches = self.sketches.lock().unwrap();
+        // Forgotten in the meantime; the next request starts over
+        let Some(sketch) = sketches.get_mut(tenant_id) else {
+            return Ok(Some(hll.estimate().round() as i64));
+        };
+        sketch.hll.merge(&hll);
+        sketch.through_event_id = sketch.through_event_id.max(newest.unwrap_or(through_event_id));
+        sketch.dirty = true;
+        sketch.loaded = true;
+        Ok(Some(sketch.hll.estimate().round() as i64))
+    }
+
+    /// Drops the tenant's sketch, e.g. after its events are deleted, since
+    /// ids can't be taken back out of one. The next request rebuilds it.
+    pub fn forget(&self, tenant_id: &str) {
+        self.sketches.lock().unwrap().remove(tenant_id);
+    }
+
+    /// Writes out every sketch changed since the last flush. A failed write
+    /// is logged and retried next time.
+    pub async fn flush(&self, db: &Database) {
+        let changed: Vec<(String, Vec<u8>, i64)> = self
+            .sketches
+            .lock()
+            .unwrap()
+            .iter_mut()
+            .filter(|(_, sketch)| sketch.dirty && sketch.loaded)
+            .map(|(tenant_id, sketch)| {
+                sketch.dirty = false;
+                (tenant_id.clone(), sketch.hll.to_bytes().to_vec(), sketch.through_event_id)
+            })
+            .collect();
+        for (tenant_id, bytes, through_event_id) in changed {
+            if let Err(e) = db.save_unique_open_sketch(&tenant_id, &bytes, through_event_id).await {
+                eprintln!("Failed to save the unique open sketch for {}: {}", tenant_id, e);
+                if let Some(sketch) = self.sketches.lock().unwrap().get_mut(&tenant_id) {
+                    sketch.dirty = true;
+                }
+            }
+        }
+    }
+}
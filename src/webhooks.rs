@@ -0,0 +1,730 @@
+use axum::{
+    body::Bytes,
+    extract::{ConnectInfo, Path, Query, State},
+    http::{header, HeaderMap, Method, StatusCode, Uri},
+    response::IntoResponse,
+    Json,
+};
+use hmac::{Hmac, Mac};
+use http_body_util::Full;
+use hyper_rustls::{HttpsConnector, HttpsConnectorBuilder};
+use hyper_util::{
+    client::legacy::{
+        connect::{dns::Name, HttpConnector},
+        Client,
+    },
+    rt::TokioExecutor,
+};
+use lru::LruCache;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::collections::{HashMap, VecDeque};
+use std::future::Future;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::num::NonZeroUsize;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use crate::database::{Database, DeliveryStatus, Event, PendingDelivery, Webhook};
+use crate::error::{AppError, AppJson};
+use crate::{admin, AppState, Config};
+
+/// `sha256=<hex>`: HMAC-SHA256 of the raw request body, keyed with the webhook's secret.
+pub const SIGNATURE_HEADER: &str = "x-littlebell-signature";
+pub const DELIVERY_HEADER: &str = "x-littlebell-delivery";
+/// On the deliveries listing: `closed`, `open` or `half_open`.
+pub const CIRCUIT_HEADER: &str = "x-webhook-circuit";
+/// On the deliveries listing: deliveries waiting for the circuit to close.
+pub const QUEUED_HEADER: &str = "x-webhook-queued";
+
+/// Tenants whose webhook lists are kept in memory.
+const WEBHOOK_CACHE_TENANTS: usize = 10_000;
+
+const DEFAULT_DELIVERY_PAGE: i64 = 100;
+const MAX_DELIVERY_PAGE: i64 = 1000;
+
+type HmacSha256 = Hmac<Sha256>;
+
+fn mac(secret: &str, body: &[u8]) -> HmacSha256 {
+    // HMAC takes keys of any length
+    let mut mac = HmacSha256::new_from_slice(secret.as_bytes()).expect("any key length is valid");
+    mac.update(body);
+    mac
+}
+
+/// Hex HMAC-SHA256 of `body` under `secret`, as sent in the signature header
+/// (without the `sha256=` prefix).
+pub fn sign(secret: &str, body: &[u8]) -> String {
+    mac(secret, body)
+        .finalize()
+        .into_bytes()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Whether `signature`, a signature header value, was made from `body` with
+/// `secret`. The comparison takes the same time however much of it matches,
+/// so timing it doesn't reveal a valid signature byte by byte.
+pub fn verify(secret: &str, body: &[u8], signature: &str) -> bool {
+    let Some(hex) = signature.strip_prefix("sha256=") else {
+        return false;
+    };
+    if hex.len() % 2 != 0 || !hex.is_ascii() {
+        return false;
+    }
+    let bytes: Option<Vec<u8>> = (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+        .collect();
+    bytes.is_some_and(|bytes| mac(secret, body).verify_slice(&bytes).is_ok())
+}
+
+/// Whether `ip` is somewhere webhooks may only reach with
+/// `webhook_allow_private_networks`: loopback, link-local, private ranges,
+/// carrier-grade NAT, and addresses that aren't a single host.
+fn is_private(ip: IpAddr) -> bool {
+    match ip.to_canonical() {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_multicast()
+                // 0.0.0.0/8 and the 100.64.0.0/10 shared address space
+                || a == 0
+                || (a == 100 && (64..128).contains(&b))
+        }
+        IpAddr::V6(ip) => {
+            let first = ip.segments()[0];
+            ip.is_loopback()
+                || ip.is_unspecified()
+                || ip.is_multicast()
+                // fc00::/7 unique local and fe80::/10 link-local
+                || first & 0xfe00 == 0xfc00
+                || first & 0xffc0 == 0xfe80
+        }
+    }
+}
+
+/// The addresses `host` resolves to that a webhook may connect to. Hosts
+/// that only resolve to private addresses fail with `PermissionDenied`.
+async fn reachable_addrs(host: &str, allow_private: bool) -> io::Result<Vec<SocketAddr>> {
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    let addrs: Vec<SocketAddr> = tokio::net::lookup_host((host, 0)).await?.collect();
+    let reachable: Vec<SocketAddr> = addrs
+        .iter()
+        .copied()
+        .filter(|addr| allow_private || !is_private(addr.ip()))
+        .collect();
+    if reachable.is_empty() {
+        let kind = if addrs.is_empty() {
+            io::ErrorKind::NotFound
+        } else {
+            io::ErrorKind::PermissionDenied
+        };
+        return Err(io::Error::new(kind, format!("{} doesn't resolve to a public address", host)));
+    }
+    Ok(reachable)
+}
+
+/// Resolves webhook hosts for the HTTP client, leaving out private
+/// addresses. Checking at connect time as well as on registration keeps a
+/// name re-pointed at an internal address from getting through.
+#[derive(Clone)]
+struct PublicResolver {
+    allow_private: bool,
+}
+
+impl tower::Service<Name> for PublicResolver {
+    type Response = std::vec::IntoIter<SocketAddr>;
+    type Error = io::Error;
+    type Future = Pin<Box<dyn Future<Output = io::Result<Self::Response>> + Send>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, name: Name) -> Self::Future {
+        let allow_private = self.allow_private;
+        Box::pin(async move { Ok(reachable_addrs(name.as_str(), allow_private).await?.into_iter()) })
+    }
+}
+
+/// Sends each logged event to the tenant's webhooks in the background,
+/// retrying 5xx answers, timeouts and connection failures with jittered
+/// exponential backoff. Every delivery is recorded in `webhook_deliveries`.
+///
+/// Each tenant has a circuit breaker: after `webhook_circuit_threshold`
+/// failed attempts in a row it opens for `webhook_circuit_cooldown_secs`,
+/// during which deliveries wait in a bounded queue instead of hitting the
+/// receiver. When the cooldown ends the queue is retried one delivery at a
+/// time; a success closes the circuit and a failure reopens it.
+#[derive(Clone)]
+pub struct WebhookDispatcher {
+    db: Arc<Database>,
+    client: Client<HttpsConnector<HttpConnector<PublicResolver>>, Full<Bytes>>,
+    allow_private: bool,
+    timeout: Duration,
+    max_attempts: u32,
+    retry_base: Duration,
+    circuit_threshold: u32,
+    circuit_cooldown: Duration,
+    queue_limit: usize,
+    circuits: Arc<Mutex<HashMap<String, Circuit>>>,
+    /// Each tenant's webhooks, so logging an event doesn't read them from
+    /// the database. Entries are dropped when the tenant's webhooks change.
+    webhooks: Arc<Mutex<LruCache<String, Arc<Vec<Webhook>>>>>,
+    /// Bumped on every invalidation, so a lookup that raced one doesn't
+    /// cache what it read.
+    webhooks_generation: Arc<AtomicU64>,
+}
+
+#[derive(Serialize)]
+struct Payload<'a> {
+    tenant_id: &'a str,
+    event: &'a Event,
+}
+
+fn payload(tenant_id: &str, event: &Event) -> Bytes {
+    Bytes::from(serde_json::to_vec(&Payload { tenant_id, event }).unwrap_or_default())
+}
+
+/// How a single attempt went, and whether it is worth another.
+enum Attempt {
+    Delivered(u16),
+    Rejected(Option<u16>, Option<String>),
+    Retryable(Option<u16>, String),
+}
+
+/// A delivery in progress, or waiting out an open circuit.
+struct Delivery {
+    tenant_id: String,
+    url: String,
+    delivery_id: i64,
+    signature: String,
+    body: Bytes,
+    /// The attempt it's up to, counting from 1.
+    attempt: u32,
+}
+
+#[derive(Default)]
+struct Circuit {
+    consecutive_failures: u32,
+    /// Set while open, and left in the past while half-open.
+    open_until: Option<Instant>,
+    queue: VecDeque<Delivery>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CircuitState {
+    Closed,
+    /// Deliveries are queued until the cooldown ends.
+    Open,
+    /// Cooldown over; the next failure reopens the circuit.
+    HalfOpen,
+}
+
+impl CircuitState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CircuitState::Closed => "closed",
+            CircuitState::Open => "open",
+            CircuitState::HalfOpen => "half_open",
+        }
+    }
+}
+
+impl Circuit {
+    fn state(&self, now: Instant) -> CircuitState {
+        match self.open_until {
+            None => CircuitState::Closed,
+            Some(until) if now < until => CircuitState::Open,
+            Some(_) => CircuitState::HalfOpen,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CircuitStatus {
+    pub state: CircuitState,
+    pub consecutive_failures: u32,
+    pub queued: usize,
+}
+
+impl WebhookDispatcher {
+    pub fn new(db: Arc<Database>, config: &Config) -> Self {
+        let allow_private = config.webhook_allow_private_networks;
+        let mut http = HttpConnector::new_with_resolver(PublicResolver { allow_private });
+        http.enforce_http(false);
+        let https = HttpsConnectorBuilder::new()
+            .with_webpki_roots()
+            .https_or_http()
+            .enable_http1()
+            .wrap_connector(http);
+        Self {
+            db,
+            client: Client::builder(TokioExecutor::new()).build(https),
+            allow_private,
+            timeout: Duration::from_secs(config.webhook_timeout_secs),
+            max_attempts: config.webhook_max_attempts.max(1),
+            retry_base: Duration::from_millis(config.webhook_retry_base_ms),
+            circuit_threshold: config.webhook_circuit_threshold.max(1),
+            circuit_cooldown: Duration::from_secs(config.webhook_circuit_cooldown_secs),
+            queue_limit: config.webhook_queue_size,
+            circuits: Arc::new(Mutex::new(HashMap::new())),
+            webhooks: Arc::new(Mutex::new(LruCache::new(
+                NonZeroUsize::new(WEBHOOK_CACHE_TENANTS).unwrap(),
+            ))),
+            webhooks_generation: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    /// The tenant's webhooks, from the cache when possible.
+    async fn webhooks_for(&self, tenant_id: &str) -> rusqlite::Result<Arc<Vec<Webhook>>> {
+        if let Some(webhooks) = self.webhooks.lock().unwrap().get(tenant_id) {
+            return Ok(webhooks.clone());
+        }
+        let generation = self.webhooks_generation.load(Ordering::SeqCst);
+        let webhooks = Arc::new(self.db.list_webhooks(tenant_id).await?);
+        let mut cache = self.webhooks.lock().unwrap();
+        if self.webhooks_generation.load(Ordering::SeqCst) == generation {
+            cache.put(tenant_id.to_string(), webhooks.clone());
+        }
+        Ok(webhooks)
+    }
+
+    /// Forgets the tenant's cached webhooks. Call this after adding or
+    /// removing one.
+    pub fn invalidate(&self, tenant_id: &str) {
+        let mut cache = self.webhooks.lock().unwrap();
+        cache.pop(tenant_id);
+        self.webhooks_generation.fetch_add(1, Ordering::SeqCst);
+    }
+
+    /// Picks up deliveries left pending by a previous run, carrying on from
+    /// the attempt they were up to.
+    pub fn spawn_resume(&self) {
+        let dispatcher = self.clone();
+        tokio::spawn(async move {
+            let pending = match dispatcher.db.pending_webhook_deliveries().await {
+                Ok(pending) => pending,
+                Err(e) => {
+                    eprintln!("Failed to load pending webhook deliveries: {}", e);
+                    return;
+                }
+            };
+            for PendingDelivery {
+                delivery_id,
+                attempts,
+                webhook,
+                event,
+            } in pending
+            {
+                let body = payload(&webhook.tenant_id, &event);
+                let attempt = u32::try_from(attempts + 1).unwrap_or(u32::MAX).min(dispatcher.max_attempts);
+                let dispatcher = dispatcher.clone();
+                tokio::spawn(async move {
+                    dispatcher
+                        .run(Delivery {
+                            signature: format!("sha256={}", sign(&webhook.secret, &body)),
+                            tenant_id: webhook.tenant_id,
+                            url: webhook.url,
+                            delivery_id,
+                            body,
+                            attempt,
+                        })
+                        .await
+                });
+            }
+        });
+    }
+
+    /// Queues `event` for every webhook the tenant has registered.
+    pub fn dispatch(&self, tenant_id: &str, event: &Event) {
+        let dispatcher = self.clone();
+        let tenant_id = tenant_id.to_string();
+        let event = event.clone();
+        tokio::spawn(async move {
+            let webhooks = match dispatcher.webhooks_for(&tenant_id).await {
+                Ok(webhooks) => webhooks,
+                Err(e) => {
+                    eprintln!("Failed to load webhooks for {}: {}", tenant_id, e);
+                    return;
+                }
+            };
+            if webhooks.is_empty() {
+                return;
+            }
+
+            let body = payload(&tenant_id, &event);
+            for webhook in webhooks.iter().cloned() {
+                let dispatcher = dispatcher.clone();
+                let tenant_id = tenant_id.clone();
+                let body = body.clone();
+                let event_id = event.id;
+                tokio::spawn(async move { dispatcher.deliver(tenant_id, webhook, event_id, body).await });
+            }
+        });
+    }
+
+    /// Where the tenant's circuit stands, for the deliveries endpoint.
+    pub fn circuit_status(&self, tenant_id: &str) -> CircuitStatus {
+        let circuits = self.circuits.lock().unwrap();
+        match circuits.get(tenant_id) {
+            Some(circuit) => CircuitStatus {
+                state: circuit.state(Instant::now()),
+                consecutive_failures: circuit.consecutive_failures,
+                queued: circuit.queue.len(),
+            },
+            None => CircuitStatus {
+                state: CircuitState::Closed,
+                consecutive_failures: 0,
+                queued: 0,
+            },
+        }
+    }
+
+    async fn deliver(&self, tenant_id: String, webhook: Webhook, event_id: i64, body: Bytes) {
+        let delivery_id = match self.db.create_webhook_delivery(webhook.id, event_id).await {
+            Ok(id) => id,
+            Err(e) => {
+                eprintln!("Failed to record webhook delivery for {}: {}", webhook.url, e);
+                return;
+            }
+        };
+        let signature = format!("sha256={}", sign(&webhook.secret, &body));
+        self.run(Delivery {
+            tenant_id,
+            url: webhook.url,
+            delivery_id,
+            signature,
+            body,
+            attempt: 1,
+        })
+        .await;
+    }
+
+    /// Attempts a delivery until it succeeds, fails for good, or finds the
+    /// tenant's circuit open and is queued.
+    async fn run(&self, mut delivery: Delivery) {
+        loop {
+            if self.is_open(&delivery.tenant_id) {
+                self.enqueue(delivery).await;
+                return;
+            }
+
+            let outcome = self
+                .attempt(&delivery.url, delivery.delivery_id, &delivery.signature, delivery.body.clone())
+                .await;
+            let last = delivery.attempt == self.max_attempts;
+            let (status, code, error) = match &outcome {
+                Attempt::Delivered(code) => (DeliveryStatus::Delivered, Some(*code), None),
+                Attempt::Rejected(code, error) => (DeliveryStatus::Failed, *code, error.as_deref()),
+                Attempt::Retryable(code, error) if last => (DeliveryStatus::Failed, *code, Some(error.as_str())),
+                Attempt::Retryable(code, error) => (DeliveryStatus::Pending, *code, Some(error.as_str())),
+            };
+            // A receiver answering 4xx is up, so only retryable failures
+            // count against the circuit
+            match outcome {
+                Attempt::Delivered(_) => self.record_success(&delivery.tenant_id),
+                Attempt::Retryable(..) => self.record_failure(&delivery.tenant_id),
+                Attempt::Rejected(..) => {}
+            }
+            if let Err(e) = self.db.record_webhook_attempt(delivery.delivery_id, status, code, error).await {
+                eprintln!("Failed to record webhook attempt {}: {}", delivery.delivery_id, e);
+            }
+            if status != DeliveryStatus::Pending {
+                return;
+            }
+            tokio::time::sleep(self.backoff(delivery.attempt)).await;
+            delivery.attempt += 1;
+        }
+    }
+
+    /// The wait after a failed `attempt`: doubling from `retry_base`, with
+    /// up to half of it randomized so retries from many deliveries spread
+    /// out.
+    fn backoff(&self, attempt: u32) -> Duration {
+        let delay = self.retry_base * 2u32.saturating_pow(attempt - 1);
+        delay / 2 + delay.mul_f64(rand::random::<f64>() / 2.0)
+    }
+
+    fn is_open(&self, tenant_id: &str) -> bool {
+        let circuits = self.circuits.lock().unwrap();
+        circuits
+            .get(tenant_id)
+            .is_some_and(|circuit| circuit.state(Instant::now()) == CircuitState::Open)
+    }
+
+    fn record_success(&self, tenant_id: &str) {
+        let mut circuits = self.circuits.lock().unwrap();
+        if let Some(circuit) = circuits.get_mut(tenant_id) {
+            circuit.consecutive_failures = 0;
+            circuit.open_until = None;
+        }
+    }
+
+    fn record_failure(&self, tenant_id: &str) {
+        let now = Instant::now();
+        let mut circuits = self.circuits.lock().unwrap();
+        let circuit = circuits.entry(tenant_id.to_string()).or_default();
+        circuit.consecutive_failures += 1;
+        let trip = match circuit.state(now) {
+            CircuitState::Closed => circuit.consecutive_failures >= self.circuit_threshold,
+            CircuitState::HalfOpen => true,
+            // Another delivery's attempt that was already under way
+            CircuitState::Open => false,
+        };
+        if trip {
+            eprintln!(
+                "Warning: pausing webhook delivery for {} for {:?} after {} failures in a row",
+                tenant_id, self.circuit_cooldown, circuit.consecutive_failures
+            );
+            circuit.open_until = Some(now + self.circuit_cooldown);
+            let dispatcher = self.clone();
+            let tenant_id = tenant_id.to_string();
+            tokio::spawn(async move {
+                tokio::time::sleep(dispatcher.circuit_cooldown).await;
+                dispatcher.drain(&tenant_id).await;
+            });
+        }
+    }
+
+    /// Holds a delivery until the circuit's cooldown ends, or drops it (as
+    /// failed) when the queue is full.
+    async fn enqueue(&self, delivery: Delivery) {
+        let dropped = {
+            let mut circuits = self.circuits.lock().unwrap();
+            let circuit = circuits.entry(delivery.tenant_id.clone()).or_default();
+            if circuit.queue.len() < self.queue_limit {
+                circuit.queue.push_back(delivery);
+                None
+            } else {
+                Some(delivery)
+            }
+        };
+        let Some(delivery) = dropped else {
+            return;
+        };
+        eprintln!(
+            "Warning: dropping webhook delivery {} for {}: circuit open and {} already queued",
+            delivery.delivery_id, delivery.tenant_id, self.queue_limit
+        );
+        let error = "dropped while the circuit was open: delivery queue full";
+        if let Err(e) = self.db.abandon_webhook_delivery(delivery.delivery_id, error).await {
+            eprintln!("Failed to record webhook delivery {}: {}", delivery.delivery_id, e);
+        }
+    }
+
+    /// Retries what queued up while the circuit was open, one at a time, so
+    /// a receiver that's still down sees a single attempt before the circuit
+    /// reopens and the rest are queued again.
+    async fn drain(&self, tenant_id: &str) {
+        let queued = {
+            let mut circuits = self.circuits.lock().unwrap();
+            match circuits.get_mut(tenant_id) {
+                // Reopened since this drain was scheduled; a later one takes over
+                Some(circuit) if circuit.state(Instant::now()) == CircuitState::Open => return,
+                Some(circuit) => std::mem::take(&mut circuit.queue),
+                None => return,
+            }
+        };
+        for delivery in queued {
+            self.run(delivery).await;
+        }
+    }
+
+    async fn attempt(&self, url: &str, delivery_id: i64, signature: &str, body: Bytes) -> Attempt {
+        // The client only resolves names through PublicResolver, so address
+        // literals are checked here
+        let host = url.parse::<Uri>().ok().and_then(|uri| uri.host().map(str::to_string));
+        if let Some(host) = host {
+            match reachable_addrs(&host, self.allow_private).await {
+                Ok(_) => {}
+                Err(e) if e.kind() == io::ErrorKind::PermissionDenied => {
+                    return Attempt::Rejected(None, Some(e.to_string()))
+                }
+                Err(e) => return Attempt::Retryable(None, e.to_string()),
+            }
+        }
+
+        let request = hyper::Request::builder()
+            .method(Method::POST)
+            .uri(url)
+            .header(header::CONTENT_TYPE, "application/json")
+            .header(header::USER_AGENT, "little-bell-webhooks")
+            .header(SIGNATURE_HEADER, signature)
+            .header(DELIVERY_HEADER, delivery_id)
+            .body(Full::new(body));
+        let request = match request {
+            Ok(request) => request,
+            // Won't get any better on retry
+            Err(e) => return Attempt::Rejected(None, Some(e.to_string())),
+        };
+
+        match tokio::time::timeout(self.timeout, self.client.request(request)).await {
+            Ok(Ok(response)) => {
+                let code = response.status().as_u16();
+                if response.status().is_success() {
+                    Attempt::Delivered(code)
+                } else if response.status().is_server_error() {
+                    Attempt::Retryable(Some(code), format!("receiver answered {}", code))
+                } else {
+                    Attempt::Rejected(Some(code), None)
+                }
+            }
+            Ok(Err(e)) => Attempt::Retryable(None, e.to_string()),
+            Err(_) => Attempt::Retryable(None, format!("timed out after {:?}", self.timeout)),
+        }
+    }
+}
+
+/// Checks that `url` is an http(s) URL whose host resolves to an address
+/// webhooks may reach.
+async fn validate_webhook_url(url: &str, allow_private: bool) -> Result<(), String> {
+    let uri: Uri = url.parse().map_err(|_| format!("'{}' is not a valid URL", url))?;
+    let host = match uri.scheme_str() {
+        Some("http") | Some("https") => uri.host(),
+        _ => None,
+    };
+    let Some(host) = host else {
+        return Err(format!("'{}' is not an http:// or https:// URL", url));
+    };
+    match reachable_addrs(host, allow_private).await {
+        Ok(_) => Ok(()),
+        Err(e) if e.kind() == io::ErrorKind::PermissionDenied => Err(format!(
+            "'{}' resolves to a private address; set WEBHOOK_ALLOW_PRIVATE_NETWORKS to allow it",
+            host
+        )),
+        Err(_) => Err(format!("couldn't resolve '{}'", host)),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct NewWebhook {
+    pub url: String,
+    /// Generated when omitted.
+    pub secret: Option<String>,
+}
+
+/// A webhook as returned on creation, the only time its secret is shown.
+#[derive(Serialize)]
+struct CreatedWebhook {
+    #[serde(flatten)]
+    webhook: Webhook,
+    secret: String,
+}
+
+pub async fn create_webhook(
+    Path(tenant_id): Path<String>,
+    headers: HeaderMap,
+    peer: Option<ConnectInfo<SocketAddr>>,
+    State(state): State<AppState>,
+    AppJson(new_webhook): AppJson<NewWebhook>,
+) -> impl IntoResponse {
+    if let Err(message) = validate_webhook_url(&new_webhook.url, state.config.webhook_allow_private_networks).await {
+        return AppError::BadRequest(message).into_response();
+    }
+    let secret = match new_webhook.secret {
+        Some(secret) if secret.is_empty() => {
+            return AppError::BadRequest("secret must not be empty".to_string()).into_response()
+        }
+        Some(secret) => secret,
+        None => uuid::Uuid::new_v4().simple().to_string(),
+    };
+
+    if let Err(e) = state.db.create_tenant(&tenant_id, &tenant_id).await {
+        eprintln!("Failed to create/ensure tenant: {}", e);
+        return AppError::internal().into_response();
+    }
+
+    match state.db.create_webhook(&tenant_id, &new_webhook.url, &secret).await {
+        Ok(webhook) => {
+            state.webhooks.invalidate(&tenant_id);
+            let actor = admin::actor(&state, &headers, peer);
+            let details = serde_json::json!({ "webhook_id": webhook.id, "url": webhook.url });
+            state.record_audit(&actor, "webhook.create", &tenant_id, details).await;
+            let secret = webhook.secret.clone();
+            (StatusCode::CREATED, Json(CreatedWebhook { webhook, secret })).into_response()
+        }
+        Err(e) => {
+            eprintln!("Failed to create webhook: {}", e);
+            AppError::internal().into_response()
+        }
+    }
+}
+
+pub async fn list_webhooks(Path(tenant_id): Path<String>, State(state): State<AppState>) -> impl IntoResponse {
+    match state.db.list_webhooks(&tenant_id).await {
+        Ok(webhooks) => Json(webhooks).into_response(),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            AppError::internal().into_response()
+        }
+    }
+}
+
+pub async fn delete_webhook(
+    Path((tenant_id, webhook_id)): Path<(String, i64)>,
+    headers: HeaderMap,
+    peer: Option<ConnectInfo<SocketAddr>>,
+    State(state): State<AppState>,
+) -> impl IntoResponse {
+    match state.db.delete_webhook(&tenant_id, webhook_id).await {
+        Ok(true) => {
+            state.webhooks.invalidate(&tenant_id);
+            let actor = admin::actor(&state, &headers, peer);
+            let details = serde_json::json!({ "webhook_id": webhook_id });
+            state.record_audit(&actor, "webhook.delete", &tenant_id, details).await;
+            StatusCode::NO_CONTENT.into_response()
+        }
+        Ok(false) => AppError::NotFound(format!("webhook {} not found", webhook_id)).into_response(),
+        Err(e) => {
+            eprintln!("Failed to delete webhook: {}", e);
+            AppError::internal().into_response()
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DeliveriesQuery {
+    pub status: Option<DeliveryStatus>,
+    pub limit: Option<i64>,
+}
+
+/// Recent deliveries across the tenant's webhooks, newest first, with the
+/// tenant's circuit state and queue length in the `X-Webhook-Circuit` and
+/// `X-Webhook-Queued` headers.
+pub async fn list_deliveries(
+    Path(tenant_id): Path<String>,
+    Query(query): Query<DeliveriesQuery>,
+    State(state): State<AppState>,
+) -> impl IntoResponse {
+    let limit = query.limit.unwrap_or(DEFAULT_DELIVERY_PAGE).clamp(1, MAX_DELIVERY_PAGE);
+
+    match state.db.list_webhook_deliveries(&tenant_id, query.status, limit).await {
+        Ok(deliveries) => {
+            let circuit = state.webhooks.circuit_status(&tenant_id);
+            (
+                [
+                    (CIRCUIT_HEADER, circuit.state.as_str().to_string()),
+                    (QUEUED_HEADER, circuit.queued.to_string()),
+                ],
+                Json(deliveries),
+            )
+                .into_response()
+        }
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            AppError::internal().into_response()
+        }
+    }
+}
@@ -0,0 +1,60 @@
+// Prepend events to the table as they're logged, without reloading.
+// Filtered views leave out the stream URL and stay as loaded, since new
+// events may not match.
+(function () {
+    var table = document.getElementById("events-table");
+    var streamUrl = table && table.getAttribute("data-stream-url");
+    if (!window.EventSource || !streamUrl) {
+        return;
+    }
+
+    var body = document.getElementById("events-body");
+    var source = new EventSource(streamUrl);
+
+    function cell(text) {
+        var td = document.createElement("td");
+        td.textContent = text;
+        return td;
+    }
+
+    function pad(n) {
+        return n < 10 ? "0" + n : "" + n;
+    }
+
+    function formatTimestamp(value) {
+        var d = new Date(value);
+        return d.getUTCFullYear() + "-" + pad(d.getUTCMonth() + 1) + "-" + pad(d.getUTCDate()) +
+            " " + pad(d.getUTCHours()) + ":" + pad(d.getUTCMinutes()) + ":" + pad(d.getUTCSeconds()) + " UTC";
+    }
+
+    function addEvent(message) {
+        var event = JSON.parse(message.data);
+        var row = document.createElement("tr");
+
+        var typeCell = document.createElement("td");
+        var badge = document.createElement("span");
+        badge.className = "event-type event-" + event.event_type;
+        badge.textContent = event.event_type;
+        typeCell.appendChild(badge);
+        row.appendChild(typeCell);
+
+        var ua = event.user_agent;
+        if (ua && ua.length > 50) {
+            ua = ua.slice(0, 50) + "...";
+        }
+
+        row.appendChild(cell(event.email_id));
+        row.appendChild(cell(formatTimestamp(event.timestamp)));
+        row.appendChild(cell(ua || "Unknown"));
+        row.appendChild(cell(event.ip_address || "Unknown"));
+
+        var empty = document.getElementById("no-events");
+        if (empty) {
+            empty.remove();
+        }
+        table.style.display = "";
+        body.insertBefore(row, body.firstChild);
+    }
+
+    source.onmessage = addEvent;
+})();
@@ -1,13 +1,4086 @@
 use axum::http::StatusCode;
 use axum_test::TestServer;
+use axum::http::HeaderMap;
+use little_bell::{
+    create_app, database::Database, extract_client_ip, normalize_ip, validate_tenant_id, Config, RedirectStatus,
+};
 use serde_json::{json, Value};
 use std::sync::Arc;
 
-// We need to refactor main.rs to export the necessary components for testing
-// For now, let's create simpler unit tests
+async fn test_server() -> TestServer {
+    let db = Arc::new(Database::new(":memory:").await.unwrap());
+    let app = create_app(db, Config::default()).await;
+    TestServer::new(app).unwrap()
+}
 
 #[tokio::test]
-async fn test_basic_functionality() {
-    // This is a placeholder test until we refactor the main module
-    assert_eq!(2 + 2, 4);
-}
\ No newline at end of file
+async fn test_health_check() {
+    let server = test_server().await;
+
+    let response = server.get("/health").await;
+    response.assert_status_ok();
+    let body: Value = response.json();
+    assert_eq!(body["status"], "healthy");
+    assert_eq!(body["schema_version"], little_bell::database::SCHEMA_VERSION);
+    assert_eq!(body["pending_migrations"], 0);
+
+    let response = server.get("/ready").await;
+    response.assert_status_ok();
+    assert_eq!(response.json::<Value>()["ready"], true);
+}
+
+#[tokio::test]
+async fn test_not_ready_once_schema_is_newer() {
+    let path = std::env::temp_dir().join(format!("little-bell-ready-{}.db", std::process::id()));
+    let path = path.to_str().unwrap().to_string();
+    let db = Arc::new(Database::new(&path).await.unwrap());
+    let server = TestServer::new(create_app(db, Config::default()).await).unwrap();
+    server.get("/ready").await.assert_status_ok();
+
+    // A newer binary sharing the database migrates it past us
+    let conn = rusqlite::Connection::open(&path).unwrap();
+    conn.pragma_update(None, "user_version", little_bell::database::SCHEMA_VERSION + 1)
+        .unwrap();
+    drop(conn);
+
+    let response = server.get("/ready").await;
+    for suffix in ["", "-wal", "-shm"] {
+        let _ = std::fs::remove_file(format!("{}{}", path, suffix));
+    }
+    response.assert_status(StatusCode::SERVICE_UNAVAILABLE);
+    let body: Value = response.json();
+    assert_eq!(body["ready"], false);
+    assert_eq!(body["pending_migrations"], 0);
+    assert_eq!(body["expected_schema_version"], little_bell::database::SCHEMA_VERSION);
+}
+
+#[test]
+fn test_in_memory_database_paths() {
+    use little_bell::database::is_in_memory;
+
+    for path in [":memory:", "", "file::memory:?cache=shared", "file:bell?mode=memory&cache=shared"] {
+        assert!(is_in_memory(path), "{}", path);
+    }
+    for path in ["data/tracking.db", "file:data/tracking.db?mode=rwc", "memory.db"] {
+        assert!(!is_in_memory(path), "{}", path);
+    }
+}
+
+#[tokio::test]
+async fn test_stats_rates() {
+    let server = test_server().await;
+
+    let response = server.get("/acme/stats").await;
+    response.assert_status_ok();
+    let body: Value = response.json();
+    assert_eq!(body["emails_sent"], 0);
+    assert_eq!(body["open_rate"], 0.0);
+    assert_eq!(body["click_rate"], 0.0);
+
+    for _ in 0..2 {
+        let response = server
+            .post("/acme/emails")
+            .json(&json!({ "subject": "Hello", "recipient": "a@example.com" }))
+            .await;
+        response.assert_status(StatusCode::CREATED);
+    }
+    let email_id = 1;
+    server.get(&format!("/acme/pixel/{}.gif", email_id)).await.assert_status_ok();
+    server.get(&format!("/acme/pixel/{}.gif", email_id)).await.assert_status_ok();
+
+    // Until any email is marked sent, rates are over every email created
+    let body: Value = server.get("/acme/stats").await.json();
+    assert_eq!(body["emails_created"], 2);
+    assert_eq!(body["emails_sent"], 0);
+    assert_eq!(body["open_rate"], 0.5);
+
+    // Then only sent emails count
+    server.post("/acme/emails").json(&json!({})).await.assert_status(StatusCode::CREATED);
+    for email_id in [1, 2] {
+        server
+            .post(&format!("/acme/emails/{}/sent", email_id))
+            .await
+            .assert_status_ok();
+    }
+    let body: Value = server.get("/acme/stats").await.json();
+    assert_eq!(body["emails_created"], 3);
+    assert_eq!(body["emails_sent"], 2);
+    assert_eq!(body["total_opens"], 2);
+    assert_eq!(body["open_rate"], 0.5);
+    assert_eq!(body["click_rate"], 0.0);
+
+    // Opens and clicks on unsent emails don't count towards the rates
+    server.post("/acme/emails").json(&json!({})).await.assert_status(StatusCode::CREATED);
+    for email_id in [3, 4] {
+        server.get(&format!("/acme/pixel/{}.gif", email_id)).await.assert_status_ok();
+    }
+    server
+        .get("/acme/click/4")
+        .add_query_param("url", "https://example.com")
+        .await
+        .assert_status(StatusCode::TEMPORARY_REDIRECT);
+    let body: Value = server.get("/acme/stats").await.json();
+    assert_eq!(body["unique_opens"], 3);
+    assert_eq!(body["unique_clicks"], 1);
+    assert_eq!(body["open_rate"], 0.5);
+    assert_eq!(body["click_rate"], 0.0);
+    assert!(body["open_rate"].as_f64().unwrap() <= 1.0);
+}
+
+#[tokio::test]
+async fn test_emails_from_before_send_tracking_count_as_sent() {
+    use little_bell::database::NewEmail;
+
+    let path = std::env::temp_dir().join(format!("little-bell-sent-at-{}.db", std::process::id()));
+    let path = path.to_str().unwrap().to_string();
+    let db = Database::new(&path).await.unwrap();
+    db.create_tenant("acme", "acme").await.unwrap();
+    let (email, _) = db.create_email("acme", &NewEmail::default()).await.unwrap();
+    drop(db);
+
+    // As an older binary would have stored it
+    let conn = rusqlite::Connection::open(&path).unwrap();
+    conn.execute("ALTER TABLE emails DROP COLUMN sent_at", []).unwrap();
+    drop(conn);
+
+    let db = Database::new(&path).await.unwrap();
+    let migrated = db.get_email(email.id, "acme").await.unwrap().unwrap();
+    let (created, _) = db.create_email("acme", &NewEmail::default()).await.unwrap();
+    drop(db);
+    for suffix in ["", "-wal", "-shm"] {
+        let _ = std::fs::remove_file(format!("{}{}", path, suffix));
+    }
+    assert_eq!(migrated.sent_at, Some(migrated.created_at));
+    assert_eq!(created.sent_at, None);
+}
+
+#[tokio::test]
+async fn test_stats_content_negotiation() {
+    let server = test_server().await;
+    server
+        .post("/acme/emails")
+        .json(&json!({}))
+        .await
+        .assert_status(StatusCode::CREATED);
+    server.get("/acme/pixel/1.gif").await.assert_status_ok();
+
+    for accept in [None, Some("*/*"), Some("image/png")] {
+        let mut request = server.get("/acme/stats");
+        if let Some(accept) = accept {
+            request = request.add_header("accept", accept);
+        }
+        let response = request.await;
+        assert_eq!(response.header("content-type"), "application/json");
+        assert_eq!(response.json::<Value>()["total_opens"], 1);
+    }
+
+    let response = server.get("/acme/stats").add_header("accept", "text/csv").await;
+    assert_eq!(response.header("content-type"), "text/csv; charset=utf-8");
+    let csv = response.text();
+    assert!(csv.starts_with("metric,value\n"));
+    assert!(csv.contains("\ntotal_opens,1\n"));
+    assert!(csv.contains("\nevents.open,1\n"));
+
+    // The query parameter beats the header, and quality values are honoured
+    let response = server
+        .get("/acme/stats?format=text")
+        .add_header("accept", "text/csv")
+        .await;
+    assert!(response.text().lines().any(|line| line.split_whitespace().eq(["total_opens", "1"])));
+    let response = server
+        .get("/acme/stats")
+        .add_header("accept", "text/csv;q=0.5, application/json")
+        .await;
+    assert_eq!(response.header("content-type"), "application/json");
+
+    let response = server.get("/acme/stats?format=prometheus").await;
+    let metrics = response.text();
+    assert!(metrics.contains("little_bell_total_opens{tenant=\"acme\"} 1\n"));
+    assert!(metrics.contains("little_bell_events{tenant=\"acme\",event_type=\"open\"} 1\n"));
+
+    server.get("/acme/stats?format=xml").await.assert_status_bad_request();
+}
+
+#[tokio::test]
+async fn test_mark_email_sent_logs_sent_event_once() {
+    let server = test_server().await;
+    server
+        .post("/acme/emails")
+        .json(&json!({}))
+        .await
+        .assert_status(StatusCode::CREATED);
+
+    let first: Value = server.post("/acme/emails/1/sent").await.json();
+    assert_eq!(first["email_id"], 1);
+    let second: Value = server.post("/acme/emails/1/sent").await.json();
+    assert_eq!(second["sent_at"], first["sent_at"]);
+
+    let events: Value = server.get("/acme/emails/1/events").await.json();
+    let events = events.as_array().unwrap();
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0]["event_type"], "sent");
+    let emails: Value = server.get("/acme/emails").await.json();
+    assert_eq!(emails[0]["sent_at"], first["sent_at"]);
+
+    server.post("/acme/emails/2/sent").await.assert_status_not_found();
+    server.post("/other/emails/1/sent").await.assert_status_not_found();
+
+    // Marking an email sent drops its cached copy
+    server.post("/acme/emails").json(&json!({})).await.assert_status(StatusCode::CREATED);
+    server.get("/acme/pixel/2.gif").await.assert_status_ok();
+    let cached = |metrics: Value| metrics["email_cache"]["entries"].as_u64().unwrap();
+    let before = cached(server.get("/metrics").await.json());
+    server.post("/acme/emails/2/sent").await.assert_status_ok();
+    assert_eq!(cached(server.get("/metrics").await.json()), before - 1);
+
+    // Reported sends would skip setting sent_at, so they're refused
+    let response = server
+        .post("/acme/events")
+        .json(&json!([{ "email_id": 1, "event_type": "sent" }]))
+        .await;
+    response.assert_status_bad_request();
+    let body: Value = response.json();
+    assert_eq!(body["code"], "BATCH_REJECTED");
+    assert!(body["details"][0]["error"].as_str().unwrap().contains("/sent"));
+}
+
+#[tokio::test]
+async fn test_resend_clones_email_with_new_identity() {
+    let server = test_server().await;
+    let original: Value = server
+        .post("/acme/emails")
+        .json(&json!({
+            "subject": "Hello",
+            "recipient": "jane@example.com",
+            "campaign_id": "spring",
+            "metadata": { "list": "news" },
+        }))
+        .await
+        .json();
+    server.get("/acme/pixel/1.gif").await.assert_status_ok();
+
+    let response = server.post("/acme/emails/1/resend").await;
+    response.assert_status(StatusCode::CREATED);
+    let resend: Value = response.json();
+    assert_eq!(resend["email_id"], 2);
+    assert_eq!(resend["resend_of"], 1);
+    assert_ne!(resend["public_token"], original["public_token"]);
+    assert!(resend["tracking_pixel_url"].as_str().unwrap().contains(resend["public_token"].as_str().unwrap()));
+    assert!(original.get("resend_of").is_none());
+
+    let emails: Value = server.get("/acme/emails").await.json();
+    let copy = &emails[0];
+    assert_eq!(copy["id"], 2);
+    assert_eq!(copy["subject"], "Hello");
+    assert_eq!(copy["recipient"], "jane@example.com");
+    assert_eq!(copy["campaign_id"], "spring");
+    assert_eq!(copy["metadata"]["list"], "news");
+    assert_eq!(copy["resend_of"], 1);
+    assert_eq!(emails[1]["resend_of"], Value::Null);
+
+    // The original's open stays with the original
+    let events: Value = server.get("/acme/emails/2/events").await.json();
+    assert!(events.as_array().unwrap().is_empty());
+
+    server.post("/acme/emails/9/resend").await.assert_status_not_found();
+    server.post("/other/emails/1/resend").await.assert_status_not_found();
+}
+
+#[tokio::test]
+async fn test_latency_excludes_unopened_emails() {
+    let server = test_server().await;
+
+    for _ in 0..2 {
+        server
+            .post("/acme/emails")
+            .json(&json!({ "subject": "Hello" }))
+            .await
+            .assert_status(StatusCode::CREATED);
+    }
+    server.get("/acme/pixel/1.gif").await.assert_status_ok();
+
+    let body: Value = server.get("/acme/latency").await.json();
+    assert_eq!(body["emails_opened"], 1);
+    assert!(body["min_seconds"].as_f64().unwrap() >= 0.0);
+    assert_eq!(body["min_seconds"], body["max_seconds"]);
+}
+
+#[tokio::test]
+async fn test_cors_restricted_origins() {
+    let db = Arc::new(Database::new(":memory:").await.unwrap());
+    let config = Config {
+        cors_allowed_origins: "https://app.example.com, https://admin.example.com".to_string(),
+        ..Config::default()
+    };
+    let server = TestServer::new(create_app(db, config).await).unwrap();
+
+    let response = server
+        .get("/health")
+        .add_header("origin", "https://app.example.com")
+        .await;
+    assert_eq!(
+        response.header("access-control-allow-origin"),
+        "https://app.example.com"
+    );
+    assert_eq!(response.header("access-control-allow-credentials"), "true");
+
+    let response = server
+        .get("/health")
+        .add_header("origin", "https://evil.example.com")
+        .await;
+    assert!(response.maybe_header("access-control-allow-origin").is_none());
+
+    // Settings updates, bulk deletes and idempotent creates pass preflight
+    let response = server
+        .method(axum::http::Method::OPTIONS, "/acme/settings")
+        .add_header("origin", "https://app.example.com")
+        .add_header("access-control-request-method", "PATCH")
+        .add_header("access-control-request-headers", "content-type, idempotency-key, x-admin-key")
+        .await;
+    response.assert_status_ok();
+    assert_eq!(response.header("access-control-allow-origin"), "https://app.example.com");
+    let methods = response.header("access-control-allow-methods");
+    for method in ["PUT", "PATCH", "DELETE"] {
+        assert!(methods.to_str().unwrap().contains(method), "{:?}", methods);
+    }
+    let headers = response.header("access-control-allow-headers");
+    for name in ["idempotency-key", "x-admin-key"] {
+        assert!(headers.to_str().unwrap().contains(name), "{:?}", headers);
+    }
+
+    let response = server
+        .post("/acme/emails")
+        .add_header("origin", "https://app.example.com")
+        .json(&json!({}))
+        .await;
+    let exposed = response.header("access-control-expose-headers");
+    assert!(exposed.to_str().unwrap().contains("x-quota-remaining"), "{:?}", exposed);
+}
+
+#[tokio::test]
+async fn test_cors_with_only_invalid_origins_allows_none() {
+    let config = Config {
+        cors_allowed_origins: "https://app.\nexample.com, \u{7f}".to_string(),
+        ..Config::default()
+    };
+    assert!(config.cors_origins().is_err());
+
+    // Partly valid lists keep the valid origins
+    let partly_valid = Config {
+        cors_allowed_origins: "https://app.example.com, \u{7f}".to_string(),
+        ..Config::default()
+    };
+    assert_eq!(partly_valid.cors_origins().unwrap().unwrap().len(), 1);
+    assert!(Config::default().cors_origins().unwrap().is_none());
+
+    let db = Arc::new(Database::new(":memory:").await.unwrap());
+    let server = TestServer::new(create_app(db, config).await).unwrap();
+    let response = server
+        .get("/health")
+        .add_header("origin", "https://evil.example.com")
+        .await;
+    response.assert_status_ok();
+    assert!(response.maybe_header("access-control-allow-origin").is_none());
+}
+
+#[tokio::test]
+async fn test_ingest_events_batch() {
+    let server = test_server().await;
+    server
+        .post("/acme/emails")
+        .json(&json!({ "subject": "Hello" }))
+        .await
+        .assert_status(StatusCode::CREATED);
+
+    let response = server
+        .post("/acme/events")
+        .json(&json!([
+            { "email_id": 1, "event_type": "open" },
+            { "email_id": 99, "event_type": "open" },
+            { "email_id": 1, "event_type": "bounce" }
+        ]))
+        .await;
+    response.assert_status(StatusCode::BAD_REQUEST);
+    let body: Value = response.json();
+    assert_eq!(body["details"].as_array().unwrap().len(), 2);
+    assert_eq!(body["details"][0]["index"], 1);
+
+    let response = server
+        .post("/acme/events")
+        .json(&json!([
+            { "email_id": 1, "event_type": "open", "timestamp": "2024-01-01T00:00:00Z" },
+            { "email_id": 1, "event_type": "click", "user_agent": "imap-bot" }
+        ]))
+        .await;
+    response.assert_status(StatusCode::CREATED);
+    assert_eq!(response.json::<Value>()["inserted"], 2);
+
+    let body: Value = server.get("/acme/stats").await.json();
+    assert_eq!(body["total_opens"], 1);
+    assert_eq!(body["total_clicks"], 1);
+}
+
+#[tokio::test]
+async fn test_pixel_accepts_public_token() {
+    let server = test_server().await;
+    let created: Value = server
+        .post("/acme/emails")
+        .json(&json!({ "subject": "Hello" }))
+        .await
+        .json();
+
+    let token = created["public_token"].as_str().unwrap();
+    assert_eq!(token.len(), 22);
+    assert!(token.chars().all(|c| c.is_ascii_alphanumeric()));
+    assert!(created["tracking_pixel_url"]
+        .as_str()
+        .unwrap()
+        .ends_with(&format!("/acme/pixel/{}.gif", token)));
+
+    server.get(&format!("/acme/pixel/{}.gif", token)).await.assert_status_ok();
+    server.get("/acme/pixel/1.gif").await.assert_status_ok();
+    server
+        .get(&format!("/other/pixel/{}.gif", token))
+        .await
+        .assert_status(StatusCode::NOT_FOUND);
+
+    let response = server
+        .get(&format!("/acme/click/{}", token))
+        .add_query_param("url", "https://example.com")
+        .await;
+    response.assert_status(StatusCode::TEMPORARY_REDIRECT);
+
+    let body: Value = server.get("/acme/stats").await.json();
+    assert_eq!(body["total_opens"], 2);
+    assert_eq!(body["total_clicks"], 1);
+}
+
+#[tokio::test]
+async fn test_metrics_record_event_writes() {
+    let server = test_server().await;
+    server
+        .post("/acme/emails")
+        .json(&json!({}))
+        .await
+        .assert_status(StatusCode::CREATED);
+    server.get("/acme/pixel/1.gif").await.assert_status_ok();
+
+    let body: Value = server.get("/metrics").await.json();
+    let writes = &body["database"]["event_writes"];
+    assert_eq!(writes["count"], 1);
+    assert!(writes["p95_ms"].as_f64().is_some());
+    assert!(body["database"]["lock"]["acquisitions"].as_u64().unwrap() > 0);
+}
+
+#[tokio::test]
+async fn test_metrics_per_route() {
+    let server = test_server().await;
+    let created: Value = server.post("/acme/emails").json(&json!({})).await.json();
+    server
+        .get(&format!("/acme/pixel/{}.gif", created["email_id"]))
+        .await
+        .assert_status_ok();
+    server.get("/acme/pixel/999999.gif").await.assert_status(StatusCode::NOT_FOUND);
+    server.get("/nothing/here/at/all").await.assert_status(StatusCode::NOT_FOUND);
+    for method in ["FOO", "BAR"] {
+        server.method(axum::http::Method::from_bytes(method.as_bytes()).unwrap(), "/nothing").await;
+    }
+
+    let body: Value = server.get("/metrics").await.json();
+    let routes = &body["routes"];
+    let pixel = &routes["GET /:tenant_id/pixel/:email_id"];
+    assert_eq!(pixel["statuses"], json!({ "2xx": 1, "4xx": 1 }));
+    assert_eq!(pixel["latency"]["count"], 2);
+    assert_eq!(routes["POST /:tenant_id/emails"]["statuses"]["2xx"], 1);
+    assert_eq!(routes["GET <unmatched>"]["statuses"]["4xx"], 1);
+    // Made-up methods share one entry
+    assert_eq!(routes["OTHER <unmatched>"]["latency"]["count"], 2);
+    assert!(routes.get("FOO <unmatched>").is_none());
+    // Keyed by pattern, so no raw paths show up
+    assert!(routes.as_object().unwrap().keys().all(|route| !route.contains("acme")));
+}
+
+fn forwarded_for(value: &str) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    headers.insert("x-forwarded-for", value.parse().unwrap());
+    headers
+}
+
+#[test]
+fn test_extract_client_ip_single_hop() {
+    let headers = forwarded_for("203.0.113.7");
+    assert_eq!(extract_client_ip(&headers, 0).as_deref(), Some("203.0.113.7"));
+    assert_eq!(extract_client_ip(&headers, 1).as_deref(), Some("203.0.113.7"));
+    // Fewer entries than proxies: the request didn't come through them all,
+    // and the one entry there is could be the client's own
+    assert_eq!(extract_client_ip(&headers, 2), None);
+}
+
+#[test]
+fn test_extract_client_ip_multi_hop() {
+    let headers = forwarded_for("198.51.100.1, 203.0.113.7, 10.0.0.2");
+    assert_eq!(extract_client_ip(&headers, 0).as_deref(), Some("198.51.100.1"));
+    assert_eq!(extract_client_ip(&headers, 1).as_deref(), Some("10.0.0.2"));
+    assert_eq!(extract_client_ip(&headers, 2).as_deref(), Some("203.0.113.7"));
+    assert_eq!(extract_client_ip(&headers, 3).as_deref(), Some("198.51.100.1"));
+    assert_eq!(extract_client_ip(&headers, 4), None);
+}
+
+#[test]
+fn test_extract_client_ip_falls_back_to_real_ip() {
+    let mut headers = HeaderMap::new();
+    headers.insert("x-real-ip", "203.0.113.9".parse().unwrap());
+    assert_eq!(extract_client_ip(&headers, 1).as_deref(), Some("203.0.113.9"));
+    assert_eq!(extract_client_ip(&HeaderMap::new(), 0), None);
+}
+
+#[test]
+fn test_extract_client_ip_normalizes_ipv6_and_ports() {
+    let headers = forwarded_for("[2001:DB8:0:0::1]:51234, 203.0.113.7:443");
+    assert_eq!(extract_client_ip(&headers, 0).as_deref(), Some("2001:db8::1"));
+    assert_eq!(extract_client_ip(&headers, 1).as_deref(), Some("203.0.113.7"));
+    assert_eq!(extract_client_ip(&headers, 2).as_deref(), Some("2001:db8::1"));
+    let headers = forwarded_for("10.0.0.1, 203.0.113.7:443, 10.0.0.2");
+    assert_eq!(extract_client_ip(&headers, 2).as_deref(), Some("203.0.113.7"));
+
+    let headers = forwarded_for("not-an-ip, 10.0.0.2");
+    assert_eq!(extract_client_ip(&headers, 0), None);
+
+    assert_eq!(normalize_ip("2001:db8::1").as_deref(), Some("2001:db8::1"));
+    assert_eq!(normalize_ip("[::1]").as_deref(), Some("::1"));
+    assert_eq!(normalize_ip("::ffff:192.0.2.1").as_deref(), Some("192.0.2.1"));
+    assert_eq!(normalize_ip(" 192.0.2.1 ").as_deref(), Some("192.0.2.1"));
+    assert_eq!(normalize_ip("192.0.2.300"), None);
+    assert_eq!(normalize_ip("[2001:db8::1"), None);
+    assert_eq!(normalize_ip(""), None);
+}
+
+#[test]
+fn test_extract_client_ip_from_custom_headers() {
+    use little_bell::extract_client_ip_from;
+
+    let mut headers = forwarded_for("198.51.100.1");
+    headers.insert("cf-connecting-ip", "203.0.113.50".parse().unwrap());
+    headers.insert("x-real-ip", "203.0.113.9".parse().unwrap());
+    let order = ["cf-connecting-ip", "x-forwarded-for", "x-real-ip"];
+    assert_eq!(extract_client_ip_from(&headers, &order, 0).as_deref(), Some("203.0.113.50"));
+
+    // Missing headers fall through to the next name in order
+    headers.remove("cf-connecting-ip");
+    assert_eq!(extract_client_ip_from(&headers, &order, 0).as_deref(), Some("198.51.100.1"));
+    headers.remove("x-forwarded-for");
+    assert_eq!(extract_client_ip_from(&headers, &order, 0).as_deref(), Some("203.0.113.9"));
+    headers.remove("x-real-ip");
+    assert_eq!(extract_client_ip_from(&headers, &order, 0), None);
+
+    // Headers left off the list are ignored
+    let headers = forwarded_for("198.51.100.1");
+    assert_eq!(extract_client_ip_from(&headers, &["cf-connecting-ip"], 0), None);
+
+    // Behind proxies a configured header is read like X-Forwarded-For: the
+    // client's own value ahead of our proxy's is skipped, and a single
+    // value isn't trusted past more proxies than it could have come through
+    let mut headers = HeaderMap::new();
+    headers.insert("cf-connecting-ip", "198.51.100.66, 203.0.113.50".parse().unwrap());
+    assert_eq!(extract_client_ip_from(&headers, &["cf-connecting-ip"], 1).as_deref(), Some("203.0.113.50"));
+    headers.insert("cf-connecting-ip", "203.0.113.50".parse().unwrap());
+    assert_eq!(extract_client_ip_from(&headers, &["cf-connecting-ip"], 1).as_deref(), Some("203.0.113.50"));
+    assert_eq!(extract_client_ip_from(&headers, &["cf-connecting-ip"], 2), None);
+}
+
+#[tokio::test]
+async fn test_client_ip_header_config() {
+    let db = Arc::new(Database::new(":memory:").await.unwrap());
+    let config = Config {
+        client_ip_header: "CF-Connecting-IP, X-Forwarded-For".to_string(),
+        ..Config::default()
+    };
+    let server = TestServer::new(create_app(db, config).await).unwrap();
+    let created: Value = server.post("/acme/emails").json(&json!({})).await.json();
+    let email_id = created["email_id"].as_i64().unwrap();
+    let pixel = format!("/acme/pixel/{}.gif", email_id);
+
+    server
+        .get(&pixel)
+        .add_header("cf-connecting-ip", "203.0.113.50")
+        .add_header("x-forwarded-for", "198.51.100.1")
+        .await
+        .assert_status_ok();
+    server
+        .get(&pixel)
+        .add_header("x-forwarded-for", "198.51.100.1")
+        .await
+        .assert_status_ok();
+    server
+        .get(&pixel)
+        .add_header("x-real-ip", "203.0.113.9")
+        .await
+        .assert_status_ok();
+
+    let events: Value = server.get(&format!("/acme/emails/{}/events", email_id)).await.json();
+    assert_eq!(events[0]["ip_address"], "203.0.113.50");
+    assert_eq!(events[1]["ip_address"], "198.51.100.1");
+    assert!(events[2]["ip_address"].is_null());
+}
+
+#[tokio::test]
+async fn test_get_pixel_url_matches_create_response() {
+    let server = test_server().await;
+    let created: Value = server
+        .post("/acme/emails")
+        .json(&json!({ "subject": "Hello" }))
+        .await
+        .json();
+
+    let response = server.get("/acme/emails/1/pixel-url").await;
+    response.assert_status_ok();
+    assert_eq!(response.json::<Value>(), created);
+
+    let response = server.get("/other/emails/1/pixel-url").await;
+    response.assert_status(StatusCode::NOT_FOUND);
+    let body: Value = response.json();
+    assert_eq!(body["code"], "EMAIL_NOT_FOUND");
+    assert_eq!(body["error"], "email 1 not found");
+}
+
+#[tokio::test]
+async fn test_pixel_cache_mode_per_tenant() {
+    let server = test_server().await;
+    server
+        .post("/acme/emails")
+        .json(&json!({}))
+        .await
+        .assert_status(StatusCode::CREATED);
+
+    let response = server.get("/acme/pixel/1.gif").await;
+    assert_eq!(
+        response.header("cache-control"),
+        "no-store, no-cache, must-revalidate"
+    );
+
+    server
+        .put("/acme/settings")
+        .json(&json!({ "pixel_cache_mode": "cache_short" }))
+        .await
+        .assert_status_ok();
+    let body: Value = server.get("/acme/settings").await.json();
+    assert_eq!(body["pixel_cache_mode"], "cache_short");
+
+    let response = server.get("/acme/pixel/1.gif").await;
+    assert_eq!(response.header("cache-control"), "private, max-age=300");
+    assert!(response.maybe_header("pragma").is_none());
+}
+
+#[tokio::test]
+async fn test_pixel_cache_mode_is_read_from_memory() {
+    use little_bell::database::{PixelCacheMode, TenantSettings};
+
+    let db = Arc::new(Database::new(":memory:").await.unwrap());
+    let config = Config {
+        admin_key: Some("secret".to_string()),
+        ..Config::default()
+    };
+    let server = TestServer::new(create_app(db.clone(), config).await).unwrap();
+    server.post("/acme/emails").json(&json!({})).await.assert_status(StatusCode::CREATED);
+    server.get("/acme/pixel/1.gif").await.assert_status_ok();
+
+    // Written behind the server's back, so the remembered mode still applies
+    let settings = TenantSettings {
+        pixel_cache_mode: PixelCacheMode::CacheShort,
+        ..TenantSettings::default()
+    };
+    db.update_tenant_settings("acme", &settings).await.unwrap();
+    let response = server.get("/acme/pixel/1.gif").await;
+    assert_eq!(response.header("cache-control"), "no-store, no-cache, must-revalidate");
+
+    // Changes through the API are seen straight away
+    server
+        .patch("/admin/tenants/acme/settings")
+        .authorization_bearer("secret")
+        .json(&json!({ "pixel_cache_mode": "cache_short" }))
+        .await
+        .assert_status_ok();
+    let response = server.get("/acme/pixel/1.gif").await;
+    assert_eq!(response.header("cache-control"), "private, max-age=300");
+    server
+        .patch("/acme/settings")
+        .json(&json!({ "pixel_cache_mode": "no_cache" }))
+        .await
+        .assert_status_ok();
+    let response = server.get("/acme/pixel/1.gif").await;
+    assert_eq!(response.header("cache-control"), "no-store, no-cache, must-revalidate");
+}
+
+#[tokio::test]
+async fn test_top_links_ranks_clicked_urls() {
+    let server = test_server().await;
+    server
+        .post("/acme/emails")
+        .json(&json!({}))
+        .await
+        .assert_status(StatusCode::CREATED);
+
+    for url in ["https://a.example", "https://b.example", "https://b.example"] {
+        server
+            .get("/acme/click/1")
+            .add_query_param("url", url)
+            .await
+            .assert_status(StatusCode::TEMPORARY_REDIRECT);
+    }
+
+    let body: Value = server.get("/acme/top-links").await.json();
+    assert_eq!(
+        body,
+        json!([
+            { "url": "https://b.example", "clicks": 2 },
+            { "url": "https://a.example", "clicks": 1 }
+        ])
+    );
+
+    let body: Value = server.get("/acme/top-links?limit=1").await.json();
+    assert_eq!(body.as_array().unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn test_hourly_heatmap_buckets_opens_by_utc_hour() {
+    let server = test_server().await;
+    server
+        .post("/acme/emails")
+        .json(&json!({}))
+        .await
+        .assert_status(StatusCode::CREATED);
+    server
+        .post("/acme/events")
+        .json(&json!([
+            { "email_id": 1, "event_type": "open", "timestamp": "2026-03-02T09:15:00Z" },
+            { "email_id": 1, "event_type": "open", "timestamp": "2026-03-02T11:45:30.5+02:00" },
+            { "email_id": 1, "event_type": "open", "timestamp": "2026-03-01T21:00:00Z" },
+            { "email_id": 1, "event_type": "click", "timestamp": "2026-03-01T21:05:00Z" }
+        ]))
+        .await
+        .assert_status(StatusCode::CREATED);
+
+    let body: Value = server.get("/acme/hourly-heatmap").await.json();
+    let hours = body["hours"].as_array().unwrap();
+    assert_eq!(hours.len(), 24);
+    assert_eq!(hours[9], 2);
+    assert_eq!(hours[21], 1);
+    assert_eq!(hours.iter().map(|count| count.as_i64().unwrap()).sum::<i64>(), 3);
+    assert!(body.get("weekdays").is_none());
+
+    // 2026-03-02 is a Monday, the first row
+    let body: Value = server.get("/acme/hourly-heatmap?by_weekday=true").await.json();
+    assert_eq!(body["weekdays"].as_array().unwrap().len(), 7);
+    assert_eq!(body["weekdays"][0][9], 2);
+    assert_eq!(body["weekdays"][6][21], 1);
+    assert_eq!(body["hours"][9], 2);
+}
+
+#[tokio::test]
+async fn test_malformed_json_returns_error_envelope() {
+    let server = test_server().await;
+
+    let response = server
+        .post("/acme/emails")
+        .text("{\"subject\": ")
+        .content_type("application/json")
+        .await;
+    response.assert_status(StatusCode::BAD_REQUEST);
+    let body: Value = response.json();
+    assert!(body["error"].as_str().unwrap().contains("JSON"));
+    assert_eq!(body["code"], "BAD_REQUEST");
+
+    let response = server
+        .post("/acme/emails")
+        .text("")
+        .content_type("application/json")
+        .await;
+    response.assert_status(StatusCode::BAD_REQUEST);
+    assert!(response.json::<Value>()["error"].is_string());
+
+    let response = server
+        .post("/acme/events")
+        .json(&json!({ "email_id": "not-a-list" }))
+        .await;
+    response.assert_status(StatusCode::BAD_REQUEST);
+    assert!(response.json::<Value>()["error"].is_string());
+}
+
+#[tokio::test]
+async fn test_campaign_stats() {
+    let server = test_server().await;
+    for campaign in ["spring", "spring", "fall"] {
+        server
+            .post("/acme/emails")
+            .json(&json!({ "campaign_id": campaign }))
+            .await
+            .assert_status(StatusCode::CREATED);
+    }
+    server.get("/acme/pixel/1.gif").await.assert_status_ok();
+    server.get("/acme/pixel/3.gif").await.assert_status_ok();
+    for email_id in [1, 2] {
+        server
+            .post(&format!("/acme/emails/{}/sent", email_id))
+            .await
+            .assert_status_ok();
+    }
+
+    let body: Value = server.get("/acme/campaigns/spring/stats").await.json();
+    assert_eq!(body["emails_created"], 2);
+    assert_eq!(body["emails_sent"], 2);
+    assert_eq!(body["total_opens"], 1);
+    assert_eq!(body["open_rate"], 0.5);
+
+    // An opened email that wasn't sent doesn't raise the rate
+    server
+        .post("/acme/emails")
+        .json(&json!({ "campaign_id": "spring" }))
+        .await
+        .assert_status(StatusCode::CREATED);
+    server.get("/acme/pixel/4.gif").await.assert_status_ok();
+    let body: Value = server.get("/acme/campaigns/spring/stats").await.json();
+    assert_eq!(body["unique_opens"], 2);
+    assert_eq!(body["open_rate"], 0.5);
+
+    let body: Value = server.get("/other/campaigns/spring/stats").await.json();
+    assert_eq!(body["emails_created"], 0);
+}
+
+#[tokio::test]
+async fn test_body_limit_returns_payload_too_large() {
+    let db = Arc::new(Database::new(":memory:").await.unwrap());
+    let config = Config {
+        max_body_bytes: 64,
+        ..Config::default()
+    };
+    let server = TestServer::new(create_app(db, config).await).unwrap();
+
+    let response = server
+        .post("/acme/emails")
+        .json(&json!({ "subject": "x".repeat(200) }))
+        .await;
+    response.assert_status(StatusCode::PAYLOAD_TOO_LARGE);
+
+    // The batch endpoint has its own, larger limit
+    let response = server
+        .post("/acme/events")
+        .json(&json!([{ "email_id": 1, "event_type": "open", "user_agent": "x".repeat(200) }]))
+        .await;
+    response.assert_status(StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn test_foreign_keys_are_enforced() {
+    let db = Database::new(":memory:").await.unwrap();
+
+    let result = db.log_event(999, "open", None, None, None, None).await;
+    assert!(result.is_err());
+}
+
+async fn admin_server() -> TestServer {
+    let db = Arc::new(Database::new(":memory:").await.unwrap());
+    let config = Config {
+        admin_key: Some("secret".to_string()),
+        ..Config::default()
+    };
+    TestServer::new(create_app(db, config).await).unwrap()
+}
+
+#[tokio::test]
+async fn test_bulk_delete_emails_by_campaign() {
+    let server = admin_server().await;
+    for campaign in ["spring", "spring", "fall"] {
+        server
+            .post("/acme/emails")
+            .json(&json!({ "campaign_id": campaign }))
+            .await
+            .assert_status(StatusCode::CREATED);
+    }
+    server.get("/acme/pixel/1.gif").await.assert_status_ok();
+    server.get("/acme/pixel/3.gif").await.assert_status_ok();
+    server
+        .get("/acme/click-url/2?url=https%3A%2F%2Fexample.com%2F&short=true")
+        .await
+        .assert_status_ok();
+
+    server
+        .delete("/acme/emails?campaign_id=spring")
+        .await
+        .assert_status(StatusCode::UNAUTHORIZED);
+    let response = server.delete("/acme/emails").authorization_bearer("secret").await;
+    response.assert_status_bad_request();
+    server
+        .delete("/acme/emails?created_before=yesterday")
+        .authorization_bearer("secret")
+        .await
+        .assert_status_bad_request();
+
+    let response = server
+        .delete("/acme/emails?campaign_id=spring")
+        .authorization_bearer("secret")
+        .await;
+    response.assert_status_ok();
+    assert_eq!(
+        response.json::<Value>(),
+        json!({ "emails": 2, "events": 1, "links": 1, "webhook_deliveries": 0 })
+    );
+
+    server.get("/acme/pixel/1.gif").await.assert_status_not_found();
+    let emails: Value = server.get("/acme/emails").await.json();
+    assert_eq!(emails.as_array().unwrap().len(), 1);
+    assert_eq!(emails[0]["campaign_id"], "fall");
+    let stats: Value = server.get("/acme/stats").await.json();
+    assert_eq!(stats["total_opens"], 1);
+
+    // Nothing else matches both filters
+    let response = server
+        .delete("/acme/emails?campaign_id=fall&created_before=2000-01-01")
+        .authorization_bearer("secret")
+        .await;
+    assert_eq!(response.json::<Value>()["emails"], 0);
+}
+
+#[tokio::test]
+async fn test_time_format_epoch_millis() {
+    let db = Arc::new(Database::new(":memory:").await.unwrap());
+    let config = Config {
+        admin_key: Some("secret".to_string()),
+        time_format: little_bell::time_format::TimeFormat::EpochMillis,
+        ..Config::default()
+    };
+    let server = TestServer::new(create_app(db, config).await).unwrap();
+    server
+        .post("/acme/emails")
+        .json(&json!({ "expires_at": "2030-01-01T00:00:00Z" }))
+        .await
+        .assert_status(StatusCode::CREATED);
+    server
+        .post("/acme/events")
+        .json(&json!([{ "email_id": 1, "event_type": "open", "timestamp": "2026-03-01T10:00:00Z" }]))
+        .await
+        .assert_status(StatusCode::CREATED);
+
+    let emails: Value = server.get("/acme/emails").await.json();
+    assert!(emails[0]["created_at"].is_i64());
+    assert_eq!(emails[0]["expires_at"], 1_893_456_000_000i64);
+    assert!(emails[0]["sent_at"].is_null());
+    assert_eq!(emails[0]["last_event_at"], 1_772_359_200_000i64);
+    let events: Value = server.get("/acme/emails/1/events").await.json();
+    assert_eq!(events[0]["timestamp"], 1_772_359_200_000i64);
+    let sent: Value = server.post("/acme/emails/1/sent").await.json();
+    assert!(sent["sent_at"].is_i64());
+    let tenants: Value = server.get("/admin/tenants").authorization_bearer("secret").await.json();
+    assert!(tenants["tenants"][0]["created_at"].is_i64());
+    assert!(tenants["tenants"][0]["last_activity_at"].is_i64());
+    let diagnostics: Value = server.get("/admin/diagnostics").authorization_bearer("secret").await.json();
+    assert!(diagnostics["newest_event_at"].is_i64());
+
+    // Exports stay RFC 3339 so any instance can import them
+    let export: Value = server
+        .get("/acme/export.json")
+        .authorization_bearer("secret")
+        .await
+        .json();
+    assert!(export["tenant"]["created_at"].is_string());
+    assert_eq!(export["events"][0]["timestamp"], "2026-03-01T10:00:00Z");
+    let audit: Value = server.get("/admin/audit").authorization_bearer("secret").await.json();
+    assert!(audit["entries"][0]["created_at"].is_i64());
+}
+
+#[tokio::test]
+async fn test_preview_pixel_records_nothing() {
+    let server = test_server().await;
+    server
+        .post("/acme/emails")
+        .json(&json!({}))
+        .await
+        .assert_status(StatusCode::CREATED);
+    let tracked = server.get("/acme/pixel/1.gif").await;
+
+    let response = server.get("/pixel.gif").await;
+    response.assert_status_ok();
+    assert_eq!(response.header("content-type"), "image/gif");
+    assert_eq!(response.as_bytes(), tracked.as_bytes());
+
+    let stats: Value = server.get("/acme/stats").await.json();
+    assert_eq!(stats["total_opens"], 1);
+}
+
+#[tokio::test]
+async fn test_admin_tenants_requires_key() {
+    let server = admin_server().await;
+    for tenant in ["acme", "acme", "globex"] {
+        server
+            .post(&format!("/{}/emails", tenant))
+            .json(&json!({}))
+            .await
+            .assert_status(StatusCode::CREATED);
+    }
+    server.get("/acme/pixel/1.gif").await.assert_status_ok();
+
+    server
+        .get("/admin/tenants")
+        .await
+        .assert_status(StatusCode::UNAUTHORIZED);
+    server
+        .get("/admin/tenants")
+        .authorization_bearer("wrong")
+        .await
+        .assert_status(StatusCode::UNAUTHORIZED);
+
+    let response = server
+        .get("/admin/tenants")
+        .authorization_bearer("secret")
+        .await;
+    response.assert_status_ok();
+    let body: Value = response.json();
+    assert_eq!(body["total"], 2);
+    assert_eq!(body["tenants"][0]["id"], "acme");
+    assert_eq!(body["tenants"][0]["email_count"], 2);
+    assert_eq!(body["tenants"][0]["total_opens"], 1);
+    assert_eq!(body["tenants"][1]["id"], "globex");
+    assert_eq!(body["tenants"][1]["total_opens"], 0);
+
+    let body: Value = server
+        .get("/admin/tenants?sort_by=email_count&direction=asc&limit=1")
+        .authorization_bearer("secret")
+        .await
+        .json();
+    assert_eq!(body["total"], 2);
+    assert_eq!(body["tenants"].as_array().unwrap().len(), 1);
+    assert_eq!(body["tenants"][0]["id"], "globex");
+
+    server
+        .get("/admin/tenants?sort_by=id%3BDROP%20TABLE%20tenants")
+        .authorization_bearer("secret")
+        .await
+        .assert_status(StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn test_admin_tenants_last_activity() {
+    let db = Arc::new(Database::new(":memory:").await.unwrap());
+    db.create_tenant("dormant", "Dormant").await.unwrap();
+    let config = Config {
+        admin_key: Some("secret".to_string()),
+        ..Config::default()
+    };
+    let server = TestServer::new(create_app(db, config).await).unwrap();
+
+    let created: Value = server.post("/acme/emails").json(&json!({})).await.json();
+    let email_id = created["email_id"].as_i64().unwrap();
+
+    let list = || async {
+        server
+            .get("/admin/tenants?sort_by=last_activity_at&direction=desc")
+            .authorization_bearer("secret")
+            .await
+            .json::<Value>()
+    };
+    let body = list().await;
+    assert_eq!(body["tenants"][0]["id"], "acme");
+    let first_seen = body["tenants"][0]["last_activity_at"].clone();
+    assert!(first_seen.is_string());
+    assert_eq!(body["tenants"][1]["id"], "dormant");
+    assert!(body["tenants"][1]["last_activity_at"].is_null());
+
+    // Within the minute further hits don't rewrite it
+    tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    server
+        .get(&format!("/acme/pixel/{}.gif", email_id))
+        .await
+        .assert_status_ok();
+    assert_eq!(list().await["tenants"][0]["last_activity_at"], first_seen);
+}
+
+#[test]
+fn test_activity_tracker_throttles_per_tenant() {
+    use little_bell::activity::ActivityTracker;
+
+    let tracker = ActivityTracker::new(std::time::Duration::from_millis(50));
+    assert!(tracker.due("acme"));
+    assert!(!tracker.due("acme"));
+    assert!(tracker.due("globex"));
+    std::thread::sleep(std::time::Duration::from_millis(60));
+    assert!(tracker.due("acme"));
+}
+
+#[tokio::test]
+async fn test_unsubscribe_suppresses_recipient() {
+    let db = Arc::new(Database::new(":memory:").await.unwrap());
+    let config = Config {
+        reject_suppressed_recipients: true,
+        unsubscribe_redirect_url: Some("https://example.com/bye".to_string()),
+        ..Config::default()
+    };
+    let server = TestServer::new(create_app(db.clone(), config).await).unwrap();
+
+    let created: Value = server
+        .post("/acme/emails")
+        .json(&json!({ "recipient": "Someone@Example.com" }))
+        .await
+        .json();
+    let unsubscribe_url = created["unsubscribe_url"].as_str().unwrap();
+    let path = unsubscribe_url.trim_start_matches("http://localhost:3000");
+
+    let response = server.get(path).await;
+    response.assert_status(StatusCode::SEE_OTHER);
+    assert_eq!(response.header("location"), "https://example.com/bye");
+    assert!(db.is_suppressed("acme", "someone@example.com").await.unwrap());
+
+    server
+        .post("/acme/emails")
+        .json(&json!({ "recipient": "someone@example.com" }))
+        .await
+        .assert_status(StatusCode::CONFLICT);
+    server
+        .post("/acme/emails")
+        .json(&json!({ "recipient": "other@example.com" }))
+        .await
+        .assert_status(StatusCode::CREATED);
+}
+
+#[tokio::test]
+async fn test_admin_diagnostics() {
+    let server = admin_server().await;
+    server
+        .post("/acme/emails")
+        .json(&json!({}))
+        .await
+        .assert_status(StatusCode::CREATED);
+    server.get("/acme/pixel/1.gif").await.assert_status_ok();
+
+    server
+        .get("/admin/diagnostics")
+        .await
+        .assert_status(StatusCode::UNAUTHORIZED);
+
+    let body: Value = server
+        .get("/admin/diagnostics")
+        .authorization_bearer("secret")
+        .await
+        .json();
+    assert_eq!(body["tenants"], 1);
+    assert_eq!(body["emails"], 1);
+    assert_eq!(body["events"], 1);
+    assert!(body["newest_event_at"].is_string());
+    assert!(body["database_bytes"].as_i64().unwrap() > 0);
+}
+
+#[tokio::test]
+async fn test_admin_config_redacts_secrets() {
+    let server = admin_server().await;
+    server.get("/admin/config").await.assert_status(StatusCode::UNAUTHORIZED);
+
+    let response = server.get("/admin/config").authorization_bearer("secret").await;
+    response.assert_status_ok();
+    let body: Value = response.json();
+    assert_eq!(body["admin_key"], "[redacted]");
+    assert!(!response.text().contains("secret"));
+    assert_eq!(body["port"], Config::default().port);
+    assert_eq!(body["redirect_status"], "temporary");
+}
+
+#[tokio::test]
+async fn test_admin_patch_tenant_settings() {
+    let server = admin_server().await;
+    server
+        .put("/acme/settings")
+        .json(&json!({ "pixel_cache_mode": "cache_short" }))
+        .await
+        .assert_status_ok();
+
+    server
+        .patch("/admin/tenants/acme/settings")
+        .json(&json!({ "daily_email_limit": 5 }))
+        .await
+        .assert_status(StatusCode::UNAUTHORIZED);
+
+    let response = server
+        .patch("/admin/tenants/acme/settings")
+        .authorization_bearer("secret")
+        .json(&json!({ "daily_email_limit": 5 }))
+        .await;
+    response.assert_status_ok();
+    let body: Value = response.json();
+    assert_eq!(body["daily_email_limit"], 5);
+    assert_eq!(body["pixel_cache_mode"], "cache_short");
+    let settings: Value = server.get("/acme/settings").await.json();
+    assert_eq!(settings, body);
+
+    server
+        .patch("/admin/tenants/acme/settings")
+        .authorization_bearer("secret")
+        .json(&json!({ "daily_email_limit": -5 }))
+        .await
+        .assert_status_bad_request();
+
+    server
+        .patch("/admin/tenants/nobody/settings")
+        .authorization_bearer("secret")
+        .json(&json!({ "daily_email_limit": 5 }))
+        .await
+        .assert_status(StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_idempotency_key_creates_one_email() {
+    let server = test_server().await;
+
+    let first = server
+        .post("/acme/emails")
+        .add_header("idempotency-key", "send-42")
+        .json(&json!({ "subject": "Hello" }))
+        .await;
+    first.assert_status(StatusCode::CREATED);
+
+    let second = server
+        .post("/acme/emails")
+        .add_header("idempotency-key", "send-42")
+        .json(&json!({ "subject": "Hello" }))
+        .await;
+    second.assert_status_ok();
+    let (first, second) = (first.json::<Value>(), second.json::<Value>());
+    assert_eq!(first["email_id"], second["email_id"]);
+    assert_eq!(first["tracking_pixel_url"], second["tracking_pixel_url"]);
+    assert!(first.get("existing").is_none());
+    assert!(first.get("stats").is_none());
+    assert_eq!(second["existing"], true);
+    assert_eq!(second["stats"]["open_count"], 0);
+
+    let body: Value = server.get("/acme/stats").await.json();
+    assert_eq!(body["emails_created"], 1);
+
+    // Replays report engagement since the email was sent
+    server.get("/acme/pixel/1.gif").await.assert_status_ok();
+    let third: Value = server
+        .post("/acme/emails")
+        .add_header("idempotency-key", "send-42")
+        .json(&json!({ "subject": "Hello" }))
+        .await
+        .json();
+    assert_eq!(third["stats"]["open_count"], 1);
+    assert_eq!(third["stats"]["click_count"], 0);
+    assert!(third["stats"]["last_event_at"].is_string());
+
+    // Keys are scoped per tenant
+    server
+        .post("/other/emails")
+        .add_header("idempotency-key", "send-42")
+        .json(&json!({ "subject": "Hello" }))
+        .await
+        .assert_status(StatusCode::CREATED);
+}
+
+#[tokio::test]
+async fn test_external_id_upserts_email() {
+    let server = test_server().await;
+
+    let first = server
+        .post("/acme/emails")
+        .json(&json!({ "subject": "Hello", "external_id": "<msg-1@mta.example>" }))
+        .await;
+    first.assert_status(StatusCode::CREATED);
+    let second = server
+        .post("/acme/emails")
+        .json(&json!({ "subject": "Retried", "external_id": "<msg-1@mta.example>" }))
+        .await;
+    second.assert_status_ok();
+    let (first, second) = (first.json::<Value>(), second.json::<Value>());
+    assert_eq!(first["email_id"], second["email_id"]);
+    assert_eq!(second["existing"], true);
+
+    let emails: Value = server.get("/acme/emails").await.json();
+    assert_eq!(emails.as_array().unwrap().len(), 1);
+    assert_eq!(emails[0]["external_id"], "<msg-1@mta.example>");
+    assert_eq!(emails[0]["subject"], "Hello");
+
+    // Unique per tenant only
+    server
+        .post("/other/emails")
+        .json(&json!({ "external_id": "<msg-1@mta.example>" }))
+        .await
+        .assert_status(StatusCode::CREATED);
+    server
+        .post("/acme/emails")
+        .json(&json!({ "external_id": "<msg-2@mta.example>" }))
+        .await
+        .assert_status(StatusCode::CREATED);
+}
+
+#[tokio::test]
+async fn test_click_fallback_url() {
+    let server = test_server().await;
+    server
+        .post("/acme/emails")
+        .json(&json!({}))
+        .await
+        .assert_status(StatusCode::CREATED);
+    server
+        .get("/acme/click/1")
+        .await
+        .assert_status(StatusCode::BAD_REQUEST);
+
+    let db = Arc::new(Database::new(":memory:").await.unwrap());
+    let config = Config {
+        click_fallback_url: Some("https://example.com/home".to_string()),
+        ..Config::default()
+    };
+    let server = TestServer::new(create_app(db, config).await).unwrap();
+    server
+        .post("/acme/emails")
+        .json(&json!({}))
+        .await
+        .assert_status(StatusCode::CREATED);
+
+    for query in ["", "?url=not%20a%20url"] {
+        let response = server.get(&format!("/acme/click/1{}", query)).await;
+        response.assert_status(StatusCode::TEMPORARY_REDIRECT);
+        assert_eq!(response.header("location"), "https://example.com/home");
+    }
+
+    let body: Value = server.get("/acme/stats").await.json();
+    assert_eq!(body["total_clicks"], 2);
+}
+
+#[tokio::test]
+async fn test_click_rejects_disallowed_schemes() {
+    let db = Arc::new(Database::new(":memory:").await.unwrap());
+    let config = Config {
+        click_fallback_url: Some("https://example.com/home".to_string()),
+        ..Config::default()
+    };
+    let server = TestServer::new(create_app(db, config).await).unwrap();
+    server
+        .post("/acme/emails")
+        .json(&json!({}))
+        .await
+        .assert_status(StatusCode::CREATED);
+
+    // Refused outright rather than sent to the fallback page
+    for url in [
+        "javascript:alert(1)",
+        "JavaScript://example.com/%0aalert(1)",
+        "data:text/html;base64,PHNjcmlwdD5hbGVydCgxKTwvc2NyaXB0Pg==",
+    ] {
+        let response = server.get("/acme/click/1").add_query_param("url", url).await;
+        response.assert_status_bad_request();
+        assert_eq!(response.json::<Value>()["code"], "INVALID_URL");
+    }
+    let body: Value = server.get("/acme/stats").await.json();
+    assert_eq!(body["total_clicks"], 0);
+
+    server
+        .get("/acme/click/1")
+        .add_query_param("url", "https://example.com/ok")
+        .await
+        .assert_status(StatusCode::TEMPORARY_REDIRECT);
+
+    // The list is configurable, for both the redirect and wrapping
+    let db = Arc::new(Database::new(":memory:").await.unwrap());
+    let config = Config {
+        allowed_redirect_schemes: "https, mailto".to_string(),
+        ..Config::default()
+    };
+    let server = TestServer::new(create_app(db, config).await).unwrap();
+    server
+        .post("/acme/emails")
+        .json(&json!({}))
+        .await
+        .assert_status(StatusCode::CREATED);
+    server
+        .get("/acme/click/1")
+        .add_query_param("url", "http://example.com/")
+        .await
+        .assert_status_bad_request();
+    server
+        .get("/acme/click-url/1")
+        .add_query_param("url", "http://example.com/")
+        .await
+        .assert_status_bad_request();
+    server
+        .get("/acme/click-url/1")
+        .add_query_param("url", "https://example.com/")
+        .await
+        .assert_status_ok();
+}
+
+#[tokio::test]
+async fn test_hsts_only_for_requests_proxied_over_https() {
+    let db = Arc::new(Database::new(":memory:").await.unwrap());
+    let config = Config {
+        trusted_proxy_hops: 1,
+        hsts_max_age_secs: Some(31_536_000),
+        ..Config::default()
+    };
+    let server = TestServer::new(create_app(db, config).await).unwrap();
+
+    let response = server.get("/health").add_header("x-forwarded-proto", "https").await;
+    assert_eq!(response.header("strict-transport-security"), "max-age=31536000");
+
+    // Plain HTTP, and a client claiming HTTPS ahead of our proxy's entry
+    for proto in [None, Some("http"), Some("https, http")] {
+        let mut request = server.get("/health");
+        if let Some(proto) = proto {
+            request = request.add_header("x-forwarded-proto", proto);
+        }
+        assert!(request.await.maybe_header("strict-transport-security").is_none());
+    }
+
+    // Without trusted proxies the header can't be believed
+    let db = Arc::new(Database::new(":memory:").await.unwrap());
+    let config = Config {
+        hsts_max_age_secs: Some(31_536_000),
+        ..Config::default()
+    };
+    let server = TestServer::new(create_app(db, config).await).unwrap();
+    let response = server.get("/health").add_header("x-forwarded-proto", "https").await;
+    assert!(response.maybe_header("strict-transport-security").is_none());
+}
+
+#[tokio::test]
+async fn test_path_prefix_routes_match_generated_urls() {
+    let db = Arc::new(Database::new(":memory:").await.unwrap());
+    let config = Config {
+        base_url: "https://track.example.com".to_string(),
+        path_prefix: "/bell/".to_string(),
+        ..Config::default()
+    };
+    let server = TestServer::new(create_app(db, config).await).unwrap();
+
+    let created: Value = server
+        .post("/bell/acme/emails")
+        .json(&json!({}))
+        .await
+        .json();
+    let pixel_url = created["tracking_pixel_url"].as_str().unwrap();
+    let path = pixel_url
+        .strip_prefix("https://track.example.com")
+        .unwrap();
+    assert!(path.starts_with("/bell/acme/pixel/"));
+
+    server.get(path).await.assert_status_ok();
+    server
+        .get(path.trim_start_matches("/bell"))
+        .await
+        .assert_status(StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_email_metadata_filtering() {
+    let server = test_server().await;
+    for variant in ["A", "B", "B"] {
+        server
+            .post("/acme/emails")
+            .json(&json!({ "metadata": { "variant": variant, "segment": "trial" } }))
+            .await
+            .assert_status(StatusCode::CREATED);
+    }
+
+    let body: Value = server.get("/acme/emails?meta.variant=B").await.json();
+    let emails = body.as_array().unwrap();
+    assert_eq!(emails.len(), 2);
+    assert_eq!(emails[0]["metadata"]["variant"], "B");
+
+    let body: Value = server
+        .get("/acme/emails?meta.variant=A&meta.segment=trial")
+        .await
+        .json();
+    assert_eq!(body.as_array().unwrap().len(), 1);
+
+    for metadata in [json!(["a"]), json!("scalar"), json!(3)] {
+        server
+            .post("/acme/emails")
+            .json(&json!({ "metadata": metadata }))
+            .await
+            .assert_status(StatusCode::BAD_REQUEST);
+    }
+}
+
+#[tokio::test]
+async fn test_pixel_is_not_compressed() {
+    let server = test_server().await;
+    for _ in 0..20 {
+        server
+            .post("/acme/emails")
+            .json(&json!({ "subject": "A reasonably long subject line to pad the listing" }))
+            .await
+            .assert_status(StatusCode::CREATED);
+    }
+
+    let response = server
+        .get("/acme/pixel/1.gif")
+        .add_header("accept-encoding", "br, gzip")
+        .await;
+    response.assert_status_ok();
+    assert!(response.maybe_header("content-encoding").is_none());
+    assert_eq!(response.as_bytes().len(), include_bytes!("../src/pixel.gif").len());
+
+    let response = server
+        .get("/acme/emails")
+        .add_header("accept-encoding", "br, gzip")
+        .await;
+    assert_eq!(response.header("content-encoding"), "br");
+
+    let response = server
+        .get("/acme/emails")
+        .add_header("accept-encoding", "gzip")
+        .await;
+    assert_eq!(response.header("content-encoding"), "gzip");
+}
+
+#[tokio::test]
+async fn test_daily_email_quota() {
+    let server = admin_server().await;
+
+    server
+        .patch("/acme/settings")
+        .authorization_bearer("secret")
+        .json(&json!({ "daily_email_limit": 2 }))
+        .await
+        .assert_status_ok();
+
+    let first = server
+        .post("/acme/emails")
+        .add_header("idempotency-key", "send-1")
+        .json(&json!({ "subject": "Hello" }))
+        .await;
+    first.assert_status(StatusCode::CREATED);
+    assert_eq!(first.header("x-quota-remaining"), "1");
+
+    let second = server.post("/acme/emails").json(&json!({ "subject": "Hello" })).await;
+    assert_eq!(second.header("x-quota-remaining"), "0");
+
+    let over = server.post("/acme/emails").json(&json!({ "subject": "Hello" })).await;
+    over.assert_status(StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(over.json::<Value>()["code"], "QUOTA_EXCEEDED");
+
+    // Replays don't create anything, so they still succeed
+    server
+        .post("/acme/emails")
+        .add_header("idempotency-key", "send-1")
+        .json(&json!({ "subject": "Hello" }))
+        .await
+        .assert_status_ok();
+
+    // The PATCH left the other settings alone
+    let settings: Value = server.get("/acme/settings").await.json();
+    assert_eq!(settings["pixel_cache_mode"], "no_cache");
+    assert_eq!(settings["daily_email_limit"], 2);
+
+    // Negative limits are refused, and null clears the limit
+    server
+        .patch("/acme/settings")
+        .json(&json!({ "daily_email_limit": -1 }))
+        .await
+        .assert_status_bad_request();
+    server
+        .put("/acme/settings")
+        .json(&json!({ "daily_email_limit": -1 }))
+        .await
+        .assert_status_bad_request();
+    let settings: Value = server
+        .patch("/acme/settings")
+        .authorization_bearer("secret")
+        .json(&json!({ "daily_email_limit": null }))
+        .await
+        .json();
+    assert!(settings["daily_email_limit"].is_null());
+    assert_eq!(settings["pixel_cache_mode"], "no_cache");
+    server
+        .post("/acme/emails")
+        .json(&json!({ "subject": "Hello" }))
+        .await
+        .assert_status(StatusCode::CREATED);
+}
+
+#[tokio::test]
+async fn test_tenant_routes_cannot_raise_daily_email_limit() {
+    let server = admin_server().await;
+    server
+        .patch("/admin/tenants/acme/settings")
+        .authorization_bearer("secret")
+        .json(&json!({ "daily_email_limit": 1 }))
+        .await
+        .assert_status(StatusCode::NOT_FOUND);
+    server.post("/acme/emails").json(&json!({})).await.assert_status(StatusCode::CREATED);
+    server
+        .patch("/admin/tenants/acme/settings")
+        .authorization_bearer("secret")
+        .json(&json!({ "daily_email_limit": 1 }))
+        .await
+        .assert_status_ok();
+
+    for limit in [json!(i64::MAX), json!(null)] {
+        let response = server
+            .patch("/acme/settings")
+            .json(&json!({ "daily_email_limit": limit }))
+            .await;
+        response.assert_status(StatusCode::UNAUTHORIZED);
+        assert_eq!(response.json::<Value>()["code"], "UNAUTHORIZED");
+    }
+    server
+        .put("/acme/settings")
+        .json(&json!({ "daily_email_limit": i64::MAX }))
+        .await
+        .assert_status(StatusCode::UNAUTHORIZED);
+    // Left out of a PUT, the limit is kept rather than cleared
+    let body: Value = server.put("/acme/settings").json(&json!({})).await.json();
+    assert_eq!(body["daily_email_limit"], 1);
+    server
+        .post("/acme/emails")
+        .json(&json!({}))
+        .await
+        .assert_status(StatusCode::TOO_MANY_REQUESTS);
+
+    // Other settings can still be changed, sending back the current limit
+    let mut settings: Value = server.get("/acme/settings").await.json();
+    settings["pixel_cache_mode"] = json!("cache_short");
+    let body: Value = server.put("/acme/settings").json(&settings).await.json();
+    assert_eq!(body["pixel_cache_mode"], "cache_short");
+    assert_eq!(body["daily_email_limit"], 1);
+    let body: Value = server
+        .patch("/acme/settings")
+        .json(&json!({ "pixel_cache_mode": "no_cache", "daily_email_limit": 1 }))
+        .await
+        .json();
+    assert_eq!(body["pixel_cache_mode"], "no_cache");
+    assert_eq!(body["daily_email_limit"], 1);
+}
+
+#[tokio::test]
+async fn test_daily_email_quota_holds_under_concurrency() {
+    use little_bell::database::NewEmail;
+
+    let db = Arc::new(Database::new(":memory:").await.unwrap());
+    db.create_tenant("acme", "acme").await.unwrap();
+    let mut tasks = tokio::task::JoinSet::new();
+    for _ in 0..10 {
+        let db = db.clone();
+        tasks.spawn(async move {
+            db.create_email_within_quota("acme", &NewEmail::default(), Some(3)).await.unwrap()
+        });
+    }
+    let mut created = 0;
+    while let Some(result) = tasks.join_next().await {
+        if result.unwrap().is_ok() {
+            created += 1;
+        }
+    }
+    assert_eq!(created, 3);
+
+    // Imports that don't fit write nothing
+    let batch = vec![NewEmail::default(); 2];
+    let over = db.create_emails("acme", &batch, Some(4)).await.unwrap();
+    assert_eq!(over.unwrap_err().remaining, 1);
+    assert_eq!(db.count_emails_today("acme").await.unwrap(), 3);
+}
+
+#[tokio::test]
+async fn test_custom_event_types() {
+    let db = Arc::new(Database::new(":memory:").await.unwrap());
+    let config = Config {
+        allowed_event_types: "open,click,forward".to_string(),
+        ..Config::default()
+    };
+    let server = TestServer::new(create_app(db, config).await).unwrap();
+    server
+        .post("/acme/emails")
+        .json(&json!({ "subject": "Hello" }))
+        .await
+        .assert_status(StatusCode::CREATED);
+
+    server
+        .post("/acme/events")
+        .json(&json!([{ "email_id": 1, "event_type": "print" }]))
+        .await
+        .assert_status(StatusCode::BAD_REQUEST);
+
+    server
+        .post("/acme/events")
+        .json(&json!([
+            { "email_id": 1, "event_type": "forward" },
+            { "email_id": 1, "event_type": "forward" },
+            { "email_id": 1, "event_type": "open" }
+        ]))
+        .await
+        .assert_status(StatusCode::CREATED);
+
+    let body: Value = server.get("/acme/stats").await.json();
+    assert_eq!(body["events_by_type"]["forward"], 2);
+    assert_eq!(body["events_by_type"]["open"], 1);
+    assert_eq!(body["total_opens"], 1);
+}
+
+#[tokio::test]
+async fn test_request_id_is_propagated() {
+    let server = test_server().await;
+
+    let response = server.get("/health").await;
+    assert!(!response.header("x-request-id").is_empty());
+
+    let response = server
+        .post("/acme/emails")
+        .add_header("x-request-id", "lb-1234")
+        .json(&json!({ "metadata": "not-an-object" }))
+        .await;
+    response.assert_status(StatusCode::BAD_REQUEST);
+    assert_eq!(response.header("x-request-id"), "lb-1234");
+    assert_eq!(response.json::<Value>()["request_id"], "lb-1234");
+}
+
+#[tokio::test]
+async fn test_admin_backup() {
+    let backup_dir = std::env::temp_dir().join(format!("little-bell-backup-{}", std::process::id()));
+    let db = Arc::new(Database::new(":memory:").await.unwrap());
+    let config = Config {
+        admin_key: Some("secret".to_string()),
+        backup_dir: backup_dir.to_string_lossy().into_owned(),
+        ..Config::default()
+    };
+    let server = TestServer::new(create_app(db, config).await).unwrap();
+    server
+        .post("/acme/emails")
+        .json(&json!({ "subject": "Hello" }))
+        .await
+        .assert_status(StatusCode::CREATED);
+
+    server
+        .post("/admin/backup")
+        .await
+        .assert_status(StatusCode::UNAUTHORIZED);
+
+    let response = server.post("/admin/backup").authorization_bearer("secret").await;
+    response.assert_status_ok();
+    let body: Value = response.json();
+    assert!(body["bytes"].as_u64().unwrap() > 0);
+
+    let copy = Database::new(body["path"].as_str().unwrap()).await.unwrap();
+    assert_eq!(copy.count_emails("acme").await.unwrap(), 1);
+
+    std::fs::remove_dir_all(backup_dir).unwrap();
+}
+
+#[tokio::test]
+async fn test_event_log_mirrors_events() {
+    let log_dir = std::env::temp_dir().join(format!("little-bell-events-{}", std::process::id()));
+    let db = Arc::new(Database::new(":memory:").await.unwrap());
+    let config = Config {
+        event_log_path: Some(log_dir.to_string_lossy().into_owned()),
+        ..Config::default()
+    };
+    let server = TestServer::new(create_app(db, config).await).unwrap();
+    let created: Value = server.post("/acme/emails").json(&json!({})).await.json();
+    let email_id = created["email_id"].as_i64().unwrap();
+    server.get(&format!("/acme/pixel/{}.gif", email_id)).await.assert_status_ok();
+    server
+        .get(&format!("/acme/click/{}?url=https://example.com/", email_id))
+        .await;
+
+    // Lines are written in the background
+    let file = log_dir.join(format!("events-{}.ndjson", chrono::Utc::now().format("%Y-%m-%d")));
+    let mut lines: Vec<Value> = Vec::new();
+    for _ in 0..100 {
+        let contents = std::fs::read_to_string(&file).unwrap_or_default();
+        lines = contents.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+        if lines.len() == 2 {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+    }
+    assert_eq!(lines.len(), 2);
+    assert_eq!(lines[0]["tenant_id"], "acme");
+    assert_eq!(lines[0]["email_id"], email_id);
+    assert_eq!(lines[0]["event_type"], "open");
+    assert_eq!(lines[1]["target_url"], "https://example.com/");
+
+    std::fs::remove_dir_all(log_dir).unwrap();
+}
+
+#[tokio::test]
+async fn test_head_pixel_is_not_logged() {
+    let server = test_server().await;
+    let created: Value = server.post("/acme/emails").json(&json!({})).await.json();
+
+    for pixel in [
+        format!("/acme/pixel/{}.gif", created["email_id"]),
+        format!("/acme/bg/{}.png", created["email_id"]),
+    ] {
+        let head = server.method(axum::http::Method::HEAD, &pixel).await;
+        head.assert_status_ok();
+        let get = server.get(&pixel).await;
+        assert_eq!(head.header("content-type"), get.header("content-type"));
+        assert_eq!(head.header("cache-control"), get.header("cache-control"));
+        assert!(head.as_bytes().is_empty());
+        assert!(!get.as_bytes().is_empty());
+    }
+
+    let stats: Value = server.get("/acme/stats").await.json();
+    assert_eq!(stats["total_opens"], 2);
+
+    server
+        .method(axum::http::Method::HEAD, "/acme/pixel/999999.gif")
+        .await
+        .assert_status(StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_opens_without_consent_are_not_logged() {
+    let server = test_server().await;
+    let consented: Value = server
+        .post("/acme/emails")
+        .json(&json!({ "subject": "Hello" }))
+        .await
+        .json();
+    let declined: Value = server
+        .post("/acme/emails")
+        .json(&json!({ "subject": "Hello", "tracking_consent": false }))
+        .await
+        .json();
+
+    for email in [&consented, &declined] {
+        let response = server
+            .get(&format!("/acme/pixel/{}.gif", email["public_token"].as_str().unwrap()))
+            .await;
+        response.assert_status_ok();
+        assert_eq!(response.header("content-type"), "image/gif");
+    }
+
+    let body: Value = server.get("/acme/stats").await.json();
+    assert_eq!(body["total_opens"], 1);
+    assert_eq!(body["recent_events"][0]["email_id"], consented["email_id"]);
+}
+
+#[tokio::test]
+async fn test_expired_emails_serve_pixel_without_recording() {
+    let server = test_server().await;
+    let yesterday = (chrono::Utc::now() - chrono::Duration::days(1)).to_rfc3339();
+    let tomorrow = (chrono::Utc::now() + chrono::Duration::days(1)).to_rfc3339();
+    let expired: Value = server
+        .post("/acme/emails")
+        .json(&json!({ "expires_at": yesterday }))
+        .await
+        .json();
+    let live: Value = server
+        .post("/acme/emails")
+        .json(&json!({ "expires_at": tomorrow }))
+        .await
+        .json();
+
+    for email in [&expired, &live] {
+        let response = server.get(&format!("/acme/pixel/{}.gif", email["email_id"])).await;
+        response.assert_status_ok();
+        assert_eq!(response.header("content-type"), "image/gif");
+        let response = server
+            .get(&format!("/acme/click/{}?url=https://example.com/", email["email_id"]))
+            .await;
+        assert_eq!(response.header("location"), "https://example.com/");
+    }
+
+    let stats: Value = server.get("/acme/stats").await.json();
+    assert_eq!(stats["total_opens"], 1);
+    assert_eq!(stats["total_clicks"], 1);
+
+    let listed: Value = server.get("/acme/emails?expired=true").await.json();
+    let listed = listed.as_array().unwrap();
+    assert_eq!(listed.len(), 1);
+    assert_eq!(listed[0]["id"], expired["email_id"]);
+    assert_eq!(listed[0]["expired"], true);
+    assert_eq!(listed[0]["open_count"], 0);
+    let listed: Value = server.get("/acme/emails?expired=false").await.json();
+    assert_eq!(listed[0]["id"], live["email_id"]);
+    assert_eq!(listed[0]["expired"], false);
+}
+
+#[tokio::test]
+async fn test_windowed_summary() {
+    let server = test_server().await;
+    server
+        .post("/acme/emails")
+        .json(&json!({ "subject": "Hello" }))
+        .await
+        .assert_status(StatusCode::CREATED);
+
+    let ten_days_ago = (chrono::Utc::now() - chrono::Duration::days(10)).to_rfc3339();
+    let last_year = (chrono::Utc::now() - chrono::Duration::days(365)).to_rfc3339();
+    server
+        .post("/acme/events")
+        .json(&json!([
+            { "email_id": 1, "event_type": "open" },
+            { "email_id": 1, "event_type": "click" },
+            { "email_id": 1, "event_type": "open", "timestamp": ten_days_ago },
+            { "email_id": 1, "event_type": "open", "timestamp": last_year }
+        ]))
+        .await
+        .assert_status(StatusCode::CREATED);
+
+    let body: Value = server.get("/acme/summary").await.json();
+    assert_eq!(body["today"]["opens"], 1);
+    assert_eq!(body["today"]["clicks"], 1);
+    assert_eq!(body["last_7_days"]["opens"], 1);
+    assert_eq!(body["last_30_days"]["opens"], 2);
+    assert_eq!(body["last_30_days"]["clicks"], 1);
+}
+
+#[test]
+fn test_validate_tenant_id() {
+    for valid in ["acme", "a", "company_a", "tenant-42", &"x".repeat(64)] {
+        assert!(validate_tenant_id(valid).is_ok(), "{} should be valid", valid);
+    }
+    for invalid in ["", "Acme", "../etc", "a b", "tenant.1", "café", &"x".repeat(65)] {
+        assert!(validate_tenant_id(invalid).is_err(), "{} should be invalid", invalid);
+    }
+}
+
+#[tokio::test]
+async fn test_invalid_tenant_id_is_rejected() {
+    let server = admin_server().await;
+
+    let response = server.post("/Acme/emails").json(&json!({})).await;
+    response.assert_status(StatusCode::BAD_REQUEST);
+    assert_eq!(response.json::<Value>()["code"], "BAD_REQUEST");
+    server
+        .get("/..%2Fetc/stats")
+        .await
+        .assert_status(StatusCode::BAD_REQUEST);
+    server
+        .get("/bad.tenant/pixel/1.gif")
+        .await
+        .assert_status(StatusCode::BAD_REQUEST);
+
+    // Nothing was created along the way
+    let body: Value = server
+        .get("/admin/tenants")
+        .authorization_bearer("secret")
+        .await
+        .json();
+    assert_eq!(body["total"], 0);
+}
+
+#[tokio::test]
+async fn test_configurable_redirect_status() {
+    for (redirect_status, expected) in [
+        (RedirectStatus::Temporary, StatusCode::TEMPORARY_REDIRECT),
+        (RedirectStatus::Found, StatusCode::FOUND),
+        (RedirectStatus::Permanent, StatusCode::MOVED_PERMANENTLY),
+    ] {
+        let db = Arc::new(Database::new(":memory:").await.unwrap());
+        let config = Config { redirect_status, ..Config::default() };
+        let server = TestServer::new(create_app(db, config).await).unwrap();
+        server
+            .post("/acme/emails")
+            .json(&json!({}))
+            .await
+            .assert_status(StatusCode::CREATED);
+
+        let response = server.get("/acme/click/1?url=https%3A%2F%2Fexample.com%2F").await;
+        response.assert_status(expected);
+        assert_eq!(response.header("location"), "https://example.com/");
+    }
+}
+
+#[tokio::test]
+async fn test_email_list_includes_engagement() {
+    let server = test_server().await;
+    for _ in 0..2 {
+        server
+            .post("/acme/emails")
+            .json(&json!({ "subject": "Hello" }))
+            .await
+            .assert_status(StatusCode::CREATED);
+    }
+    server
+        .post("/acme/events")
+        .json(&json!([
+            { "email_id": 1, "event_type": "open" },
+            { "email_id": 1, "event_type": "open" },
+            { "email_id": 1, "event_type": "click" }
+        ]))
+        .await
+        .assert_status(StatusCode::CREATED);
+
+    let body: Value = server.get("/acme/emails").await.json();
+    let emails = body.as_array().unwrap();
+    assert_eq!(emails.len(), 2);
+    assert_eq!(emails[0]["id"], 2);
+    assert_eq!(emails[0]["open_count"], 0);
+    assert_eq!(emails[0]["last_event_at"], Value::Null);
+    assert_eq!(emails[1]["subject"], "Hello");
+    assert_eq!(emails[1]["open_count"], 2);
+    assert_eq!(emails[1]["click_count"], 1);
+    assert!(emails[1]["last_event_at"].is_string());
+}
+
+#[tokio::test]
+async fn test_short_click_links() {
+    let server = test_server().await;
+    server
+        .post("/acme/emails")
+        .json(&json!({ "subject": "Hello" }))
+        .await
+        .assert_status(StatusCode::CREATED);
+
+    let url = "/acme/click-url/1?short=true&url=https%3A%2F%2Fexample.com%2Fa%3Fb%3Dc";
+    let body: Value = server.get(url).await.json();
+    let click_url = body["click_url"].as_str().unwrap();
+    let path = click_url.strip_prefix("http://localhost:3000").unwrap();
+    assert!(path.starts_with("/acme/l/"));
+    assert_eq!(path.len(), "/acme/l/".len() + 8);
+
+    // The same target reuses its code
+    let again: Value = server.get(url).await.json();
+    assert_eq!(again["click_url"], body["click_url"]);
+
+    let response = server.get(path).await;
+    response.assert_status(StatusCode::TEMPORARY_REDIRECT);
+    assert_eq!(response.header("location"), "https://example.com/a?b=c");
+
+    // Codes are scoped to their tenant
+    server.get(&path.replace("/acme/", "/other/")).await.assert_status_not_found();
+    server.get("/acme/l/nope").await.assert_status_not_found();
+
+    let body: Value = server.get("/acme/top-links").await.json();
+    assert_eq!(body[0]["url"], "https://example.com/a?b=c");
+    assert_eq!(body[0]["clicks"], 1);
+}
+
+#[tokio::test]
+async fn test_settings_changes_are_audited() {
+    let server = admin_server().await;
+
+    server
+        .patch("/acme/settings")
+        .authorization_bearer("secret")
+        .json(&json!({ "daily_email_limit": 10 }))
+        .await
+        .assert_status_ok();
+    // Without trusted proxies a forwarding header says nothing about who
+    // made the change
+    server
+        .put("/acme/settings")
+        .add_header("x-forwarded-for", "203.0.113.7")
+        .json(&json!({ "pixel_cache_mode": "cache_short" }))
+        .await
+        .assert_status_ok();
+
+    server
+        .get("/admin/audit")
+        .await
+        .assert_status(StatusCode::UNAUTHORIZED);
+
+    let body: Value = server
+        .get("/admin/audit?limit=1")
+        .authorization_bearer("secret")
+        .await
+        .json();
+    assert_eq!(body["total"], 2);
+    let entries = body["entries"].as_array().unwrap();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0]["action"], "tenant_settings.update");
+    assert_eq!(entries[0]["target"], "acme");
+    assert_eq!(entries[0]["actor"], "anonymous");
+    assert_eq!(entries[0]["details"]["pixel_cache_mode"], "cache_short");
+
+    let body: Value = server
+        .get("/admin/audit?offset=1")
+        .authorization_bearer("secret")
+        .await
+        .json();
+    assert_eq!(body["entries"][0]["actor"], "admin");
+    assert_eq!(body["entries"][0]["details"]["daily_email_limit"], 10);
+}
+
+#[tokio::test]
+async fn test_tenant_export() {
+    let server = admin_server().await;
+    for subject in ["One", "Two"] {
+        server
+            .post("/acme/emails")
+            .json(&json!({ "subject": subject }))
+            .await
+            .assert_status(StatusCode::CREATED);
+    }
+    server
+        .post("/other/emails")
+        .json(&json!({ "subject": "Elsewhere" }))
+        .await
+        .assert_status(StatusCode::CREATED);
+    server.get("/acme/pixel/2.gif").await.assert_status_ok();
+    server.get("/other/pixel/3.gif").await.assert_status_ok();
+
+    server
+        .get("/acme/export.json")
+        .await
+        .assert_status(StatusCode::UNAUTHORIZED);
+    server
+        .get("/nobody/export.json")
+        .authorization_bearer("secret")
+        .await
+        .assert_status_not_found();
+
+    let response = server.get("/acme/export.json").authorization_bearer("secret").await;
+    response.assert_status_ok();
+    let body: Value = response.json();
+    assert_eq!(body["format"], "little-bell-export");
+    assert_eq!(body["tenant"]["id"], "acme");
+    assert_eq!(body["settings"]["pixel_cache_mode"], "no_cache");
+    let emails = body["emails"].as_array().unwrap();
+    assert_eq!(emails.len(), 2);
+    assert_eq!(emails[0]["subject"], "One");
+    let events = body["events"].as_array().unwrap();
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0]["email_id"], 2);
+}
+
+#[tokio::test]
+async fn test_tenant_import_round_trip() {
+    let server = admin_server().await;
+    let created: Value = server
+        .post("/acme/emails")
+        .json(&json!({ "subject": "Hello", "metadata": { "variant": "B" } }))
+        .await
+        .json();
+    let token = created["public_token"].as_str().unwrap();
+    server.get(&format!("/acme/pixel/{}.gif", token)).await.assert_status_ok();
+    server
+        .get(&format!("/acme/click/{}?url=https%3A%2F%2Fexample.com%2F", token))
+        .await
+        .assert_status(StatusCode::TEMPORARY_REDIRECT);
+
+    let export: Value = server
+        .get("/acme/export.json")
+        .authorization_bearer("secret")
+        .await
+        .json();
+
+    server
+        .post("/copy/import.json")
+        .json(&export)
+        .await
+        .assert_status(StatusCode::UNAUTHORIZED);
+
+    let response = server
+        .post("/copy/import.json")
+        .authorization_bearer("secret")
+        .json(&export)
+        .await;
+    response.assert_status(StatusCode::CREATED);
+    let body: Value = response.json();
+    assert_eq!(body["emails"], 1);
+    assert_eq!(body["events"], 2);
+
+    let stats: Value = server.get("/copy/stats").await.json();
+    assert_eq!(stats["total_opens"], 1);
+    assert_eq!(stats["total_clicks"], 1);
+    let emails: Value = server.get("/copy/emails?meta.variant=B").await.json();
+    assert_eq!(emails[0]["subject"], "Hello");
+    // The token already belongs to acme's email, so the copy gets a new one
+    assert_ne!(emails[0]["public_token"], created["public_token"]);
+
+    let response = server
+        .post("/copy/import.json")
+        .authorization_bearer("secret")
+        .json(&export)
+        .await;
+    response.assert_status(StatusCode::CONFLICT);
+    let response = server
+        .post("/copy/import.json?merge=maybe")
+        .authorization_bearer("secret")
+        .json(&export)
+        .await;
+    response.assert_status(StatusCode::BAD_REQUEST);
+    assert_eq!(response.json::<Value>()["code"], "BAD_REQUEST");
+    server
+        .post("/copy/import.json?merge=true")
+        .authorization_bearer("secret")
+        .json(&export)
+        .await
+        .assert_status(StatusCode::CREATED);
+    let stats: Value = server.get("/copy/stats").await.json();
+    assert_eq!(stats["emails_created"], 2);
+
+    server
+        .post("/other/import.json")
+        .authorization_bearer("secret")
+        .json(&json!({ "format": "something-else", "version": 1 }))
+        .await
+        .assert_status(StatusCode::BAD_REQUEST);
+}
+
+#[test]
+fn test_open_log_sampling_bounds() {
+    let always = Config::default();
+    assert!((0..100).all(|_| always.sample_open_log()));
+
+    let never = Config { open_log_sample_rate: 0.0, ..Config::default() };
+    assert!((0..100).all(|_| !never.sample_open_log()));
+
+    let some = Config { open_log_sample_rate: 0.5, ..Config::default() };
+    let sampled = (0..10_000).filter(|_| some.sample_open_log()).count();
+    assert!((4_000..6_000).contains(&sampled));
+}
+
+#[tokio::test]
+async fn test_tenant_stats_query_uses_indexes() {
+    let path = std::env::temp_dir().join(format!("little-bell-plan-{}.db", std::process::id()));
+    let path = path.to_str().unwrap().to_string();
+    Database::new(&path).await.unwrap();
+
+    let conn = rusqlite::Connection::open(&path).unwrap();
+    let mut stmt = conn
+        .prepare(
+            "EXPLAIN QUERY PLAN
+             SELECT COUNT(CASE WHEN e.event_type = 'open' THEN 1 END)
+             FROM events e
+             JOIN emails em ON e.email_id = em.id
+             WHERE em.tenant_id = ?1",
+        )
+        .unwrap();
+    let plan: Vec<String> = stmt
+        .query_map(["acme"], |row| row.get::<_, String>(3))
+        .unwrap()
+        .collect::<Result<_, _>>()
+        .unwrap();
+    drop(stmt);
+    drop(conn);
+    for suffix in ["", "-wal", "-shm"] {
+        let _ = std::fs::remove_file(format!("{}{}", path, suffix));
+    }
+
+    let plan = plan.join("\n");
+    assert!(plan.contains("idx_emails_tenant"), "{}", plan);
+    assert!(plan.contains("COVERING INDEX idx_events_email_type"), "{}", plan);
+}
+
+#[tokio::test]
+async fn test_pixel_conditional_requests() {
+    let db = Arc::new(Database::new(":memory:").await.unwrap());
+    let config = Config { pixel_conditional_requests: true, ..Config::default() };
+    let server = TestServer::new(create_app(db, config).await).unwrap();
+    server
+        .post("/acme/emails")
+        .json(&json!({}))
+        .await
+        .assert_status(StatusCode::CREATED);
+
+    let response = server.get("/acme/pixel/1.gif").await;
+    response.assert_status_ok();
+    let last_modified = response.header("last-modified");
+    let last_modified = last_modified.to_str().unwrap();
+
+    let response = server
+        .get("/acme/pixel/1.gif")
+        .add_header("if-modified-since", last_modified)
+        .await;
+    response.assert_status(StatusCode::NOT_MODIFIED);
+    assert!(response.as_bytes().is_empty());
+
+    let response = server
+        .get("/acme/pixel/1.gif")
+        .add_header("if-modified-since", "Thu, 01 Jan 1970 00:00:00 GMT")
+        .await;
+    response.assert_status_ok();
+    assert_eq!(response.header("content-type"), "image/gif");
+
+    // Revalidations still count as opens
+    let body: Value = server.get("/acme/stats").await.json();
+    assert_eq!(body["total_opens"], 3);
+
+    // Off by default
+    let server = test_server().await;
+    server
+        .post("/acme/emails")
+        .json(&json!({}))
+        .await
+        .assert_status(StatusCode::CREATED);
+    let response = server
+        .get("/acme/pixel/1.gif")
+        .add_header("if-modified-since", last_modified)
+        .await;
+    response.assert_status_ok();
+    assert!(response.maybe_header("last-modified").is_none());
+}
+
+#[tokio::test]
+async fn test_pixel_snippet() {
+    let server = test_server().await;
+    let created: Value = server
+        .post("/acme/emails")
+        .json(&json!({ "subject": "Hello" }))
+        .await
+        .json();
+    let pixel_url = created["tracking_pixel_url"].as_str().unwrap();
+    let expected = format!(
+        "<img src=\"{}\" width=\"1\" height=\"1\" alt=\"\" style=\"display:none\">",
+        pixel_url
+    );
+
+    let body: Value = server.get("/acme/emails/1/snippet").await.json();
+    assert_eq!(body["url"], pixel_url);
+    assert_eq!(body["html"], expected);
+
+    let response = server
+        .get("/acme/emails/1/snippet")
+        .add_header("accept", "text/plain")
+        .await;
+    assert!(response.header("content-type").to_str().unwrap().starts_with("text/plain"));
+    assert_eq!(response.text(), expected);
+
+    server.get("/other/emails/1/snippet").await.assert_status_not_found();
+}
+
+#[test]
+fn test_webhook_signature_is_hmac_sha256() {
+    use little_bell::webhooks::{sign, verify};
+
+    // RFC 4231, test case 2
+    let body = b"what do ya want for nothing?";
+    let expected = "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843";
+    assert_eq!(sign("Jefe", body), expected);
+
+    assert!(verify("Jefe", body, &format!("sha256={}", expected)));
+    assert!(!verify("Jefe", b"what do ya want for something?", &format!("sha256={}", expected)));
+    assert!(!verify("Jeff", body, &format!("sha256={}", expected)));
+    assert!(!verify("Jefe", body, expected));
+    assert!(!verify("Jefe", body, &format!("sha256={}", &expected[..62])));
+    assert!(!verify("Jefe", body, "sha256=not-hex"));
+}
+
+#[tokio::test]
+async fn test_webhook_delivery_with_retry() {
+    use axum::{extract::State, routing::post, Router};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Mutex;
+
+    // A receiver that fails the first attempt, then records what it gets
+    #[derive(Clone, Default)]
+    struct Receiver {
+        hits: Arc<AtomicUsize>,
+        received: Arc<Mutex<Vec<(String, String)>>>,
+    }
+    async fn receive(State(receiver): State<Receiver>, headers: HeaderMap, body: String) -> StatusCode {
+        if receiver.hits.fetch_add(1, Ordering::SeqCst) == 0 {
+            return StatusCode::SERVICE_UNAVAILABLE;
+        }
+        let signature = headers["x-littlebell-signature"].to_str().unwrap().to_string();
+        receiver.received.lock().unwrap().push((signature, body));
+        StatusCode::OK
+    }
+    let receiver = Receiver::default();
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let receiver_url = format!("http://{}/hook", listener.local_addr().unwrap());
+    let receiver_app = Router::new().route("/hook", post(receive)).with_state(receiver.clone());
+    tokio::spawn(async move { axum::serve(listener, receiver_app).await.unwrap() });
+
+    let config = Config {
+        admin_key: Some("secret".to_string()),
+        webhook_retry_base_ms: 10,
+        webhook_allow_private_networks: true,
+        ..Config::default()
+    };
+    let db = Arc::new(Database::new(":memory:").await.unwrap());
+    let server = TestServer::new(create_app(db, config).await).unwrap();
+
+    server
+        .post("/acme/webhooks")
+        .authorization_bearer("secret")
+        .json(&json!({ "url": "ftp://example.com/hook" }))
+        .await
+        .assert_status_bad_request();
+    let response = server
+        .post("/acme/webhooks")
+        .authorization_bearer("secret")
+        .json(&json!({ "url": receiver_url, "secret": "s3cret" }))
+        .await;
+    response.assert_status(StatusCode::CREATED);
+    assert_eq!(response.json::<Value>()["secret"], "s3cret");
+    let listed: Value = server.get("/acme/webhooks").authorization_bearer("secret").await.json();
+    assert_eq!(listed[0]["url"], receiver_url.as_str());
+    assert!(listed[0].get("secret").is_none());
+
+    server
+        .post("/acme/emails")
+        .json(&json!({}))
+        .await
+        .assert_status(StatusCode::CREATED);
+    server.get("/acme/pixel/1.gif").await.assert_status_ok();
+
+    let mut deliveries = Value::Null;
+    for _ in 0..100 {
+        deliveries = server.get("/acme/webhooks/deliveries").authorization_bearer("secret").await.json();
+        if deliveries[0]["status"] == "delivered" {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+    }
+    assert_eq!(deliveries.as_array().unwrap().len(), 1);
+    assert_eq!(deliveries[0]["status"], "delivered");
+    assert_eq!(deliveries[0]["attempts"], 2);
+    assert_eq!(deliveries[0]["status_code"], 200);
+
+    let received = receiver.received.lock().unwrap().clone();
+    let (signature, body) = &received[0];
+    assert!(little_bell::webhooks::verify("s3cret", body.as_bytes(), signature));
+    let payload: Value = serde_json::from_str(body).unwrap();
+    assert_eq!(payload["tenant_id"], "acme");
+    assert_eq!(payload["event"]["event_type"], "open");
+
+    let failed: Value = server
+        .get("/acme/webhooks/deliveries?status=failed")
+        .authorization_bearer("secret")
+        .await
+        .json();
+    assert!(failed.as_array().unwrap().is_empty());
+    server.delete("/acme/webhooks/1").await.assert_status(StatusCode::UNAUTHORIZED);
+    server
+        .delete("/acme/webhooks/1")
+        .authorization_bearer("secret")
+        .await
+        .assert_status(StatusCode::NO_CONTENT);
+    server
+        .delete("/acme/webhooks/1")
+        .authorization_bearer("secret")
+        .await
+        .assert_status_not_found();
+
+    let audit: Value = server.get("/admin/audit").authorization_bearer("secret").await.json();
+    assert_eq!(audit["entries"][0]["action"], "webhook.delete");
+    assert_eq!(audit["entries"][0]["details"]["webhook_id"], 1);
+    assert_eq!(audit["entries"][1]["action"], "webhook.create");
+    assert_eq!(audit["entries"][1]["target"], "acme");
+}
+
+#[tokio::test]
+async fn test_webhooks_refuse_private_addresses() {
+    let db = Arc::new(Database::new(":memory:").await.unwrap());
+    let config = Config {
+        admin_key: Some("secret".to_string()),
+        ..Config::default()
+    };
+    let server = TestServer::new(create_app(db.clone(), config).await).unwrap();
+
+    for url in [
+        "http://127.0.0.1:8080/hook",
+        "http://localhost/hook",
+        "https://10.1.2.3/hook",
+        "http://169.254.169.254/latest/meta-data",
+        "http://[::1]/hook",
+        "http://[fd00::1]/hook",
+        "http://[::ffff:192.168.1.1]/hook",
+    ] {
+        let response = server
+            .post("/acme/webhooks")
+            .authorization_bearer("secret")
+            .json(&json!({ "url": url }))
+            .await;
+        response.assert_status_bad_request();
+        assert!(response.json::<Value>()["error"].as_str().unwrap().contains("private address"), "{}", url);
+    }
+    server
+        .post("/acme/webhooks")
+        .authorization_bearer("secret")
+        .json(&json!({ "url": "https://93.184.215.14/hook" }))
+        .await
+        .assert_status(StatusCode::CREATED);
+
+    // One registered before the rule, or under a name that has since moved,
+    // fails at delivery without being sent or retried
+    db.delete_webhook("acme", 1).await.unwrap();
+    db.create_webhook("acme", "http://127.0.0.1:9/hook", "s3cret").await.unwrap();
+    let created: Value = server.post("/acme/emails").json(&json!({})).await.json();
+    server
+        .get(&format!("/acme/pixel/{}.gif", created["email_id"]))
+        .await
+        .assert_status_ok();
+
+    let mut deliveries = Value::Null;
+    for _ in 0..100 {
+        deliveries = server
+            .get("/acme/webhooks/deliveries")
+            .authorization_bearer("secret")
+            .await
+            .json();
+        if deliveries[0]["status"] == "failed" {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+    }
+    assert_eq!(deliveries[0]["status"], "failed");
+    assert_eq!(deliveries[0]["attempts"], 1);
+    assert!(deliveries[0]["error"].as_str().unwrap().contains("public address"));
+}
+
+#[tokio::test]
+async fn test_webhook_list_changes_and_pending_deliveries_after_restart() {
+    use axum::{extract::State, routing::post, Router};
+    use little_bell::database::NewEmail;
+    use std::sync::Mutex;
+
+    type Received = Arc<Mutex<Vec<(String, Value)>>>;
+    async fn receive(State(received): State<Received>, uri: axum::http::Uri, body: String) -> StatusCode {
+        let payload = serde_json::from_str(&body).unwrap();
+        received.lock().unwrap().push((uri.path().to_string(), payload));
+        StatusCode::OK
+    }
+    let received = Received::default();
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let receiver_url = format!("http://{}", listener.local_addr().unwrap());
+    let receiver_app = Router::new()
+        .route("/a", post(receive))
+        .route("/b", post(receive))
+        .with_state(received.clone());
+    tokio::spawn(async move { axum::serve(listener, receiver_app).await.unwrap() });
+    let wait_for = |count: usize| {
+        let received = received.clone();
+        async move {
+            for _ in 0..100 {
+                if received.lock().unwrap().len() >= count {
+                    break;
+                }
+                tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+            }
+            let received = received.lock().unwrap().clone();
+            assert_eq!(received.len(), count);
+            received
+        }
+    };
+
+    // A delivery left pending when the server last stopped
+    let db = Arc::new(Database::new(":memory:").await.unwrap());
+    db.create_tenant("acme", "acme").await.unwrap();
+    let (email, _) = db.create_email("acme", &NewEmail::default()).await.unwrap();
+    let event = db.log_event(email.id, "click", None, None, None, None).await.unwrap();
+    let webhook = db.create_webhook("acme", &format!("{}/a", receiver_url), "s3cret").await.unwrap();
+    db.create_webhook_delivery(webhook.id, event.id).await.unwrap();
+
+    let config = Config {
+        admin_key: Some("secret".to_string()),
+        webhook_allow_private_networks: true,
+        ..Config::default()
+    };
+    let server = TestServer::new(create_app(db, config).await).unwrap();
+    let received = wait_for(1).await;
+    assert_eq!(received[0].1["event"]["id"], event.id);
+    assert_eq!(received[0].1["event"]["event_type"], "click");
+
+    // Webhooks added or removed after the list was cached take effect
+    // straight away
+    let pixel = format!("/acme/pixel/{}.gif", email.id);
+    server.get(&pixel).await.assert_status_ok();
+    wait_for(2).await;
+    server
+        .post("/acme/webhooks")
+        .authorization_bearer("secret")
+        .json(&json!({ "url": format!("{}/b", receiver_url) }))
+        .await
+        .assert_status(StatusCode::CREATED);
+    server.get(&pixel).await.assert_status_ok();
+    let received = wait_for(4).await;
+    let mut paths: Vec<&str> = received[2..].iter().map(|(path, _)| path.as_str()).collect();
+    paths.sort();
+    assert_eq!(paths, ["/a", "/b"]);
+
+    server
+        .delete(&format!("/acme/webhooks/{}", webhook.id))
+        .authorization_bearer("secret")
+        .await
+        .assert_status(StatusCode::NO_CONTENT);
+    server.get(&pixel).await.assert_status_ok();
+    let received = wait_for(5).await;
+    assert_eq!(received[4].0, "/b");
+}
+
+#[tokio::test]
+async fn test_webhook_circuit_breaker_queues_and_drops() {
+    use axum::{extract::State, routing::post, Router};
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    // A receiver that answers 503 until it's brought back up
+    async fn receive(State(up): State<Arc<AtomicBool>>) -> StatusCode {
+        if up.load(Ordering::SeqCst) {
+            StatusCode::OK
+        } else {
+            StatusCode::SERVICE_UNAVAILABLE
+        }
+    }
+    let up = Arc::new(AtomicBool::new(false));
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let receiver_url = format!("http://{}/hook", listener.local_addr().unwrap());
+    let receiver_app = Router::new().route("/hook", post(receive)).with_state(up.clone());
+    tokio::spawn(async move { axum::serve(listener, receiver_app).await.unwrap() });
+
+    let config = Config {
+        webhook_max_attempts: 1,
+        webhook_circuit_threshold: 2,
+        webhook_circuit_cooldown_secs: 1,
+        webhook_queue_size: 1,
+        webhook_allow_private_networks: true,
+        admin_key: Some("secret".to_string()),
+        ..Config::default()
+    };
+    let db = Arc::new(Database::new(":memory:").await.unwrap());
+    let server = TestServer::new(create_app(db, config).await).unwrap();
+    server
+        .post("/acme/webhooks")
+        .authorization_bearer("secret")
+        .json(&json!({ "url": receiver_url }))
+        .await
+        .assert_status(StatusCode::CREATED);
+
+    let open_new_email = || async {
+        let created: Value = server.post("/acme/emails").json(&json!({})).await.json();
+        server
+            .get(&format!("/acme/pixel/{}.gif", created["email_id"]))
+            .await
+            .assert_status_ok();
+    };
+    let wait_for = |check: fn(&axum_test::TestResponse) -> bool| {
+        let server = &server;
+        async move {
+            for _ in 0..150 {
+                let response = server.get("/acme/webhooks/deliveries").authorization_bearer("secret").await;
+                if check(&response) {
+                    return response;
+                }
+                tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+            }
+            panic!("deliveries never reached the expected state");
+        }
+    };
+
+    let response = server.get("/acme/webhooks/deliveries").authorization_bearer("secret").await;
+    assert_eq!(response.header("x-webhook-circuit"), "closed");
+
+    // Two failures in a row open the circuit
+    open_new_email().await;
+    open_new_email().await;
+    wait_for(|response| response.header("x-webhook-circuit") == "open").await;
+
+    // While it's open one delivery is queued and the next dropped
+    open_new_email().await;
+    open_new_email().await;
+    let response = wait_for(|response| {
+        let deliveries: Value = response.json();
+        deliveries.as_array().unwrap().len() == 4
+            && response.header("x-webhook-queued") == "1"
+            && deliveries[0]["status"] == "failed"
+    })
+    .await;
+    let deliveries: Value = response.json();
+    assert_eq!(deliveries[0]["attempts"], 0);
+    assert!(deliveries[0]["error"].as_str().unwrap().contains("queue full"));
+    assert_eq!(deliveries[1]["status"], "pending");
+
+    // Once the cooldown ends the queued delivery goes out and closes it
+    up.store(true, Ordering::SeqCst);
+    let response = wait_for(|response| {
+        let deliveries: Value = response.json();
+        deliveries[1]["status"] == "delivered"
+    })
+    .await;
+    assert_eq!(response.header("x-webhook-circuit"), "closed");
+    assert_eq!(response.header("x-webhook-queued"), "0");
+}
+
+#[tokio::test]
+async fn test_click_url_validation() {
+    let db = Arc::new(Database::new(":memory:").await.unwrap());
+    let config = Config {
+        max_click_url_len: 64,
+        ..Config::default()
+    };
+    let server = TestServer::new(create_app(db, config).await).unwrap();
+    server
+        .post("/acme/emails")
+        .json(&json!({}))
+        .await
+        .assert_status(StatusCode::CREATED);
+
+    server
+        .get("/acme/click-url/1?url=https%3A%2F%2Fexample.com%2Fok")
+        .await
+        .assert_status_ok();
+
+    let long_url = format!("https://example.com/{}", "a".repeat(64));
+    let response = server
+        .get(&format!("/acme/click-url/1?url={}", urlencoding::encode(&long_url)))
+        .await;
+    response.assert_status_bad_request();
+    assert_eq!(response.json::<Value>()["code"], "INVALID_URL");
+
+    for relative in ["%2Fpath%2Fonly", "example.com", "javascript%3Aalert(1)"] {
+        let response = server.get(&format!("/acme/click-url/1?url={}", relative)).await;
+        response.assert_status_bad_request();
+        assert_eq!(response.json::<Value>()["code"], "INVALID_URL");
+    }
+}
+
+#[tokio::test]
+async fn test_dashboard_filters() {
+    let server = test_server().await;
+    server
+        .post("/acme/emails")
+        .json(&json!({}))
+        .await
+        .assert_status(StatusCode::CREATED);
+    server
+        .post("/acme/events")
+        .json(&json!([
+            { "email_id": 1, "event_type": "open", "timestamp": "2026-03-01T10:00:00Z" },
+            { "email_id": 1, "event_type": "click", "timestamp": "2026-03-01T10:05:00Z", "target_url": "https://example.com/" },
+            { "email_id": 1, "event_type": "click", "timestamp": "2026-03-02T09:00:00Z", "target_url": "https://example.com/" },
+        ]))
+        .await
+        .assert_status(StatusCode::CREATED);
+
+    let response = server.get("/acme/dashboard").await;
+    response.assert_status_ok();
+    assert!(!response.text().contains("id=\"active-filters\""));
+
+    let response = server
+        .get("/acme/dashboard?event_type=click&from=2026-03-01&to=2026-03-01")
+        .await;
+    response.assert_status_ok();
+    let html = response.text();
+    assert!(html.contains("id=\"active-filters\""));
+    assert_eq!(html.matches("event-type event-click").count(), 1);
+    assert!(!html.contains("event-type event-open"));
+
+    server
+        .get("/acme/dashboard?from=yesterday")
+        .await
+        .assert_status_bad_request();
+}
+
+#[tokio::test]
+async fn test_dashboard_recent_events_buffer() {
+    let db = Arc::new(Database::new(":memory:").await.unwrap());
+    let config = Config {
+        admin_key: Some("secret".to_string()),
+        recent_events_cache_size: 10,
+        ..Config::default()
+    };
+    let server = TestServer::new(create_app(db.clone(), config).await).unwrap();
+    let created: Value = server.post("/acme/emails").json(&json!({})).await.json();
+    let email_id = created["email_id"].as_i64().unwrap();
+    let pixel = format!("/acme/pixel/{}.gif", email_id);
+    server.get(&pixel).await.assert_status_ok();
+
+    // The first view reads the database and seeds the buffer
+    let html = server.get("/acme/dashboard").await.text();
+    assert_eq!(html.matches("event-type event-open").count(), 1);
+
+    // Later views list what went through the server, without a query: an
+    // event written straight to the database doesn't show up
+    server.get(&pixel).await.assert_status_ok();
+    db.log_event(email_id, "click", None, None, None, None).await.unwrap();
+    let html = server.get("/acme/dashboard").await.text();
+    assert_eq!(html.matches("event-type event-open").count(), 2);
+    assert!(!html.contains("event-type event-click"));
+    // The totals still come from the database
+    let filtered = server.get("/acme/dashboard?event_type=click").await.text();
+    assert_eq!(filtered.matches("event-type event-click").count(), 1);
+
+    // Resetting stats drops the buffer along with the events
+    server
+        .post("/acme/reset-stats")
+        .authorization_bearer("secret")
+        .await
+        .assert_status_ok();
+    let html = server.get("/acme/dashboard").await.text();
+    assert!(!html.contains("event-type event-open"));
+}
+
+#[tokio::test]
+async fn test_unknown_route_returns_json_404() {
+    let server = test_server().await;
+
+    let response = server.get("/acme/no-such-page").await;
+    response.assert_status_not_found();
+    let body: Value = response.json();
+    assert_eq!(body["error"], "not found");
+    assert_eq!(body["code"], "ROUTE_NOT_FOUND");
+
+    let response = server.get("/acme/dashboard").await;
+    response.assert_status_ok();
+    assert!(response.header("content-type").to_str().unwrap().starts_with("text/html"));
+}
+
+#[tokio::test]
+async fn test_admin_ip_allowlist() {
+    async fn server_with_allowlist(allowlist: &str) -> TestServer {
+        let db = Arc::new(Database::new(":memory:").await.unwrap());
+        let config = Config {
+            admin_key: Some("secret".to_string()),
+            admin_ip_allowlist: allowlist.to_string(),
+            ..Config::default()
+        };
+        let app = create_app(db, config).await;
+        TestServer::builder()
+            .http_transport()
+            .build(app.into_make_service_with_connect_info::<std::net::SocketAddr>())
+            .unwrap()
+    }
+
+    let server = server_with_allowlist("10.0.0.0/8, 192.168.1.5").await;
+    let response = server.get("/metrics").await;
+    response.assert_status(StatusCode::FORBIDDEN);
+    assert_eq!(response.json::<Value>()["code"], "FORBIDDEN");
+    server
+        .get("/admin/tenants")
+        .authorization_bearer("secret")
+        .await
+        .assert_status(StatusCode::FORBIDDEN);
+    // A forged forwarding header doesn't help without trusted proxies
+    server
+        .get("/metrics")
+        .add_header("x-forwarded-for", "10.1.2.3")
+        .await
+        .assert_status(StatusCode::FORBIDDEN);
+    // Tracking routes aren't affected
+    server.get("/acme/stats").await.assert_status_ok();
+
+    let server = server_with_allowlist("127.0.0.0/8").await;
+    server.get("/metrics").await.assert_status_ok();
+    server
+        .get("/admin/diagnostics")
+        .authorization_bearer("secret")
+        .await
+        .assert_status_ok();
+
+    // An allowlist with nothing valid in it admits nobody
+    let server = server_with_allowlist("not-a-network").await;
+    server.get("/metrics").await.assert_status(StatusCode::FORBIDDEN);
+
+    let server = server_with_allowlist("").await;
+    server.get("/metrics").await.assert_status_ok();
+}
+
+#[tokio::test]
+async fn test_admin_ip_allowlist_behind_proxy_ignores_client_entries() {
+    let db = Arc::new(Database::new(":memory:").await.unwrap());
+    let config = Config {
+        admin_key: Some("secret".to_string()),
+        admin_ip_allowlist: "10.0.0.0/8".to_string(),
+        trusted_proxy_hops: 1,
+        ..Config::default()
+    };
+    let server = TestServer::new(create_app(db, config).await).unwrap();
+
+    // The client put an allowlisted address first; our proxy appended the
+    // address it really came from
+    for path in ["/metrics", "/admin/tenants"] {
+        server
+            .get(path)
+            .authorization_bearer("secret")
+            .add_header("x-forwarded-for", "10.1.2.3, 203.0.113.7")
+            .await
+            .assert_status(StatusCode::FORBIDDEN);
+    }
+    server
+        .get("/metrics")
+        .add_header("x-forwarded-for", "203.0.113.7, 10.1.2.3")
+        .await
+        .assert_status_ok();
+}
+
+#[tokio::test]
+async fn test_unique_opens_by_recipient_and_ip() {
+    let server = test_server().await;
+    // One newsletter subscriber, two issues, plus an email with no recipient
+    for recipient in [json!("reader@example.com"), json!("reader@example.com"), Value::Null] {
+        server
+            .post("/acme/emails")
+            .json(&json!({ "recipient": recipient }))
+            .await
+            .assert_status(StatusCode::CREATED);
+    }
+    for (email, ip) in [(1, "203.0.113.1"), (2, "203.0.113.2"), (2, "203.0.113.2"), (3, "203.0.113.1")] {
+        server
+            .get(&format!("/acme/pixel/{}.gif", email))
+            .add_header("x-forwarded-for", ip)
+            .await
+            .assert_status_ok();
+    }
+
+    let body: Value = server.get("/acme/stats").await.json();
+    assert_eq!(body["total_opens"], 4);
+    assert_eq!(body["unique_opens"], 3);
+    assert_eq!(body["unique_open_recipients"], 1);
+    assert_eq!(body["unique_open_ips"], 2);
+}
+
+#[tokio::test]
+async fn test_busy_database_writes_are_retried() {
+    use little_bell::database::DatabaseOptions;
+    use std::time::Duration;
+
+    let path = std::env::temp_dir().join(format!("little-bell-busy-{}.db", std::process::id()));
+    let path = path.to_str().unwrap().to_string();
+    let options = |busy_retries| DatabaseOptions {
+        busy_timeout: Duration::from_millis(20),
+        busy_retries,
+        ..DatabaseOptions::default()
+    };
+    let no_retries = Database::with_options(&path, &options(0)).await.unwrap();
+    let db = Database::with_options(&path, &options(5)).await.unwrap();
+
+    // Another process holding the write lock
+    let locker = rusqlite::Connection::open(&path).unwrap();
+    locker.execute_batch("BEGIN EXCLUSIVE").unwrap();
+
+    let error = no_retries.create_tenant("acme", "Acme").await.unwrap_err();
+    assert_eq!(error.sqlite_error_code(), Some(rusqlite::ErrorCode::DatabaseBusy));
+
+    let release = std::thread::spawn(move || {
+        std::thread::sleep(Duration::from_millis(150));
+        locker.execute_batch("COMMIT").unwrap();
+    });
+    db.create_tenant("acme", "Acme").await.unwrap();
+    release.join().unwrap();
+    assert!(db.get_tenant("acme").await.unwrap().is_some());
+    assert!(db.metrics().snapshot().lock.busy_retries > 0);
+
+    drop((no_retries, db));
+    for suffix in ["", "-wal", "-shm"] {
+        let _ = std::fs::remove_file(format!("{}{}", path, suffix));
+    }
+}
+
+#[tokio::test]
+async fn test_css_background_pixel() {
+    let server = test_server().await;
+    let created: Value = server
+        .post("/acme/emails")
+        .json(&json!({}))
+        .await
+        .json();
+    let token = created["public_token"].as_str().unwrap();
+
+    let response = server.get(&format!("/acme/bg/{}.png", token)).await;
+    response.assert_status_ok();
+    assert_eq!(response.header("content-type"), "image/png");
+    assert_eq!(response.header("cache-control"), "no-store, no-cache, must-revalidate");
+    assert!(response.as_bytes().starts_with(b"\x89PNG\r\n\x1a\n"));
+
+    server.get("/acme/bg/1.png").await.assert_status_ok();
+    server.get("/acme/bg/999.png").await.assert_status_not_found();
+
+    let body: Value = server.get("/acme/stats").await.json();
+    assert_eq!(body["total_opens"], 2);
+    assert_eq!(body["unique_opens"], 1);
+}
+
+#[test]
+fn test_select_pixel_format() {
+    use little_bell::{select_pixel_format, PixelFormat};
+
+    let format_for = |accept: Option<&str>| {
+        let mut headers = HeaderMap::new();
+        if let Some(accept) = accept {
+            headers.insert("accept", accept.parse().unwrap());
+        }
+        select_pixel_format(&headers)
+    };
+    assert_eq!(format_for(None), PixelFormat::Gif);
+    assert_eq!(format_for(Some("*/*")), PixelFormat::Gif);
+    assert_eq!(format_for(Some("image/*,*/*;q=0.8")), PixelFormat::Gif);
+    assert_eq!(format_for(Some("text/html")), PixelFormat::Gif);
+    assert_eq!(
+        format_for(Some("image/avif,image/webp,image/apng,image/svg+xml,image/*,*/*;q=0.8")),
+        PixelFormat::WebP
+    );
+    assert_eq!(format_for(Some("image/png")), PixelFormat::Png);
+    assert_eq!(format_for(Some("image/webp;q=0.5, image/png")), PixelFormat::Png);
+    assert_eq!(format_for(Some("image/gif, image/webp")), PixelFormat::Gif);
+    assert_eq!(format_for(Some("IMAGE/WEBP")), PixelFormat::WebP);
+    assert_eq!(format_for(Some("image/webp;q=0")), PixelFormat::Gif);
+    assert_eq!(PixelFormat::WebP.content_type(), "image/webp");
+}
+
+#[tokio::test]
+async fn test_pixel_format_follows_accept() {
+    let server = test_server().await;
+    let created: Value = server.post("/acme/emails").json(&json!({})).await.json();
+    let pixel = format!("/acme/pixel/{}.gif", created["email_id"]);
+
+    let response = server.get(&pixel).add_header("accept", "image/webp,*/*").await;
+    response.assert_status_ok();
+    assert_eq!(response.header("content-type"), "image/webp");
+    assert_eq!(response.header("vary"), "Accept");
+    assert_eq!(response.as_bytes().as_ref(), include_bytes!("../src/pixel.webp"));
+    assert!(response.as_bytes().starts_with(b"RIFF"));
+
+    let response = server.get(&pixel).add_header("accept", "image/png").await;
+    assert_eq!(response.header("content-type"), "image/png");
+    let response = server.get(&pixel).add_header("accept", "*/*").await;
+    assert_eq!(response.header("content-type"), "image/gif");
+    // The extension doesn't pick the format
+    let response = server.get(&format!("/acme/pixel/{}.webp", created["email_id"])).await;
+    assert_eq!(response.header("content-type"), "image/gif");
+
+    let stats: Value = server.get("/acme/stats").await.json();
+    assert_eq!(stats["total_opens"], 4);
+}
+
+#[test]
+fn test_pii_masking_helpers() {
+    use little_bell::privacy::{display_recipient, display_subject, mask_recipient, truncate_subject};
+
+    assert_eq!(mask_recipient("jane.doe@example.com"), "j***@example.com");
+    assert_eq!(mask_recipient("not-an-address"), "***");
+    assert_eq!(mask_recipient("@example.com"), "***");
+    assert_eq!(truncate_subject("Your March invoice, Jane"), "Your March i…");
+    assert_eq!(truncate_subject("Hi"), "Hi");
+
+    // Off by default: values pass through untouched
+    assert_eq!(display_recipient("jane@example.com", false), "jane@example.com");
+    assert_eq!(display_recipient("jane@example.com", true), "j***@example.com");
+    assert_eq!(display_subject("Your March invoice, Jane", true), "Your March i…");
+    assert!(!Config::default().mask_pii);
+}
+
+#[tokio::test]
+async fn test_reset_stats_keeps_emails() {
+    let server = admin_server().await;
+    for tenant in ["acme", "other"] {
+        let created: Value = server
+            .post(&format!("/{}/emails", tenant))
+            .json(&json!({}))
+            .await
+            .json();
+        server
+            .get(&format!("/{}/pixel/{}.gif", tenant, created["email_id"]))
+            .await
+            .assert_status_ok();
+    }
+    server.get("/acme/pixel/1.gif").await.assert_status_ok();
+
+    server
+        .post("/acme/reset-stats")
+        .await
+        .assert_status(StatusCode::UNAUTHORIZED);
+
+    let response = server.post("/acme/reset-stats").authorization_bearer("secret").await;
+    response.assert_status_ok();
+    assert_eq!(response.json::<Value>()["deleted"], 2);
+
+    let body: Value = server.get("/acme/stats").await.json();
+    assert_eq!(body["total_opens"], 0);
+    assert_eq!(body["emails_created"], 1);
+    server.get("/acme/pixel/1.gif").await.assert_status_ok();
+
+    // Other tenants are untouched
+    let body: Value = server.get("/other/stats").await.json();
+    assert_eq!(body["total_opens"], 1);
+
+    let audit: Value = server.get("/admin/audit").authorization_bearer("secret").await.json();
+    assert_eq!(audit["entries"][0]["action"], "tenant.reset_stats");
+}
+
+#[tokio::test]
+async fn test_email_lookup_cache() {
+    let server = test_server().await;
+    let created: Value = server
+        .post("/acme/emails")
+        .json(&json!({}))
+        .await
+        .json();
+    let token = created["public_token"].as_str().unwrap();
+
+    for _ in 0..3 {
+        server.get(&format!("/acme/pixel/{}.gif", token)).await.assert_status_ok();
+    }
+    // Cached entries stay scoped to their tenant
+    server
+        .get(&format!("/other/pixel/{}.gif", token))
+        .await
+        .assert_status_not_found();
+
+    let cache = &server.get("/metrics").await.json::<Value>()["email_cache"];
+    assert_eq!(cache["capacity"], 10_000);
+    assert_eq!(cache["entries"], 1);
+    assert_eq!(cache["hits"], 2);
+    assert_eq!(cache["misses"], 2);
+
+    let body: Value = server.get("/acme/stats").await.json();
+    assert_eq!(body["total_opens"], 3);
+
+    // Size 0 turns it off
+    let db = Arc::new(Database::new(":memory:").await.unwrap());
+    let config = Config {
+        email_cache_size: 0,
+        ..Config::default()
+    };
+    let server = TestServer::new(create_app(db, config).await).unwrap();
+    server
+        .post("/acme/emails")
+        .json(&json!({}))
+        .await
+        .assert_status(StatusCode::CREATED);
+    server.get("/acme/pixel/1.gif").await.assert_status_ok();
+    server.get("/acme/pixel/1.gif").await.assert_status_ok();
+    let cache = &server.get("/metrics").await.json::<Value>()["email_cache"];
+    assert_eq!(cache["entries"], 0);
+    assert_eq!(cache["hits"], 0);
+}
+
+#[test]
+fn test_email_cache_invalidation() {
+    use little_bell::database::{Email, EmailRef};
+    use little_bell::email_cache::EmailCache;
+
+    let email = Email {
+        id: 7,
+        tenant_id: "acme".to_string(),
+        subject: None,
+        recipient: None,
+        created_at: chrono::Utc::now(),
+        public_token: Some("tok".to_string()),
+        campaign_id: None,
+        metadata: None,
+        tracking_consent: true,
+        expires_at: None,
+        sent_at: None,
+        resend_of: None,
+        external_id: None,
+    };
+    let cache = EmailCache::new(2);
+    cache.insert("acme", &EmailRef::Id(7), &email);
+    cache.insert("acme", &EmailRef::Token("tok".to_string()), &email);
+    assert!(cache.get("acme", &EmailRef::Id(7)).is_some());
+
+    cache.invalidate(&email);
+    assert!(cache.get("acme", &EmailRef::Id(7)).is_none());
+    assert!(cache.get("acme", &EmailRef::Token("tok".to_string())).is_none());
+}
+
+#[tokio::test]
+async fn test_import_emails_from_csv() {
+    use axum_test::multipart::{MultipartForm, Part};
+
+    let server = test_server().await;
+    let csv = "recipient,subject\n\
+               ann@example.com,Spring sale\n\
+               ,No recipient\n\
+               bob@example.com\n\
+               cat@example.com,Spring sale\n";
+    let form = MultipartForm::new().add_part("file", Part::text(csv).file_name("list.csv"));
+    let response = server.post("/acme/emails/import").multipart(form).await;
+    response.assert_status(StatusCode::CREATED);
+    let body: Value = response.json();
+
+    let created = body["created"].as_array().unwrap();
+    assert_eq!(created.len(), 2);
+    assert_eq!(created[0]["row"], 1);
+    assert_eq!(created[1]["row"], 4);
+    assert!(created[0]["tracking_pixel_url"].as_str().unwrap().contains("/acme/pixel/"));
+    let errors = body["errors"].as_array().unwrap();
+    assert_eq!(errors.len(), 2);
+    assert_eq!(errors[0]["row"], 2);
+    assert_eq!(errors[0]["error"], "recipient is required");
+    assert_eq!(errors[1]["row"], 3);
+
+    let pixel = created[1]["tracking_pixel_url"].as_str().unwrap();
+    let path = &pixel[pixel.find("/acme/").unwrap()..];
+    server.get(path).await.assert_status_ok();
+
+    let emails: Value = server.get("/acme/emails").await.json();
+    assert_eq!(emails.as_array().unwrap().len(), 2);
+
+    // A CSV without the recipient column isn't an import
+    let form = MultipartForm::new().add_text("file", "email,subject\nann@example.com,Hi\n");
+    server
+        .post("/acme/emails/import")
+        .multipart(form)
+        .await
+        .assert_status(StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn test_import_emails_json_strict() {
+    let server = test_server().await;
+    let rows = json!([
+        {"recipient": "ann@example.com", "subject": "Hi"},
+        {"subject": "Nobody"},
+    ]);
+
+    let response = server
+        .post("/acme/emails/import?strict=true")
+        .json(&rows)
+        .await;
+    response.assert_status(StatusCode::BAD_REQUEST);
+    let body: Value = response.json();
+    assert_eq!(body["code"], "IMPORT_REJECTED");
+    assert_eq!(body["details"][0]["row"], 2);
+    let emails: Value = server.get("/acme/emails").await.json();
+    assert_eq!(emails.as_array().unwrap().len(), 0);
+
+    let response = server
+        .post("/acme/emails/import?strict=yes")
+        .json(&rows)
+        .await;
+    response.assert_status(StatusCode::BAD_REQUEST);
+    assert_eq!(response.json::<Value>()["code"], "BAD_REQUEST");
+
+    let body: Value = server.post("/acme/emails/import").json(&rows).await.json();
+    assert_eq!(body["created"].as_array().unwrap().len(), 1);
+    assert_eq!(body["errors"].as_array().unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn test_client_cookie_counts_unique_clients() {
+    let db = Arc::new(Database::new(":memory:").await.unwrap());
+    let config = Config {
+        set_client_cookie: true,
+        ..Config::default()
+    };
+    let server = TestServer::new(create_app(db, config).await).unwrap();
+    let created: Value = server.post("/acme/emails").json(&json!({})).await.json();
+    let pixel = format!("/acme/pixel/{}.gif", created["email_id"]);
+
+    let response = server.get(&pixel).await;
+    let set_cookie = response.header("set-cookie");
+    let set_cookie = set_cookie.to_str().unwrap();
+    assert!(set_cookie.starts_with("lb_uid="));
+    assert!(set_cookie.contains("HttpOnly"));
+    let cookie = set_cookie.split(';').next().unwrap().to_string();
+
+    // A returning client keeps its id and isn't sent a new cookie
+    let response = server
+        .get(&pixel)
+        .add_header(
+            axum::http::header::COOKIE,
+            axum::http::HeaderValue::from_str(&format!("theme=dark; {}", cookie)).unwrap(),
+        )
+        .await;
+    assert!(response.maybe_header("set-cookie").is_none());
+    server.get(&pixel).await.assert_status_ok();
+
+    let stats: Value = server.get("/acme/stats").await.json();
+    assert_eq!(stats["total_opens"], 3);
+    assert_eq!(stats["unique_open_clients"], 2);
+    let client_id = &cookie["lb_uid=".len()..];
+    let with_id = stats["recent_events"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .filter(|event| event["client_id"] == client_id)
+        .count();
+    assert_eq!(with_id, 2);
+
+    // Off by default
+    let server = test_server().await;
+    let created: Value = server.post("/acme/emails").json(&json!({})).await.json();
+    let response = server.get(&format!("/acme/pixel/{}.gif", created["email_id"])).await;
+    assert!(response.maybe_header("set-cookie").is_none());
+    let stats: Value = server.get("/acme/stats").await.json();
+    assert_eq!(stats["unique_open_clients"], 0);
+}
+
+#[tokio::test]
+async fn test_opens_past_cap_are_flagged_suspicious() {
+    let db = Arc::new(Database::new(":memory:").await.unwrap());
+    let config = Config {
+        max_opens_per_email: Some(2),
+        ..Config::default()
+    };
+    let server = TestServer::new(create_app(db, config).await).unwrap();
+    let created: Value = server.post("/acme/emails").json(&json!({})).await.json();
+    let pixel = format!("/acme/pixel/{}.gif", created["email_id"]);
+
+    for _ in 0..4 {
+        server.get(&pixel).await.assert_status_ok();
+    }
+
+    let stats: Value = server.get("/acme/stats").await.json();
+    assert_eq!(stats["total_opens"], 2);
+    assert_eq!(stats["unique_opens"], 1);
+    assert_eq!(stats["suspicious_opens"], 2);
+    let flagged = stats["recent_events"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .filter(|event| event["suspicious"] == true)
+        .count();
+    assert_eq!(flagged, 2);
+}
+
+#[tokio::test]
+async fn test_stats_exclude_bots_and_include_suspicious_on_request() {
+    let db = Arc::new(Database::new(":memory:").await.unwrap());
+    let config = Config {
+        max_opens_per_email: Some(1),
+        ..Config::default()
+    };
+    let server = TestServer::new(create_app(db, config).await).unwrap();
+    let created: Value = server.post("/acme/emails").json(&json!({})).await.json();
+    let pixel = format!("/acme/pixel/{}.gif", created["email_id"]);
+    let browser = "Mozilla/5.0 (Windows NT 10.0; Win64; x64)";
+
+    // A person's open, a second (suspicious) one, and a crawler's
+    server.get(&pixel).add_header("user-agent", browser).await;
+    server.get(&pixel).add_header("user-agent", browser).await;
+    server.get(&pixel).add_header("user-agent", "Mozilla/5.0 (compatible; Googlebot/2.1)").await;
+
+    let stats: Value = server.get("/acme/stats").await.json();
+    assert_eq!(stats["total_opens"], 1);
+    assert_eq!(stats["suspicious_opens"], 2);
+
+    let stats: Value = server.get("/acme/stats?include_suspicious=true").await.json();
+    assert_eq!(stats["total_opens"], 3);
+    assert_eq!(stats["unique_opens"], 1);
+
+    let stats: Value = server
+        .get("/acme/stats?include_bots=false&include_suspicious=true")
+        .await
+        .json();
+    assert_eq!(stats["total_opens"], 2);
+    assert_eq!(stats["suspicious_opens"], 1);
+    assert_eq!(stats["events_by_type"]["open"], 2);
+
+    // The dashboard counts people only unless toggled
+    let html = server.get("/acme/dashboard").await.text();
+    assert!(html.contains(r#"<div class="stat-value">1</div>"#));
+    assert!(!html.contains("name=\"include_bots\" value=\"true\" checked"));
+    let html = server
+        .get("/acme/dashboard?include_bots=true&include_suspicious=true")
+        .await
+        .text();
+    assert!(html.contains(r#"<div class="stat-value">3</div>"#));
+    assert!(html.contains("name=\"include_bots\" value=\"true\" checked"));
+}
+
+#[tokio::test]
+async fn test_rate_limited_pixel_is_served_without_recording() {
+    let db = Arc::new(Database::new(":memory:").await.unwrap());
+    let config = Config {
+        open_rate_limit: Some(2),
+        open_rate_window_secs: 60,
+        open_rate_cooldown_secs: 60,
+        ..Config::default()
+    };
+    let server = TestServer::new(create_app(db, config).await).unwrap();
+    let created: Value = server.post("/acme/emails").json(&json!({})).await.json();
+    let other: Value = server.post("/acme/emails").json(&json!({})).await.json();
+    let pixel = format!("/acme/pixel/{}.gif", created["email_id"]);
+
+    for _ in 0..5 {
+        let response = server.get(&pixel).await;
+        response.assert_status_ok();
+        assert_eq!(response.header("content-type"), "image/gif");
+    }
+    // Other emails have their own counters
+    server
+        .get(&format!("/acme/pixel/{}.gif", other["email_id"]))
+        .await
+        .assert_status_ok();
+
+    let stats: Value = server.get("/acme/stats").await.json();
+    assert_eq!(stats["total_opens"], 3);
+}
+
+#[tokio::test]
+async fn test_batched_writer_persists_events() {
+    let db = Arc::new(Database::new(":memory:").await.unwrap());
+    let config = Config {
+        event_buffer_size: 1000,
+        ..Config::default()
+    };
+    let server = TestServer::new(create_app(db, config).await).unwrap();
+    let created: Value = server.post("/acme/emails").json(&json!({})).await.json();
+    let pixel = format!("/acme/pixel/{}.gif", created["email_id"]);
+
+    for _ in 0..250 {
+        server.get(&pixel).await.assert_status_ok();
+    }
+
+    // Written in the background within a batch window or two
+    let mut total_opens = Value::Null;
+    for _ in 0..50 {
+        total_opens = server.get("/acme/stats").await.json::<Value>()["total_opens"].clone();
+        if total_opens == 250 {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+    }
+    assert_eq!(total_opens, 250);
+}
+
+#[tokio::test]
+async fn test_event_writer_close_flushes_queue() {
+    use little_bell::{database::NewEvent, AppState};
+
+    let db = Arc::new(Database::new(":memory:").await.unwrap());
+    db.create_tenant("acme", "acme").await.unwrap();
+    let (email, _) = db
+        .create_email("acme", &little_bell::database::NewEmail::default())
+        .await
+        .unwrap();
+    let config = Config {
+        event_buffer_size: 10,
+        ..Config::default()
+    };
+    let state = AppState::new(db.clone(), config);
+    let open = || NewEvent {
+        email_id: email.id,
+        event_type: "open".to_string(),
+        timestamp: Some(chrono::Utc::now()),
+        user_agent: None,
+        ip_address: None,
+        target_url: None,
+        client_id: None,
+        suspicious: false,
+        premature: false,
+    };
+
+    // More than the queue holds, so some sends wait for room
+    for _ in 0..35 {
+        state.log_tracking_event("acme", open()).await.unwrap();
+    }
+    assert!(state.shutdown().await);
+    assert_eq!(db.get_tenant_stats("acme").await.unwrap().total_opens, 35);
+
+    // Once closed, events are written inline
+    state.log_tracking_event("acme", open()).await.unwrap();
+    assert_eq!(db.get_tenant_stats("acme").await.unwrap().total_opens, 36);
+}
+
+#[tokio::test]
+async fn test_event_writer_retries_failed_batch_row_by_row() {
+    use little_bell::{database::NewEvent, AppState};
+
+    let db = Arc::new(Database::new(":memory:").await.unwrap());
+    db.create_tenant("acme", "acme").await.unwrap();
+    let (email, _) = db
+        .create_email("acme", &little_bell::database::NewEmail::default())
+        .await
+        .unwrap();
+    let config = Config {
+        event_buffer_size: 10,
+        ..Config::default()
+    };
+    let state = AppState::new(db.clone(), config);
+    let open = |email_id| NewEvent {
+        email_id,
+        event_type: "open".to_string(),
+        timestamp: Some(chrono::Utc::now()),
+        user_agent: None,
+        ip_address: None,
+        target_url: None,
+        client_id: None,
+        suspicious: false,
+        premature: false,
+    };
+
+    // An event for a deleted email fails its batch on the foreign key; the
+    // others in it are still written
+    state.event_writer.queue("acme", open(email.id)).await.unwrap();
+    state.event_writer.queue("acme", open(email.id + 1)).await.unwrap();
+    state.event_writer.queue("acme", open(email.id)).await.unwrap();
+    assert!(state.shutdown().await);
+
+    assert_eq!(db.get_tenant_stats("acme").await.unwrap().total_opens, 2);
+    assert_eq!(state.event_writer.backlog().unwrap().dropped, 1);
+}
+
+#[test]
+fn test_hyperloglog_estimate_within_tolerance() {
+    use little_bell::unique_opens::HyperLogLog;
+
+    // Standard error of 2^14 registers
+    let sigma = 1.04 / (16_384f64).sqrt();
+    for exact in [10i64, 1_000, 20_000, 50_000, 200_000, 1_000_000] {
+        // Sequential ids, and ids spread out as after deletions
+        for stride in [1, 7_919] {
+            let mut sketch = HyperLogLog::default();
+            for n in 1..=exact {
+                sketch.insert(n * stride);
+                // Repeats don't count twice
+                sketch.insert(n * stride);
+            }
+            let error = (sketch.estimate() - exact as f64).abs() / exact as f64;
+            assert!(
+                error <= 3.0 * sigma,
+                "estimated {} for {} (stride {}), {:.2}σ off",
+                sketch.estimate(),
+                exact,
+                stride,
+                error / sigma
+            );
+        }
+    }
+
+    // Merging counts the union, and the stored form round-trips
+    let (mut evens, mut odds) = (HyperLogLog::default(), HyperLogLog::default());
+    for email_id in 1..=10_000i64 {
+        if email_id % 2 == 0 { evens.insert(email_id) } else { odds.insert(email_id) };
+    }
+    evens.merge(&odds);
+    let restored = HyperLogLog::from_bytes(evens.to_bytes()).unwrap();
+    assert!((restored.estimate() - 10_000.0).abs() < 300.0);
+    assert!(HyperLogLog::from_bytes(&[0; 16]).is_none());
+}
+
+#[tokio::test]
+async fn test_unique_opens_estimated_from_sketch() {
+    use little_bell::{database::NewEvent, AppState};
+
+    let db = Arc::new(Database::new(":memory:").await.unwrap());
+    let config = Config {
+        unique_open_sketch: true,
+        unique_open_sketch_min_emails: 3,
+        ..Config::default()
+    };
+    let server = TestServer::new(create_app(db.clone(), config.clone()).await).unwrap();
+    for _ in 0..2 {
+        server.post("/acme/emails").json(&json!({})).await;
+    }
+    server.get("/acme/pixel/1.gif").await;
+
+    // Too small: counted exactly
+    let stats: Value = server.get("/acme/stats").await.json();
+    assert_eq!(stats["unique_opens"], 1);
+    assert!(stats.get("approximate").is_none());
+
+    server.post("/acme/emails").json(&json!({})).await;
+    let stats: Value = server.get("/acme/stats").await.json();
+    assert_eq!(stats["unique_opens"], 1);
+    assert_eq!(stats["approximate"], true);
+
+    // Kept current by later opens, with repeats counted once
+    for id in [2, 3, 3] {
+        server.get(&format!("/acme/pixel/{}.gif", id)).await;
+    }
+    let stats: Value = server.get("/acme/stats").await.json();
+    assert_eq!(stats["unique_opens"], 3);
+    assert_eq!(stats["total_opens"], 4);
+    assert_eq!(stats["approximate"], true);
+    assert!(stats["recent_events"].as_array().unwrap().len() == 4);
+    // Overrides are counted exactly
+    let stats: Value = server.get("/acme/stats?include_suspicious=true").await.json();
+    assert!(stats.get("approximate").is_none());
+    // As are tenants marking sends, whose rates need sent emails' opens
+    server.post("/acme/emails/1/sent").await.assert_status_ok();
+    let stats: Value = server.get("/acme/stats").await.json();
+    assert!(stats.get("approximate").is_none());
+    assert_eq!(stats["open_rate"], 1.0);
+
+    // Saved on shutdown, and caught up with later opens when reloaded
+    let state = AppState::new(db.clone(), config.clone());
+    state.unique_opens.as_ref().unwrap().estimate(&db, "acme").await.unwrap();
+    assert!(state.shutdown().await);
+    assert!(db.load_unique_open_sketch("acme").await.unwrap().is_some());
+    let (email, _) = db
+        .create_email("acme", &little_bell::database::NewEmail::default())
+        .await
+        .unwrap();
+    db.log_new_event(&NewEvent {
+        email_id: email.id,
+        event_type: "open".to_string(),
+        timestamp: None,
+        user_agent: None,
+        ip_address: None,
+        target_url: None,
+        client_id: None,
+        suspicious: false,
+        premature: false,
+    })
+    .await
+    .unwrap();
+    let state = AppState::new(db.clone(), config);
+    let estimate = state.unique_opens.as_ref().unwrap().estimate(&db, "acme").await.unwrap();
+    assert_eq!(estimate, Some(4));
+
+    // Resetting the stats starts the sketch over
+    let admin = Config {
+        admin_key: Some("secret".to_string()),
+        unique_open_sketch: true,
+        unique_open_sketch_min_emails: 3,
+        ..Config::default()
+    };
+    let server = TestServer::new(create_app(db.clone(), admin).await).unwrap();
+    server
+        .post("/acme/reset-stats")
+        .authorization_bearer("secret")
+        .await
+        .assert_status_ok();
+    assert!(db.load_unique_open_sketch("acme").await.unwrap().is_none());
+    let stats: Value = server.get("/acme/stats").await.json();
+    assert_eq!(stats["unique_opens"], 0);
+}
+
+#[tokio::test]
+async fn test_event_writer_reports_backlog() {
+    use little_bell::{database::NewEvent, event_writer::EventWriter};
+
+    let open = || NewEvent {
+        email_id: 1,
+        event_type: "open".to_string(),
+        timestamp: None,
+        user_agent: None,
+        ip_address: None,
+        target_url: None,
+        client_id: None,
+        suspicious: false,
+        premature: false,
+    };
+    // Nothing drains the queue, so it only grows
+    let (writer, _receiver) = EventWriter::channel(10, 2);
+    for _ in 0..2 {
+        writer.queue("acme", open()).await.unwrap();
+    }
+    let backlog = writer.backlog().unwrap();
+    assert_eq!((backlog.queued, backlog.capacity, backlog.high_water), (2, 10, 2));
+    assert!(!backlog.degraded);
+    writer.queue("acme", open()).await.unwrap();
+    assert!(writer.backlog().unwrap().degraded);
+
+    let (disabled, _) = EventWriter::channel(0, 0);
+    assert!(disabled.backlog().is_none());
+
+    // An idle writer is ready and says how much room it has
+    let db = Arc::new(Database::new(":memory:").await.unwrap());
+    let config = Config {
+        event_buffer_size: 10,
+        ..Config::default()
+    };
+    let server = TestServer::new(create_app(db, config).await).unwrap();
+    let response = server.get("/ready").await;
+    response.assert_status_ok();
+    let body: Value = response.json();
+    assert_eq!(body["event_writer"]["capacity"], 10);
+    assert_eq!(body["event_writer"]["threshold"], 9);
+    assert_eq!(body["event_writer"]["degraded"], false);
+}
+
+#[tokio::test]
+async fn test_list_email_events() {
+    let server = test_server().await;
+    let created: Value = server.post("/acme/emails").json(&json!({})).await.json();
+    let email_id = created["email_id"].as_i64().unwrap();
+    let other: Value = server.post("/acme/emails").json(&json!({})).await.json();
+
+    server.get(&format!("/acme/pixel/{}.gif", email_id)).await.assert_status_ok();
+    server
+        .get(&format!("/acme/click/{}?url=https://example.com/a", email_id))
+        .await;
+    server.get(&format!("/acme/pixel/{}.gif", other["email_id"])).await.assert_status_ok();
+    server.get(&format!("/acme/pixel/{}.gif", email_id)).await.assert_status_ok();
+
+    let events: Value = server.get(&format!("/acme/emails/{}/events", email_id)).await.json();
+    let types: Vec<&str> = events
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|event| event["event_type"].as_str().unwrap())
+        .collect();
+    assert_eq!(types, ["open", "click", "open"]);
+    assert_eq!(events[1]["target_url"], "https://example.com/a");
+
+    let page: Value = server
+        .get(&format!("/acme/emails/{}/events?limit=1&offset=1", email_id))
+        .await
+        .json();
+    assert_eq!(page.as_array().unwrap().len(), 1);
+    assert_eq!(page[0]["event_type"], "click");
+
+    // Another tenant can't read them
+    let response = server.get(&format!("/globex/emails/{}/events", email_id)).await;
+    response.assert_status_not_found();
+    assert_eq!(response.json::<Value>()["code"], "EMAIL_NOT_FOUND");
+}
+
+#[tokio::test]
+async fn test_cross_tenant_click_is_counted_but_hidden() {
+    let server = test_server().await;
+    let created: Value = server.post("/acme/emails").json(&json!({})).await.json();
+    let email_id = created["email_id"].as_i64().unwrap();
+    let token = created["public_token"].as_str().unwrap();
+
+    let cross_tenant = || async {
+        server.get("/metrics").await.json::<Value>()["tracking"]["cross_tenant_lookups"].clone()
+    };
+
+    // Never existed: plain 404, nothing counted
+    server
+        .get("/acme/click/99999?url=https://example.com")
+        .await
+        .assert_status_not_found();
+    assert_eq!(cross_tenant().await, 0);
+
+    // Exists under another tenant: the same 404, but counted
+    server
+        .get(&format!("/globex/click/{}?url=https://example.com", email_id))
+        .await
+        .assert_status_not_found();
+    server
+        .get(&format!("/globex/click/{}?url=https://example.com", token))
+        .await
+        .assert_status_not_found();
+    assert_eq!(cross_tenant().await, 2);
+
+    let stats: Value = server.get("/acme/stats").await.json();
+    assert_eq!(stats["total_clicks"], 0);
+}
+
+#[tokio::test]
+async fn test_dashboard_assets_are_served() {
+    let server = test_server().await;
+
+    let html = server.get("/acme/dashboard").await.text();
+    assert!(html.contains("href=\"/assets/dashboard.css\""));
+    assert!(html.contains("src=\"/assets/dashboard.js\""));
+    assert!(html.contains("data-stream-url=\"/acme/events/stream\""));
+    let filtered = server.get("/acme/dashboard?event_type=open").await.text();
+    assert!(!filtered.contains("data-stream-url"));
+
+    let response = server.get("/assets/dashboard.css").await;
+    response.assert_status_ok();
+    assert_eq!(response.header("content-type"), "text/css; charset=utf-8");
+    assert_eq!(response.header("cache-control"), "public, max-age=3600");
+    assert!(response.text().contains(".stat-card"));
+    let etag = response.header("etag");
+
+    server
+        .get("/assets/dashboard.css")
+        .add_header(axum::http::header::IF_NONE_MATCH, etag)
+        .await
+        .assert_status(StatusCode::NOT_MODIFIED);
+
+    let response = server.get("/assets/dashboard.js").await;
+    response.assert_status_ok();
+    assert!(response.header("content-type").to_str().unwrap().starts_with("text/javascript"));
+    assert!(response.text().contains("EventSource"));
+
+    server.get("/assets/missing.css").await.assert_status_not_found();
+}
+
+#[tokio::test]
+async fn test_verify_email_does_not_record_open() {
+    let server = test_server().await;
+    let created: Value = server.post("/acme/emails").json(&json!({})).await.json();
+    let email_id = created["email_id"].as_i64().unwrap();
+    let token = created["public_token"].as_str().unwrap();
+
+    for path in [
+        format!("/acme/verify/{}", email_id),
+        format!("/acme/verify/{}.gif", token),
+        format!("/acme/verify/{}", token),
+    ] {
+        let response = server.get(&path).await;
+        response.assert_status_ok();
+        assert_eq!(response.json::<Value>(), json!({ "valid": true }));
+    }
+
+    for path in [
+        "/acme/verify/99999".to_string(),
+        format!("/globex/verify/{}.gif", email_id),
+        "/acme/verify/.gif".to_string(),
+    ] {
+        let response = server.get(&path).await;
+        response.assert_status_ok();
+        assert_eq!(response.json::<Value>(), json!({ "valid": false }));
+    }
+
+    let stats: Value = server.get("/acme/stats").await.json();
+    assert_eq!(stats["total_opens"], 0);
+}
+
+#[tokio::test]
+async fn test_ingested_timestamps_are_kept_within_limits() {
+    let server = test_server().await;
+    let created: Value = server.post("/acme/emails").json(&json!({})).await.json();
+    let email_id = created["email_id"].as_i64().unwrap();
+
+    let backfilled = (chrono::Utc::now() - chrono::Duration::days(3)).to_rfc3339();
+    server
+        .post("/acme/events")
+        .json(&json!([{ "email_id": email_id, "event_type": "open", "timestamp": backfilled }]))
+        .await
+        .assert_status(StatusCode::CREATED);
+    let events: Value = server.get(&format!("/acme/emails/{}/events", email_id)).await.json();
+    let stored = chrono::DateTime::parse_from_rfc3339(events[0]["timestamp"].as_str().unwrap()).unwrap();
+    assert_eq!(stored, chrono::DateTime::parse_from_rfc3339(&backfilled).unwrap());
+
+    let future = (chrono::Utc::now() + chrono::Duration::hours(1)).to_rfc3339();
+    let response = server
+        .post("/acme/events")
+        .json(&json!([
+            { "email_id": email_id, "event_type": "open" },
+            { "email_id": email_id, "event_type": "open", "timestamp": future },
+            { "email_id": email_id, "event_type": "open", "timestamp": "1999-01-01T00:00:00Z" },
+        ]))
+        .await;
+    response.assert_status_bad_request();
+    let body: Value = response.json();
+    assert_eq!(body["code"], "BATCH_REJECTED");
+    assert_eq!(body["details"][0]["index"], 1);
+    assert!(body["details"][0]["error"].as_str().unwrap().contains("in the future"));
+    assert_eq!(body["details"][1]["index"], 2);
+    assert!(body["details"][1]["error"].as_str().unwrap().contains("days old"));
+
+    // Within the skew tolerance is fine
+    let db = Arc::new(Database::new(":memory:").await.unwrap());
+    let config = Config {
+        event_clock_skew_secs: 7200,
+        ..Config::default()
+    };
+    let server = TestServer::new(create_app(db, config).await).unwrap();
+    let created: Value = server.post("/acme/emails").json(&json!({})).await.json();
+    server
+        .post("/acme/events")
+        .json(&json!([{ "email_id": created["email_id"], "event_type": "open", "timestamp": future }]))
+        .await
+        .assert_status(StatusCode::CREATED);
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_timed_out_pixel_still_serves_image() {
+    use little_bell::database::DatabaseOptions;
+
+    let path = std::env::temp_dir().join(format!("little-bell-timeout-{}.db", std::process::id()));
+    let path = path.to_str().unwrap().to_string();
+    // Writes find the database busy straight away and back off for far
+    // longer than the timeout; the paused clock skips the waiting
+    let options = DatabaseOptions {
+        busy_timeout: std::time::Duration::ZERO,
+        busy_retries: 20,
+        ..DatabaseOptions::default()
+    };
+    let db = Arc::new(Database::with_options(&path, &options).await.unwrap());
+    let config = Config {
+        request_timeout_secs: Some(1),
+        ..Config::default()
+    };
+    let server = TestServer::new(create_app(db, config).await).unwrap();
+    server.post("/acme/emails").json(&json!({})).await.assert_status(StatusCode::CREATED);
+
+    let blocker = rusqlite::Connection::open(&path).unwrap();
+    blocker.execute_batch("BEGIN EXCLUSIVE").unwrap();
+
+    let response = server.get("/acme/pixel/1.gif").await;
+    response.assert_status_ok();
+    assert_eq!(response.header("content-type"), "image/gif");
+    assert_eq!(response.header("cache-control"), "no-store, no-cache, must-revalidate");
+
+    let response = server.get("/acme/bg/1.png").await;
+    response.assert_status_ok();
+    assert_eq!(response.header("content-type"), "image/png");
+
+    let response = server.post("/acme/emails").json(&json!({})).await;
+    drop(blocker);
+    for suffix in ["", "-wal", "-shm"] {
+        let _ = std::fs::remove_file(format!("{}{}", path, suffix));
+    }
+    response.assert_status(StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(response.json::<Value>()["code"], "SERVICE_UNAVAILABLE");
+}
+
+#[tokio::test]
+async fn test_stats_socket_closes_with_reason_on_database_error() {
+    let path = std::env::temp_dir().join(format!("little-bell-ws-{}.db", std::process::id()));
+    let path = path.to_str().unwrap().to_string();
+    let db = Arc::new(Database::new(&path).await.unwrap());
+    let server = TestServer::builder()
+        .http_transport()
+        .build(create_app(db, Config::default()).await)
+        .unwrap();
+
+    // Counters can't be read without the events table
+    rusqlite::Connection::open(&path).unwrap().execute_batch("DROP TABLE events").unwrap();
+
+    let mut socket = server.get_websocket("/acme/ws/stats").await.into_websocket().await;
+    let message = socket.receive_message().await;
+    for suffix in ["", "-wal", "-shm"] {
+        let _ = std::fs::remove_file(format!("{}{}", path, suffix));
+    }
+    match message {
+        axum_test::WsMessage::Close(Some(frame)) => {
+            assert_eq!(u16::from(frame.code), 1011);
+            assert_eq!(frame.reason, "internal server error");
+        }
+        other => panic!("expected a close frame, got {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn test_recipients_listed_by_engagement() {
+    let server = test_server().await;
+    for recipient in [json!("ann@example.com"), json!("bob@example.com"), json!("ann@example.com"), json!(null)] {
+        let created: Value = server.post("/acme/emails").json(&json!({"recipient": recipient})).await.json();
+        let email_id = created["email_id"].as_i64().unwrap();
+        server.get(&format!("/acme/pixel/{}.gif", email_id)).await.assert_status_ok();
+    }
+    // Bob's second open and click put him ahead of Ann's two opens
+    server.get("/acme/pixel/2.gif").await.assert_status_ok();
+    server
+        .get("/acme/click/2")
+        .add_query_param("url", "https://example.com")
+        .await
+        .assert_status(StatusCode::TEMPORARY_REDIRECT);
+    server.post("/acme/emails/1/sent").await.assert_status_ok();
+
+    let body: Value = server.get("/acme/recipients").await.json();
+    assert_eq!(body["total"], 2);
+    let recipients = body["recipients"].as_array().unwrap();
+    assert_eq!(recipients.len(), 2);
+    assert_eq!(recipients[0]["recipient"], "bob@example.com");
+    assert_eq!(recipients[0]["total_opens"], 2);
+    assert_eq!(recipients[0]["total_clicks"], 1);
+    assert_eq!(recipients[1]["recipient"], "ann@example.com");
+    assert_eq!(recipients[1]["emails_created"], 2);
+    assert_eq!(recipients[1]["emails_sent"], 1);
+    assert_eq!(recipients[1]["total_opens"], 2);
+    assert!(recipients[1]["last_engaged_at"].is_string());
+
+    let body: Value = server
+        .get("/acme/recipients?sort_by=recipient&direction=asc&limit=1&offset=1")
+        .await
+        .json();
+    assert_eq!(body["recipients"][0]["recipient"], "bob@example.com");
+    assert_eq!(body["total"], 2);
+
+    // Addresses list alphabetically unless asked otherwise
+    let body: Value = server.get("/acme/recipients?sort_by=recipient").await.json();
+    assert_eq!(body["recipients"][0]["recipient"], "ann@example.com");
+
+    server
+        .get("/acme/recipients?sort_by=nonsense")
+        .await
+        .assert_status(StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn test_user_agents_moved_to_lookup_table() {
+    use little_bell::database::NewEmail;
+
+    let path = std::env::temp_dir().join(format!("little-bell-user-agents-{}.db", std::process::id()));
+    let path = path.to_str().unwrap().to_string();
+    let db = Database::new(&path).await.unwrap();
+    db.create_tenant("acme", "acme").await.unwrap();
+    let (email, _) = db.create_email("acme", &NewEmail::default()).await.unwrap();
+    db.log_event(email.id, "open", Some("Mail/1.0"), None, None, None).await.unwrap();
+    db.log_event(email.id, "open", Some("Mail/1.0"), None, None, None).await.unwrap();
+    db.log_event(email.id, "open", None, None, None, None).await.unwrap();
+    drop(db);
+
+    // As an older binary would have stored it
+    let conn = rusqlite::Connection::open(&path).unwrap();
+    conn.execute(
+        "INSERT INTO events (email_id, event_type, timestamp, user_agent) VALUES (?1, 'click', ?2, 'Mail/1.0')",
+        rusqlite::params![email.id, chrono::Utc::now().to_rfc3339()],
+    )
+    .unwrap();
+    drop(conn);
+
+    let db = Database::new(&path).await.unwrap();
+    let events = db.list_email_events(email.id, "acme", 10, 0).await.unwrap().unwrap();
+    let user_agents: Vec<Option<&str>> = events.iter().map(|e| e.user_agent.as_deref()).collect();
+    assert_eq!(user_agents, [Some("Mail/1.0"), Some("Mail/1.0"), None, Some("Mail/1.0")]);
+    drop(db);
+
+    let conn = rusqlite::Connection::open(&path).unwrap();
+    let (distinct, inline): (i64, i64) = conn
+        .query_row(
+            "SELECT (SELECT COUNT(*) FROM user_agents), (SELECT COUNT(user_agent) FROM events)",
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .unwrap();
+    drop(conn);
+    for suffix in ["", "-wal", "-shm"] {
+        let _ = std::fs::remove_file(format!("{}{}", path, suffix));
+    }
+    assert_eq!((distinct, inline), (1, 0));
+}
+
+#[tokio::test]
+async fn test_default_tenant_routes() {
+    let db = Arc::new(Database::new(":memory:").await.unwrap());
+    let config = Config {
+        default_tenant: Some("acme".to_string()),
+        ..Config::default()
+    };
+    let server = TestServer::new(create_app(db, config).await).unwrap();
+
+    let response = server.post("/emails").json(&json!({"subject": "Hello"})).await;
+    response.assert_status(StatusCode::CREATED);
+    let email_id = response.json::<Value>()["email_id"].as_i64().unwrap();
+
+    let response = server.get(&format!("/pixel/{}.gif", email_id)).await;
+    response.assert_status_ok();
+    assert_eq!(response.header("content-type"), "image/gif");
+    server
+        .get(&format!("/click/{}", email_id))
+        .add_query_param("url", "https://example.com")
+        .await
+        .assert_status(StatusCode::TEMPORARY_REDIRECT);
+    server.get("/dashboard").await.assert_status_ok();
+
+    let emails: Value = server.get("/emails").await.json();
+    assert_eq!(emails[0]["id"], email_id);
+    let stats: Value = server.get("/acme/stats").await.json();
+    assert_eq!(stats["total_opens"], 1);
+    assert_eq!(stats["total_clicks"], 1);
+
+    // Tenants the aliases would shadow can't be used
+    for tenant in ["pixel", "click", "emails", "dashboard"] {
+        server
+            .get(&format!("/{}/emails/1/events", tenant))
+            .await
+            .assert_status_bad_request();
+    }
+
+    // Under a path prefix the aliases are too
+    let db = Arc::new(Database::new(":memory:").await.unwrap());
+    let config = Config {
+        default_tenant: Some("acme".to_string()),
+        path_prefix: "/bell".to_string(),
+        ..Config::default()
+    };
+    let server = TestServer::new(create_app(db, config).await).unwrap();
+    server.post("/bell/emails").json(&json!({})).await.assert_status(StatusCode::CREATED);
+    server.get("/bell/pixel/1.gif").await.assert_status_ok();
+    server.get("/bell/acme/pixel/1.gif").await.assert_status_ok();
+    server.get("/emails").await.assert_status(StatusCode::NOT_FOUND);
+
+    // Without a default tenant only the tenant-scoped routes exist, and
+    // any tenant name is fine
+    let server = test_server().await;
+    server.get("/pixel/1.gif").await.assert_status(StatusCode::NOT_FOUND);
+    server.get("/emails").await.assert_status(StatusCode::NOT_FOUND);
+    server.get("/pixel/stats").await.assert_status_ok();
+}
+
+#[tokio::test]
+async fn test_opens_within_grace_period_are_premature() {
+    let db = Arc::new(Database::new(":memory:").await.unwrap());
+    let config = Config {
+        open_grace_secs: 3600,
+        ..Config::default()
+    };
+    let server = TestServer::new(create_app(db, config).await).unwrap();
+    let created: Value = server.post("/acme/emails").json(&json!({})).await.json();
+    let pixel = format!("/acme/pixel/{}.gif", created["email_id"]);
+
+    for _ in 0..2 {
+        server.get(&pixel).await.assert_status_ok();
+    }
+
+    let stats: Value = server.get("/acme/stats").await.json();
+    assert_eq!(stats["total_opens"], 0);
+    assert_eq!(stats["unique_opens"], 0);
+    assert_eq!(stats["premature_opens"], 2);
+    assert!(stats["recent_events"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .all(|event| event["premature"] == true));
+    let stats: Value = server.get("/acme/stats?include_suspicious=true").await.json();
+    assert_eq!(stats["total_opens"], 0);
+    let emails: Value = server.get("/acme/emails").await.json();
+    assert_eq!(emails[0]["open_count"], 0);
+    let latency: Value = server.get("/acme/latency").await.json();
+    assert_eq!(latency["emails_opened"], 0);
+}